@@ -1,6 +1,8 @@
 //! API types for the Pipeliner gRPC and REST API.
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
+use pipeliner_events::RunStatus;
+use pipeliner_events::projection::{ExecutionView, StageTimeline, StepTimeline, WorkerView};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -93,6 +95,22 @@ pub enum PipelineStatus {
     Unstable,
 }
 
+impl From<RunStatus> for PipelineStatus {
+    fn from(status: RunStatus) -> Self {
+        match status {
+            RunStatus::Running => Self::Running,
+            RunStatus::Success => Self::Success,
+            RunStatus::Unstable => Self::Unstable,
+            RunStatus::Failure => Self::Failure,
+            RunStatus::Aborted => Self::Cancelled,
+        }
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.num_milliseconds() as f64 / 1000.0
+}
+
 /// Pipeline info response
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PipelineInfo {
@@ -117,6 +135,23 @@ pub struct ExecutionInfo {
     pub completed_at: Option<DateTime<Utc>>,
 }
 
+impl ExecutionInfo {
+    /// Builds the response from the execution status and stage timeline
+    /// read models
+    pub fn from_views(execution: &ExecutionView, stages: &[StageTimeline]) -> Self {
+        Self {
+            id: execution.execution_id,
+            pipeline_id: execution.pipeline_id,
+            pipeline_name: execution.pipeline_name.clone().unwrap_or_default(),
+            status: execution.status.into(),
+            stages: stages.iter().map(StageInfo::from).collect(),
+            created_at: execution.started_at,
+            started_at: Some(execution.started_at),
+            completed_at: execution.completed_at,
+        }
+    }
+}
+
 /// Stage info response
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StageInfo {
@@ -126,6 +161,37 @@ pub struct StageInfo {
     pub duration_seconds: Option<f64>,
 }
 
+impl From<&StageTimeline> for StageInfo {
+    /// Steps have no result of their own in the event log: a finished step
+    /// counts as successful, except the last one of a stage that did not
+    /// succeed, which takes the stage's status.
+    fn from(stage: &StageTimeline) -> Self {
+        let last_finished = stage.steps.iter().rposition(|s| s.completed_at.is_some());
+        let steps = stage
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| {
+                let status = if step.completed_at.is_none() {
+                    PipelineStatus::Running
+                } else if Some(i) == last_finished && stage.status.is_finished() {
+                    stage.status.into()
+                } else {
+                    PipelineStatus::Success
+                };
+                StepInfo::from_timeline(step, status)
+            })
+            .collect();
+
+        Self {
+            name: stage.name.clone(),
+            status: stage.status.into(),
+            steps,
+            duration_seconds: stage.duration().map(seconds),
+        }
+    }
+}
+
 /// Step info response
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StepInfo {
@@ -135,6 +201,17 @@ pub struct StepInfo {
     pub output: Option<String>,
 }
 
+impl StepInfo {
+    fn from_timeline(step: &StepTimeline, status: PipelineStatus) -> Self {
+        Self {
+            name: step.name.clone(),
+            status,
+            duration_seconds: step.duration().map(seconds),
+            output: step.output.clone(),
+        }
+    }
+}
+
 /// Worker info response
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkerInfo {
@@ -146,6 +223,19 @@ pub struct WorkerInfo {
     pub last_heartbeat: Option<DateTime<Utc>>,
 }
 
+impl From<&WorkerView> for WorkerInfo {
+    fn from(worker: &WorkerView) -> Self {
+        Self {
+            id: worker.worker_id.clone(),
+            status: worker.state.as_str().to_string(),
+            current_job_id: worker.current_job,
+            jobs_completed: worker.jobs_completed,
+            jobs_failed: worker.jobs_failed,
+            last_heartbeat: worker.last_heartbeat,
+        }
+    }
+}
+
 /// Health check response
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HealthResponse {
@@ -203,4 +293,42 @@ mod tests {
         };
         assert_eq!(info.status, PipelineStatus::Running);
     }
+
+    #[test]
+    fn test_execution_info_from_views() {
+        let started_at = Utc::now();
+        let execution = ExecutionView {
+            execution_id: Uuid::new_v4(),
+            pipeline_id: Uuid::new_v4(),
+            pipeline_name: Some("deploy".to_string()),
+            status: RunStatus::Failure,
+            current_stage: None,
+            started_at,
+            completed_at: Some(started_at + Duration::seconds(3)),
+            error: Some("exit code 1".to_string()),
+        };
+        let step = |name: &str, seconds| StepTimeline {
+            name: name.to_string(),
+            started_at,
+            completed_at: Some(started_at + Duration::seconds(seconds)),
+            output: None,
+        };
+        let stage = StageTimeline {
+            name: "build".to_string(),
+            status: RunStatus::Failure,
+            started_at,
+            completed_at: Some(started_at + Duration::milliseconds(2500)),
+            steps: vec![step("checkout", 1), step("make", 2)],
+        };
+
+        let info = ExecutionInfo::from_views(&execution, &[stage]);
+        assert_eq!(info.pipeline_name, "deploy");
+        assert_eq!(info.status, PipelineStatus::Failure);
+        assert_eq!(info.stages[0].duration_seconds, Some(2.5));
+        let statuses: Vec<_> = info.stages[0].steps.iter().map(|s| &s.status).collect();
+        assert_eq!(
+            statuses,
+            [&PipelineStatus::Success, &PipelineStatus::Failure]
+        );
+    }
 }
@@ -2,11 +2,16 @@
 
 use anyhow::{Context, Result};
 use clap::{Args, CommandFactory, Parser, Subcommand};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tracing::info;
+use uuid::Uuid;
 
 use pipeliner_core::Pipeline;
-use pipeliner_events::LocalEventBus;
+use pipeliner_events::{FileEventStore, LocalEventBus};
+use pipeliner_executor::listener::{CompositeListener, TracingListener};
+use pipeliner_executor::{CancellationToken, EventStreamListener, ExecutionConfig, Executor};
+use pipeliner_worker::{JobQueue, JournalConfig};
 
 /// Command-line interface for Pipeliner pipeline execution
 #[derive(Parser, Debug)]
@@ -46,6 +51,10 @@ enum Commands {
     /// Check pipeline syntax without execution
     #[command(name = "check")]
     Check(CheckArgs),
+
+    /// List and replay jobs that exhausted their retries
+    #[command(name = "dlq")]
+    DeadLetters(DeadLettersArgs),
 }
 
 #[derive(Args, Debug)]
@@ -62,6 +71,14 @@ struct RunArgs {
     #[arg(short, long)]
     working_dir: Option<PathBuf>,
 
+    /// Directory of an event store recording the run's events
+    #[arg(long)]
+    events: Option<PathBuf>,
+
+    /// Pipeline ID the events are recorded under, a new one if not given
+    #[arg(long, requires = "events")]
+    pipeline_id: Option<Uuid>,
+
     /// Verbose output
     #[arg(short, long, default_value = "false")]
     verbose: bool,
@@ -145,6 +162,50 @@ struct CheckArgs {
     definition: Option<String>,
 }
 
+#[derive(Args, Debug)]
+struct DeadLettersArgs {
+    #[command(subcommand)]
+    command: DeadLettersCommand,
+}
+
+/// Commands on the dead-letter queue of a persistent job queue
+///
+/// Listing only reads the queue's journal. Replaying opens the queue, and
+/// fails while a controller has it open.
+#[derive(Subcommand, Debug)]
+enum DeadLettersCommand {
+    /// List the jobs in the dead-letter queue
+    #[command(name = "list")]
+    List(DeadLettersListArgs),
+
+    /// Enqueue jobs of the dead-letter queue again, while no controller
+    /// has the queue open
+    #[command(name = "replay")]
+    Replay(DeadLettersReplayArgs),
+}
+
+#[derive(Args, Debug)]
+struct DeadLettersListArgs {
+    /// Journal of the job queue
+    #[arg(short, long)]
+    queue: PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct DeadLettersReplayArgs {
+    /// Journal of the job queue
+    #[arg(short, long)]
+    queue: PathBuf,
+
+    /// IDs of the jobs to replay
+    #[arg(required_unless_present = "all", conflicts_with = "all")]
+    ids: Vec<Uuid>,
+
+    /// Replay every job in the dead-letter queue
+    #[arg(short, long, default_value = "false")]
+    all: bool,
+}
+
 pub async fn run() -> Result<()> {
     let args = Cli::parse();
 
@@ -156,6 +217,7 @@ pub async fn run() -> Result<()> {
         Commands::Export(export_args) => export_pipeline(export_args),
         Commands::Completions(completions_args) => generate_completions(completions_args),
         Commands::Check(check_args) => check_pipeline(check_args),
+        Commands::DeadLetters(dlq_args) => dead_letters(dlq_args),
     }
 }
 
@@ -166,13 +228,74 @@ async fn run_pipeline(args: RunArgs) -> Result<()> {
     let pipeline: Pipeline =
         serde_yaml::from_str(&definition).context("Failed to parse pipeline definition")?;
 
-    let event_bus = LocalEventBus::new();
-    let name = pipeline.name.unwrap_or_else(|| "Unnamed".to_string());
+    let name = pipeline
+        .name
+        .clone()
+        .unwrap_or_else(|| "Unnamed".to_string());
     info!("Pipeline '{}' parsed successfully", name);
 
+    let mut config = ExecutionConfig::default();
+    if let Some(dir) = args.working_dir {
+        config.working_dir = dir;
+    }
+    let mut listener = CompositeListener::new();
+    listener.add(TracingListener);
+    if let Some(dir) = &args.events {
+        let store = FileEventStore::open(dir)
+            .await
+            .with_context(|| format!("Failed to open event store {}", dir.display()))?;
+        let pipeline_id = args.pipeline_id.unwrap_or_else(Uuid::new_v4);
+        info!("Recording events of pipeline {}", pipeline_id);
+        listener.add(EventStreamListener::new(
+            pipeline_id,
+            Arc::new(store),
+            Arc::new(LocalEventBus::new()),
+        ));
+    }
+    let mut executor = Executor::new(pipeline, config).with_listener(listener);
+    let interrupt = tokio::spawn(cancel_on_interrupt(executor.cancellation()));
+
+    let result = executor.run().await;
+    interrupt.abort();
+    let result = result.context("Pipeline execution failed")?;
+
+    if result.is_aborted() {
+        anyhow::bail!(
+            "Pipeline '{}' aborted: {}",
+            name,
+            result.error.unwrap_or_default()
+        );
+    }
+    if !result.is_success() {
+        anyhow::bail!(
+            "Pipeline '{}' failed: {}",
+            name,
+            result.error.unwrap_or_default()
+        );
+    }
+    println!(
+        "Pipeline '{}' succeeded ({} stages)",
+        name, result.stages_executed
+    );
     Ok(())
 }
 
+/// Cancels the execution on Ctrl-C, and exits on a second Ctrl-C
+///
+/// The first interrupt lets the running stage clean up and its post
+/// conditions run; the second one gives up on them.
+async fn cancel_on_interrupt(cancellation: CancellationToken) {
+    if tokio::signal::ctrl_c().await.is_err() {
+        return;
+    }
+    eprintln!("Interrupted, aborting pipeline (press Ctrl-C again to exit immediately)");
+    cancellation.cancel("Interrupted by user");
+
+    if tokio::signal::ctrl_c().await.is_ok() {
+        std::process::exit(130);
+    }
+}
+
 fn validate_pipeline(args: ValidateArgs) -> Result<()> {
     info!("Validating pipeline");
 
@@ -256,6 +379,59 @@ fn check_pipeline(args: CheckArgs) -> Result<()> {
     Ok(())
 }
 
+fn dead_letters(args: DeadLettersArgs) -> Result<()> {
+    match args.command {
+        DeadLettersCommand::List(list_args) => {
+            let jobs = JobQueue::read_dead_letters(&list_args.queue)
+                .with_context(|| format!("Failed to read job queue: {:?}", list_args.queue))?;
+            if jobs.is_empty() {
+                println!("No jobs in the dead-letter queue");
+            }
+            for job in jobs {
+                let name = job
+                    .pipeline
+                    .as_ref()
+                    .and_then(|pipeline| pipeline.name.clone())
+                    .unwrap_or_else(|| "Unnamed".to_string());
+                println!(
+                    "{}  {}  {} retries  {}",
+                    job.id,
+                    name,
+                    job.retries,
+                    job.error.unwrap_or_default()
+                );
+            }
+            Ok(())
+        }
+        DeadLettersCommand::Replay(replay_args) => {
+            let queue = open_queue(&replay_args.queue)?;
+            replay_dead_letters(&queue, replay_args)
+        }
+    }
+}
+
+fn replay_dead_letters(queue: &JobQueue, args: DeadLettersReplayArgs) -> Result<()> {
+    info!("Replaying dead-lettered jobs");
+
+    let ids = if args.all {
+        queue.dead_letters().iter().map(|job| job.id).collect()
+    } else {
+        args.ids
+    };
+    for id in ids {
+        if !queue.replay_dead_letter(&id) {
+            anyhow::bail!("Job {} is not in the dead-letter queue", id);
+        }
+        println!("Replayed job {}", id);
+    }
+    Ok(())
+}
+
+fn open_queue(path: &Path) -> Result<JobQueue> {
+    JobQueue::open(JournalConfig::new(path))
+        .with_context(|| format!("Failed to open job queue: {:?}", path))
+}
+
 fn get_definition(file: &Option<PathBuf>, definition: &Option<String>) -> Result<String> {
     match (file, definition) {
         (Some(path), None) => std::fs::read_to_string(path)
@@ -315,4 +491,135 @@ mod tests {
             _ => panic!("Expected Check command"),
         }
     }
+
+    #[test]
+    fn test_cli_dlq_parse() {
+        let id = Uuid::new_v4();
+        let args = Cli::parse_from([
+            "pipeliner",
+            "dlq",
+            "replay",
+            "--queue",
+            "queue.log",
+            &id.to_string(),
+        ]);
+        match args.command {
+            Commands::DeadLetters(DeadLettersArgs {
+                command: DeadLettersCommand::Replay(replay),
+            }) => {
+                assert_eq!(replay.queue, PathBuf::from("queue.log"));
+                assert_eq!(replay.ids, [id]);
+                assert!(!replay.all);
+            }
+            _ => panic!("Expected dlq replay command"),
+        }
+
+        assert!(Cli::try_parse_from(["pipeliner", "dlq", "replay", "--queue", "q.log"]).is_err());
+        assert!(
+            Cli::try_parse_from([
+                "pipeliner",
+                "dlq",
+                "replay",
+                "--queue",
+                "q.log",
+                "--all",
+                &id.to_string(),
+            ])
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_records_events() {
+        use pipeliner_core::{Stage, Step, agent::AgentType};
+        use pipeliner_events::EventStore;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let pipeline = Pipeline::new()
+            .with_agent(AgentType::any())
+            .with_stage(Stage::new("Build").with_step(Step::echo("compiling")));
+        let definition = serde_yaml::to_string(&pipeline).unwrap();
+        let events = dir.path().join("events");
+        let pipeline_id = Uuid::new_v4();
+
+        let args = Cli::parse_from([
+            "pipeliner",
+            "run",
+            "--definition",
+            &definition,
+            "--working-dir",
+            &dir.path().to_string_lossy(),
+            "--events",
+            &events.to_string_lossy(),
+            "--pipeline-id",
+            &pipeline_id.to_string(),
+        ]);
+        let Commands::Run(args) = args.command else {
+            panic!("Expected Run command");
+        };
+        run_pipeline(args).await.unwrap();
+
+        let store = FileEventStore::open(&events).await.unwrap();
+        let events = store.get_events(&pipeline_id).await.unwrap();
+        let types: Vec<_> = events.iter().map(|e| e.event.event_type()).collect();
+        assert_eq!(types.first(), Some(&"PipelineStarted"));
+        assert_eq!(types.last(), Some(&"PipelineCompleted"));
+    }
+
+    #[test]
+    fn test_dlq_list_reads_open_queue() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("queue.log");
+        let queue = open_queue(&path).unwrap();
+        let journal = || path.to_string_lossy().to_string();
+
+        let list = Cli::parse_from(["pipeliner", "dlq", "list", "--queue", &journal()]);
+        let Commands::DeadLetters(list) = list.command else {
+            panic!("Expected dlq command");
+        };
+        dead_letters(list).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+
+        let replay = Cli::parse_from(["pipeliner", "dlq", "replay", "-q", &journal(), "--all"]);
+        let Commands::DeadLetters(replay) = replay.command else {
+            panic!("Expected dlq command");
+        };
+        let error = format!("{:#}", dead_letters(replay).unwrap_err());
+        assert!(error.contains("already opened"), "{error}");
+        drop(queue);
+    }
+
+    #[test]
+    fn test_replay_dead_letters() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("queue.log");
+        let queue = open_queue(&path).unwrap();
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            queue.enqueue(pipeliner_worker::Job::new());
+            let mut job = queue.dequeue().unwrap();
+            job.fail("timeout: job timeout");
+            queue.update(&job);
+            queue.dead_letter(&job.id);
+            ids.push(job.id);
+        }
+
+        let args = Cli::parse_from(["pipeliner", "dlq", "replay", "-q", "queue.log", "--all"]);
+        let Commands::DeadLetters(DeadLettersArgs {
+            command: DeadLettersCommand::Replay(replay),
+        }) = args.command
+        else {
+            panic!("Expected dlq replay command");
+        };
+        replay_dead_letters(&queue, replay).unwrap();
+        assert!(queue.dead_letters().is_empty());
+        assert_eq!(queue.len(), 2);
+
+        let missing = DeadLettersReplayArgs {
+            queue: path,
+            ids: vec![ids[0]],
+            all: false,
+        };
+        assert!(replay_dead_letters(&queue, missing).is_err());
+    }
 }
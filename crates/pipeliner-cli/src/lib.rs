@@ -11,6 +11,10 @@
 //! # Validate a pipeline
 //! pipeliner validate --file pipeline.jenkins
 //!
+//! # List and replay jobs that exhausted their retries
+//! pipeliner dlq list --queue queue.log
+//! pipeliner dlq replay --queue queue.log --all
+//!
 //! # Generate shell completions
 //! pipeliner completions --shell bash
 //! ```
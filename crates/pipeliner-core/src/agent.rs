@@ -7,6 +7,9 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
 
+use crate::label::{LabelError, LabelExpression};
+use crate::validation::{Validate, ValidationError};
+
 /// Agent type for pipeline execution
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "camelCase")]
@@ -325,6 +328,40 @@ impl AgentType {
             node_selector: HashMap::new(),
         }
     }
+
+    /// Returns the labels a worker needs to run the agent
+    ///
+    /// Label and custom agents name a [label expression](crate::label),
+    /// container agents need a worker labelled with their runtime, and
+    /// `Any` runs anywhere.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the label expression is malformed.
+    pub fn label_expression(&self) -> Result<Option<LabelExpression>, LabelError> {
+        let runtime = |name: &str| Ok(Some(LabelExpression::Label(name.to_string())));
+        match self {
+            Self::Any => Ok(None),
+            Self::Label { label } | Self::Custom { label } => {
+                LabelExpression::parse(label).map(Some)
+            }
+            Self::Docker { .. } => runtime("docker"),
+            Self::Podman { .. } => runtime("podman"),
+            Self::Kubernetes { .. } => runtime("kubernetes"),
+        }
+    }
+}
+
+impl Validate for AgentType {
+    type Error = ValidationError;
+
+    fn validate(&self) -> Result<(), Self::Error> {
+        self.label_expression()
+            .map_err(|e| ValidationError::InvalidAgent {
+                reason: format!("invalid label expression: {e}"),
+            })?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -343,6 +380,29 @@ mod tests {
         assert!(matches!(agent, AgentType::Label { label } if label == "linux"));
     }
 
+    #[test]
+    fn test_agent_label_expression() {
+        let labels = ["linux", "docker"].map(String::from).into();
+        let agent = AgentType::label("linux && !windows");
+        assert!(agent.label_expression().unwrap().unwrap().matches(&labels));
+        assert!(agent.validate().is_ok());
+        assert!(
+            AgentType::docker("rust:1.75")
+                .label_expression()
+                .unwrap()
+                .unwrap()
+                .matches(&labels)
+        );
+        assert_eq!(AgentType::any().label_expression().unwrap(), None);
+
+        let invalid = AgentType::label("linux &&");
+        assert!(invalid.label_expression().is_err());
+        assert!(matches!(
+            invalid.validate(),
+            Err(ValidationError::InvalidAgent { reason }) if reason.contains("column 9")
+        ));
+    }
+
     #[test]
     fn test_agent_docker() {
         let agent = AgentType::docker("rust:1.75");
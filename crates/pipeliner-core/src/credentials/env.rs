@@ -0,0 +1,196 @@
+//! Credentials from environment variables.
+//!
+//! Credentials `docker-hub` are read from variables named after the id,
+//! upper-cased with every other character replaced by `_`:
+//!
+//! - `PIPELINER_CREDENTIALS_DOCKER_HUB_PRIVATE_KEY`, with optional `_USERNAME`
+//!   and `_PASSPHRASE`: SSH key
+//! - `PIPELINER_CREDENTIALS_DOCKER_HUB_USERNAME` and `_PASSWORD`: username and
+//!   password
+//! - `PIPELINER_CREDENTIALS_DOCKER_HUB_FILE`: path of a secret file
+//! - `PIPELINER_CREDENTIALS_DOCKER_HUB`: secret text
+//!
+//! The first match in this order wins.
+
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use super::{Credentials, CredentialsError, CredentialsProvider};
+
+/// Prefix of the variables read by default
+pub const DEFAULT_ENV_PREFIX: &str = "PIPELINER_CREDENTIALS_";
+
+/// Reads credentials from environment variables
+#[derive(Debug, Clone)]
+pub struct EnvCredentialsProvider {
+    /// Prefix of the variable names
+    prefix: String,
+
+    /// Variables to read instead of the process environment
+    vars: Option<HashMap<String, String>>,
+}
+
+impl Default for EnvCredentialsProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EnvCredentialsProvider {
+    /// Creates a provider reading the process environment
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            prefix: DEFAULT_ENV_PREFIX.to_string(),
+            vars: None,
+        }
+    }
+
+    /// Creates a provider reading the given variables
+    #[must_use]
+    pub fn from_vars(vars: HashMap<String, String>) -> Self {
+        Self {
+            prefix: DEFAULT_ENV_PREFIX.to_string(),
+            vars: Some(vars),
+        }
+    }
+
+    /// Sets the prefix of the variable names
+    #[must_use]
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Returns the variable name for credentials `id`
+    #[must_use]
+    pub fn variable(&self, id: &str) -> String {
+        let name: String = id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("{}{name}", self.prefix)
+    }
+
+    /// Reads a variable
+    fn var(&self, name: &str) -> Option<String> {
+        match &self.vars {
+            Some(vars) => vars.get(name).cloned(),
+            None => std::env::var(name).ok(),
+        }
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for EnvCredentialsProvider {
+    async fn lookup(&self, id: &str) -> Result<Option<Credentials>, CredentialsError> {
+        let base = self.variable(id);
+        let field = |suffix: &str| self.var(&format!("{base}_{suffix}"));
+
+        if let Some(private_key) = field("PRIVATE_KEY") {
+            return Ok(Some(Credentials::SshKey {
+                username: field("USERNAME").unwrap_or_default(),
+                private_key,
+                passphrase: field("PASSPHRASE"),
+            }));
+        }
+
+        if let (Some(username), Some(password)) = (field("USERNAME"), field("PASSWORD")) {
+            return Ok(Some(Credentials::UsernamePassword { username, password }));
+        }
+
+        if let Some(path) = field("FILE") {
+            let path = PathBuf::from(path);
+            let content =
+                tokio::fs::read_to_string(&path)
+                    .await
+                    .map_err(|reason| CredentialsError::Io {
+                        path: path.clone(),
+                        reason,
+                    })?;
+            let file_name = path.file_name().map_or_else(
+                || id.to_string(),
+                |name| name.to_string_lossy().into_owned(),
+            );
+            return Ok(Some(Credentials::File { file_name, content }));
+        }
+
+        Ok(self
+            .var(&base)
+            .map(|secret| Credentials::SecretText { secret }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider(vars: &[(&str, &str)]) -> EnvCredentialsProvider {
+        EnvCredentialsProvider::from_vars(
+            vars.iter()
+                .map(|(name, value)| ((*name).to_string(), (*value).to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_variable_names() {
+        let provider = EnvCredentialsProvider::new();
+        assert_eq!(
+            provider.variable("docker-hub"),
+            "PIPELINER_CREDENTIALS_DOCKER_HUB"
+        );
+        assert_eq!(
+            provider.with_prefix("CI_").variable("npm.token"),
+            "CI_NPM_TOKEN"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_typed_credentials_from_variables() {
+        let provider = provider(&[
+            ("PIPELINER_CREDENTIALS_REGISTRY_USERNAME", "deploy"),
+            ("PIPELINER_CREDENTIALS_REGISTRY_PASSWORD", "hunter2"),
+            ("PIPELINER_CREDENTIALS_GIT_PRIVATE_KEY", "KEY"),
+            ("PIPELINER_CREDENTIALS_TOKEN", "abc123"),
+        ]);
+
+        assert_eq!(
+            provider.get("registry").await.unwrap(),
+            Credentials::UsernamePassword {
+                username: "deploy".to_string(),
+                password: "hunter2".to_string(),
+            }
+        );
+        assert_eq!(provider.get("git").await.unwrap().kind(), "sshKey");
+        assert_eq!(
+            provider.get("token").await.unwrap().field("secret"),
+            Some("abc123")
+        );
+        assert!(provider.lookup("missing").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_file_credentials_from_variable() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("kubeconfig");
+        std::fs::write(&path, "apiVersion: v1").unwrap();
+
+        let provider = provider(&[("PIPELINER_CREDENTIALS_KUBE_FILE", path.to_str().unwrap())]);
+
+        assert_eq!(
+            provider.get("kube").await.unwrap(),
+            Credentials::File {
+                file_name: "kubeconfig".to_string(),
+                content: "apiVersion: v1".to_string(),
+            }
+        );
+    }
+}
@@ -0,0 +1,352 @@
+//! Credentials and their providers.
+//!
+//! Pipelines refer to credentials by id, either from the environment
+//! through a [`CredentialsValue`](crate::environment::CredentialsValue) or
+//! from a `withCredentials` step. A [`CredentialsProvider`] looks the id up
+//! and returns typed [`Credentials`].
+//!
+//! Built-in providers:
+//!
+//! - [`EnvCredentialsProvider`]: environment variables of the executor
+//! - [`FileCredentialsProvider`]: a local JSON or TOML credentials file
+//! - [`VaultCredentialsProvider`]: an encrypted local vault, unlocked by a
+//!   master key from the environment
+//!
+//! [`CredentialsChain`] tries several providers in order.
+
+mod env;
+mod file;
+mod vault;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use thiserror::Error;
+
+pub use env::{DEFAULT_ENV_PREFIX, EnvCredentialsProvider};
+pub use file::FileCredentialsProvider;
+pub use vault::{DEFAULT_VAULT_KEY_VAR, VaultCredentialsProvider};
+
+/// Credentials stored by id
+pub type CredentialsMap = BTreeMap<String, Credentials>;
+
+/// Typed credentials
+///
+/// `Debug` only shows the non-secret fields.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Credentials {
+    /// Username and password
+    UsernamePassword {
+        /// User name
+        username: String,
+        /// Password
+        password: String,
+    },
+
+    /// A single secret, such as an API token
+    SecretText {
+        /// The secret
+        secret: String,
+    },
+
+    /// SSH private key
+    #[serde(rename_all = "camelCase")]
+    SshKey {
+        /// User name
+        #[serde(default)]
+        username: String,
+        /// Private key in PEM or OpenSSH format
+        private_key: String,
+        /// Passphrase of the key
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        passphrase: Option<String>,
+    },
+
+    /// Secret file, such as a kubeconfig
+    #[serde(rename_all = "camelCase")]
+    File {
+        /// Name of the file
+        file_name: String,
+        /// File content
+        content: String,
+    },
+}
+
+impl Credentials {
+    /// Returns the type name used in credentials files
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::UsernamePassword { .. } => "usernamePassword",
+            Self::SecretText { .. } => "secretText",
+            Self::SshKey { .. } => "sshKey",
+            Self::File { .. } => "file",
+        }
+    }
+
+    /// Returns a field by its name in credentials files
+    #[must_use]
+    pub fn field(&self, name: &str) -> Option<&str> {
+        match (self, name) {
+            (
+                Self::UsernamePassword { username, .. } | Self::SshKey { username, .. },
+                "username",
+            ) => Some(username),
+            (Self::UsernamePassword { password, .. }, "password") => Some(password),
+            (Self::SecretText { secret }, "secret") => Some(secret),
+            (Self::SshKey { private_key, .. }, "privateKey") => Some(private_key),
+            (Self::SshKey { passphrase, .. }, "passphrase") => passphrase.as_deref(),
+            (Self::File { file_name, .. }, "fileName") => Some(file_name),
+            (Self::File { content, .. }, "content") => Some(content),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the field must be masked in output
+    ///
+    /// User and file names are not secret.
+    #[must_use]
+    pub fn is_secret_field(name: &str) -> bool {
+        !matches!(name, "username" | "fileName")
+    }
+
+    /// Returns the values that must be masked in output
+    #[must_use]
+    pub fn secrets(&self) -> Vec<&str> {
+        match self {
+            Self::UsernamePassword { password, .. } => vec![password],
+            Self::SecretText { secret } => vec![secret],
+            Self::SshKey {
+                private_key,
+                passphrase,
+                ..
+            } => std::iter::once(private_key.as_str())
+                .chain(passphrase.as_deref())
+                .collect(),
+            Self::File { content, .. } => vec![content],
+        }
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UsernamePassword { username, .. } => f
+                .debug_struct("UsernamePassword")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Self::SecretText { .. } => f.debug_struct("SecretText").finish_non_exhaustive(),
+            Self::SshKey { username, .. } => f
+                .debug_struct("SshKey")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Self::File { file_name, .. } => f
+                .debug_struct("File")
+                .field("file_name", file_name)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+/// Credentials lookup errors
+#[derive(Debug, Error)]
+pub enum CredentialsError {
+    /// No provider knows the id
+    #[error("credentials '{id}' not found")]
+    NotFound {
+        /// Credentials id
+        id: String,
+    },
+
+    /// The credentials do not have the requested field
+    #[error("credentials '{id}' have no field '{field}'")]
+    MissingField {
+        /// Credentials id
+        id: String,
+        /// Requested field
+        field: String,
+    },
+
+    /// The credentials are not of the expected type
+    #[error("credentials '{id}' are {actual}, expected {expected}")]
+    WrongType {
+        /// Credentials id
+        id: String,
+        /// Expected type
+        expected: &'static str,
+        /// Actual type
+        actual: &'static str,
+    },
+
+    /// A credentials file could not be read or written
+    #[error("cannot access credentials file {}: {reason}", path.display())]
+    Io {
+        /// File path
+        path: PathBuf,
+        /// Underlying error
+        reason: std::io::Error,
+    },
+
+    /// A credentials file is malformed
+    #[error("invalid credentials file {}: {reason}", path.display())]
+    Parse {
+        /// File path
+        path: PathBuf,
+        /// Parser error
+        reason: String,
+    },
+
+    /// The vault could not be unlocked
+    #[error("credentials vault {} is locked: {reason}", path.display())]
+    Locked {
+        /// Vault path
+        path: PathBuf,
+        /// Why unlocking failed
+        reason: String,
+    },
+}
+
+/// Source of credentials
+#[async_trait]
+pub trait CredentialsProvider: Send + Sync + fmt::Debug {
+    /// Looks up credentials, returning `None` if this provider has no such id
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the provider's store cannot be read.
+    async fn lookup(&self, id: &str) -> Result<Option<Credentials>, CredentialsError>;
+
+    /// Returns the credentials with the given id
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CredentialsError::NotFound`] if the id is unknown, or the
+    /// lookup error.
+    async fn get(&self, id: &str) -> Result<Credentials, CredentialsError> {
+        self.lookup(id)
+            .await?
+            .ok_or_else(|| CredentialsError::NotFound { id: id.to_string() })
+    }
+}
+
+/// Providers tried in order, the first one knowing an id wins
+#[derive(Debug, Clone, Default)]
+pub struct CredentialsChain {
+    providers: Vec<Arc<dyn CredentialsProvider>>,
+}
+
+impl CredentialsChain {
+    /// Creates an empty chain
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a provider after the existing ones
+    #[must_use]
+    pub fn with(mut self, provider: impl CredentialsProvider + 'static) -> Self {
+        self.providers.push(Arc::new(provider));
+        self
+    }
+
+    /// Returns the number of providers
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.providers.len()
+    }
+
+    /// Returns true if the chain has no providers
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for CredentialsChain {
+    async fn lookup(&self, id: &str) -> Result<Option<Credentials>, CredentialsError> {
+        for provider in &self.providers {
+            if let Some(credentials) = provider.lookup(id).await? {
+                return Ok(Some(credentials));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_credentials_fields() {
+        let credentials = Credentials::UsernamePassword {
+            username: "deploy".to_string(),
+            password: "hunter2".to_string(),
+        };
+        assert_eq!(credentials.field("username"), Some("deploy"));
+        assert_eq!(credentials.field("password"), Some("hunter2"));
+        assert_eq!(credentials.field("secret"), None);
+        assert_eq!(credentials.secrets(), vec!["hunter2"]);
+        assert!(!Credentials::is_secret_field("username"));
+    }
+
+    #[test]
+    fn test_debug_hides_secrets() {
+        let credentials = Credentials::SshKey {
+            username: "git".to_string(),
+            private_key: "-----BEGIN KEY-----".to_string(),
+            passphrase: Some("open sesame".to_string()),
+        };
+        let debug = format!("{credentials:?}");
+        assert!(debug.contains("git"));
+        assert!(!debug.contains("BEGIN"));
+        assert!(!debug.contains("sesame"));
+    }
+
+    #[test]
+    fn test_credentials_serialization() {
+        let json = r#"{"type": "sshKey", "username": "git", "privateKey": "KEY"}"#;
+        let credentials: Credentials = serde_json::from_str(json).unwrap();
+        assert_eq!(credentials.kind(), "sshKey");
+        assert_eq!(credentials.field("privateKey"), Some("KEY"));
+        assert_eq!(credentials.field("passphrase"), None);
+    }
+
+    #[tokio::test]
+    async fn test_chain_uses_first_provider_with_id() {
+        let first = EnvCredentialsProvider::from_vars(HashMap::from([(
+            "PIPELINER_CREDENTIALS_TOKEN".to_string(),
+            "first".to_string(),
+        )]));
+        let second = EnvCredentialsProvider::from_vars(HashMap::from([
+            (
+                "PIPELINER_CREDENTIALS_TOKEN".to_string(),
+                "second".to_string(),
+            ),
+            (
+                "PIPELINER_CREDENTIALS_OTHER".to_string(),
+                "other".to_string(),
+            ),
+        ]));
+        let chain = CredentialsChain::new().with(first).with(second);
+
+        assert_eq!(
+            chain.get("token").await.unwrap().field("secret"),
+            Some("first")
+        );
+        assert_eq!(
+            chain.get("other").await.unwrap().field("secret"),
+            Some("other")
+        );
+        assert!(matches!(
+            chain.get("missing").await,
+            Err(CredentialsError::NotFound { .. })
+        ));
+    }
+}
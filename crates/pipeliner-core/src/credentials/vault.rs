@@ -0,0 +1,316 @@
+//! Credentials from an encrypted local vault.
+//!
+//! The vault is a JSON file holding the credentials map encrypted with
+//! AES-256-GCM. The key is derived with Argon2id from a master key, read
+//! from `PIPELINER_VAULT_KEY` unless configured otherwise, and a random
+//! salt stored next to the ciphertext. Every write uses a new salt and
+//! nonce.
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use super::{Credentials, CredentialsError, CredentialsMap, CredentialsProvider};
+
+/// Variable holding the master key by default
+pub const DEFAULT_VAULT_KEY_VAR: &str = "PIPELINER_VAULT_KEY";
+
+/// Version of the vault file format
+const VAULT_VERSION: u32 = 1;
+
+/// Length of the key derivation salt
+const SALT_LEN: usize = 16;
+
+/// Vault file content
+#[derive(Debug, Serialize, Deserialize)]
+struct VaultFile {
+    /// Format version
+    version: u32,
+    /// Base64 salt of the key derivation
+    salt: String,
+    /// Base64 AES-GCM nonce
+    nonce: String,
+    /// Base64 encrypted credentials map
+    ciphertext: String,
+}
+
+/// Reads credentials from an encrypted vault file
+#[derive(Clone)]
+pub struct VaultCredentialsProvider {
+    /// Path of the vault file
+    path: PathBuf,
+
+    /// Variable holding the master key
+    key_var: String,
+
+    /// Master key given directly, used instead of the variable
+    master_key: Option<String>,
+}
+
+impl std::fmt::Debug for VaultCredentialsProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VaultCredentialsProvider")
+            .field("path", &self.path)
+            .field("key_var", &self.key_var)
+            .finish_non_exhaustive()
+    }
+}
+
+impl VaultCredentialsProvider {
+    /// Creates a provider for the vault at `path`
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            key_var: DEFAULT_VAULT_KEY_VAR.to_string(),
+            master_key: None,
+        }
+    }
+
+    /// Reads the master key from `var` instead of `PIPELINER_VAULT_KEY`
+    #[must_use]
+    pub fn with_key_var(mut self, var: impl Into<String>) -> Self {
+        self.key_var = var.into();
+        self
+    }
+
+    /// Unlocks the vault with `key` instead of reading the environment
+    #[must_use]
+    pub fn with_master_key(mut self, key: impl Into<String>) -> Self {
+        self.master_key = Some(key.into());
+        self
+    }
+
+    /// Returns the path of the vault file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Decrypts every credentials in the vault
+    ///
+    /// A missing vault holds no credentials.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vault cannot be read, the master key is
+    /// missing or wrong, or the content is malformed.
+    pub async fn load(&self) -> Result<CredentialsMap, CredentialsError> {
+        let content = match tokio::fs::read_to_string(&self.path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(CredentialsMap::new());
+            }
+            Err(reason) => return Err(self.io_error(reason)),
+        };
+
+        let file: VaultFile =
+            serde_json::from_str(&content).map_err(|e| self.parse_error(e.to_string()))?;
+        if file.version != VAULT_VERSION {
+            return Err(self.parse_error(format!("unsupported version {}", file.version)));
+        }
+
+        let salt = self.decode(&file.salt)?;
+        let nonce = self.decode(&file.nonce)?;
+        let ciphertext = self.decode(&file.ciphertext)?;
+        if nonce.len() != 12 {
+            return Err(self.parse_error("invalid nonce".to_string()));
+        }
+
+        let cipher = self.cipher(&salt)?;
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| self.locked("wrong master key or corrupted vault"))?;
+        serde_json::from_slice(&plaintext).map_err(|e| self.parse_error(e.to_string()))
+    }
+
+    /// Encrypts `credentials` into the vault, replacing its content
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the master key is missing or the vault cannot be
+    /// written.
+    pub async fn store(&self, credentials: &CredentialsMap) -> Result<(), CredentialsError> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+        let plaintext =
+            serde_json::to_vec(credentials).map_err(|e| self.parse_error(e.to_string()))?;
+        let ciphertext = self
+            .cipher(&salt)?
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| self.locked("encryption failed"))?;
+
+        let file = VaultFile {
+            version: VAULT_VERSION,
+            salt: BASE64.encode(salt),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        };
+        let content =
+            serde_json::to_string_pretty(&file).map_err(|e| self.parse_error(e.to_string()))?;
+
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| self.io_error(e))?;
+        }
+        let temp_path = self.path.with_extension("tmp");
+        tokio::fs::write(&temp_path, content)
+            .await
+            .map_err(|e| self.io_error(e))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(&temp_path, std::fs::Permissions::from_mode(0o600))
+                .await
+                .map_err(|e| self.io_error(e))?;
+        }
+        tokio::fs::rename(&temp_path, &self.path)
+            .await
+            .map_err(|e| self.io_error(e))
+    }
+
+    /// Adds or replaces credentials in the vault
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vault cannot be loaded or stored.
+    pub async fn insert(
+        &self,
+        id: impl Into<String>,
+        credentials: Credentials,
+    ) -> Result<(), CredentialsError> {
+        let mut all = self.load().await?;
+        all.insert(id.into(), credentials);
+        self.store(&all).await
+    }
+
+    /// Removes credentials from the vault, returning true if they existed
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vault cannot be loaded or stored.
+    pub async fn remove(&self, id: &str) -> Result<bool, CredentialsError> {
+        let mut all = self.load().await?;
+        if all.remove(id).is_none() {
+            return Ok(false);
+        }
+        self.store(&all).await?;
+        Ok(true)
+    }
+
+    /// Derives the cipher for a salt from the master key
+    fn cipher(&self, salt: &[u8]) -> Result<Aes256Gcm, CredentialsError> {
+        let master_key = match &self.master_key {
+            Some(key) => key.clone(),
+            None => std::env::var(&self.key_var)
+                .map_err(|_| self.locked(&format!("set {} to unlock it", self.key_var)))?,
+        };
+
+        let mut key = [0u8; 32];
+        argon2::Argon2::default()
+            .hash_password_into(master_key.as_bytes(), salt, &mut key)
+            .map_err(|e| self.locked(&format!("key derivation failed: {e}")))?;
+        Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+    }
+
+    /// Decodes a base64 field of the vault file
+    fn decode(&self, value: &str) -> Result<Vec<u8>, CredentialsError> {
+        BASE64
+            .decode(value)
+            .map_err(|e| self.parse_error(e.to_string()))
+    }
+
+    fn io_error(&self, reason: std::io::Error) -> CredentialsError {
+        CredentialsError::Io {
+            path: self.path.clone(),
+            reason,
+        }
+    }
+
+    fn parse_error(&self, reason: String) -> CredentialsError {
+        CredentialsError::Parse {
+            path: self.path.clone(),
+            reason,
+        }
+    }
+
+    fn locked(&self, reason: &str) -> CredentialsError {
+        CredentialsError::Locked {
+            path: self.path.clone(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for VaultCredentialsProvider {
+    async fn lookup(&self, id: &str) -> Result<Option<Credentials>, CredentialsError> {
+        Ok(self.load().await?.remove(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_vault_round_trip() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("vault.json");
+        let vault = VaultCredentialsProvider::new(&path).with_master_key("correct horse");
+
+        vault
+            .insert(
+                "npm-token",
+                Credentials::SecretText {
+                    secret: "npm_abc123".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("npm_abc123"));
+        assert_eq!(
+            vault.get("npm-token").await.unwrap().field("secret"),
+            Some("npm_abc123")
+        );
+
+        assert!(vault.remove("npm-token").await.unwrap());
+        assert!(vault.lookup("npm-token").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_vault_needs_the_right_master_key() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("vault.json");
+        VaultCredentialsProvider::new(&path)
+            .with_master_key("correct horse")
+            .insert(
+                "token",
+                Credentials::SecretText {
+                    secret: "abc".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+
+        let wrong = VaultCredentialsProvider::new(&path).with_master_key("battery staple");
+        assert!(matches!(
+            wrong.lookup("token").await,
+            Err(CredentialsError::Locked { .. })
+        ));
+
+        let unset = VaultCredentialsProvider::new(&path).with_key_var("PIPELINER_TEST_UNSET_KEY");
+        let error = unset.lookup("token").await.unwrap_err();
+        assert!(error.to_string().contains("PIPELINER_TEST_UNSET_KEY"));
+    }
+}
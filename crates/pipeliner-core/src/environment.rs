@@ -7,6 +7,9 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt;
 
+use crate::credentials::{Credentials, CredentialsError, CredentialsProvider};
+use crate::expression::{Expression, ExpressionContext, ExpressionError};
+
 /// Environment variable collection
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
@@ -83,6 +86,66 @@ impl Environment {
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
+
+    /// Replaces every credentials reference with the referenced field
+    ///
+    /// Secret fields become masked secrets, user and file names plain
+    /// values.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if credentials are unknown, lack the field or
+    /// cannot be read.
+    pub async fn resolve_credentials(
+        &mut self,
+        provider: &dyn CredentialsProvider,
+    ) -> Result<(), CredentialsError> {
+        for value in self.0.values_mut() {
+            let EnvVarValue::Credentials(reference) = value else {
+                continue;
+            };
+
+            let credentials = provider.get(&reference.credentials_id).await?;
+            let field = &reference.key_field;
+            let Some(resolved) = credentials.field(field).map(str::to_string) else {
+                return Err(CredentialsError::MissingField {
+                    id: reference.credentials_id.clone(),
+                    field: field.clone(),
+                });
+            };
+
+            *value = if Credentials::is_secret_field(field) {
+                EnvVarValue::Secret(SecretValue {
+                    value: resolved,
+                    masked: true,
+                })
+            } else {
+                EnvVarValue::Value(resolved)
+            };
+        }
+        Ok(())
+    }
+
+    /// Replaces every expression with its value
+    ///
+    /// Expressions see the variables of `context`, not each other's values.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first expression that fails to parse or evaluate.
+    pub fn resolve_expressions(
+        &mut self,
+        context: &ExpressionContext,
+    ) -> Result<(), ExpressionError> {
+        for value in self.0.values_mut() {
+            let EnvVarValue::Expression(expression) = value else {
+                continue;
+            };
+            let resolved = Expression::parse(&expression.expression)?.evaluate(context)?;
+            *value = EnvVarValue::Value(resolved.to_string());
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for Environment {
@@ -168,6 +231,20 @@ impl VariableResolver for HashMap<String, String> {
     }
 }
 
+impl EnvVarValue {
+    /// Returns the value handed to processes
+    ///
+    /// Unlike `Display`, secrets are returned in clear. Output containing
+    /// them must go through a [`SecretMasker`](crate::SecretMasker).
+    #[must_use]
+    pub fn value(&self) -> String {
+        match self {
+            EnvVarValue::Secret(s) => s.value.clone(),
+            other => other.to_string(),
+        }
+    }
+}
+
 impl fmt::Display for EnvVarValue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -226,6 +303,10 @@ mod tests {
         } else {
             panic!("Expected Secret value");
         }
+
+        let secret = env.get("SECRET").unwrap();
+        assert_eq!(secret.to_string(), "***");
+        assert_eq!(secret.value(), "my-secret-value");
     }
 
     #[test]
@@ -240,6 +321,61 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_resolve_credentials() {
+        let provider = crate::credentials::EnvCredentialsProvider::from_vars(HashMap::from([
+            (
+                "PIPELINER_CREDENTIALS_REGISTRY_USERNAME".to_string(),
+                "deploy".to_string(),
+            ),
+            (
+                "PIPELINER_CREDENTIALS_REGISTRY_PASSWORD".to_string(),
+                "hunter2".to_string(),
+            ),
+        ]));
+        let mut env = Environment::new();
+        env.insert_credentials("REGISTRY_USER", "registry", "username");
+        env.insert_credentials("REGISTRY_PASSWORD", "registry", "password");
+
+        env.resolve_credentials(&provider).await.unwrap();
+        assert_eq!(
+            env.get("REGISTRY_USER"),
+            Some(&EnvVarValue::Value("deploy".to_string()))
+        );
+        assert_eq!(env.get("REGISTRY_PASSWORD").unwrap().to_string(), "***");
+        assert_eq!(env.get("REGISTRY_PASSWORD").unwrap().value(), "hunter2");
+
+        env.insert_credentials("TOKEN", "registry", "secret");
+        assert!(matches!(
+            env.resolve_credentials(&provider).await,
+            Err(CredentialsError::MissingField { .. })
+        ));
+    }
+
+    #[test]
+    fn test_resolve_expressions() {
+        let mut env = Environment::new();
+        env.insert("BRANCH_NAME", "release/1.2");
+        env.0.insert(
+            "CHANNEL".to_string(),
+            EnvVarValue::Expression(ExpressionValue {
+                expression: "env.BRANCH_NAME.startsWith('release/') + '-' + params.REPLICAS"
+                    .to_string(),
+            }),
+        );
+
+        let context = ExpressionContext::new()
+            .with_environment(&env)
+            .with_param("REPLICAS", "3");
+        assert!(!context.env.contains_key("CHANNEL"));
+
+        env.resolve_expressions(&context).unwrap();
+        assert_eq!(
+            env.get("CHANNEL"),
+            Some(&EnvVarValue::Value("true-3".to_string()))
+        );
+    }
+
     #[test]
     fn test_environment_display() {
         let mut env = Environment::new();
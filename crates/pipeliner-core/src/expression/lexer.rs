@@ -0,0 +1,252 @@
+//! Tokenizer of the expression language.
+
+use super::ExpressionError;
+
+/// Token kinds
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Token {
+    Number(f64),
+    String(String),
+    Ident(String),
+    True,
+    False,
+    Null,
+    Dot,
+    Comma,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Not,
+    And,
+    Or,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Plus,
+    Minus,
+    Eof,
+}
+
+impl Token {
+    /// Describes the token in error messages
+    pub(super) fn describe(&self) -> String {
+        match self {
+            Self::Number(n) => format!("number {n}"),
+            Self::String(s) => format!("string '{s}'"),
+            Self::Ident(name) => format!("'{name}'"),
+            Self::True => "'true'".to_string(),
+            Self::False => "'false'".to_string(),
+            Self::Null => "'null'".to_string(),
+            Self::Dot => "'.'".to_string(),
+            Self::Comma => "','".to_string(),
+            Self::LParen => "'('".to_string(),
+            Self::RParen => "')'".to_string(),
+            Self::LBracket => "'['".to_string(),
+            Self::RBracket => "']'".to_string(),
+            Self::Not => "'!'".to_string(),
+            Self::And => "'&&'".to_string(),
+            Self::Or => "'||'".to_string(),
+            Self::Eq => "'=='".to_string(),
+            Self::Ne => "'!='".to_string(),
+            Self::Lt => "'<'".to_string(),
+            Self::Le => "'<='".to_string(),
+            Self::Gt => "'>'".to_string(),
+            Self::Ge => "'>='".to_string(),
+            Self::Plus => "'+'".to_string(),
+            Self::Minus => "'-'".to_string(),
+            Self::Eof => "end of expression".to_string(),
+        }
+    }
+}
+
+/// A token with the column it starts at
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Spanned {
+    pub(super) token: Token,
+    pub(super) column: usize,
+}
+
+/// Splits `source` into tokens, ending with [`Token::Eof`]
+pub(super) fn tokenize(source: &str) -> Result<Vec<Spanned>, ExpressionError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let next = chars.get(i + 1).copied();
+        let (token, len) = match (c, next) {
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Eq, 2),
+            ('!', Some('=')) => (Token::Ne, 2),
+            ('<', Some('=')) => (Token::Le, 2),
+            ('>', Some('=')) => (Token::Ge, 2),
+            ('&', _) => return Err(ExpressionError::new(column, "expected '&&'")),
+            ('|', _) => return Err(ExpressionError::new(column, "expected '||'")),
+            ('=', _) => return Err(ExpressionError::new(column, "expected '=='")),
+            ('!', _) => (Token::Not, 1),
+            ('<', _) => (Token::Lt, 1),
+            ('>', _) => (Token::Gt, 1),
+            ('+', _) => (Token::Plus, 1),
+            ('-', _) => (Token::Minus, 1),
+            ('.', _) => (Token::Dot, 1),
+            (',', _) => (Token::Comma, 1),
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            ('[', _) => (Token::LBracket, 1),
+            (']', _) => (Token::RBracket, 1),
+            ('\'' | '"', _) => string(&chars, i)?,
+            (c, _) if c.is_ascii_digit() => number(&chars, i)?,
+            (c, _) if c.is_alphabetic() || c == '_' => identifier(&chars, i),
+            (c, _) => {
+                return Err(ExpressionError::new(
+                    column,
+                    format!("unexpected character '{c}'"),
+                ));
+            }
+        };
+
+        tokens.push(Spanned { token, column });
+        i += len;
+    }
+
+    tokens.push(Spanned {
+        token: Token::Eof,
+        column: chars.len() + 1,
+    });
+    Ok(tokens)
+}
+
+/// Reads a quoted string starting at `start`
+fn string(chars: &[char], start: usize) -> Result<(Token, usize), ExpressionError> {
+    let quote = chars[start];
+    let mut value = String::new();
+    let mut i = start + 1;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => {
+                let escaped = match chars.get(i + 1) {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some(c @ ('\\' | '\'' | '"')) => *c,
+                    Some(c) => {
+                        return Err(ExpressionError::new(
+                            i + 1,
+                            format!("unknown escape '\\{c}'"),
+                        ));
+                    }
+                    None => break,
+                };
+                value.push(escaped);
+                i += 2;
+            }
+            c if c == quote => return Ok((Token::String(value), i + 1 - start)),
+            c => {
+                value.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    Err(ExpressionError::new(start + 1, "unterminated string"))
+}
+
+/// Reads a decimal number starting at `start`
+fn number(chars: &[char], start: usize) -> Result<(Token, usize), ExpressionError> {
+    let mut end = start;
+    while end < chars.len() && chars[end].is_ascii_digit() {
+        end += 1;
+    }
+    if end + 1 < chars.len() && chars[end] == '.' && chars[end + 1].is_ascii_digit() {
+        end += 1;
+        while end < chars.len() && chars[end].is_ascii_digit() {
+            end += 1;
+        }
+    }
+
+    let text: String = chars[start..end].iter().collect();
+    let value = text
+        .parse()
+        .map_err(|_| ExpressionError::new(start + 1, format!("invalid number '{text}'")))?;
+    Ok((Token::Number(value), end - start))
+}
+
+/// Reads an identifier or keyword starting at `start`
+fn identifier(chars: &[char], start: usize) -> (Token, usize) {
+    let mut end = start;
+    while end < chars.len() && (chars[end].is_alphanumeric() || chars[end] == '_') {
+        end += 1;
+    }
+
+    let name: String = chars[start..end].iter().collect();
+    let token = match name.as_str() {
+        "true" => Token::True,
+        "false" => Token::False,
+        "null" => Token::Null,
+        _ => Token::Ident(name),
+    };
+    (token, end - start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source)
+            .unwrap()
+            .into_iter()
+            .map(|spanned| spanned.token)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize_operators_and_literals() {
+        assert_eq!(
+            tokens("env.A == 'x' && !(n >= 1.5)"),
+            vec![
+                Token::Ident("env".to_string()),
+                Token::Dot,
+                Token::Ident("A".to_string()),
+                Token::Eq,
+                Token::String("x".to_string()),
+                Token::And,
+                Token::Not,
+                Token::LParen,
+                Token::Ident("n".to_string()),
+                Token::Ge,
+                Token::Number(1.5),
+                Token::RParen,
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_escapes_and_columns() {
+        let spanned = tokenize(r#"  "a\"b" 'c'"#).unwrap();
+        assert_eq!(spanned[0].token, Token::String("a\"b".to_string()));
+        assert_eq!(spanned[0].column, 3);
+        assert_eq!(spanned[1].column, 10);
+    }
+
+    #[test]
+    fn test_tokenize_errors_report_column() {
+        assert_eq!(tokenize("a = b").unwrap_err().column, 3);
+        assert_eq!(tokenize("x == 'open").unwrap_err().column, 6);
+        assert_eq!(tokenize("a # b").unwrap_err().column, 3);
+    }
+}
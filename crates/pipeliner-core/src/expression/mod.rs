@@ -0,0 +1,729 @@
+//! Expression language for conditions, computed values and matrix axes.
+//!
+//! Expressions are small and side-effect free: they read the build's
+//! variables and compute a [`Value`], nothing else. There are no loops,
+//! assignments or access to processes and files, and the size of an
+//! expression, its nesting and the lists and patterns it builds are
+//! bounded.
+//!
+//! ```text
+//! env.BRANCH_NAME.startsWith('release/') && params.DEPLOY == true
+//! currentBuild.result != 'FAILURE' || stages['Unit Tests'] == 'SUCCESS'
+//! split(env.TARGETS, ',')
+//! ```
+//!
+//! Variables:
+//!
+//! - `env.NAME` or `env['NAME']`: environment variables
+//! - `params.NAME`: build parameters
+//! - `currentBuild.result`: `null` while the build succeeds, otherwise
+//!   `UNSTABLE`, `FAILURE` or `ABORTED`; `currentBuild.currentResult` is
+//!   `SUCCESS` instead of `null`
+//! - `stages.NAME`: result of a previous stage
+//!
+//! Unset variables are `null`. Operators are `||`, `&&`, `!`, `==`, `!=`,
+//! `<`, `<=`, `>`, `>=`, `+` and `-`, with the usual precedence. Functions
+//! can also be called as methods, `s.startsWith(p)` being
+//! `startsWith(s, p)`:
+//!
+//! - `startsWith(s, prefix)`, `endsWith(s, suffix)`, `contains(s, part)`
+//!   (or list membership), `matches(s, regex)` (whole string)
+//! - `toLowerCase(s)`, `toUpperCase(s)`, `trim(s)`, `length(s)`
+//! - `split(s, separator)`, `join(list, separator)`,
+//!   `range(start, end[, step])` (inclusive)
+//!
+//! Errors, both when parsing and evaluating, report the column they occur
+//! at.
+
+mod lexer;
+mod parser;
+mod value;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::environment::{EnvVarValue, Environment};
+use parser::{BinaryOp, Expr, UnaryOp};
+
+pub use value::Value;
+
+/// Longest expression accepted, in characters
+pub const MAX_EXPRESSION_LEN: usize = 4096;
+
+/// Longest list a function may build
+const MAX_LIST_LEN: i64 = 10_000;
+
+/// Size limit of compiled regular expressions, in bytes
+const MAX_REGEX_SIZE: usize = 1 << 20;
+
+/// Error parsing or evaluating an expression
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{message} at column {column}")]
+pub struct ExpressionError {
+    /// Column the error occurs at, starting at 1
+    pub column: usize,
+    /// What went wrong
+    pub message: String,
+}
+
+impl ExpressionError {
+    pub(crate) fn new(column: usize, message: impl Into<String>) -> Self {
+        Self {
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+/// Variables visible to expressions
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExpressionContext {
+    /// Environment variables, read as `env.NAME`
+    pub env: HashMap<String, String>,
+    /// Build parameters, read as `params.NAME`
+    pub params: HashMap<String, String>,
+    /// Result of the build so far, `None` while it succeeds
+    pub build_result: Option<String>,
+    /// Results of previous stages by stage name
+    pub stage_results: HashMap<String, String>,
+}
+
+impl ExpressionContext {
+    /// Creates an empty context
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the resolved variables of `environment`
+    ///
+    /// Secrets are readable, since expressions only produce values for the
+    /// executor. Credentials references and expressions are skipped until
+    /// they are resolved.
+    #[must_use]
+    pub fn with_environment(mut self, environment: &Environment) -> Self {
+        for (name, value) in environment.iter() {
+            if matches!(value, EnvVarValue::Value(_) | EnvVarValue::Secret(_)) {
+                self.env.insert(name.to_string(), value.value());
+            }
+        }
+        self
+    }
+
+    /// Sets an environment variable
+    #[must_use]
+    pub fn with_env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.insert(name.into(), value.into());
+        self
+    }
+
+    /// Sets a build parameter
+    #[must_use]
+    pub fn with_param(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.params.insert(name.into(), value.into());
+        self
+    }
+
+    /// Sets the result of the build so far
+    #[must_use]
+    pub fn with_build_result(mut self, result: impl Into<String>) -> Self {
+        self.build_result = Some(result.into());
+        self
+    }
+
+    /// Sets the result of a previous stage
+    #[must_use]
+    pub fn with_stage_result(
+        mut self,
+        stage: impl Into<String>,
+        result: impl Into<String>,
+    ) -> Self {
+        self.stage_results.insert(stage.into(), result.into());
+        self
+    }
+
+    /// Reads `root.key`
+    fn lookup(&self, root: &str, key: &str, column: usize) -> Result<Value, ExpressionError> {
+        let value = match root {
+            "env" => self.env.get(key).cloned(),
+            "params" => self.params.get(key).cloned(),
+            "stages" => self.stage_results.get(key).cloned(),
+            "currentBuild" => match key {
+                "result" => self.build_result.clone(),
+                "currentResult" => Some(
+                    self.build_result
+                        .clone()
+                        .unwrap_or_else(|| "SUCCESS".to_string()),
+                ),
+                _ => {
+                    return Err(ExpressionError::new(
+                        column,
+                        format!("unknown property 'currentBuild.{key}'"),
+                    ));
+                }
+            },
+            _ => {
+                return Err(ExpressionError::new(
+                    column,
+                    format!("unknown variable '{root}'"),
+                ));
+            }
+        };
+        Ok(value.into())
+    }
+}
+
+/// A parsed expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    source: String,
+    root: Expr,
+}
+
+impl Expression {
+    /// Parses an expression
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with the column of the first syntax error.
+    pub fn parse(source: &str) -> Result<Self, ExpressionError> {
+        if source.chars().count() > MAX_EXPRESSION_LEN {
+            return Err(ExpressionError::new(
+                MAX_EXPRESSION_LEN + 1,
+                format!("expression is longer than {MAX_EXPRESSION_LEN} characters"),
+            ));
+        }
+        let root = parser::parse(lexer::tokenize(source)?)?;
+        Ok(Self {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    /// Returns the expression's source text
+    #[must_use]
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Evaluates the expression
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for unknown variables or functions, and for
+    /// operands of the wrong type.
+    pub fn evaluate(&self, context: &ExpressionContext) -> Result<Value, ExpressionError> {
+        eval(&self.root, context)
+    }
+
+    /// Evaluates the expression as a condition
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if evaluation fails.
+    pub fn is_true(&self, context: &ExpressionContext) -> Result<bool, ExpressionError> {
+        Ok(self.evaluate(context)?.is_truthy())
+    }
+}
+
+impl FromStr for Expression {
+    type Err = ExpressionError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        Self::parse(source)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Parses and evaluates an expression
+///
+/// # Errors
+///
+/// Returns the parse or evaluation error.
+pub fn evaluate(source: &str, context: &ExpressionContext) -> Result<Value, ExpressionError> {
+    Expression::parse(source)?.evaluate(context)
+}
+
+/// Returns true if the whole of `text` matches the regular expression
+///
+/// # Errors
+///
+/// Returns an error if the pattern is invalid or too large.
+pub fn matches_regex(text: &str, pattern: &str) -> Result<bool, ExpressionError> {
+    regex::RegexBuilder::new(&format!("^(?:{pattern})$"))
+        .size_limit(MAX_REGEX_SIZE)
+        .build()
+        .map(|regex| regex.is_match(text))
+        .map_err(|_| ExpressionError::new(1, format!("invalid pattern '{pattern}'")))
+}
+
+/// Returns true if `text` matches a glob where `*` matches any characters
+/// and `?` a single one
+#[must_use]
+pub fn matches_glob(text: &str, glob: &str) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let glob: Vec<char> = glob.chars().collect();
+    let (mut t, mut g) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                backtrack = Some((g, t));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                t += 1;
+                g += 1;
+            }
+            _ => match backtrack {
+                // Let the last star swallow one more character
+                Some((star, from)) => {
+                    backtrack = Some((star, from + 1));
+                    g = star + 1;
+                    t = from + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+fn eval(expr: &Expr, context: &ExpressionContext) -> Result<Value, ExpressionError> {
+    match expr {
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::List(items) => items
+            .iter()
+            .map(|item| eval(item, context))
+            .collect::<Result<_, _>>()
+            .map(Value::List),
+        Expr::Variable { name, column } => Err(ExpressionError::new(
+            *column,
+            match name.as_str() {
+                "env" | "params" | "stages" | "currentBuild" => {
+                    format!("'{name}' is not a value, use {name}.NAME")
+                }
+                _ => format!("unknown variable '{name}'"),
+            },
+        )),
+        Expr::Member {
+            object,
+            name,
+            column,
+        } => {
+            if let Expr::Variable { name: root, column } = object.as_ref() {
+                return context.lookup(root, name, *column);
+            }
+            let value = eval(object, context)?;
+            Err(ExpressionError::new(
+                *column,
+                format!("{} has no property '{name}'", value.type_name()),
+            ))
+        }
+        Expr::Index {
+            object,
+            index,
+            column,
+        } => {
+            let key = eval(index, context)?;
+            if let Expr::Variable { name: root, column } = object.as_ref() {
+                let key = key.as_string().ok_or_else(|| {
+                    ExpressionError::new(*column, format!("cannot index '{root}' with a list"))
+                })?;
+                return context.lookup(root, &key, *column);
+            }
+            match eval(object, context)? {
+                Value::List(items) => {
+                    let position = integer(&key, *column, "list index")?;
+                    Ok(usize::try_from(position)
+                        .ok()
+                        .and_then(|i| items.get(i).cloned())
+                        .unwrap_or(Value::Null))
+                }
+                value => Err(ExpressionError::new(
+                    *column,
+                    format!("cannot index a {}", value.type_name()),
+                )),
+            }
+        }
+        Expr::Call { name, args, column } => {
+            let args = args
+                .iter()
+                .map(|arg| eval(arg, context))
+                .collect::<Result<Vec<_>, _>>()?;
+            call(name, &args, *column)
+        }
+        Expr::Unary {
+            op,
+            operand,
+            column,
+        } => {
+            let value = eval(operand, context)?;
+            match op {
+                UnaryOp::Not => Ok(Value::Bool(!value.is_truthy())),
+                UnaryOp::Negate => value.as_number().map(|n| Value::Number(-n)).ok_or_else(|| {
+                    ExpressionError::new(*column, format!("cannot negate a {}", value.type_name()))
+                }),
+            }
+        }
+        Expr::Binary {
+            op,
+            left,
+            right,
+            column,
+        } => {
+            let left = eval(left, context)?;
+            // Short-circuit so guards like `env.X && env.X.matches(...)` work
+            match op {
+                BinaryOp::And if !left.is_truthy() => return Ok(Value::Bool(false)),
+                BinaryOp::Or if left.is_truthy() => return Ok(Value::Bool(true)),
+                _ => {}
+            }
+            let right = eval(right, context)?;
+            binary(*op, &left, &right, *column)
+        }
+    }
+}
+
+fn binary(
+    op: BinaryOp,
+    left: &Value,
+    right: &Value,
+    column: usize,
+) -> Result<Value, ExpressionError> {
+    let ordering = |accept: fn(std::cmp::Ordering) -> bool| {
+        left.compare(right)
+            .map(|o| Value::Bool(accept(o)))
+            .ok_or_else(|| {
+                ExpressionError::new(
+                    column,
+                    format!(
+                        "cannot compare {} with {}",
+                        left.type_name(),
+                        right.type_name()
+                    ),
+                )
+            })
+    };
+
+    match op {
+        BinaryOp::And | BinaryOp::Or => Ok(Value::Bool(right.is_truthy())),
+        BinaryOp::Eq => Ok(Value::Bool(left.loosely_equals(right))),
+        BinaryOp::Ne => Ok(Value::Bool(!left.loosely_equals(right))),
+        BinaryOp::Lt => ordering(std::cmp::Ordering::is_lt),
+        BinaryOp::Le => ordering(std::cmp::Ordering::is_le),
+        BinaryOp::Gt => ordering(std::cmp::Ordering::is_gt),
+        BinaryOp::Ge => ordering(std::cmp::Ordering::is_ge),
+        BinaryOp::Add => match (left, right) {
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
+            (Value::List(a), Value::List(b)) => Ok(Value::List([a.clone(), b.clone()].concat())),
+            (Value::String(_), _) | (_, Value::String(_)) => left
+                .as_string()
+                .zip(right.as_string())
+                .map(|(a, b)| Value::String(a + &b))
+                .ok_or_else(|| cannot("add", left, right, column)),
+            _ => Err(cannot("add", left, right, column)),
+        },
+        BinaryOp::Subtract => left
+            .as_number()
+            .zip(right.as_number())
+            .map(|(a, b)| Value::Number(a - b))
+            .ok_or_else(|| cannot("subtract", left, right, column)),
+    }
+}
+
+fn cannot(action: &str, left: &Value, right: &Value, column: usize) -> ExpressionError {
+    ExpressionError::new(
+        column,
+        format!(
+            "cannot {action} {} and {}",
+            left.type_name(),
+            right.type_name()
+        ),
+    )
+}
+
+/// Calls a built-in function
+fn call(name: &str, args: &[Value], column: usize) -> Result<Value, ExpressionError> {
+    let string = |index: usize| string_arg(name, &args[index], column);
+
+    match name {
+        "startsWith" | "endsWith" | "matches" => {
+            arity(name, args, 2, 2, column)?;
+            let (text, other) = (string(0)?, string(1)?);
+            let result = match name {
+                "startsWith" => text.starts_with(&other),
+                "endsWith" => text.ends_with(&other),
+                _ => matches_regex(&text, &other)
+                    .map_err(|e| ExpressionError::new(column, e.message))?,
+            };
+            Ok(Value::Bool(result))
+        }
+        "contains" => {
+            arity(name, args, 2, 2, column)?;
+            match &args[0] {
+                Value::List(items) => Ok(Value::Bool(
+                    items.iter().any(|item| item.loosely_equals(&args[1])),
+                )),
+                _ => Ok(Value::Bool(string(0)?.contains(&string(1)?))),
+            }
+        }
+        "toLowerCase" | "toUpperCase" | "trim" => {
+            arity(name, args, 1, 1, column)?;
+            let text = string(0)?;
+            Ok(Value::String(match name {
+                "toLowerCase" => text.to_lowercase(),
+                "toUpperCase" => text.to_uppercase(),
+                _ => text.trim().to_string(),
+            }))
+        }
+        "length" => {
+            arity(name, args, 1, 1, column)?;
+            let length = match &args[0] {
+                Value::List(items) => items.len(),
+                _ => string(0)?.chars().count(),
+            };
+            Ok(Value::Number(f64::from(
+                u32::try_from(length).unwrap_or(u32::MAX),
+            )))
+        }
+        "split" => {
+            arity(name, args, 2, 2, column)?;
+            let (text, separator) = (string(0)?, string(1)?);
+            if separator.is_empty() {
+                return Err(ExpressionError::new(column, "split separator is empty"));
+            }
+            Ok(Value::List(
+                text.split(separator.as_str())
+                    .map(str::trim)
+                    .filter(|part| !part.is_empty())
+                    .map(Value::from)
+                    .collect(),
+            ))
+        }
+        "join" => {
+            arity(name, args, 2, 2, column)?;
+            let Value::List(items) = &args[0] else {
+                return Err(ExpressionError::new(
+                    column,
+                    format!("join expects a list, got {}", args[0].type_name()),
+                ));
+            };
+            let parts: Vec<String> = items.iter().map(ToString::to_string).collect();
+            Ok(Value::String(parts.join(&string(1)?)))
+        }
+        "range" => {
+            arity(name, args, 2, 3, column)?;
+            let start = integer(&args[0], column, "range start")?;
+            let end = integer(&args[1], column, "range end")?;
+            let step = args
+                .get(2)
+                .map_or(Ok(1), |step| integer(step, column, "range step"))?;
+            if step <= 0 {
+                return Err(ExpressionError::new(column, "range step must be positive"));
+            }
+            let count = if end < start {
+                0
+            } else {
+                (end - start) / step + 1
+            };
+            if count > MAX_LIST_LEN {
+                return Err(ExpressionError::new(
+                    column,
+                    format!("range has more than {MAX_LIST_LEN} values"),
+                ));
+            }
+            Ok(Value::List(
+                (0..count)
+                    .map(|i| Value::String((start + i * step).to_string()))
+                    .collect(),
+            ))
+        }
+        _ => Err(ExpressionError::new(
+            column,
+            format!("unknown function '{name}'"),
+        )),
+    }
+}
+
+fn arity(
+    name: &str,
+    args: &[Value],
+    min: usize,
+    max: usize,
+    column: usize,
+) -> Result<(), ExpressionError> {
+    if (min..=max).contains(&args.len()) {
+        return Ok(());
+    }
+    let expected = if min == max {
+        min.to_string()
+    } else {
+        format!("{min} to {max}")
+    };
+    Err(ExpressionError::new(
+        column,
+        format!("{name} expects {expected} arguments, got {}", args.len()),
+    ))
+}
+
+fn string_arg(name: &str, value: &Value, column: usize) -> Result<String, ExpressionError> {
+    value.as_string().ok_or_else(|| {
+        ExpressionError::new(
+            column,
+            format!("{name} expects a string, got {}", value.type_name()),
+        )
+    })
+}
+
+/// Converts a value to a whole number
+#[allow(clippy::cast_possible_truncation)]
+fn integer(value: &Value, column: usize, what: &str) -> Result<i64, ExpressionError> {
+    match value.as_number() {
+        Some(n) if n.fract() == 0.0 && n.abs() < 1e15 => Ok(n as i64),
+        _ => Err(ExpressionError::new(
+            column,
+            format!("{what} must be a whole number"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> ExpressionContext {
+        ExpressionContext::new()
+            .with_env("BRANCH_NAME", "release/1.2")
+            .with_env("TARGETS", "linux, macos,,windows")
+            .with_param("DEPLOY", "true")
+            .with_param("REPLICAS", "3")
+            .with_stage_result("Unit Tests", "SUCCESS")
+    }
+
+    fn eval_str(source: &str) -> Value {
+        evaluate(source, &context()).unwrap()
+    }
+
+    #[test]
+    fn test_comparisons_and_logic() {
+        assert_eq!(eval_str("params.REPLICAS > 2"), Value::Bool(true));
+        assert_eq!(eval_str("params.DEPLOY == true"), Value::Bool(true));
+        assert_eq!(
+            eval_str("env.BRANCH_NAME == 'main' || !(params.REPLICAS <= 1)"),
+            Value::Bool(true)
+        );
+        assert_eq!(eval_str("env.MISSING == null"), Value::Bool(true));
+        assert_eq!(eval_str("params.REPLICAS - 1 + 10"), Value::Number(12.0));
+        assert_eq!(eval_str("'v' + params.REPLICAS"), Value::from("v3"));
+    }
+
+    #[test]
+    fn test_string_functions() {
+        assert_eq!(
+            eval_str("env.BRANCH_NAME.startsWith('release/')"),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            eval_str("matches(env.BRANCH_NAME, 'release/\\\\d+\\\\.\\\\d+')"),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            eval_str("env.BRANCH_NAME.matches('release')"),
+            Value::Bool(false)
+        );
+        assert_eq!(
+            eval_str("env.BRANCH_NAME.toUpperCase()"),
+            Value::from("RELEASE/1.2")
+        );
+        assert_eq!(eval_str("length(env.MISSING)"), Value::Number(0.0));
+    }
+
+    #[test]
+    fn test_build_and_stage_results() {
+        assert_eq!(eval_str("currentBuild.result"), Value::Null);
+        assert_eq!(
+            eval_str("currentBuild.currentResult"),
+            Value::from("SUCCESS")
+        );
+        assert_eq!(
+            eval_str("stages['Unit Tests'] == 'SUCCESS'"),
+            Value::Bool(true)
+        );
+        assert_eq!(eval_str("stages.Deploy"), Value::Null);
+
+        let failed = context().with_build_result("UNSTABLE");
+        assert!(
+            Expression::parse("currentBuild.result == 'UNSTABLE'")
+                .unwrap()
+                .is_true(&failed)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_lists_for_matrix_axes() {
+        assert_eq!(
+            eval_str("split(env.TARGETS, ',')").into_strings(),
+            vec!["linux", "macos", "windows"]
+        );
+        assert_eq!(
+            eval_str("range(1, 7, 3)").into_strings(),
+            vec!["1", "4", "7"]
+        );
+        assert_eq!(
+            eval_str("['a', 'b'] + ['c']").into_strings(),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(eval_str("['a', 'b'].contains('b')"), Value::Bool(true));
+        assert_eq!(eval_str("join(['a', 'b'], '-')"), Value::from("a-b"));
+        assert!(evaluate("range(0, 100000)", &context()).is_err());
+    }
+
+    #[test]
+    fn test_evaluation_errors_report_column() {
+        let error = evaluate("env.A == 'x' || shell('rm -rf /')", &context()).unwrap_err();
+        assert_eq!(error.column, 17);
+        assert_eq!(error.to_string(), "unknown function 'shell' at column 17");
+
+        assert_eq!(evaluate("foo.bar", &context()).unwrap_err().column, 1);
+        assert_eq!(evaluate("1 < [1]", &context()).unwrap_err().column, 3);
+        assert!(
+            evaluate("startsWith('a')", &context())
+                .unwrap_err()
+                .message
+                .contains("expects 2 arguments")
+        );
+        assert!(evaluate("matches('a', '(')", &context()).is_err());
+    }
+
+    #[test]
+    fn test_short_circuit_skips_errors() {
+        assert_eq!(eval_str("false && unknown()"), Value::Bool(false));
+        assert_eq!(eval_str("true || unknown()"), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_expression_length_is_limited() {
+        let long = "1 + ".repeat(MAX_EXPRESSION_LEN) + "1";
+        let error = Expression::parse(&long).unwrap_err();
+        assert!(error.message.contains("longer than"));
+    }
+
+    #[test]
+    fn test_glob_matching() {
+        assert!(matches_glob("release/1.2", "release/*"));
+        assert!(matches_glob("feature-x", "feature-?"));
+        assert!(matches_glob("a/b/c", "*b*"));
+        assert!(!matches_glob("main", "release/*"));
+        assert!(!matches_glob("feature-xy", "feature-?"));
+    }
+}
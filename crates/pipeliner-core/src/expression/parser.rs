@@ -0,0 +1,388 @@
+//! Parser of the expression language.
+//!
+//! Precedence, from lowest to highest: `||`, `&&`, `==` `!=`,
+//! `<` `<=` `>` `>=`, `+` `-`, unary `!` `-`, then member access, indexing
+//! and calls.
+
+use super::ExpressionError;
+use super::lexer::{Spanned, Token};
+use super::value::Value;
+
+/// Deepest nesting accepted, keeping evaluation far from the stack limit
+const MAX_DEPTH: usize = 64;
+
+/// Unary operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum UnaryOp {
+    Not,
+    Negate,
+}
+
+/// Binary operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum BinaryOp {
+    And,
+    Or,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Subtract,
+}
+
+/// Expression tree
+///
+/// Nodes that can fail at evaluation keep the column they start at.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Expr {
+    Literal(Value),
+    List(Vec<Expr>),
+    Variable {
+        name: String,
+        column: usize,
+    },
+    Member {
+        object: Box<Expr>,
+        name: String,
+        column: usize,
+    },
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        column: usize,
+    },
+    Call {
+        name: String,
+        args: Vec<Expr>,
+        column: usize,
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+        column: usize,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+        column: usize,
+    },
+}
+
+/// Parses a token stream into an expression tree
+pub(super) fn parse(tokens: Vec<Spanned>) -> Result<Expr, ExpressionError> {
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        depth: 0,
+    };
+    let expr = parser.or()?;
+    let next = parser.peek();
+    if next.token != Token::Eof {
+        return Err(parser.unexpected("end of expression"));
+    }
+    Ok(expr)
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Spanned {
+        // The stream always ends with Eof, which is never consumed
+        &self.tokens[self.position.min(self.tokens.len() - 1)]
+    }
+
+    fn advance(&mut self) -> Spanned {
+        let spanned = self.peek().clone();
+        if spanned.token != Token::Eof {
+            self.position += 1;
+        }
+        spanned
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if &self.peek().token == token {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<(), ExpressionError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&token.describe()))
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> ExpressionError {
+        let next = self.peek();
+        ExpressionError::new(
+            next.column,
+            format!("expected {expected}, found {}", next.token.describe()),
+        )
+    }
+
+    /// Counts one more level of nesting
+    fn descend(&mut self, column: usize) -> Result<(), ExpressionError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(ExpressionError::new(
+                column,
+                "expression is nested too deeply",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Parses a left-associative chain of binary operators
+    fn binary(
+        &mut self,
+        operand: fn(&mut Self) -> Result<Expr, ExpressionError>,
+        operator: fn(&Token) -> Option<BinaryOp>,
+    ) -> Result<Expr, ExpressionError> {
+        let depth = self.depth;
+        let mut left = operand(self)?;
+        while let Some(op) = operator(&self.peek().token) {
+            let column = self.advance().column;
+            self.descend(column)?;
+            let right = operand(self)?;
+            left = Expr::Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+                column,
+            };
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Expr, ExpressionError> {
+        self.binary(Self::and, |token| {
+            (*token == Token::Or).then_some(BinaryOp::Or)
+        })
+    }
+
+    fn and(&mut self) -> Result<Expr, ExpressionError> {
+        self.binary(Self::equality, |token| {
+            (*token == Token::And).then_some(BinaryOp::And)
+        })
+    }
+
+    fn equality(&mut self) -> Result<Expr, ExpressionError> {
+        self.binary(Self::comparison, |token| match token {
+            Token::Eq => Some(BinaryOp::Eq),
+            Token::Ne => Some(BinaryOp::Ne),
+            _ => None,
+        })
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ExpressionError> {
+        self.binary(Self::additive, |token| match token {
+            Token::Lt => Some(BinaryOp::Lt),
+            Token::Le => Some(BinaryOp::Le),
+            Token::Gt => Some(BinaryOp::Gt),
+            Token::Ge => Some(BinaryOp::Ge),
+            _ => None,
+        })
+    }
+
+    fn additive(&mut self) -> Result<Expr, ExpressionError> {
+        self.binary(Self::unary, |token| match token {
+            Token::Plus => Some(BinaryOp::Add),
+            Token::Minus => Some(BinaryOp::Subtract),
+            _ => None,
+        })
+    }
+
+    fn unary(&mut self) -> Result<Expr, ExpressionError> {
+        let op = match self.peek().token {
+            Token::Not => UnaryOp::Not,
+            Token::Minus => UnaryOp::Negate,
+            _ => return self.postfix(),
+        };
+        let column = self.advance().column;
+        self.descend(column)?;
+        let operand = self.unary()?;
+        self.depth -= 1;
+        Ok(Expr::Unary {
+            op,
+            operand: Box::new(operand),
+            column,
+        })
+    }
+
+    /// Parses member access, indexing and method calls
+    ///
+    /// `a.f(b)` is the same call as `f(a, b)`.
+    fn postfix(&mut self) -> Result<Expr, ExpressionError> {
+        let depth = self.depth;
+        let mut expr = self.primary()?;
+        loop {
+            let column = self.peek().column;
+            if self.eat(&Token::Dot) {
+                self.descend(column)?;
+                let name_column = self.peek().column;
+                let Token::Ident(name) = self.advance().token else {
+                    return Err(ExpressionError::new(
+                        name_column,
+                        "expected a name after '.'",
+                    ));
+                };
+                expr = if self.eat(&Token::LParen) {
+                    let mut args = vec![expr];
+                    args.extend(self.arguments()?);
+                    Expr::Call {
+                        name,
+                        args,
+                        column: name_column,
+                    }
+                } else {
+                    Expr::Member {
+                        object: Box::new(expr),
+                        name,
+                        column: name_column,
+                    }
+                };
+            } else if self.eat(&Token::LBracket) {
+                self.descend(column)?;
+                let index = self.or()?;
+                self.expect(&Token::RBracket)?;
+                expr = Expr::Index {
+                    object: Box::new(expr),
+                    index: Box::new(index),
+                    column,
+                };
+            } else {
+                break;
+            }
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, ExpressionError> {
+        let Spanned { token, column } = self.advance();
+        match token {
+            Token::Number(n) => Ok(Expr::Literal(Value::Number(n))),
+            Token::String(s) => Ok(Expr::Literal(Value::String(s))),
+            Token::True => Ok(Expr::Literal(Value::Bool(true))),
+            Token::False => Ok(Expr::Literal(Value::Bool(false))),
+            Token::Null => Ok(Expr::Literal(Value::Null)),
+            Token::Ident(name) => {
+                if self.eat(&Token::LParen) {
+                    let args = self.arguments()?;
+                    Ok(Expr::Call { name, args, column })
+                } else {
+                    Ok(Expr::Variable { name, column })
+                }
+            }
+            Token::LParen => {
+                self.descend(column)?;
+                let expr = self.or()?;
+                self.expect(&Token::RParen)?;
+                self.depth -= 1;
+                Ok(expr)
+            }
+            Token::LBracket => {
+                self.descend(column)?;
+                let items = self.list(&Token::RBracket)?;
+                self.depth -= 1;
+                Ok(Expr::List(items))
+            }
+            token => Err(ExpressionError::new(
+                column,
+                format!("expected a value, found {}", token.describe()),
+            )),
+        }
+    }
+
+    /// Parses call arguments after the opening parenthesis
+    fn arguments(&mut self) -> Result<Vec<Expr>, ExpressionError> {
+        self.list(&Token::RParen)
+    }
+
+    /// Parses comma separated expressions up to `close`
+    fn list(&mut self, close: &Token) -> Result<Vec<Expr>, ExpressionError> {
+        let mut items = Vec::new();
+        if self.eat(close) {
+            return Ok(items);
+        }
+        loop {
+            items.push(self.or()?);
+            if self.eat(close) {
+                return Ok(items);
+            }
+            if !self.eat(&Token::Comma) {
+                return Err(self.unexpected(&format!("',' or {}", close.describe())));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::lexer::tokenize;
+    use super::*;
+
+    fn parse_str(source: &str) -> Result<Expr, ExpressionError> {
+        parse(tokenize(source)?)
+    }
+
+    #[test]
+    fn test_precedence() {
+        let Expr::Binary { op, right, .. } = parse_str("a || b && c").unwrap() else {
+            panic!("expected a binary expression");
+        };
+        assert_eq!(op, BinaryOp::Or);
+        assert!(matches!(
+            *right,
+            Expr::Binary {
+                op: BinaryOp::And,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_method_call_takes_receiver_as_first_argument() {
+        let Expr::Call { name, args, column } = parse_str("env.BRANCH.startsWith('r')").unwrap()
+        else {
+            panic!("expected a call");
+        };
+        assert_eq!(name, "startsWith");
+        assert_eq!(args.len(), 2);
+        assert_eq!(column, 12);
+    }
+
+    #[test]
+    fn test_parse_errors_report_column() {
+        let error = parse_str("a == (b").unwrap_err();
+        assert_eq!(error.column, 8);
+        assert!(error.message.contains("expected ')'"));
+
+        assert_eq!(parse_str("a b").unwrap_err().column, 3);
+        assert_eq!(parse_str("a && ").unwrap_err().column, 6);
+        assert_eq!(parse_str("env.").unwrap_err().column, 5);
+    }
+
+    #[test]
+    fn test_nesting_is_limited() {
+        let deep = format!("{}1{}", "(".repeat(100), ")".repeat(100));
+        let error = parse_str(&deep).unwrap_err();
+        assert!(error.message.contains("nested too deeply"));
+
+        assert!(parse_str(&["a"; 100].join(" && ")).is_err());
+        assert!(parse_str(&["a"; 10].join(" && ")).is_ok());
+    }
+}
@@ -0,0 +1,211 @@
+//! Values of the expression language.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Result of evaluating an expression
+///
+/// Environment variables and parameters are strings. Comparisons and
+/// arithmetic convert strings holding numbers, so `params.REPLICAS > 2`
+/// works as expected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Missing value, such as an unset variable
+    Null,
+    /// Boolean
+    Bool(bool),
+    /// Number
+    Number(f64),
+    /// String
+    String(String),
+    /// List, used for matrix axes
+    List(Vec<Value>),
+}
+
+impl Value {
+    /// Returns the name of the value's type for error messages
+    #[must_use]
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Bool(_) => "boolean",
+            Self::Number(_) => "number",
+            Self::String(_) => "string",
+            Self::List(_) => "list",
+        }
+    }
+
+    /// Returns true if the value counts as true in a condition
+    ///
+    /// Like shell flags, the strings `false` and `0` are false, as are
+    /// null, zero, and empty strings and lists.
+    #[must_use]
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Self::Null => false,
+            Self::Bool(b) => *b,
+            Self::Number(n) => *n != 0.0,
+            Self::String(s) => !(s.is_empty() || s == "0" || s.eq_ignore_ascii_case("false")),
+            Self::List(items) => !items.is_empty(),
+        }
+    }
+
+    /// Returns the value as a number, converting numeric strings
+    #[must_use]
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) => Some(*n),
+            Self::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a string, with null as the empty string
+    ///
+    /// Lists have no string form.
+    #[must_use]
+    pub fn as_string(&self) -> Option<String> {
+        match self {
+            Self::List(_) => None,
+            Self::Null => Some(String::new()),
+            other => Some(other.to_string()),
+        }
+    }
+
+    /// Returns the values of a matrix axis
+    ///
+    /// Lists give one value per item, null none, and anything else a single
+    /// value.
+    #[must_use]
+    pub fn into_strings(self) -> Vec<String> {
+        match self {
+            Self::List(items) => items.iter().map(ToString::to_string).collect(),
+            Self::Null => Vec::new(),
+            other => vec![other.to_string()],
+        }
+    }
+
+    /// Compares for equality, converting numeric strings and booleans
+    #[must_use]
+    pub fn loosely_equals(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Number(n), Self::String(_)) => other.as_number() == Some(*n),
+            (Self::String(_), Self::Number(n)) => self.as_number() == Some(*n),
+            (Self::Bool(b), Self::String(s)) | (Self::String(s), Self::Bool(b)) => {
+                s.eq_ignore_ascii_case(if *b { "true" } else { "false" })
+            }
+            (Self::List(a), Self::List(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.loosely_equals(b))
+            }
+            _ => self == other,
+        }
+    }
+
+    /// Orders two values, or returns `None` if they cannot be compared
+    ///
+    /// Numbers, and strings next to numbers, compare numerically; strings
+    /// compare lexicographically.
+    #[must_use]
+    pub fn compare(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::String(a), Self::String(b)) => Some(a.cmp(b)),
+            (Self::Number(_), Self::Number(_) | Self::String(_))
+            | (Self::String(_), Self::Number(_)) => {
+                self.as_number()?.partial_cmp(&other.as_number()?)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => Ok(()),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Number(n) => write!(f, "{n}"),
+            Self::String(s) => write!(f, "{s}"),
+            Self::List(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Self::Number(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truthiness() {
+        assert!(Value::from("yes").is_truthy());
+        assert!(!Value::from("false").is_truthy());
+        assert!(!Value::from("0").is_truthy());
+        assert!(!Value::from("").is_truthy());
+        assert!(!Value::Null.is_truthy());
+        assert!(Value::Number(2.0).is_truthy());
+    }
+
+    #[test]
+    fn test_loose_comparisons() {
+        assert!(Value::from("3").loosely_equals(&Value::Number(3.0)));
+        assert!(Value::Bool(true).loosely_equals(&Value::from("TRUE")));
+        assert!(!Value::from("abc").loosely_equals(&Value::Number(0.0)));
+        assert_eq!(
+            Value::from("10").compare(&Value::Number(9.0)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            Value::from("10").compare(&Value::from("9")),
+            Some(Ordering::Less)
+        );
+        assert_eq!(Value::Bool(true).compare(&Value::Number(1.0)), None);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Value::Number(3.0).to_string(), "3");
+        assert_eq!(Value::Number(1.5).to_string(), "1.5");
+        assert_eq!(Value::Null.to_string(), "");
+        assert_eq!(
+            Value::List(vec![Value::from("a"), Value::Number(1.0)]).to_string(),
+            "a,1"
+        );
+    }
+}
@@ -0,0 +1,603 @@
+//! Requests for input and the registry they wait in.
+//!
+//! An `input` step becomes an [`InputRequest`]. A request nothing answers
+//! up front waits in an [`InputRegistry`], a directory another process
+//! answers through. Registry entries are keyed by the execution waiting
+//! on them as well as the input id, so executions waiting on the same
+//! input are answered separately.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::pipeline::StepParameter;
+
+/// Prefix of environment variables that pre-answer an input
+pub const ANSWER_VAR_PREFIX: &str = "PIPELINER_ANSWER_";
+
+/// Environment variable pointing at a JSON answers file
+pub const ANSWERS_FILE_VAR: &str = "PIPELINER_INPUT_ANSWERS";
+
+/// Environment variable overriding the pending-input registry directory
+pub const INPUT_DIR_VAR: &str = "PIPELINER_INPUT_DIR";
+
+/// Environment variable naming the submitter of non-interactive answers
+pub const SUBMITTER_VAR: &str = "PIPELINER_SUBMITTER";
+
+/// Input errors
+#[derive(Debug, Error)]
+pub enum InputError {
+    /// The input was aborted, or timed out without a default
+    #[error("input '{id}' aborted: {reason}")]
+    Aborted {
+        /// Input id
+        id: String,
+        /// Why the input was aborted
+        reason: String,
+    },
+
+    /// The answer comes from a user outside the submitter list
+    #[error("input '{id}' cannot be answered by '{submitter}'")]
+    Rejected {
+        /// Input id
+        id: String,
+        /// User who answered
+        submitter: String,
+    },
+
+    /// The answer does not fit the request's parameters
+    #[error("invalid answer to input '{id}': {reason}")]
+    InvalidAnswer {
+        /// Input id
+        id: String,
+        /// What is wrong with the answer
+        reason: String,
+    },
+
+    /// No pending request has the key or id
+    #[error("no pending input '{key}'")]
+    NotPending {
+        /// Requested key or id
+        key: String,
+    },
+
+    /// Several executions wait on an input with the id
+    #[error("input '{id}' is pending in several executions, answer one of: {}", keys.join(", "))]
+    Ambiguous {
+        /// Requested id
+        id: String,
+        /// Keys of the matching requests
+        keys: Vec<String>,
+    },
+
+    /// A registry or answers file could not be read or written
+    #[error("cannot access input file {}: {reason}", path.display())]
+    Io {
+        /// File path
+        path: PathBuf,
+        /// Underlying error
+        reason: std::io::Error,
+    },
+
+    /// A registry or answers file is malformed
+    #[error("invalid input file {}: {reason}", path.display())]
+    Parse {
+        /// File path
+        path: PathBuf,
+        /// Parser error
+        reason: String,
+    },
+}
+
+/// A pending request for input
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InputRequest {
+    /// Input identifier
+    pub id: String,
+    /// Message shown to the submitter
+    pub message: String,
+    /// Value used when no explicit value is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    /// Parameters the submitter may fill in
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parameters: Vec<StepParameter>,
+    /// Users allowed to answer; empty means anyone
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub submitters: Vec<String>,
+    /// Execution waiting for the answer
+    #[serde(default)]
+    pub execution_id: String,
+}
+
+impl InputRequest {
+    /// Creates a request from the fields of an `input` step
+    ///
+    /// The id defaults to a slug of the message and the submitter list is
+    /// split on commas.
+    #[must_use]
+    pub fn new(
+        message: &str,
+        default: Option<&String>,
+        parameters: &[StepParameter],
+        id: Option<&String>,
+        submitter: Option<&String>,
+    ) -> Self {
+        Self {
+            id: id.cloned().unwrap_or_else(|| slug(message)),
+            message: message.to_string(),
+            default: default.cloned(),
+            parameters: parameters.to_vec(),
+            submitters: submitter
+                .map(|s| {
+                    s.split(',')
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            execution_id: String::new(),
+        }
+    }
+
+    /// Sets the execution waiting for the answer
+    #[must_use]
+    pub fn with_execution_id(mut self, execution_id: impl Into<String>) -> Self {
+        self.execution_id = execution_id.into();
+        self
+    }
+
+    /// Registry key of this request, `<execution>.<id>`
+    #[must_use]
+    pub fn key(&self) -> String {
+        if self.execution_id.is_empty() {
+            slug(&self.id)
+        } else {
+            format!("{}.{}", slug(&self.execution_id), slug(&self.id))
+        }
+    }
+
+    /// Returns whether `submitter` may answer this request
+    #[must_use]
+    pub fn allows(&self, submitter: Option<&str>) -> bool {
+        self.submitters.is_empty()
+            || submitter.is_some_and(|s| self.submitters.iter().any(|allowed| allowed == s))
+    }
+
+    /// Environment variable name that carries this input's answer
+    #[must_use]
+    pub fn env_key(&self) -> String {
+        format!("INPUT_{}", env_suffix(&self.id))
+    }
+
+    /// Environment variable name that pre-answers this input
+    #[must_use]
+    pub fn answer_var(&self) -> String {
+        format!("{ANSWER_VAR_PREFIX}{}", env_suffix(&self.id))
+    }
+
+    /// Checks an answer against this request and fills in default values
+    ///
+    /// # Errors
+    ///
+    /// Returns `Rejected` if the submitter may not answer, `Aborted` if the
+    /// answer aborts and `InvalidAnswer` if a choice parameter has a value
+    /// outside its choices.
+    pub fn accept(&self, answer: InputAnswer) -> Result<InputAnswer, InputError> {
+        if !self.allows(answer.submitter.as_deref()) {
+            return Err(InputError::Rejected {
+                id: self.id.clone(),
+                submitter: answer.submitter.unwrap_or_default(),
+            });
+        }
+        if answer.abort {
+            return Err(InputError::Aborted {
+                id: self.id.clone(),
+                reason: format!(
+                    "aborted by {}",
+                    answer.submitter.as_deref().unwrap_or("<unknown>")
+                ),
+            });
+        }
+
+        let mut parameters = answer.parameters;
+        for parameter in &self.parameters {
+            match parameter {
+                StepParameter::String {
+                    name,
+                    default_value,
+                    ..
+                } => {
+                    if let Some(default) = default_value {
+                        parameters
+                            .entry(name.clone())
+                            .or_insert_with(|| default.clone());
+                    }
+                }
+                StepParameter::Boolean {
+                    name,
+                    default_value,
+                    ..
+                } => {
+                    parameters
+                        .entry(name.clone())
+                        .or_insert_with(|| default_value.to_string());
+                }
+                StepParameter::Choice { name, choices, .. } => {
+                    let value = parameters
+                        .entry(name.clone())
+                        .or_insert_with(|| choices.first().cloned().unwrap_or_default());
+                    if !choices.contains(value) {
+                        return Err(InputError::InvalidAnswer {
+                            id: self.id.clone(),
+                            reason: format!(
+                                "'{value}' is not a valid choice for parameter '{name}'"
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(InputAnswer {
+            value: answer.value.or_else(|| self.default.clone()),
+            parameters,
+            ..answer
+        })
+    }
+}
+
+/// An answer to an input request
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InputAnswer {
+    /// Submitted value; the request default is used when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    /// Values for the request parameters
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub parameters: HashMap<String, String>,
+    /// User who answered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submitter: Option<String>,
+    /// Whether the submitter aborted instead of proceeding
+    #[serde(default)]
+    pub abort: bool,
+}
+
+impl InputAnswer {
+    /// Creates an answer that proceeds with an optional value
+    #[must_use]
+    pub fn proceed(value: Option<String>) -> Self {
+        Self {
+            value,
+            ..Self::default()
+        }
+    }
+
+    /// Creates an answer that aborts the input
+    #[must_use]
+    pub fn abort() -> Self {
+        Self {
+            abort: true,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the submitter of this answer
+    #[must_use]
+    pub fn with_submitter(mut self, submitter: impl Into<String>) -> Self {
+        self.submitter = Some(submitter.into());
+        self
+    }
+
+    /// Sets a parameter value
+    #[must_use]
+    pub fn with_parameter(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.parameters.insert(name.into(), value.into());
+        self
+    }
+
+    /// Looks up the answer to `id` in a JSON answers file
+    ///
+    /// Entries are either a bare value or a full answer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or parsed.
+    pub fn from_file(path: &Path, id: &str) -> Result<Option<Self>, InputError> {
+        let mut answers: HashMap<String, AnswerEntry> = read_json(path)?;
+        Ok(answers.remove(id).map(|entry| match entry {
+            AnswerEntry::Value(value) => Self::proceed(Some(value)),
+            AnswerEntry::Answer(answer) => answer,
+        }))
+    }
+}
+
+/// Entry of an answers file: either a bare value or a full answer
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum AnswerEntry {
+    Value(String),
+    Answer(InputAnswer),
+}
+
+/// Directory-backed registry of inputs waiting for an answer
+///
+/// Each pending input is a `<key>.request.json` file; answering writes a
+/// matching `<key>.answer.json` that the waiting execution picks up.
+#[derive(Debug, Clone)]
+pub struct InputRegistry {
+    dir: PathBuf,
+}
+
+impl InputRegistry {
+    /// Creates a registry rooted at `dir`
+    #[must_use]
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Gets the registry directory
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Registers a pending request, discarding any stale answer to it
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the request cannot be written.
+    pub fn register(&self, request: &InputRequest) -> Result<(), InputError> {
+        fs::create_dir_all(&self.dir).map_err(|reason| InputError::Io {
+            path: self.dir.clone(),
+            reason,
+        })?;
+        let key = request.key();
+        remove_if_exists(&self.answer_path(&key))?;
+        write_json(&self.request_path(&key), request)
+    }
+
+    /// Lists pending requests
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the registry cannot be read.
+    pub fn pending(&self) -> Result<Vec<InputRequest>, InputError> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let io = |reason| InputError::Io {
+            path: self.dir.clone(),
+            reason,
+        };
+
+        let mut requests: Vec<InputRequest> = Vec::new();
+        for entry in fs::read_dir(&self.dir).map_err(io)? {
+            let path = entry.map_err(io)?.path();
+            if path.to_string_lossy().ends_with(".request.json") {
+                requests.push(read_json(&path)?);
+            }
+        }
+        requests.sort_by_key(InputRequest::key);
+        Ok(requests)
+    }
+
+    /// Answers the pending request with key or id `target`
+    ///
+    /// An id only names a request while a single execution waits on it.
+    /// Returns the key of the answered request.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotPending` if no request matches, `Ambiguous` if several
+    /// executions wait on the id, or an I/O error if the answer cannot be
+    /// written.
+    pub fn answer(&self, target: &str, answer: &InputAnswer) -> Result<String, InputError> {
+        let mut keys: Vec<String> = self
+            .pending()?
+            .into_iter()
+            .map(|request| (request.key(), request))
+            .filter(|(key, request)| key == target || request.id == target)
+            .map(|(key, _)| key)
+            .collect();
+
+        match keys.len() {
+            0 => Err(InputError::NotPending {
+                key: target.to_string(),
+            }),
+            1 => {
+                let key = keys.remove(0);
+                write_json(&self.answer_path(&key), answer)?;
+                Ok(key)
+            }
+            _ => Err(InputError::Ambiguous {
+                id: target.to_string(),
+                keys,
+            }),
+        }
+    }
+
+    /// Takes the answer to `request`, if one has been written
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the answer exists but cannot be read.
+    pub fn take_answer(&self, request: &InputRequest) -> Result<Option<InputAnswer>, InputError> {
+        let path = self.answer_path(&request.key());
+        if !path.exists() {
+            return Ok(None);
+        }
+        let answer = read_json(&path)?;
+        remove_if_exists(&path)?;
+        Ok(Some(answer))
+    }
+
+    /// Removes a request and any answer to it
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the files cannot be removed.
+    pub fn withdraw(&self, request: &InputRequest) -> Result<(), InputError> {
+        let key = request.key();
+        remove_if_exists(&self.request_path(&key))?;
+        remove_if_exists(&self.answer_path(&key))
+    }
+
+    fn request_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.request.json"))
+    }
+
+    fn answer_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.answer.json"))
+    }
+}
+
+/// Lowercase, dash-separated form of a message or id
+fn slug(value: &str) -> String {
+    value
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Uppercase, underscore-separated form of an id for variable names
+fn env_suffix(id: &str) -> String {
+    slug(id).replace('-', "_").to_uppercase()
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, InputError> {
+    let content = fs::read_to_string(path).map_err(|reason| InputError::Io {
+        path: path.to_path_buf(),
+        reason,
+    })?;
+    serde_json::from_str(&content).map_err(|e| InputError::Parse {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    })
+}
+
+/// Writes JSON through a temp file so readers never see partial content
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), InputError> {
+    let content = serde_json::to_string_pretty(value).map_err(|e| InputError::Parse {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    })?;
+    let io = |reason| InputError::Io {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let partial = path.with_extension("partial");
+    fs::write(&partial, content).map_err(io)?;
+    fs::rename(&partial, path).map_err(io)
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), InputError> {
+    match fs::remove_file(path) {
+        Err(reason) if reason.kind() != std::io::ErrorKind::NotFound => Err(InputError::Io {
+            path: path.to_path_buf(),
+            reason,
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(execution_id: &str) -> InputRequest {
+        InputRequest::new("Deploy?", None, &[], None, None).with_execution_id(execution_id)
+    }
+
+    #[test]
+    fn test_request_id_and_keys() {
+        let request = InputRequest::new(
+            "Deploy to production?",
+            None,
+            &[],
+            None,
+            Some(&"alice, bob".to_string()),
+        );
+
+        assert_eq!(request.id, "deploy-to-production");
+        assert_eq!(request.key(), "deploy-to-production");
+        assert_eq!(request.env_key(), "INPUT_DEPLOY_TO_PRODUCTION");
+        assert_eq!(
+            request.answer_var(),
+            "PIPELINER_ANSWER_DEPLOY_TO_PRODUCTION"
+        );
+        assert_eq!(request.submitters, vec!["alice", "bob"]);
+        assert!(request.allows(Some("bob")));
+        assert!(!request.allows(Some("mallory")));
+        assert!(!request.allows(None));
+
+        let request = request.with_execution_id("Build 7");
+        assert_eq!(request.key(), "build-7.deploy-to-production");
+    }
+
+    #[test]
+    fn test_registry_keeps_executions_apart() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let registry = InputRegistry::new(dir.path());
+        let (first, second) = (request("1"), request("2"));
+        registry.register(&first).unwrap();
+
+        assert_eq!(
+            registry.answer("deploy", &InputAnswer::abort()).unwrap(),
+            "1.deploy"
+        );
+        registry.register(&second).unwrap();
+        assert!(matches!(
+            registry.answer("deploy", &InputAnswer::proceed(None)),
+            Err(InputError::Ambiguous { keys, .. }) if keys == ["1.deploy", "2.deploy"]
+        ));
+        assert_eq!(registry.take_answer(&second).unwrap(), None);
+
+        registry
+            .answer("2.deploy", &InputAnswer::proceed(None))
+            .unwrap();
+        assert!(registry.take_answer(&first).unwrap().unwrap().abort);
+        assert!(!registry.take_answer(&second).unwrap().unwrap().abort);
+
+        registry.withdraw(&first).unwrap();
+        assert_eq!(registry.pending().unwrap(), vec![second]);
+        assert!(matches!(
+            registry.answer("1.deploy", &InputAnswer::abort()),
+            Err(InputError::NotPending { .. })
+        ));
+    }
+
+    #[test]
+    fn test_accept_fills_defaults_and_checks_choices() {
+        let request = InputRequest::new(
+            "Deploy?",
+            Some(&"yes".to_string()),
+            &[StepParameter::Choice {
+                name: "TARGET".to_string(),
+                description: String::new(),
+                choices: vec!["staging".to_string(), "prod".to_string()],
+            }],
+            None,
+            None,
+        );
+
+        let answer = request.accept(InputAnswer::proceed(None)).unwrap();
+        assert_eq!(answer.value.as_deref(), Some("yes"));
+        assert_eq!(answer.parameters["TARGET"], "staging");
+
+        let answer = InputAnswer::proceed(None).with_parameter("TARGET", "moon");
+        assert!(matches!(
+            request.accept(answer),
+            Err(InputError::InvalidAnswer { .. })
+        ));
+    }
+}
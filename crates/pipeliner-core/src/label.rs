@@ -0,0 +1,436 @@
+//! Label expressions selecting the workers a job may run on.
+//!
+//! Workers advertise labels, such as `linux`, `x86_64` or `docker`, and a
+//! job names the labels it needs with an expression over them:
+//!
+//! ```text
+//! linux && (docker || podman) && !gpu
+//! ```
+//!
+//! Operators are `!`, `&&` and `||`, in decreasing precedence, and
+//! parentheses group. Labels are made of letters, digits and `-_.:/+=`.
+//! Expressions are limited in length and nesting, as they come from
+//! submitted jobs.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Longest label expression accepted, in characters
+pub const MAX_LABEL_EXPRESSION_LEN: usize = 1024;
+
+/// Deepest nesting accepted, keeping matching far from the stack limit
+const MAX_DEPTH: usize = 64;
+
+/// Error parsing a label expression
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{message} at column {column}")]
+pub struct LabelError {
+    /// Column the error occurs at, starting at 1
+    pub column: usize,
+    /// What went wrong
+    pub message: String,
+}
+
+impl LabelError {
+    fn new(column: usize, message: impl Into<String>) -> Self {
+        Self {
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+/// Boolean expression over worker labels
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum LabelExpression {
+    /// The worker has the label
+    Label(String),
+    /// The expression does not match
+    Not(Box<LabelExpression>),
+    /// Both expressions match
+    And(Box<LabelExpression>, Box<LabelExpression>),
+    /// Either expression matches
+    Or(Box<LabelExpression>, Box<LabelExpression>),
+}
+
+impl LabelExpression {
+    /// Parses a label expression
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the expression is empty, malformed, too long or
+    /// nested too deeply.
+    pub fn parse(source: &str) -> Result<Self, LabelError> {
+        let len = source.chars().count();
+        if len > MAX_LABEL_EXPRESSION_LEN {
+            return Err(LabelError::new(
+                MAX_LABEL_EXPRESSION_LEN + 1,
+                format!("expression is longer than {MAX_LABEL_EXPRESSION_LEN} characters"),
+            ));
+        }
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+            depth: 0,
+            end: len + 1,
+        };
+        let expression = parser.or()?;
+        match parser.peek() {
+            None => Ok(expression),
+            Some((column, token)) => Err(LabelError::new(
+                *column,
+                format!("unexpected {}", token.describe()),
+            )),
+        }
+    }
+
+    /// Returns true if a worker with `labels` matches the expression
+    #[must_use]
+    pub fn matches(&self, labels: &BTreeSet<String>) -> bool {
+        match self {
+            Self::Label(label) => labels.contains(label),
+            Self::Not(inner) => !inner.matches(labels),
+            Self::And(left, right) => left.matches(labels) && right.matches(labels),
+            Self::Or(left, right) => left.matches(labels) || right.matches(labels),
+        }
+    }
+
+    /// Returns the labels the expression mentions
+    #[must_use]
+    pub fn labels(&self) -> BTreeSet<&str> {
+        let mut labels = BTreeSet::new();
+        self.collect_labels(&mut labels);
+        labels
+    }
+
+    fn collect_labels<'a>(&'a self, labels: &mut BTreeSet<&'a str>) {
+        match self {
+            Self::Label(label) => {
+                labels.insert(label);
+            }
+            Self::Not(inner) => inner.collect_labels(labels),
+            Self::And(left, right) | Self::Or(left, right) => {
+                left.collect_labels(labels);
+                right.collect_labels(labels);
+            }
+        }
+    }
+
+    /// Returns how tightly the expression binds, for parenthesizing
+    fn precedence(&self) -> u8 {
+        match self {
+            Self::Or(..) => 0,
+            Self::And(..) => 1,
+            Self::Not(_) | Self::Label(_) => 2,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, precedence: u8) -> fmt::Result {
+        if self.precedence() < precedence {
+            write!(f, "({self})")
+        } else {
+            write!(f, "{self}")
+        }
+    }
+}
+
+impl fmt::Display for LabelExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Label(label) => f.write_str(label),
+            Self::Not(inner) => {
+                f.write_str("!")?;
+                inner.fmt_operand(f, 2)
+            }
+            Self::And(left, right) => {
+                left.fmt_operand(f, 1)?;
+                f.write_str(" && ")?;
+                right.fmt_operand(f, 2)
+            }
+            Self::Or(left, right) => {
+                left.fmt_operand(f, 0)?;
+                f.write_str(" || ")?;
+                right.fmt_operand(f, 1)
+            }
+        }
+    }
+}
+
+impl FromStr for LabelExpression {
+    type Err = LabelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl TryFrom<String> for LabelExpression {
+    type Error = LabelError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Self::parse(&source)
+    }
+}
+
+impl From<LabelExpression> for String {
+    fn from(expression: LabelExpression) -> Self {
+        expression.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Label(String),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Self::Label(label) => format!("label '{label}'"),
+            Self::Not => "'!'".to_string(),
+            Self::And => "'&&'".to_string(),
+            Self::Or => "'||'".to_string(),
+            Self::Open => "'('".to_string(),
+            Self::Close => "')'".to_string(),
+        }
+    }
+}
+
+fn is_label_char(c: char) -> bool {
+    c.is_alphanumeric() || "-_.:/+=".contains(c)
+}
+
+/// Splits an expression into tokens and the columns they start at
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, LabelError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().zip(1..).peekable();
+    while let Some((c, column)) = chars.next() {
+        let token = match c {
+            _ if c.is_whitespace() => continue,
+            '!' => Token::Not,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' | '|' => {
+                if chars.next_if(|(next, _)| *next == c).is_none() {
+                    return Err(LabelError::new(column, format!("expected '{c}{c}'")));
+                }
+                if c == '&' { Token::And } else { Token::Or }
+            }
+            _ if is_label_char(c) => {
+                let mut label = c.to_string();
+                while let Some((next, _)) = chars.next_if(|(next, _)| is_label_char(*next)) {
+                    label.push(next);
+                }
+                Token::Label(label)
+            }
+            _ => {
+                return Err(LabelError::new(
+                    column,
+                    format!("unexpected character '{c}'"),
+                ));
+            }
+        };
+        tokens.push((column, token));
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [(usize, Token)],
+    position: usize,
+    /// Nesting of the expression being parsed
+    depth: usize,
+    /// Column just past the end of the source
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&(usize, Token)> {
+        self.tokens.get(self.position)
+    }
+
+    /// Consumes the next token if it is `token`, returning its column
+    fn eat(&mut self, token: &Token) -> Option<usize> {
+        let column = self
+            .peek()
+            .filter(|(_, next)| next == token)
+            .map(|(column, _)| *column)?;
+        self.position += 1;
+        Some(column)
+    }
+
+    /// Counts one more level of nesting
+    fn descend(&mut self, column: usize) -> Result<(), LabelError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(LabelError::new(column, "expression is nested too deeply"));
+        }
+        Ok(())
+    }
+
+    fn or(&mut self) -> Result<LabelExpression, LabelError> {
+        let depth = self.depth;
+        let mut expression = self.and()?;
+        while let Some(column) = self.eat(&Token::Or) {
+            self.descend(column)?;
+            expression = LabelExpression::Or(Box::new(expression), Box::new(self.and()?));
+        }
+        self.depth = depth;
+        Ok(expression)
+    }
+
+    fn and(&mut self) -> Result<LabelExpression, LabelError> {
+        let depth = self.depth;
+        let mut expression = self.unary()?;
+        while let Some(column) = self.eat(&Token::And) {
+            self.descend(column)?;
+            expression = LabelExpression::And(Box::new(expression), Box::new(self.unary()?));
+        }
+        self.depth = depth;
+        Ok(expression)
+    }
+
+    fn unary(&mut self) -> Result<LabelExpression, LabelError> {
+        let Some((column, token)) = self.peek().cloned() else {
+            return Err(LabelError::new(self.end, "expected a label"));
+        };
+        self.position += 1;
+        match token {
+            Token::Label(label) => Ok(LabelExpression::Label(label)),
+            Token::Not => {
+                self.descend(column)?;
+                let inner = self.unary()?;
+                self.depth -= 1;
+                Ok(LabelExpression::Not(Box::new(inner)))
+            }
+            Token::Open => {
+                self.descend(column)?;
+                let expression = self.or()?;
+                self.depth -= 1;
+                if self.eat(&Token::Close).is_some() {
+                    Ok(expression)
+                } else {
+                    let column = self.peek().map_or(self.end, |(column, _)| *column);
+                    Err(LabelError::new(column, "expected ')'"))
+                }
+            }
+            token => Err(LabelError::new(
+                column,
+                format!("expected a label, found {}", token.describe()),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(labels: &[&str]) -> BTreeSet<String> {
+        labels.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_label_expression_matches() {
+        let expression = LabelExpression::parse("linux && (docker || podman) && !gpu").unwrap();
+        assert!(expression.matches(&labels(&["linux", "docker"])));
+        assert!(expression.matches(&labels(&["linux", "podman", "x86_64"])));
+        assert!(!expression.matches(&labels(&["linux", "docker", "gpu"])));
+        assert!(!expression.matches(&labels(&["windows", "docker"])));
+        assert_eq!(
+            expression.labels().into_iter().collect::<Vec<_>>(),
+            ["docker", "gpu", "linux", "podman"]
+        );
+    }
+
+    #[test]
+    fn test_label_expression_precedence() {
+        let expression = LabelExpression::parse("a || b && !c").unwrap();
+        assert_eq!(expression.to_string(), "a || b && !c");
+        assert!(expression.matches(&labels(&["a", "c"])));
+        assert!(!expression.matches(&labels(&["b", "c"])));
+
+        let grouped = LabelExpression::parse("!(a || b) && (c || d)").unwrap();
+        assert_eq!(grouped.to_string(), "!(a || b) && (c || d)");
+        assert_eq!(
+            LabelExpression::parse(&grouped.to_string()).unwrap(),
+            grouped
+        );
+        assert_eq!(
+            LabelExpression::parse("((linux))").unwrap(),
+            LabelExpression::Label("linux".to_string())
+        );
+    }
+
+    #[test]
+    fn test_label_expression_errors() {
+        let error = |source: &str| LabelExpression::parse(source).unwrap_err().to_string();
+        assert_eq!(error(""), "expected a label at column 1");
+        assert_eq!(error("linux &&"), "expected a label at column 9");
+        assert_eq!(error("linux & docker"), "expected '&&' at column 7");
+        assert_eq!(
+            error("linux docker"),
+            "unexpected label 'docker' at column 7"
+        );
+        assert_eq!(error("(linux"), "expected ')' at column 7");
+        assert_eq!(error("linux)"), "unexpected ')' at column 6");
+        assert_eq!(
+            error("|| linux"),
+            "expected a label, found '||' at column 1"
+        );
+        assert_eq!(
+            error("linux && $os"),
+            "unexpected character '$' at column 10"
+        );
+    }
+
+    #[test]
+    fn test_label_expression_limits() {
+        let error = |source: &str| LabelExpression::parse(source).unwrap_err().to_string();
+        let deep_not = "!".repeat(MAX_LABEL_EXPRESSION_LEN - 5) + "linux";
+        assert_eq!(
+            error(&deep_not),
+            "expression is nested too deeply at column 65"
+        );
+        let deep_group = "(".repeat(100) + "linux" + &")".repeat(100);
+        assert_eq!(
+            error(&deep_group),
+            "expression is nested too deeply at column 65"
+        );
+        let long_chain = vec!["linux"; 100].join(" && ");
+        assert!(error(&long_chain).starts_with("expression is nested too deeply"));
+
+        let nested = "(".repeat(32) + "!linux" + &")".repeat(32);
+        assert!(LabelExpression::parse(&nested).is_ok());
+        let huge = "!".repeat(100_000) + "linux";
+        assert_eq!(
+            error(&huge),
+            format!(
+                "expression is longer than {MAX_LABEL_EXPRESSION_LEN} characters at column {}",
+                MAX_LABEL_EXPRESSION_LEN + 1
+            )
+        );
+    }
+
+    #[test]
+    fn test_label_expression_serde() {
+        let expression = LabelExpression::parse("linux&&!gpu").unwrap();
+        let json = serde_json::to_string(&expression).unwrap();
+        assert_eq!(json, "\"linux && !gpu\"");
+        assert_eq!(
+            serde_json::from_str::<LabelExpression>(&json).unwrap(),
+            expression
+        );
+        assert!(serde_json::from_str::<LabelExpression>("\"linux &&\"").is_err());
+    }
+}
@@ -11,7 +11,12 @@
 //!
 //! - `pipeline`: Pipeline, Stage, Step definitions
 //! - `agent`: Agent types (Docker, Kubernetes, Podman, etc.)
+//! - `credentials`: Typed credentials and their providers
 //! - `environment`: Environment variable handling
+//! - `expression`: Expression language for conditions and computed values
+//! - `input`: Input requests and the registry they wait in
+//! - `label`: Label expressions matching jobs to workers
+//! - `masking`: Masking of secret values in output
 //! - `validation`: Pipeline validation rules
 //! - `matrix`: Matrix execution configuration
 //!
@@ -71,7 +76,12 @@
 #![warn(clippy::pedantic)]
 
 pub mod agent;
+pub mod credentials;
 pub mod environment;
+pub mod expression;
+pub mod input;
+pub mod label;
+pub mod masking;
 pub mod matrix;
 pub mod options;
 pub mod parameters;
@@ -81,7 +91,12 @@ pub mod validation;
 
 // Re-exports for common use
 pub use agent::{AgentConfig, AgentType, DockerConfig, KubernetesConfig, PodmanConfig};
+pub use credentials::{Credentials, CredentialsError, CredentialsProvider};
 pub use environment::{Environment, VariableResolver};
+pub use expression::{Expression, ExpressionContext, ExpressionError};
+pub use input::{InputAnswer, InputError, InputRegistry, InputRequest};
+pub use label::{LabelError, LabelExpression};
+pub use masking::SecretMasker;
 pub use matrix::{MatrixAxis, MatrixConfig, MatrixExclude};
 pub use options::{PipelineOptions, Retry, Timeout, Trigger};
 pub use parameters::{ParameterType, Parameters};
@@ -0,0 +1,258 @@
+//! Masking of secret values in output.
+//!
+//! A [`SecretMasker`] knows the secret values in scope of an execution and
+//! replaces them with [`MASK`] wherever they show up: step output, logs,
+//! events and error messages. Besides the plain value it also masks the
+//! forms a secret is commonly printed in, base64 and URL encoding, and
+//! every line of a multi-line secret such as a private key.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::environment::{EnvVarValue, Environment};
+
+/// Replacement for masked values
+pub const MASK: &str = "****";
+
+/// Shortest value that is masked
+///
+/// Shorter values would mask unrelated output far more often than they
+/// would hide a secret.
+pub const MIN_SECRET_LEN: usize = 3;
+
+/// Replaces known secret values in text
+///
+/// Clones share the same set of secrets, so a secret added anywhere in an
+/// execution is masked everywhere in it.
+#[derive(Debug, Clone, Default)]
+pub struct SecretMasker {
+    /// Patterns to replace, the secrets and their encodings
+    patterns: Arc<RwLock<BTreeSet<String>>>,
+}
+
+impl SecretMasker {
+    /// Creates a masker without secrets
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a masker for the masked secrets of an environment
+    #[must_use]
+    pub fn from_environment(environment: &Environment) -> Self {
+        let masker = Self::new();
+        masker.add_environment(environment);
+        masker
+    }
+
+    /// Adds a secret value
+    ///
+    /// Values shorter than [`MIN_SECRET_LEN`] are ignored.
+    pub fn add(&self, secret: &str) {
+        if secret.len() < MIN_SECRET_LEN {
+            return;
+        }
+        let candidates = std::iter::once(secret.to_string())
+            .chain(secret.lines().map(str::trim).map(str::to_string))
+            .chain([
+                base64_encode(secret.as_bytes(), true),
+                base64_encode(secret.as_bytes(), false),
+                url_encode(secret),
+            ]);
+
+        let mut patterns = self
+            .patterns
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        patterns.extend(candidates.filter(|pattern| pattern.len() >= MIN_SECRET_LEN));
+    }
+
+    /// Adds every secret of an environment whose `masked` flag is set
+    pub fn add_environment(&self, environment: &Environment) {
+        for (_, value) in environment.iter() {
+            if let EnvVarValue::Secret(secret) = value
+                && secret.masked
+            {
+                self.add(&secret.value);
+            }
+        }
+    }
+
+    /// Returns true if no secret is known
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.patterns
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_empty()
+    }
+
+    /// Replaces every known secret in `text` with [`MASK`]
+    #[must_use]
+    pub fn mask(&self, text: &str) -> String {
+        let patterns = self.patterns.read().unwrap_or_else(PoisonError::into_inner);
+
+        // Longer patterns first, so a secret is not partly masked through
+        // one of its lines or a shorter secret it contains
+        let mut ordered: Vec<&String> = patterns
+            .iter()
+            .filter(|pattern| text.contains(pattern.as_str()))
+            .collect();
+        ordered.sort_by_key(|pattern| std::cmp::Reverse(pattern.len()));
+
+        let mut masked = text.to_string();
+        for pattern in ordered {
+            masked = masked.replace(pattern.as_str(), MASK);
+        }
+        masked
+    }
+
+    /// Replaces every known secret in raw output, such as a process' stdout
+    ///
+    /// The output is searched as bytes, so output that is not UTF-8 is
+    /// kept as it is apart from the secrets.
+    #[must_use]
+    pub fn mask_bytes(&self, bytes: &[u8]) -> Vec<u8> {
+        let patterns = self.patterns.read().unwrap_or_else(PoisonError::into_inner);
+
+        let mut ordered: Vec<&[u8]> = patterns
+            .iter()
+            .map(String::as_bytes)
+            .filter(|pattern| find_bytes(bytes, pattern).is_some())
+            .collect();
+        ordered.sort_by_key(|pattern| std::cmp::Reverse(pattern.len()));
+
+        let mut masked = bytes.to_vec();
+        for pattern in ordered {
+            masked = replace_bytes(&masked, pattern, MASK.as_bytes());
+        }
+        masked
+    }
+}
+
+/// Returns the position of the first occurrence of `needle` in `haystack`
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Replaces every occurrence of `needle` in `haystack`
+fn replace_bytes(haystack: &[u8], needle: &[u8], replacement: &[u8]) -> Vec<u8> {
+    let mut replaced = Vec::with_capacity(haystack.len());
+    let mut rest = haystack;
+    while let Some(position) = find_bytes(rest, needle) {
+        replaced.extend_from_slice(&rest[..position]);
+        replaced.extend_from_slice(replacement);
+        rest = &rest[position + needle.len()..];
+    }
+    replaced.extend_from_slice(rest);
+    replaced
+}
+
+/// Encodes bytes as standard base64
+fn base64_encode(bytes: &[u8], padding: bool) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | u32::from(byte) << (16 - 8 * i)
+        });
+        for i in 0..=chunk.len() {
+            let index = (group >> (18 - 6 * i)) & 0x3f;
+            encoded.push(char::from(ALPHABET[index as usize]));
+        }
+        if padding {
+            for _ in chunk.len()..3 {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Percent-encodes every byte outside the URL unreserved set
+fn url_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(char::from(byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b"user:pass", true), "dXNlcjpwYXNz");
+        assert_eq!(base64_encode(b"hunter2", true), "aHVudGVyMg==");
+        assert_eq!(base64_encode(b"hunter2", false), "aHVudGVyMg");
+        assert_eq!(base64_encode(b"", true), "");
+    }
+
+    #[test]
+    fn test_masks_value_and_encodings() {
+        let masker = SecretMasker::new();
+        masker.add("p@ss word!");
+
+        assert_eq!(masker.mask("token=p@ss word! end"), "token=**** end");
+        assert_eq!(masker.mask("b64 cEBzcyB3b3JkIQ=="), "b64 ****");
+        assert_eq!(masker.mask("url p%40ss%20word%21"), "url ****");
+        assert_eq!(masker.mask("nothing here"), "nothing here");
+    }
+
+    #[test]
+    fn test_masks_lines_of_multiline_secret() {
+        let masker = SecretMasker::new();
+        masker.add("-----BEGIN KEY-----\nAAAAB3NzaC1yc2E\n-----END KEY-----");
+
+        assert_eq!(masker.mask("line: AAAAB3NzaC1yc2E"), "line: ****");
+    }
+
+    #[test]
+    fn test_mask_bytes_keeps_binary_output() {
+        let masker = SecretMasker::new();
+        masker.add("s3cr3t-token");
+
+        let binary = [0xff, 0xfe, 0x00, b'a', 0x80];
+        assert_eq!(masker.mask_bytes(&binary), binary);
+
+        let mut output = vec![0xff, b' '];
+        output.extend_from_slice(b"s3cr3t-token");
+        output.extend_from_slice(&[b' ', 0x80]);
+        assert_eq!(masker.mask_bytes(&output), b"\xff **** \x80");
+    }
+
+    #[test]
+    fn test_short_values_are_not_masked() {
+        let masker = SecretMasker::new();
+        masker.add("ab");
+
+        assert!(masker.is_empty());
+        assert_eq!(masker.mask("abc"), "abc");
+    }
+
+    #[test]
+    fn test_environment_secrets_shared_by_clones() {
+        let mut environment = Environment::new();
+        environment.insert("USER", "deploy");
+        environment.insert_secret("TOKEN", "s3cr3t-token");
+
+        let masker = SecretMasker::from_environment(&environment);
+        let clone = masker.clone();
+        clone.add("another-secret");
+
+        assert_eq!(
+            masker.mask("deploy s3cr3t-token another-secret"),
+            "deploy **** ****"
+        );
+    }
+}
@@ -6,6 +6,8 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::expression::{Expression, ExpressionContext, ExpressionError};
+
 /// Matrix configuration for parallel execution
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
@@ -127,6 +129,33 @@ impl MatrixConfig {
         self.generate_combinations().len()
     }
 
+    /// Returns the configuration with expression axes evaluated
+    ///
+    /// An expression axis becomes a values axis: a list gives one value per
+    /// item, anything else a single value.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first expression that fails to parse or evaluate.
+    pub fn resolve_expressions(
+        &self,
+        context: &ExpressionContext,
+    ) -> Result<Self, ExpressionError> {
+        let mut resolved = self.clone();
+        for axis in &mut resolved.axes {
+            if let MatrixAxis::Expression { name, expression } = axis {
+                let values = Expression::parse(expression)?
+                    .evaluate(context)?
+                    .into_strings();
+                *axis = MatrixAxis::Values {
+                    name: std::mem::take(name),
+                    values,
+                };
+            }
+        }
+        Ok(resolved)
+    }
+
     /// Checks if the matrix configuration is valid
     pub fn validate(&self) -> Result<(), crate::ValidationError> {
         if self.axes.is_empty() {
@@ -161,7 +190,15 @@ impl MatrixConfig {
                         });
                     }
                 }
-                _ => {}
+                MatrixAxis::Expression { expression, .. } => {
+                    Expression::parse(expression).map_err(|e| {
+                        crate::ValidationError::InvalidExpression {
+                            expression: expression.clone(),
+                            reason: e.to_string(),
+                        }
+                    })?;
+                }
+                MatrixAxis::File { .. } => {}
             }
         }
 
@@ -399,6 +436,54 @@ mod tests {
         assert_eq!(cells[2].values["version"], "3");
     }
 
+    #[test]
+    fn test_matrix_expression_axis() {
+        let config = MatrixConfig {
+            axes: vec![
+                MatrixAxis::Expression {
+                    name: "os".to_string(),
+                    expression: "split(env.PLATFORMS, ',')".to_string(),
+                },
+                MatrixAxis::Expression {
+                    name: "version".to_string(),
+                    expression: "range(1, params.MAX_VERSION)".to_string(),
+                },
+            ],
+            exclude: Vec::new(),
+            agent: None,
+            name_template: None,
+        };
+        assert!(config.validate().is_ok());
+
+        let context = ExpressionContext::new()
+            .with_env("PLATFORMS", "linux,macos")
+            .with_param("MAX_VERSION", "3");
+        let resolved = config.resolve_expressions(&context).unwrap();
+        assert_eq!(resolved.cell_count(), 6);
+        assert_eq!(
+            resolved.axes[0],
+            MatrixAxis::Values {
+                name: "os".to_string(),
+                values: vec!["linux".to_string(), "macos".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_matrix_validation_invalid_expression() {
+        let config = MatrixConfig {
+            axes: vec![MatrixAxis::Expression {
+                name: "os".to_string(),
+                expression: "split(env.PLATFORMS ',')".to_string(),
+            }],
+            exclude: Vec::new(),
+            agent: None,
+            name_template: None,
+        };
+        let error = config.validate().unwrap_err();
+        assert!(error.to_string().contains("column 21"));
+    }
+
     #[test]
     fn test_matrix_validation_empty_axes() {
         let config = MatrixConfig {
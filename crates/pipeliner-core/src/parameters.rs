@@ -199,6 +199,25 @@ impl Parameter {
         }
     }
 
+    /// Returns the value used when a build does not provide one
+    ///
+    /// A choice defaults to its first choice.
+    #[must_use]
+    pub fn default_value(&self) -> Option<String> {
+        match self {
+            Parameter::String { default_value, .. } | Parameter::Text { default_value, .. } => {
+                default_value.clone()
+            }
+            Parameter::Boolean { default_value, .. } => Some(default_value.to_string()),
+            Parameter::Choice {
+                choices,
+                default_choice,
+                ..
+            } => choices.get(default_choice.unwrap_or(0)).cloned(),
+            Parameter::Password { .. } | Parameter::File { .. } | Parameter::Run { .. } => None,
+        }
+    }
+
     /// Returns the parameter type
     #[must_use]
     pub fn parameter_type(&self) -> ParameterType {
@@ -290,6 +309,30 @@ mod tests {
         assert_eq!(params.len(), 1);
     }
 
+    #[test]
+    fn test_parameter_default_values() {
+        let choice = Parameter::Choice {
+            name: "ENV".to_string(),
+            description: String::new(),
+            choices: vec!["staging".to_string(), "production".to_string()],
+            default_choice: None,
+        };
+        assert_eq!(choice.default_value().as_deref(), Some("staging"));
+
+        let flag = Parameter::Boolean {
+            name: "DEPLOY".to_string(),
+            description: String::new(),
+            default_value: true,
+        };
+        assert_eq!(flag.default_value().as_deref(), Some("true"));
+
+        let password = Parameter::Password {
+            name: "TOKEN".to_string(),
+            description: String::new(),
+        };
+        assert_eq!(password.default_value(), None);
+    }
+
     #[test]
     fn test_parameters_get() {
         let mut params = Parameters::new();
@@ -6,6 +6,9 @@ use std::time::Duration;
 
 use crate::agent::AgentType;
 use crate::environment::Environment;
+use crate::expression::{
+    Expression, ExpressionContext, ExpressionError, matches_glob, matches_regex,
+};
 use crate::matrix::MatrixConfig;
 use crate::options::PipelineOptions;
 use crate::parameters::Parameters;
@@ -137,7 +140,7 @@ pub struct StageOptions {
 }
 
 /// When condition for conditional stage execution
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct WhenCondition {
     /// Branch condition
@@ -237,6 +240,10 @@ pub struct PostCondition {
     #[serde(default)]
     pub changed: Vec<Step>,
 
+    /// Run when the build was aborted
+    #[serde(default)]
+    pub aborted: Vec<Step>,
+
     /// Cleanup (always runs last)
     #[serde(default)]
     pub cleanup: Vec<Step>,
@@ -302,6 +309,12 @@ pub enum StepType {
         /// Parameters to request
         #[serde(default)]
         parameters: Vec<StepParameter>,
+        /// Input identifier, derived from the message when absent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        /// Comma-separated list of users allowed to answer
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        submitter: Option<String>,
     },
 
     /// Change directory
@@ -330,6 +343,14 @@ pub enum StepType {
         fingerprint: bool,
     },
 
+    /// Bind credentials to environment variables or files while steps run
+    WithCredentials {
+        /// Credentials to bind
+        bindings: Vec<CredentialsBinding>,
+        /// Steps that see the bound credentials
+        steps: Vec<Step>,
+    },
+
     /// Custom step (from plugin)
     Custom {
         /// Step name
@@ -339,6 +360,72 @@ pub enum StepType {
     },
 }
 
+/// How a `withCredentials` step exposes credentials
+///
+/// Values are bound to environment variables for the nested steps. Keys and
+/// secret files are written to a private temporary directory and the
+/// variable holds the file path. Everything is removed once the steps end.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum CredentialsBinding {
+    /// Username and password into two variables
+    #[serde(rename_all = "camelCase")]
+    UsernamePassword {
+        /// Credentials id
+        credentials_id: String,
+        /// Variable holding the username
+        username_variable: String,
+        /// Variable holding the password
+        password_variable: String,
+    },
+
+    /// Secret text into a variable
+    #[serde(rename_all = "camelCase")]
+    String {
+        /// Credentials id
+        credentials_id: String,
+        /// Variable holding the secret
+        variable: String,
+    },
+
+    /// SSH key into a file
+    #[serde(rename_all = "camelCase")]
+    SshUserPrivateKey {
+        /// Credentials id
+        credentials_id: String,
+        /// Variable holding the path of the key file
+        key_file_variable: String,
+        /// Variable holding the username
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        username_variable: Option<String>,
+        /// Variable holding the passphrase
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        passphrase_variable: Option<String>,
+    },
+
+    /// Secret file into a file
+    #[serde(rename_all = "camelCase")]
+    File {
+        /// Credentials id
+        credentials_id: String,
+        /// Variable holding the path of the file
+        variable: String,
+    },
+}
+
+impl CredentialsBinding {
+    /// Returns the id of the bound credentials
+    #[must_use]
+    pub fn credentials_id(&self) -> &str {
+        match self {
+            Self::UsernamePassword { credentials_id, .. }
+            | Self::String { credentials_id, .. }
+            | Self::SshUserPrivateKey { credentials_id, .. }
+            | Self::File { credentials_id, .. } => credentials_id,
+        }
+    }
+}
+
 /// Step parameter definition
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "camelCase")]
@@ -495,6 +582,10 @@ impl Validate for Pipeline {
             return Err(ValidationError::EmptyStages);
         }
 
+        if let Some(agent) = &self.agent {
+            agent.validate()?;
+        }
+
         for stage in &self.stages {
             stage.validate()?;
         }
@@ -525,6 +616,38 @@ impl Validate for Stage {
             });
         }
 
+        if let Some(agent) = &self.agent {
+            agent.validate()?;
+        }
+
+        if let Some(when) = &self.when {
+            when.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Validate for WhenCondition {
+    type Error = ValidationError;
+
+    fn validate(&self) -> Result<(), Self::Error> {
+        if let Some(source) = &self.expression {
+            Expression::parse(source).map_err(|e| ValidationError::InvalidExpression {
+                expression: source.clone(),
+                reason: e.to_string(),
+            })?;
+        }
+
+        for condition in self
+            .all_of
+            .iter()
+            .chain(&self.any_of)
+            .chain(self.not.as_deref())
+        {
+            condition.validate()?;
+        }
+
         Ok(())
     }
 }
@@ -587,6 +710,98 @@ impl Default for Stage {
     }
 }
 
+impl WhenCondition {
+    /// Returns true if the stage should run
+    ///
+    /// Every condition that is set must hold. Branch conditions match
+    /// `BRANCH_NAME` and tag conditions `TAG_NAME`; without the variable
+    /// they do not hold.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an expression, pattern or comparator is invalid.
+    pub fn evaluate(&self, context: &ExpressionContext) -> Result<bool, ExpressionError> {
+        if let Some(branch) = &self.branch {
+            let name = context.env.get("BRANCH_NAME");
+            if !compare(name, &branch.pattern, &branch.comparator)? {
+                return Ok(false);
+            }
+        }
+
+        if let Some(tag) = &self.tag {
+            let name = context.env.get("TAG_NAME");
+            if !compare(name, &tag.pattern, &tag.comparator)? {
+                return Ok(false);
+            }
+        }
+
+        if let Some(condition) = &self.environment {
+            let holds = match (context.env.get(&condition.name), &condition.value) {
+                (None, _) => false,
+                (Some(value), Some(expected)) => value == expected,
+                (Some(value), None) => match &condition.pattern {
+                    Some(pattern) => matches_regex(value, pattern)?,
+                    None => !value.is_empty(),
+                },
+            };
+            if !holds {
+                return Ok(false);
+            }
+        }
+
+        if let Some(source) = &self.expression
+            && !Expression::parse(source)?.is_true(context)?
+        {
+            return Ok(false);
+        }
+
+        for condition in &self.all_of {
+            if !condition.evaluate(context)? {
+                return Ok(false);
+            }
+        }
+
+        if !self.any_of.is_empty() {
+            let mut any = false;
+            for condition in &self.any_of {
+                if condition.evaluate(context)? {
+                    any = true;
+                    break;
+                }
+            }
+            if !any {
+                return Ok(false);
+            }
+        }
+
+        match &self.not {
+            Some(condition) => Ok(!condition.evaluate(context)?),
+            None => Ok(true),
+        }
+    }
+}
+
+/// Matches a branch or tag name with a `GLOB`, `REGEXP` or `EQUALS`
+/// comparator
+fn compare(
+    name: Option<&String>,
+    pattern: &str,
+    comparator: &str,
+) -> Result<bool, ExpressionError> {
+    let Some(name) = name else {
+        return Ok(false);
+    };
+    match comparator.to_ascii_uppercase().as_str() {
+        "GLOB" => Ok(matches_glob(name, pattern)),
+        "REGEXP" => matches_regex(name, pattern),
+        "EQUALS" => Ok(name == pattern),
+        _ => Err(ExpressionError::new(
+            1,
+            format!("unknown comparator '{comparator}'"),
+        )),
+    }
+}
+
 impl Step {
     /// Creates a new shell step
     #[must_use]
@@ -614,6 +829,38 @@ impl Step {
         }
     }
 
+    /// Creates an approval input that only the given submitters may answer
+    #[must_use]
+    pub fn approval(
+        id: impl Into<String>,
+        message: impl Into<String>,
+        submitter: impl Into<String>,
+    ) -> Self {
+        Self {
+            step_type: StepType::Input {
+                message: message.into(),
+                default: None,
+                parameters: Vec::new(),
+                id: Some(id.into()),
+                submitter: Some(submitter.into()),
+            },
+            name: None,
+            timeout: None,
+            retry: None,
+        }
+    }
+
+    /// Creates a step running `steps` with credentials bound
+    #[must_use]
+    pub fn credentials(bindings: Vec<CredentialsBinding>, steps: Vec<Step>) -> Self {
+        Self {
+            step_type: StepType::WithCredentials { bindings, steps },
+            name: None,
+            timeout: None,
+            retry: None,
+        }
+    }
+
     /// Sets the step name
     #[must_use]
     pub fn with_name(mut self, name: impl Into<String>) -> Self {
@@ -641,6 +888,71 @@ mod tests {
     use super::*;
     use crate::agent::AgentType;
 
+    #[test]
+    fn test_when_condition_evaluation() {
+        let context = ExpressionContext::new()
+            .with_env("BRANCH_NAME", "release/1.2")
+            .with_param("DEPLOY", "true");
+
+        let when = WhenCondition {
+            branch: Some(BranchCondition {
+                pattern: "release/*".to_string(),
+                comparator: "GLOB".to_string(),
+            }),
+            expression: Some("params.DEPLOY == true".to_string()),
+            ..WhenCondition::default()
+        };
+        assert!(when.evaluate(&context).unwrap());
+
+        let negated = WhenCondition {
+            not: Some(Box::new(when)),
+            ..WhenCondition::default()
+        };
+        assert!(!negated.evaluate(&context).unwrap());
+
+        let tagged = WhenCondition {
+            tag: Some(TagCondition {
+                pattern: "v*".to_string(),
+                comparator: "GLOB".to_string(),
+            }),
+            ..WhenCondition::default()
+        };
+        assert!(!tagged.evaluate(&context).unwrap());
+
+        let any = WhenCondition {
+            any_of: vec![
+                tagged,
+                WhenCondition {
+                    environment: Some(EnvironmentCondition {
+                        name: "BRANCH_NAME".to_string(),
+                        value: None,
+                        pattern: Some("release/\\d+\\.\\d+".to_string()),
+                    }),
+                    ..WhenCondition::default()
+                },
+            ],
+            ..WhenCondition::default()
+        };
+        assert!(any.evaluate(&context).unwrap());
+    }
+
+    #[test]
+    fn test_stage_validation_rejects_invalid_expression() {
+        let mut stage = Stage::new("Deploy");
+        stage.steps.push(Step::shell("./deploy.sh"));
+        stage.when = Some(WhenCondition {
+            all_of: vec![WhenCondition {
+                expression: Some("env.BRANCH == ".to_string()),
+                ..WhenCondition::default()
+            }],
+            ..WhenCondition::default()
+        });
+
+        let error = stage.validate().unwrap_err();
+        assert!(matches!(error, ValidationError::InvalidExpression { .. }));
+        assert!(error.to_string().contains("at column 15"));
+    }
+
     #[test]
     fn test_pipeline_creation() {
         let pipeline = Pipeline::new()
@@ -678,4 +990,32 @@ mod tests {
         };
         assert!(matches!(echo, StepType::Echo { .. }));
     }
+
+    #[test]
+    fn test_with_credentials_step_serialization() {
+        let json = r#"{
+            "type": "withCredentials",
+            "bindings": [
+                {"type": "usernamePassword", "credentialsId": "registry",
+                 "usernameVariable": "USER", "passwordVariable": "PASS"},
+                {"type": "sshUserPrivateKey", "credentialsId": "deploy-key",
+                 "keyFileVariable": "KEY_FILE"}
+            ],
+            "steps": [{"type": "shell", "command": "make deploy"}]
+        }"#;
+        let step_type: StepType = serde_json::from_str(json).unwrap();
+
+        let StepType::WithCredentials { bindings, steps } = step_type else {
+            panic!("expected withCredentials");
+        };
+        assert_eq!(bindings[0].credentials_id(), "registry");
+        assert!(matches!(
+            &bindings[1],
+            CredentialsBinding::SshUserPrivateKey {
+                username_variable: None,
+                ..
+            }
+        ));
+        assert_eq!(steps.len(), 1);
+    }
 }
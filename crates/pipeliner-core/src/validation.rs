@@ -81,6 +81,15 @@ pub enum ValidationError {
         reason: String,
     },
 
+    /// Expression that does not parse
+    #[error("invalid expression '{expression}': {reason}")]
+    InvalidExpression {
+        /// Expression source
+        expression: String,
+        /// Parse error, with its column
+        reason: String,
+    },
+
     /// Missing required option
     #[error("missing required option: {option}")]
     MissingOption {
@@ -1,16 +1,30 @@
 //! Event bus implementation.
 //!
 //! Provides an in-memory event bus for pub/sub communication.
+//!
+//! [`LocalEventBus`] delivers every published event to each subscribed
+//! handler, in publication order, through a queue and task of the
+//! handler's own (see [`worker`]). A slow handler only holds up its own
+//! queue; what publishing does once that queue is full is the bus's
+//! [`Backpressure`]. [`Subscription`]s receive the events as a stream
+//! instead and count the events they missed by falling behind.
+
+pub mod worker;
 
 use async_trait::async_trait;
 use dashmap::DashMap;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
 use uuid::Uuid;
 
 use crate::types::{AnyEvent, EventEnvelope};
 
+use worker::Worker;
+pub use worker::{DeadLetter, HandlerStats};
+
 /// Event handler trait
 #[async_trait::async_trait]
 pub trait EventHandler: Send + Sync {
@@ -32,19 +46,59 @@ pub trait EventBus: Send + Sync {
     async fn unsubscribe(&self, handler_id: &Uuid) -> Result<(), Self::Error>;
 }
 
+/// What publishing does when a handler's queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backpressure {
+    /// Drop the event for that handler
+    Drop,
+    /// Wait until the handler's queue has room
+    #[default]
+    Block,
+    /// Queue the event beyond the capacity, without bound
+    Spill,
+}
+
+/// Local event bus configuration
+#[derive(Debug, Clone)]
+pub struct EventBusConfig {
+    /// Events queued per handler before backpressure applies
+    pub queue_capacity: usize,
+    /// What publishing does when a handler's queue is full
+    pub backpressure: Backpressure,
+    /// Deliveries attempted before an event becomes a dead letter
+    pub max_attempts: usize,
+    /// Delay between delivery attempts
+    pub retry_delay: Duration,
+    /// Events kept for subscriptions that fall behind
+    pub subscription_capacity: usize,
+}
+
+impl Default for EventBusConfig {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 1024,
+            backpressure: Backpressure::Block,
+            max_attempts: 3,
+            retry_delay: Duration::from_millis(100),
+            subscription_capacity: 1024,
+        }
+    }
+}
+
 /// Local in-memory event bus
+///
+/// Handlers must be subscribed from within a Tokio runtime, which runs
+/// their workers.
 pub struct LocalEventBus {
+    config: EventBusConfig,
     sender: broadcast::Sender<Arc<EventEnvelope>>,
-    handlers: DashMap<Uuid, Arc<dyn EventHandler>>,
+    handlers: DashMap<Uuid, Arc<Worker>>,
+    dead_letters: Arc<Mutex<Vec<DeadLetter>>>,
 }
 
 impl Default for LocalEventBus {
     fn default() -> Self {
-        let (sender, _) = broadcast::channel(1024);
-        Self {
-            sender,
-            handlers: DashMap::new(),
-        }
+        Self::with_config(EventBusConfig::default())
     }
 }
 
@@ -53,11 +107,44 @@ impl LocalEventBus {
         Self::default()
     }
 
+    /// Creates a bus with the given queueing and retry behavior
+    pub fn with_config(config: EventBusConfig) -> Self {
+        let (sender, _) = broadcast::channel(config.subscription_capacity.max(1));
+        Self {
+            config,
+            sender,
+            handlers: DashMap::new(),
+            dead_letters: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Subscribes a handler and returns the ID to unsubscribe it with
     pub fn subscribe_with_id(&self, handler: Arc<dyn EventHandler>) -> Uuid {
         let id = Uuid::new_v4();
-        self.handlers.insert(id, handler);
+        let worker = Worker::spawn(id, handler, &self.config, Arc::clone(&self.dead_letters));
+        self.handlers.insert(id, Arc::new(worker));
         id
     }
+
+    /// Returns a stream of the events published from now on
+    pub fn subscription(&self) -> Subscription {
+        Subscription::new(self.sender.subscribe())
+    }
+
+    /// Returns the delivery counters of a handler
+    pub fn handler_stats(&self, handler_id: &Uuid) -> Option<HandlerStats> {
+        self.handlers.get(handler_id).map(|worker| worker.stats())
+    }
+
+    /// Returns the events handlers failed to process
+    pub fn dead_letters(&self) -> Vec<DeadLetter> {
+        self.dead_letters.lock().unwrap().clone()
+    }
+
+    /// Removes and returns the events handlers failed to process
+    pub fn take_dead_letters(&self) -> Vec<DeadLetter> {
+        std::mem::take(&mut *self.dead_letters.lock().unwrap())
+    }
 }
 
 #[async_trait::async_trait]
@@ -66,7 +153,18 @@ impl EventBus for LocalEventBus {
 
     async fn publish(&self, event: EventEnvelope) -> Result<(), Self::Error> {
         let event = Arc::new(event);
-        let _ = self.sender.send(event);
+        // No subscription listening is not an error
+        let _ = self.sender.send(Arc::clone(&event));
+
+        // Not holding map entries while queueing may wait
+        let workers: Vec<_> = self
+            .handlers
+            .iter()
+            .map(|entry| Arc::clone(entry.value()))
+            .collect();
+        for worker in workers {
+            worker.enqueue(Arc::clone(&event)).await;
+        }
         Ok(())
     }
 
@@ -75,9 +173,12 @@ impl EventBus for LocalEventBus {
         Ok(())
     }
 
+    /// Unsubscribes a handler; events already queued are still delivered
     async fn unsubscribe(&self, handler_id: &Uuid) -> Result<(), Self::Error> {
-        self.handlers.remove(handler_id);
-        Ok(())
+        self.handlers
+            .remove(handler_id)
+            .map(|_| ())
+            .ok_or(EventBusError::HandlerNotFound(*handler_id))
     }
 }
 
@@ -92,20 +193,41 @@ pub enum EventBusError {
 }
 
 /// Subscription for receiving events
+///
+/// A subscription that falls more than the bus's subscription capacity
+/// behind skips the oldest events and counts them as missed.
 #[derive(Debug)]
 pub struct Subscription {
     receiver: broadcast::Receiver<Arc<EventEnvelope>>,
+    missed: u64,
 }
 
 impl Subscription {
     pub fn new(receiver: broadcast::Receiver<Arc<EventEnvelope>>) -> Self {
-        Self { receiver }
+        Self {
+            receiver,
+            missed: 0,
+        }
+    }
+
+    /// Waits for the next event, or returns `None` once the bus is gone
+    pub async fn recv(&mut self) -> Option<Arc<EventEnvelope>> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("Subscription fell behind, missed {} events", missed);
+                    self.missed += missed;
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
     }
 
-    pub async fn recv(
-        &mut self,
-    ) -> Result<Arc<EventEnvelope>, tokio::sync::broadcast::error::RecvError> {
-        self.receiver.recv().await
+    /// Returns the number of events skipped because the subscription fell
+    /// behind
+    pub fn missed(&self) -> u64 {
+        self.missed
     }
 }
 
@@ -131,4 +253,186 @@ mod tests {
         let result = bus.publish(event).await;
         assert!(result.is_ok());
     }
+
+    fn created(name: &str) -> EventEnvelope {
+        EventEnvelope::new(
+            AnyEvent::Pipeline(crate::types::PipelineEvent::Created {
+                pipeline_id: Uuid::new_v4(),
+                name: name.to_string(),
+            }),
+            EventMetadata::new("test"),
+        )
+    }
+
+    fn name(event: &EventEnvelope) -> String {
+        match &event.event {
+            AnyEvent::Pipeline(crate::types::PipelineEvent::Created { name, .. }) => name.clone(),
+            other => other.event_type().to_string(),
+        }
+    }
+
+    /// Handler recording event names, optionally waiting for a permit or
+    /// panicking on each event
+    #[derive(Default)]
+    struct Recorder {
+        names: Mutex<Vec<String>>,
+        gate: Option<Arc<tokio::sync::Semaphore>>,
+        panics: bool,
+    }
+
+    impl EventHandler for Recorder {
+        fn handle<'a>(
+            &'a self,
+            event: &'a EventEnvelope,
+        ) -> Pin<Box<dyn std::future::Future<Output = ()> + Send + 'a>> {
+            Box::pin(async move {
+                if let Some(gate) = &self.gate {
+                    gate.acquire().await.unwrap().forget();
+                }
+                assert!(!self.panics, "audit log unavailable");
+                self.names.lock().unwrap().push(name(event));
+            })
+        }
+    }
+
+    impl Recorder {
+        fn names(&self) -> Vec<String> {
+            self.names.lock().unwrap().clone()
+        }
+    }
+
+    async fn wait_until(condition: impl Fn() -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("condition not reached");
+    }
+
+    #[tokio::test]
+    async fn test_handlers_receive_events_in_order() {
+        let bus = LocalEventBus::new();
+        let audit = Arc::new(Recorder::default());
+        let id = bus.subscribe_with_id(audit.clone());
+
+        let expected: Vec<String> = (0..50).map(|i| format!("pipeline-{i}")).collect();
+        for name in &expected {
+            bus.publish(created(name)).await.unwrap();
+        }
+
+        wait_until(|| audit.names().len() == expected.len()).await;
+        assert_eq!(audit.names(), expected);
+        assert_eq!(bus.handler_stats(&id).unwrap().delivered, 50);
+
+        bus.unsubscribe(&id).await.unwrap();
+        assert!(matches!(
+            bus.unsubscribe(&id).await,
+            Err(EventBusError::HandlerNotFound(_))
+        ));
+        bus.publish(created("after")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(audit.names().len(), 50);
+    }
+
+    #[tokio::test]
+    async fn test_panicking_handler_is_isolated_and_dead_lettered() {
+        let bus = LocalEventBus::with_config(EventBusConfig {
+            max_attempts: 2,
+            retry_delay: Duration::from_millis(1),
+            ..EventBusConfig::default()
+        });
+        let failing = bus.subscribe_with_id(Arc::new(Recorder {
+            panics: true,
+            ..Recorder::default()
+        }));
+        let audit = Arc::new(Recorder::default());
+        bus.subscribe_with_id(audit.clone());
+
+        bus.publish(created("first")).await.unwrap();
+        bus.publish(created("second")).await.unwrap();
+
+        wait_until(|| bus.dead_letters().len() == 2).await;
+        assert_eq!(audit.names(), vec!["first", "second"]);
+
+        let dead_letters = bus.take_dead_letters();
+        assert_eq!(dead_letters[0].handler_id, failing);
+        assert_eq!(dead_letters[0].attempts, 2);
+        assert_eq!(name(&dead_letters[0].event), "first");
+        assert!(dead_letters[0].error.contains("audit log unavailable"));
+        assert!(bus.dead_letters().is_empty());
+
+        let stats = bus.handler_stats(&failing).unwrap();
+        assert_eq!((stats.delivered, stats.failed), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn test_backpressure_policies() {
+        async fn publish_while_blocked(
+            backpressure: Backpressure,
+        ) -> (Arc<Recorder>, LocalEventBus, Uuid) {
+            let bus = LocalEventBus::with_config(EventBusConfig {
+                queue_capacity: 1,
+                backpressure,
+                ..EventBusConfig::default()
+            });
+            let gate = Arc::new(tokio::sync::Semaphore::new(0));
+            let slow = Arc::new(Recorder {
+                gate: Some(gate.clone()),
+                ..Recorder::default()
+            });
+            let id = bus.subscribe_with_id(slow.clone());
+
+            // The first event holds the only slot while it is handled
+            bus.publish(created("0")).await.unwrap();
+            for i in 1..4 {
+                let publish = bus.publish(created(&i.to_string()));
+                if tokio::time::timeout(Duration::from_millis(50), publish)
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            gate.add_permits(10);
+            (slow, bus, id)
+        }
+
+        let (slow, bus, id) = publish_while_blocked(Backpressure::Drop).await;
+        wait_until(|| slow.names().len() == 1).await;
+        assert_eq!(bus.handler_stats(&id).unwrap().dropped, 3);
+
+        let (slow, bus, id) = publish_while_blocked(Backpressure::Spill).await;
+        wait_until(|| slow.names().len() == 4).await;
+        assert_eq!(slow.names(), vec!["0", "1", "2", "3"]);
+        assert_eq!(bus.handler_stats(&id).unwrap().spilled, 3);
+
+        // Blocking gives up on the second event, which is then not queued
+        let (slow, _bus, _) = publish_while_blocked(Backpressure::Block).await;
+        wait_until(|| slow.names().len() == 1).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(slow.names(), vec!["0"]);
+    }
+
+    #[tokio::test]
+    async fn test_subscription_reports_missed_events() {
+        let bus = LocalEventBus::with_config(EventBusConfig {
+            subscription_capacity: 2,
+            ..EventBusConfig::default()
+        });
+        let mut subscription = bus.subscription();
+
+        for i in 0..5 {
+            bus.publish(created(&i.to_string())).await.unwrap();
+        }
+
+        let event = subscription.recv().await.unwrap();
+        assert_eq!(name(&event), "3");
+        assert_eq!(subscription.missed(), 3);
+
+        drop(bus);
+        assert_eq!(name(&subscription.recv().await.unwrap()), "4");
+        assert!(subscription.recv().await.is_none());
+    }
 }
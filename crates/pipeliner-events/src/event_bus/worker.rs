@@ -0,0 +1,202 @@
+//! Delivery of published events to one handler.
+//!
+//! Every subscribed handler has a worker: a task reading the handler's
+//! queue in order. Each delivery runs in a task of its own, so a panicking
+//! handler fails that delivery without taking the worker or the bus down.
+//! Failed deliveries are retried and end up in the dead letters once the
+//! attempts are exhausted.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, mpsc};
+use tracing::warn;
+use uuid::Uuid;
+
+use super::{Backpressure, EventBusConfig, EventHandler};
+use crate::types::EventEnvelope;
+
+/// An event a handler could not process
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    /// Handler that failed
+    pub handler_id: Uuid,
+    /// Event that was not delivered
+    pub event: Arc<EventEnvelope>,
+    /// Number of deliveries attempted
+    pub attempts: usize,
+    /// Why the last attempt failed
+    pub error: String,
+    /// When the event was given up on
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Delivery counters of a handler
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HandlerStats {
+    /// Events handled successfully
+    pub delivered: u64,
+    /// Events moved to the dead letters
+    pub failed: u64,
+    /// Events dropped because the queue was full
+    pub dropped: u64,
+    /// Events queued beyond the queue capacity
+    pub spilled: u64,
+}
+
+/// Counters shared by a worker and the bus
+#[derive(Debug, Default)]
+struct Counters {
+    delivered: AtomicU64,
+    failed: AtomicU64,
+    dropped: AtomicU64,
+    spilled: AtomicU64,
+}
+
+/// A queued event, holding a slot of the queue capacity unless it spilled
+struct Queued {
+    event: Arc<EventEnvelope>,
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+/// Queue and task delivering events to one handler
+pub(super) struct Worker {
+    sender: mpsc::UnboundedSender<Queued>,
+    slots: Arc<Semaphore>,
+    backpressure: Backpressure,
+    counters: Arc<Counters>,
+}
+
+impl Worker {
+    /// Starts the task delivering events to `handler`
+    ///
+    /// The task ends once the worker is dropped and its queue is drained.
+    pub(super) fn spawn(
+        id: Uuid,
+        handler: Arc<dyn EventHandler>,
+        config: &EventBusConfig,
+        dead_letters: Arc<Mutex<Vec<DeadLetter>>>,
+    ) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Queued>();
+        let counters = Arc::new(Counters::default());
+        let delivery = Delivery {
+            id,
+            handler,
+            max_attempts: config.max_attempts.max(1),
+            retry_delay: config.retry_delay,
+            counters: Arc::clone(&counters),
+            dead_letters,
+        };
+
+        tokio::spawn(async move {
+            while let Some(queued) = receiver.recv().await {
+                delivery.deliver(&queued.event).await;
+            }
+        });
+
+        Self {
+            sender,
+            slots: Arc::new(Semaphore::new(config.queue_capacity.max(1))),
+            backpressure: config.backpressure,
+            counters,
+        }
+    }
+
+    /// Queues an event according to the backpressure policy
+    pub(super) async fn enqueue(&self, event: Arc<EventEnvelope>) {
+        let slot = match self.backpressure {
+            Backpressure::Block => Arc::clone(&self.slots).acquire_owned().await.ok(),
+            Backpressure::Drop => match Arc::clone(&self.slots).try_acquire_owned() {
+                Ok(slot) => Some(slot),
+                Err(_) => {
+                    self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+            },
+            Backpressure::Spill => {
+                let slot = Arc::clone(&self.slots).try_acquire_owned().ok();
+                if slot.is_none() {
+                    self.counters.spilled.fetch_add(1, Ordering::Relaxed);
+                }
+                slot
+            }
+        };
+
+        // Sending only fails once the task is gone, when the bus is dropped
+        let _ = self.sender.send(Queued { event, _slot: slot });
+    }
+
+    /// Returns the delivery counters
+    pub(super) fn stats(&self) -> HandlerStats {
+        HandlerStats {
+            delivered: self.counters.delivered.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+            spilled: self.counters.spilled.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Delivers events to a handler, retrying failed deliveries
+struct Delivery {
+    id: Uuid,
+    handler: Arc<dyn EventHandler>,
+    max_attempts: usize,
+    retry_delay: std::time::Duration,
+    counters: Arc<Counters>,
+    dead_letters: Arc<Mutex<Vec<DeadLetter>>>,
+}
+
+impl Delivery {
+    async fn deliver(&self, event: &Arc<EventEnvelope>) {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let handler = Arc::clone(&self.handler);
+            let envelope = Arc::clone(event);
+            let outcome = tokio::spawn(async move { handler.handle(&envelope).await }).await;
+
+            let error = match outcome {
+                Ok(()) => {
+                    self.counters.delivered.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                Err(e) if e.is_panic() => panic_message(e.into_panic()),
+                Err(e) => e.to_string(),
+            };
+
+            warn!(
+                "Handler {} failed on {} (attempt {}/{}): {}",
+                self.id,
+                event.event.event_type(),
+                attempts,
+                self.max_attempts,
+                error
+            );
+            if attempts >= self.max_attempts {
+                self.counters.failed.fetch_add(1, Ordering::Relaxed);
+                self.dead_letters.lock().unwrap().push(DeadLetter {
+                    handler_id: self.id,
+                    event: Arc::clone(event),
+                    attempts,
+                    error,
+                    failed_at: Utc::now(),
+                });
+                return;
+            }
+            tokio::time::sleep(self.retry_delay).await;
+        }
+    }
+}
+
+/// Returns the message a handler panicked with
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "handler panicked".to_string()
+    }
+}
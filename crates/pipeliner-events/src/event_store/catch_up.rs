@@ -0,0 +1,214 @@
+//! Catch-up subscriptions.
+//!
+//! A [`CatchUpSubscription`] replays the events of a store after a
+//! checkpoint, then follows the events appended afterwards. Events on the
+//! bus only signal that the store has grown: what is delivered is always
+//! read from the store by position, so the switch from history to live
+//! events neither skips nor repeats an event. This relies on events being
+//! appended to the store before they are published, as the executor's
+//! event listener does.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use super::in_memory::EventStore;
+use super::query::{EventQuery, RecordedEvent};
+use crate::event_bus::Subscription;
+
+/// Number of events read from the store at a time
+const DEFAULT_BATCH_SIZE: usize = 256;
+
+/// Subscription replaying stored events, then following new ones
+pub struct CatchUpSubscription<S: EventStore> {
+    store: Arc<S>,
+    live: Subscription,
+    filter: EventQuery,
+    batch_size: usize,
+    checkpoint: u64,
+    read_position: u64,
+    buffer: VecDeque<RecordedEvent>,
+}
+
+impl<S: EventStore> CatchUpSubscription<S> {
+    /// Subscribes to the events after the `checkpoint` position
+    ///
+    /// `live` is a subscription to the bus the store's events are published
+    /// on. A checkpoint of 0 replays the whole store.
+    pub fn new(store: Arc<S>, live: Subscription, checkpoint: u64) -> Self {
+        Self {
+            store,
+            live,
+            filter: EventQuery::default(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            checkpoint,
+            read_position: checkpoint,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    /// Only delivers events matching a query
+    ///
+    /// The query's position and limit are not used.
+    pub fn with_filter(mut self, filter: EventQuery) -> Self {
+        self.filter = EventQuery {
+            from_position: 0,
+            limit: None,
+            ..filter
+        };
+        self
+    }
+
+    /// Sets the number of events read from the store at a time
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Returns the position to resume from after a restart
+    ///
+    /// Every event up to the checkpoint has been delivered or did not
+    /// match the filter.
+    pub fn checkpoint(&self) -> u64 {
+        self.checkpoint
+    }
+
+    /// Returns the next event
+    ///
+    /// Once the history is replayed, waits for new events. Returns `None`
+    /// when the bus is closed.
+    pub async fn next(&mut self) -> Result<Option<RecordedEvent>, S::Error> {
+        loop {
+            if let Some(event) = self.buffer.pop_front() {
+                self.checkpoint = if self.buffer.is_empty() {
+                    self.read_position
+                } else {
+                    event.position
+                };
+                return Ok(Some(event));
+            }
+
+            let batch = self
+                .store
+                .read_all(self.read_position + 1, self.batch_size)
+                .await?;
+            if let Some(last) = batch.last() {
+                self.read_position = last.position;
+                self.buffer
+                    .extend(batch.into_iter().filter(|e| self.filter.matches(e)));
+                if self.buffer.is_empty() {
+                    self.checkpoint = self.read_position;
+                }
+                continue;
+            }
+
+            // Caught up: wait until the bus announces another event
+            if self.live.recv().await.is_none() {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_bus::{EventBus, LocalEventBus};
+    use crate::event_store::InMemoryEventStore;
+    use crate::types::{AnyEvent, EventEnvelope, EventMetadata, PipelineEvent};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    fn stage_started(pipeline_id: Uuid, stage_name: &str) -> EventEnvelope {
+        EventEnvelope::new(
+            AnyEvent::Pipeline(PipelineEvent::StageStarted {
+                pipeline_id,
+                execution_id: Uuid::new_v4(),
+                stage_name: stage_name.to_string(),
+            }),
+            EventMetadata::new("test"),
+        )
+    }
+
+    async fn next_position<S: EventStore>(subscription: &mut CatchUpSubscription<S>) -> u64 {
+        tokio::time::timeout(Duration::from_secs(5), subscription.next())
+            .await
+            .expect("no event delivered")
+            .unwrap()
+            .unwrap()
+            .position
+    }
+
+    #[tokio::test]
+    async fn test_replays_history_then_follows_live_events() {
+        let store = Arc::new(InMemoryEventStore::new());
+        let bus = LocalEventBus::new();
+        let id = Uuid::new_v4();
+        store
+            .append(
+                &id,
+                None,
+                &[stage_started(id, "build"), stage_started(id, "test")],
+            )
+            .await
+            .unwrap();
+
+        let mut subscription =
+            CatchUpSubscription::new(Arc::clone(&store), bus.subscription(), 0).with_batch_size(1);
+        assert_eq!(next_position(&mut subscription).await, 1);
+        assert_eq!(next_position(&mut subscription).await, 2);
+
+        let publisher = {
+            let store = Arc::clone(&store);
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                for stage in ["deploy", "verify"] {
+                    let event = stage_started(id, stage);
+                    store
+                        .append(&id, None, std::slice::from_ref(&event))
+                        .await
+                        .unwrap();
+                    bus.publish(event).await.unwrap();
+                }
+            })
+        };
+        assert_eq!(next_position(&mut subscription).await, 3);
+        assert_eq!(next_position(&mut subscription).await, 4);
+        assert_eq!(subscription.checkpoint(), 4);
+        publisher.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resumes_after_checkpoint_with_filter() {
+        let store = Arc::new(InMemoryEventStore::new());
+        let bus = LocalEventBus::new();
+        let id = Uuid::new_v4();
+        let created = EventEnvelope::new(
+            AnyEvent::Pipeline(PipelineEvent::Created {
+                pipeline_id: id,
+                name: "pipeline".to_string(),
+            }),
+            EventMetadata::new("test"),
+        );
+        store
+            .append(
+                &id,
+                None,
+                &[
+                    stage_started(id, "build"),
+                    created.clone(),
+                    stage_started(id, "test"),
+                    created,
+                ],
+            )
+            .await
+            .unwrap();
+
+        let mut subscription = CatchUpSubscription::new(store, bus.subscription(), 1)
+            .with_filter(EventQuery::new().with_event_type("StageStarted"));
+        assert_eq!(next_position(&mut subscription).await, 3);
+        assert_eq!(subscription.checkpoint(), 4);
+
+        drop(bus);
+        assert!(subscription.next().await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,903 @@
+//! File-backed event and snapshot stores.
+//!
+//! Events live in an append-only log split into segment files named
+//! `segment-00000001.log`, `segment-00000002.log`, and so on. Each append
+//! is written as one record holding all of its events, so a batch is stored
+//! entirely or not at all:
+//!
+//! ```text
+//! length: u32 LE | crc32: u32 LE | payload: JSON record
+//! ```
+//!
+//! Opening a store scans the segments and rebuilds the index, which
+//! locates the records of the log and of every aggregate. Positions are
+//! counted during the scan rather than stored. A record at the end of
+//! the last segment that is cut short or fails its checksum is a torn write
+//! left by a crash, and the segment is truncated before it. Damage anywhere
+//! else is reported rather than repaired. Events are stored as written and
+//! upcast to the current schema when read.
+//!
+//! Snapshots are stored one file per aggregate, written to a temporary file
+//! that is then renamed over the previous snapshot.
+//!
+//! A directory must only be opened by one store at a time.
+
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tracing::warn;
+use uuid::Uuid;
+
+use super::in_memory::{
+    EventStore, EventStoreError, Snapshot, SnapshotStore, SnapshotStoreError, check_version,
+};
+use super::query::RecordedEvent;
+use crate::types::{EventEnvelope, UpcasterRegistry};
+
+/// Bytes before the payload of a record: its length and checksum
+const HEADER_LEN: usize = 8;
+
+/// When appends are synced to disk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Sync every append before it returns
+    #[default]
+    Always,
+    /// Sync on the first append once the interval has passed
+    ///
+    /// A crash can lose the appends of the last interval.
+    Interval(Duration),
+    /// Leave flushing to the operating system
+    Never,
+}
+
+/// Configuration of a [`FileEventStore`]
+#[derive(Debug, Clone)]
+pub struct FileEventStoreConfig {
+    /// Size in bytes after which appends go to a new segment
+    pub segment_size: u64,
+    /// When appends are synced to disk
+    pub fsync: FsyncPolicy,
+    /// Upcasters applied to events written with older schema versions
+    pub upcasters: Arc<UpcasterRegistry>,
+}
+
+impl Default for FileEventStoreConfig {
+    fn default() -> Self {
+        Self {
+            segment_size: 64 * 1024 * 1024,
+            fsync: FsyncPolicy::Always,
+            upcasters: Arc::new(UpcasterRegistry::default()),
+        }
+    }
+}
+
+/// Payload of a record: events appended to an aggregate at a version
+#[derive(Debug, Serialize, Deserialize)]
+struct Record<E> {
+    aggregate_id: Uuid,
+    version: u64,
+    events: Vec<E>,
+}
+
+/// Where a record is in the log, and the positions of its events
+#[derive(Debug, Clone, Copy)]
+struct RecordLocation {
+    segment: u64,
+    offset: u64,
+    length: u32,
+    position: u64,
+    count: u64,
+}
+
+/// Records of the log, in append order, and of each aggregate
+#[derive(Debug, Default)]
+struct Index {
+    records: Vec<RecordLocation>,
+    aggregates: HashMap<Uuid, AggregateIndex>,
+    last_position: u64,
+}
+
+/// Version of an aggregate and the indices of its records
+#[derive(Debug, Default)]
+struct AggregateIndex {
+    version: u64,
+    records: Vec<usize>,
+}
+
+impl Index {
+    fn version(&self, aggregate_id: &Uuid) -> u64 {
+        self.aggregates.get(aggregate_id).map_or(0, |a| a.version)
+    }
+
+    fn push(&mut self, aggregate_id: Uuid, location: RecordLocation) {
+        let aggregate = self.aggregates.entry(aggregate_id).or_default();
+        aggregate.version += location.count;
+        aggregate.records.push(self.records.len());
+        self.last_position += location.count;
+        self.records.push(location);
+    }
+
+    /// Returns the records holding up to `limit` events from a position on
+    fn records_from(&self, from_position: u64, limit: usize) -> Vec<RecordLocation> {
+        let start = self
+            .records
+            .partition_point(|r| r.position + r.count <= from_position);
+        let mut remaining = limit as u64;
+        let mut records = Vec::new();
+        for record in &self.records[start..] {
+            if remaining == 0 {
+                break;
+            }
+            let end = record.position + record.count;
+            remaining = remaining.saturating_sub(end - record.position.max(from_position));
+            records.push(*record);
+        }
+        records
+    }
+}
+
+/// Segment being appended to, and the index of the whole log
+#[derive(Debug)]
+struct State {
+    segment: u64,
+    file: File,
+    size: u64,
+    last_sync: Instant,
+    index: Index,
+}
+
+/// Event store keeping events in a segmented log on disk
+#[derive(Debug)]
+pub struct FileEventStore {
+    dir: PathBuf,
+    config: FileEventStoreConfig,
+    state: Mutex<State>,
+}
+
+impl FileEventStore {
+    /// Opens the store in `dir` with the default configuration
+    pub async fn open(dir: impl AsRef<Path>) -> Result<Self, EventStoreError> {
+        Self::open_with_config(dir, FileEventStoreConfig::default()).await
+    }
+
+    /// Opens the store in `dir`, creating it if needed
+    ///
+    /// Rebuilds the index from the log and truncates a torn write at its
+    /// end.
+    pub async fn open_with_config(
+        dir: impl AsRef<Path>,
+        config: FileEventStoreConfig,
+    ) -> Result<Self, EventStoreError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).await.map_err(storage)?;
+
+        let segments = list_segments(&dir).await?;
+        let mut index = Index::default();
+        let mut active = (1, 0);
+        for (i, &segment) in segments.iter().enumerate() {
+            let is_last = i + 1 == segments.len();
+            let size =
+                recover_segment(&segment_path(&dir, segment), segment, is_last, &mut index).await?;
+            active = (segment, size);
+        }
+
+        let (segment, size) = active;
+        let file = open_segment(&dir, segment).await?;
+        if segments.is_empty() {
+            sync_dir(&dir).await.map_err(storage)?;
+        }
+
+        Ok(Self {
+            dir,
+            config,
+            state: Mutex::new(State {
+                segment,
+                file,
+                size,
+                last_sync: Instant::now(),
+                index,
+            }),
+        })
+    }
+
+    /// Returns the directory of the store
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Syncs appends to disk regardless of the fsync policy
+    pub async fn sync(&self) -> Result<(), EventStoreError> {
+        let mut state = self.state.lock().await;
+        state.file.sync_data().await.map_err(storage)?;
+        state.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Writes a record to the active segment, starting a new one if full
+    ///
+    /// A failed write is cut off again, so the log never continues after a
+    /// partial record.
+    async fn write(
+        &self,
+        state: &mut State,
+        payload: &[u8],
+        count: u64,
+    ) -> Result<RecordLocation, EventStoreError> {
+        let length = u32::try_from(payload.len())
+            .map_err(|_| storage(format!("record of {} bytes is too large", payload.len())))?;
+        let record_len = (HEADER_LEN + payload.len()) as u64;
+        if state.size > 0 && state.size + record_len > self.config.segment_size {
+            self.rotate(state).await?;
+        }
+
+        let offset = state.size;
+        let sync = match self.config.fsync {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Interval(interval) => state.last_sync.elapsed() >= interval,
+            FsyncPolicy::Never => false,
+        };
+        if let Err(e) = write_record(&mut state.file, length, payload, sync).await {
+            if let Err(truncate) = state.file.set_len(offset).await {
+                warn!(
+                    "Cannot truncate {} after a failed append: {}",
+                    segment_path(&self.dir, state.segment).display(),
+                    truncate
+                );
+            }
+            return Err(storage(e));
+        }
+
+        state.size += record_len;
+        if sync {
+            state.last_sync = Instant::now();
+        }
+        Ok(RecordLocation {
+            segment: state.segment,
+            offset,
+            length,
+            position: state.index.last_position + 1,
+            count,
+        })
+    }
+
+    /// Closes the active segment and starts the next one
+    async fn rotate(&self, state: &mut State) -> Result<(), EventStoreError> {
+        let durable = self.config.fsync != FsyncPolicy::Never;
+        if durable {
+            state.file.sync_data().await.map_err(storage)?;
+        }
+
+        let segment = state.segment + 1;
+        state.file = open_segment(&self.dir, segment).await?;
+        state.segment = segment;
+        state.size = 0;
+        if durable {
+            sync_dir(&self.dir).await.map_err(storage)?;
+        }
+        Ok(())
+    }
+
+    /// Reads records from the log
+    async fn read(
+        &self,
+        locations: &[RecordLocation],
+    ) -> Result<Vec<(RecordLocation, Record<EventEnvelope>)>, EventStoreError> {
+        let mut records = Vec::new();
+        let mut open: Option<(u64, File)> = None;
+        for location in locations {
+            let path = segment_path(&self.dir, location.segment);
+            let file = match open.take() {
+                Some((segment, file)) if segment == location.segment => file,
+                _ => File::open(&path).await.map_err(storage)?,
+            };
+            let file = &mut open.insert((location.segment, file)).1;
+
+            let mut buffer = vec![0; HEADER_LEN + location.length as usize];
+            file.seek(SeekFrom::Start(location.offset))
+                .await
+                .map_err(storage)?;
+            file.read_exact(&mut buffer).await.map_err(storage)?;
+            let payload = decode(&buffer).ok_or_else(|| {
+                storage(format!(
+                    "corrupt record at {}:{}",
+                    path.display(),
+                    location.offset
+                ))
+            })?;
+            let record: Record<serde_json::Value> =
+                serde_json::from_slice(payload).map_err(storage)?;
+            let events = record
+                .events
+                .into_iter()
+                .map(|event| self.config.upcasters.deserialize(event))
+                .collect::<Result<_, _>>()
+                .map_err(storage)?;
+            records.push((
+                *location,
+                Record {
+                    aggregate_id: record.aggregate_id,
+                    version: record.version,
+                    events,
+                },
+            ));
+        }
+        Ok(records)
+    }
+}
+
+#[async_trait::async_trait]
+impl EventStore for FileEventStore {
+    type Error = EventStoreError;
+
+    async fn append(
+        &self,
+        aggregate_id: &Uuid,
+        expected_version: Option<u64>,
+        events: &[EventEnvelope],
+    ) -> Result<(), Self::Error> {
+        let mut state = self.state.lock().await;
+        let version = state.index.version(aggregate_id);
+        check_version(aggregate_id, expected_version, version)?;
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let payload = serde_json::to_vec(&Record {
+            aggregate_id: *aggregate_id,
+            version,
+            events: events.iter().collect(),
+        })
+        .map_err(storage)?;
+        let location = self
+            .write(&mut state, &payload, events.len() as u64)
+            .await?;
+        state.index.push(*aggregate_id, location);
+        Ok(())
+    }
+
+    async fn get_events(&self, aggregate_id: &Uuid) -> Result<Vec<EventEnvelope>, Self::Error> {
+        let locations: Vec<_> = {
+            let state = self.state.lock().await;
+            let Some(aggregate) = state.index.aggregates.get(aggregate_id) else {
+                return Ok(Vec::new());
+            };
+            aggregate
+                .records
+                .iter()
+                .map(|&i| state.index.records[i])
+                .collect()
+        };
+        let records = self.read(&locations).await?;
+        Ok(records
+            .into_iter()
+            .flat_map(|(_, record)| record.events)
+            .collect())
+    }
+
+    async fn list_aggregates(&self) -> Result<Vec<Uuid>, Self::Error> {
+        let state = self.state.lock().await;
+        Ok(state.index.aggregates.keys().copied().collect())
+    }
+
+    async fn read_all(
+        &self,
+        from_position: u64,
+        limit: usize,
+    ) -> Result<Vec<RecordedEvent>, Self::Error> {
+        let locations = {
+            let state = self.state.lock().await;
+            state.index.records_from(from_position, limit)
+        };
+
+        let mut events = Vec::new();
+        for (location, record) in self.read(&locations).await? {
+            for (i, envelope) in (0..).zip(record.events) {
+                let position = location.position + i;
+                if position >= from_position {
+                    events.push(RecordedEvent {
+                        position,
+                        aggregate_id: record.aggregate_id,
+                        version: record.version + i + 1,
+                        envelope,
+                    });
+                }
+            }
+        }
+        events.truncate(limit);
+        Ok(events)
+    }
+
+    async fn last_position(&self) -> Result<u64, Self::Error> {
+        Ok(self.state.lock().await.index.last_position)
+    }
+}
+
+/// Snapshot store keeping one file per aggregate
+#[derive(Debug)]
+pub struct FileSnapshotStore {
+    dir: PathBuf,
+}
+
+impl FileSnapshotStore {
+    /// Opens the store in `dir`, creating it if needed
+    pub async fn open(dir: impl AsRef<Path>) -> Result<Self, SnapshotStoreError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).await.map_err(snapshot_error)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, aggregate_id: &Uuid) -> PathBuf {
+        self.dir.join(format!("{aggregate_id}.json"))
+    }
+}
+
+#[async_trait::async_trait]
+impl SnapshotStore for FileSnapshotStore {
+    type Error = SnapshotStoreError;
+
+    async fn save(&self, snapshot: &Snapshot) -> Result<(), Self::Error> {
+        let path = self.path(&snapshot.aggregate_id);
+        let temp = path.with_extension("json.tmp");
+        let data = serde_json::to_vec(snapshot).map_err(snapshot_error)?;
+
+        let mut file = File::create(&temp).await.map_err(snapshot_error)?;
+        file.write_all(&data).await.map_err(snapshot_error)?;
+        file.sync_all().await.map_err(snapshot_error)?;
+        drop(file);
+
+        fs::rename(&temp, &path).await.map_err(snapshot_error)?;
+        sync_dir(&self.dir).await.map_err(snapshot_error)
+    }
+
+    async fn load(&self, aggregate_id: &Uuid) -> Result<Option<Snapshot>, Self::Error> {
+        match fs::read(self.path(aggregate_id)).await {
+            Ok(data) => serde_json::from_slice(&data)
+                .map(Some)
+                .map_err(snapshot_error),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(snapshot_error(e)),
+        }
+    }
+}
+
+fn storage(error: impl std::fmt::Display) -> EventStoreError {
+    EventStoreError::StorageError(error.to_string())
+}
+
+fn snapshot_error(error: impl std::fmt::Display) -> SnapshotStoreError {
+    SnapshotStoreError(error.to_string())
+}
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("segment-{segment:08}.log"))
+}
+
+/// Returns the numbers of the segments in `dir`, in order
+async fn list_segments(dir: &Path) -> Result<Vec<u64>, EventStoreError> {
+    let mut entries = fs::read_dir(dir).await.map_err(storage)?;
+    let mut segments = Vec::new();
+    while let Some(entry) = entries.next_entry().await.map_err(storage)? {
+        let segment = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix("segment-"))
+            .and_then(|name| name.strip_suffix(".log"))
+            .and_then(|number| number.parse::<u64>().ok());
+        segments.extend(segment);
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+async fn open_segment(dir: &Path, segment: u64) -> Result<File, EventStoreError> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(segment_path(dir, segment))
+        .await
+        .map_err(storage)
+}
+
+/// Indexes the records of a segment and returns the size of its valid part
+///
+/// A damaged tail of the last segment is a torn write and is truncated.
+async fn recover_segment(
+    path: &Path,
+    segment: u64,
+    is_last: bool,
+    index: &mut Index,
+) -> Result<u64, EventStoreError> {
+    let data = fs::read(path).await.map_err(storage)?;
+    let mut offset = 0;
+    while let Some(payload) = decode(&data[offset..]) {
+        let Ok(record) = serde_json::from_slice::<Record<IgnoredAny>>(payload) else {
+            break;
+        };
+        let version = index.version(&record.aggregate_id);
+        if record.version != version {
+            return Err(storage(format!(
+                "record at {}:{} appends to {} at version {}, but it is at version {}",
+                path.display(),
+                offset,
+                record.aggregate_id,
+                record.version,
+                version
+            )));
+        }
+        index.push(
+            record.aggregate_id,
+            RecordLocation {
+                segment,
+                offset: offset as u64,
+                length: payload.len() as u32,
+                position: index.last_position + 1,
+                count: record.events.len() as u64,
+            },
+        );
+        offset += HEADER_LEN + payload.len();
+    }
+
+    if offset < data.len() {
+        if !is_last {
+            return Err(storage(format!(
+                "corrupt record at {}:{}",
+                path.display(),
+                offset
+            )));
+        }
+        warn!(
+            "Truncating torn write of {} bytes at {}:{}",
+            data.len() - offset,
+            path.display(),
+            offset
+        );
+        let file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .await
+            .map_err(storage)?;
+        file.set_len(offset as u64).await.map_err(storage)?;
+        file.sync_all().await.map_err(storage)?;
+    }
+    Ok(offset as u64)
+}
+
+async fn write_record(
+    file: &mut File,
+    length: u32,
+    payload: &[u8],
+    sync: bool,
+) -> std::io::Result<()> {
+    let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+    record.extend_from_slice(&length.to_le_bytes());
+    record.extend_from_slice(&crc32(payload).to_le_bytes());
+    record.extend_from_slice(payload);
+    file.write_all(&record).await?;
+    file.flush().await?;
+    if sync {
+        file.sync_data().await?;
+    }
+    Ok(())
+}
+
+/// Returns the payload of the record at the start of `data`
+///
+/// Returns `None` if the record is cut short or fails its checksum.
+fn decode(data: &[u8]) -> Option<&[u8]> {
+    let length = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    let checksum = u32::from_le_bytes(data.get(4..HEADER_LEN)?.try_into().ok()?);
+    let payload = data.get(HEADER_LEN..HEADER_LEN + length)?;
+    (crc32(payload) == checksum).then_some(payload)
+}
+
+/// Syncs a directory so that files created or renamed in it survive a crash
+async fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir).await?.sync_all().await?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Lookup table of the CRC-32 (IEEE) checksum
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC_TABLE[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AnyEvent, EventMetadata, PipelineEvent};
+    use tempfile::TempDir;
+
+    fn created(aggregate_id: Uuid, name: &str) -> EventEnvelope {
+        EventEnvelope::new(
+            AnyEvent::Pipeline(PipelineEvent::Created {
+                pipeline_id: aggregate_id,
+                name: name.to_string(),
+            }),
+            EventMetadata::new("test"),
+        )
+    }
+
+    fn names(events: &[EventEnvelope]) -> Vec<String> {
+        events
+            .iter()
+            .map(|e| match &e.event {
+                AnyEvent::Pipeline(PipelineEvent::Created { name, .. }) => name.clone(),
+                other => other.event_type().to_string(),
+            })
+            .collect()
+    }
+
+    fn envelopes(events: &[RecordedEvent]) -> Vec<EventEnvelope> {
+        events.iter().map(|e| e.envelope.clone()).collect()
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[tokio::test]
+    async fn test_events_survive_reopen_across_segments() {
+        let dir = TempDir::new().unwrap();
+        let config = FileEventStoreConfig {
+            segment_size: 1,
+            fsync: FsyncPolicy::Never,
+            ..FileEventStoreConfig::default()
+        };
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let store = FileEventStore::open_with_config(dir.path(), config.clone())
+            .await
+            .unwrap();
+        store
+            .append(&a, Some(0), &[created(a, "a1"), created(a, "a2")])
+            .await
+            .unwrap();
+        store.append(&b, None, &[created(b, "b1")]).await.unwrap();
+        store
+            .append(&a, Some(2), &[created(a, "a3")])
+            .await
+            .unwrap();
+        drop(store);
+
+        assert_eq!(list_segments(dir.path()).await.unwrap(), vec![1, 2, 3]);
+
+        let store = FileEventStore::open_with_config(dir.path(), config)
+            .await
+            .unwrap();
+        assert_eq!(
+            names(&store.get_events(&a).await.unwrap()),
+            ["a1", "a2", "a3"]
+        );
+        assert_eq!(names(&store.get_events(&b).await.unwrap()), ["b1"]);
+        let mut aggregates = store.list_aggregates().await.unwrap();
+        aggregates.sort();
+        let mut expected = vec![a, b];
+        expected.sort();
+        assert_eq!(aggregates, expected);
+
+        store
+            .append(&a, Some(3), &[created(a, "a4")])
+            .await
+            .unwrap();
+        assert_eq!(store.get_events(&a).await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_read_all_from_position() {
+        let dir = TempDir::new().unwrap();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let store = FileEventStore::open(dir.path()).await.unwrap();
+        store
+            .append(&a, None, &[created(a, "a1"), created(a, "a2")])
+            .await
+            .unwrap();
+        store.append(&b, None, &[created(b, "b1")]).await.unwrap();
+        store.append(&a, None, &[created(a, "a3")]).await.unwrap();
+        drop(store);
+
+        let store = FileEventStore::open(dir.path()).await.unwrap();
+        assert_eq!(store.last_position().await.unwrap(), 4);
+        let all = store.read_all(1, 10).await.unwrap();
+        assert_eq!(names(&envelopes(&all)), ["a1", "a2", "b1", "a3"]);
+        let versions: Vec<_> = all.iter().map(|e| (e.position, e.version)).collect();
+        assert_eq!(versions, [(1, 1), (2, 2), (3, 1), (4, 3)]);
+
+        let page = store.read_all(2, 2).await.unwrap();
+        assert_eq!(names(&envelopes(&page)), ["a2", "b1"]);
+        assert!(store.read_all(5, 10).await.unwrap().is_empty());
+
+        store.append(&b, None, &[created(b, "b2")]).await.unwrap();
+        let tail = store.read_all(4, 10).await.unwrap();
+        assert_eq!(tail.iter().map(|e| e.position).collect::<Vec<_>>(), [4, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_append_checks_expected_version() {
+        let dir = TempDir::new().unwrap();
+        let store = FileEventStore::open(dir.path()).await.unwrap();
+        let id = Uuid::new_v4();
+
+        store
+            .append(&id, Some(0), &[created(id, "1")])
+            .await
+            .unwrap();
+        let conflict = store
+            .append(&id, Some(0), &[created(id, "2")])
+            .await
+            .unwrap_err();
+        assert!(matches!(conflict, EventStoreError::ConcurrencyConflict(_)));
+        drop(store);
+
+        let store = FileEventStore::open(dir.path()).await.unwrap();
+        assert!(
+            store
+                .append(&id, Some(0), &[created(id, "2")])
+                .await
+                .is_err()
+        );
+        store
+            .append(&id, Some(1), &[created(id, "2")])
+            .await
+            .unwrap();
+        assert_eq!(names(&store.get_events(&id).await.unwrap()), ["1", "2"]);
+    }
+
+    #[tokio::test]
+    async fn test_recovery_truncates_torn_write() {
+        let dir = TempDir::new().unwrap();
+        let id = Uuid::new_v4();
+        let store = FileEventStore::open(dir.path()).await.unwrap();
+        store
+            .append(&id, None, &[created(id, "kept")])
+            .await
+            .unwrap();
+        drop(store);
+
+        // A record that claims 100 bytes but only 10 made it to disk
+        let path = segment_path(dir.path(), 1);
+        let valid_len = std::fs::metadata(&path).unwrap().len();
+        let mut data = std::fs::read(&path).unwrap();
+        data.extend_from_slice(&100u32.to_le_bytes());
+        data.extend_from_slice(&[0; 14]);
+        std::fs::write(&path, data).unwrap();
+
+        let store = FileEventStore::open(dir.path()).await.unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), valid_len);
+        assert_eq!(names(&store.get_events(&id).await.unwrap()), ["kept"]);
+
+        store
+            .append(&id, Some(1), &[created(id, "next")])
+            .await
+            .unwrap();
+        drop(store);
+        let store = FileEventStore::open(dir.path()).await.unwrap();
+        assert_eq!(
+            names(&store.get_events(&id).await.unwrap()),
+            ["kept", "next"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recovery_drops_record_failing_checksum() {
+        let dir = TempDir::new().unwrap();
+        let id = Uuid::new_v4();
+        let store = FileEventStore::open(dir.path()).await.unwrap();
+        store
+            .append(&id, None, &[created(id, "kept")])
+            .await
+            .unwrap();
+        store
+            .append(&id, None, &[created(id, "torn")])
+            .await
+            .unwrap();
+        drop(store);
+
+        let path = segment_path(dir.path(), 1);
+        let mut data = std::fs::read(&path).unwrap();
+        let last = data.len() - 3;
+        data[last] ^= 0xFF;
+        std::fs::write(&path, data).unwrap();
+
+        let store = FileEventStore::open(dir.path()).await.unwrap();
+        assert_eq!(names(&store.get_events(&id).await.unwrap()), ["kept"]);
+        assert!(store.append(&id, Some(1), &[]).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_events_are_upcast_when_read() {
+        let dir = TempDir::new().unwrap();
+        let id = Uuid::new_v4();
+        let store = FileEventStore::open(dir.path()).await.unwrap();
+        store
+            .append(&id, None, &[created(id, "deploy")])
+            .await
+            .unwrap();
+        drop(store);
+
+        let config = FileEventStoreConfig {
+            upcasters: Arc::new(UpcasterRegistry::targeting(2).register(1, |envelope| {
+                if let Some(created) = envelope["event"].get_mut("Created") {
+                    let name = created["name"].as_str().unwrap_or_default();
+                    created["name"] = format!("{name}-v2").into();
+                }
+                Ok(())
+            })),
+            ..FileEventStoreConfig::default()
+        };
+        let store = FileEventStore::open_with_config(dir.path(), config)
+            .await
+            .unwrap();
+        let events = store.get_events(&id).await.unwrap();
+        assert_eq!(names(&events), ["deploy-v2"]);
+        assert_eq!(events[0].schema_version, 2);
+
+        let newer = FileEventStoreConfig {
+            upcasters: Arc::new(UpcasterRegistry::targeting(0)),
+            ..FileEventStoreConfig::default()
+        };
+        let store = FileEventStore::open_with_config(dir.path(), newer)
+            .await
+            .unwrap();
+        assert!(store.get_events(&id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_file_snapshot_store() {
+        let dir = TempDir::new().unwrap();
+        let id = Uuid::new_v4();
+        let snapshot = |version| Snapshot {
+            aggregate_id: id,
+            aggregate_type: "Pipeline".to_string(),
+            version,
+            state: serde_json::json!({"version": version}),
+            created_at: chrono::Utc::now(),
+        };
+
+        let store = FileSnapshotStore::open(dir.path()).await.unwrap();
+        assert!(store.load(&id).await.unwrap().is_none());
+        store.save(&snapshot(1)).await.unwrap();
+        store.save(&snapshot(2)).await.unwrap();
+        drop(store);
+
+        let store = FileSnapshotStore::open(dir.path()).await.unwrap();
+        let loaded = store.load(&id).await.unwrap().unwrap();
+        assert_eq!(loaded.version, 2);
+        assert_eq!(loaded.state["version"], 2);
+    }
+}
@@ -2,40 +2,88 @@
 //!
 //! Provides an in-memory event store for development and testing.
 
+use std::collections::HashMap;
+use std::sync::RwLock;
+
 use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use super::query::{EventQuery, RecordedEvent};
 use crate::types::{AnyEvent, EventEnvelope, EventMetadata};
 
+/// Number of events [`EventStore::query`] reads at a time
+const QUERY_BATCH: usize = 1000;
+
 /// Event store trait
+///
+/// The version of an aggregate is the number of events appended to it.
 #[async_trait::async_trait]
 pub trait EventStore: Send + Sync {
     type Error: std::fmt::Debug + std::fmt::Display;
 
+    /// Appends events to an aggregate
+    ///
+    /// With an `expected_version`, the append fails with a concurrency
+    /// conflict unless the aggregate is at that version, so writers that
+    /// decided on stale events do not interleave. `None` appends
+    /// unconditionally.
     async fn append(
         &self,
         aggregate_id: &Uuid,
+        expected_version: Option<u64>,
         events: &[EventEnvelope],
     ) -> Result<(), Self::Error>;
 
     async fn get_events(&self, aggregate_id: &Uuid) -> Result<Vec<EventEnvelope>, Self::Error>;
 
     async fn list_aggregates(&self) -> Result<Vec<Uuid>, Self::Error>;
+
+    /// Reads up to `limit` events of all aggregates, from a position on
+    async fn read_all(
+        &self,
+        from_position: u64,
+        limit: usize,
+    ) -> Result<Vec<RecordedEvent>, Self::Error>;
+
+    /// Returns the position of the last event, or 0 if the store is empty
+    async fn last_position(&self) -> Result<u64, Self::Error>;
+
+    /// Returns the events matching a query, in position order
+    async fn query(&self, query: &EventQuery) -> Result<Vec<RecordedEvent>, Self::Error> {
+        let limit = query.limit.unwrap_or(usize::MAX);
+        let mut matched = Vec::new();
+        let mut position = query.from_position;
+        while matched.len() < limit {
+            let batch = self.read_all(position, QUERY_BATCH).await?;
+            let Some(last) = batch.last() else {
+                break;
+            };
+            position = last.position + 1;
+            matched.extend(batch.into_iter().filter(|e| query.matches(e)));
+        }
+        matched.truncate(limit);
+        Ok(matched)
+    }
 }
 
 /// In-memory event store for development and testing
 #[derive(Debug, Default)]
 pub struct InMemoryEventStore {
-    events: DashMap<Uuid, Vec<EventEnvelope>>,
+    log: RwLock<Log>,
+}
+
+/// Events in position order, and where each aggregate's events are
+#[derive(Debug, Default)]
+struct Log {
+    events: Vec<RecordedEvent>,
+    aggregates: HashMap<Uuid, Vec<usize>>,
 }
 
 impl InMemoryEventStore {
     pub fn new() -> Self {
-        Self {
-            events: DashMap::new(),
-        }
+        Self::default()
     }
 }
 
@@ -46,23 +94,66 @@ impl EventStore for InMemoryEventStore {
     async fn append(
         &self,
         aggregate_id: &Uuid,
+        expected_version: Option<u64>,
         new_events: &[EventEnvelope],
     ) -> Result<(), Self::Error> {
-        let mut events = self.events.entry(*aggregate_id).or_default();
-        events.extend_from_slice(new_events);
+        let mut log = self.log.write().unwrap();
+        let Log { events, aggregates } = &mut *log;
+        let version = aggregates.get(aggregate_id).map_or(0, Vec::len);
+        check_version(aggregate_id, expected_version, version as u64)?;
+        if new_events.is_empty() {
+            return Ok(());
+        }
+
+        let indices = aggregates.entry(*aggregate_id).or_default();
+        for envelope in new_events {
+            indices.push(events.len());
+            events.push(RecordedEvent {
+                position: events.len() as u64 + 1,
+                aggregate_id: *aggregate_id,
+                version: indices.len() as u64,
+                envelope: envelope.clone(),
+            });
+        }
         Ok(())
     }
 
     async fn get_events(&self, aggregate_id: &Uuid) -> Result<Vec<EventEnvelope>, Self::Error> {
-        if let Some(events) = self.events.get(aggregate_id) {
-            Ok(events.clone())
-        } else {
-            Ok(Vec::new())
-        }
+        let log = self.log.read().unwrap();
+        Ok(log
+            .aggregates
+            .get(aggregate_id)
+            .map_or_else(Vec::new, |indices| {
+                indices
+                    .iter()
+                    .map(|&i| log.events[i].envelope.clone())
+                    .collect()
+            }))
     }
 
     async fn list_aggregates(&self) -> Result<Vec<Uuid>, Self::Error> {
-        Ok(self.events.iter().map(|e| *e.key()).collect())
+        Ok(self
+            .log
+            .read()
+            .unwrap()
+            .aggregates
+            .keys()
+            .copied()
+            .collect())
+    }
+
+    async fn read_all(
+        &self,
+        from_position: u64,
+        limit: usize,
+    ) -> Result<Vec<RecordedEvent>, Self::Error> {
+        let log = self.log.read().unwrap();
+        let start = usize::try_from(from_position.saturating_sub(1)).unwrap_or(usize::MAX);
+        Ok(log.events.iter().skip(start).take(limit).cloned().collect())
+    }
+
+    async fn last_position(&self) -> Result<u64, Self::Error> {
+        Ok(self.log.read().unwrap().events.len() as u64)
     }
 }
 
@@ -79,6 +170,20 @@ pub enum EventStoreError {
     StorageError(String),
 }
 
+/// Fails with a concurrency conflict if `current` is not the expected version
+pub(crate) fn check_version(
+    aggregate_id: &Uuid,
+    expected_version: Option<u64>,
+    current: u64,
+) -> Result<(), EventStoreError> {
+    match expected_version {
+        Some(expected) if expected != current => Err(EventStoreError::ConcurrencyConflict(
+            format!("aggregate {aggregate_id} is at version {current}, expected {expected}"),
+        )),
+        _ => Ok(()),
+    }
+}
+
 /// A snapshot of aggregate state
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Snapshot {
@@ -130,7 +235,7 @@ impl SnapshotStore for InMemorySnapshotStore {
 
 #[derive(Debug, thiserror::Error)]
 #[error("Snapshot store error: {0}")]
-pub struct SnapshotStoreError(String);
+pub struct SnapshotStoreError(pub(crate) String);
 
 #[cfg(test)]
 mod tests {
@@ -152,7 +257,7 @@ mod tests {
 
         let result = tokio::runtime::Runtime::new()
             .unwrap()
-            .block_on(async { store.append(&aggregate_id, &[event.clone()]).await });
+            .block_on(async { store.append(&aggregate_id, None, &[event.clone()]).await });
 
         assert!(result.is_ok());
 
@@ -199,8 +304,8 @@ mod tests {
         );
 
         tokio::runtime::Runtime::new().unwrap().block_on(async {
-            store.append(&id1, &[event1]).await.unwrap();
-            store.append(&id2, &[event2]).await.unwrap();
+            store.append(&id1, None, &[event1]).await.unwrap();
+            store.append(&id2, None, &[event2]).await.unwrap();
         });
 
         let aggregates = tokio::runtime::Runtime::new()
@@ -210,6 +315,73 @@ mod tests {
         assert_eq!(aggregates.len(), 2);
     }
 
+    #[tokio::test]
+    async fn test_in_memory_event_store_checks_expected_version() {
+        let store = InMemoryEventStore::new();
+        let aggregate_id = Uuid::new_v4();
+        let event = EventEnvelope::new(
+            AnyEvent::Pipeline(PipelineEvent::Created {
+                pipeline_id: aggregate_id,
+                name: "test".to_string(),
+            }),
+            EventMetadata::new("test"),
+        );
+
+        store
+            .append(&aggregate_id, Some(0), &[event.clone(), event.clone()])
+            .await
+            .unwrap();
+        let conflict = store
+            .append(&aggregate_id, Some(1), &[event.clone()])
+            .await
+            .unwrap_err();
+        assert!(matches!(conflict, EventStoreError::ConcurrencyConflict(_)));
+        store
+            .append(&aggregate_id, Some(2), &[event])
+            .await
+            .unwrap();
+
+        assert_eq!(store.get_events(&aggregate_id).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_event_store_reads_all_in_position_order() {
+        let store = InMemoryEventStore::new();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let created = |id, name: &str| {
+            EventEnvelope::new(
+                AnyEvent::Pipeline(PipelineEvent::Created {
+                    pipeline_id: id,
+                    name: name.to_string(),
+                }),
+                EventMetadata::new(name),
+            )
+        };
+        store
+            .append(&a, None, &[created(a, "a1"), created(a, "a2")])
+            .await
+            .unwrap();
+        store.append(&b, None, &[created(b, "b1")]).await.unwrap();
+        store.append(&a, None, &[created(a, "a3")]).await.unwrap();
+
+        assert_eq!(store.last_position().await.unwrap(), 4);
+        let all = store.read_all(0, 10).await.unwrap();
+        let positions: Vec<_> = all.iter().map(|e| (e.position, e.version)).collect();
+        assert_eq!(positions, [(1, 1), (2, 2), (3, 1), (4, 3)]);
+        assert_eq!(all[2].aggregate_id, b);
+
+        let page = store.read_all(2, 2).await.unwrap();
+        assert_eq!(page.iter().map(|e| e.position).collect::<Vec<_>>(), [2, 3]);
+        assert!(store.read_all(5, 10).await.unwrap().is_empty());
+
+        let queried = store
+            .query(&EventQuery::new().starting_at(2).with_source("a3"))
+            .await
+            .unwrap();
+        assert_eq!(queried.len(), 1);
+        assert_eq!(queried[0].position, 4);
+    }
+
     #[test]
     fn test_in_memory_snapshot_store() {
         let store = InMemorySnapshotStore::new();
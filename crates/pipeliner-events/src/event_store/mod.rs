@@ -1,8 +1,14 @@
 //! Event store module.
 
+pub mod catch_up;
+pub mod file;
 pub mod in_memory;
+pub mod query;
 
+pub use catch_up::CatchUpSubscription;
+pub use file::{FileEventStore, FileEventStoreConfig, FileSnapshotStore, FsyncPolicy};
 pub use in_memory::{
-    EventStore, EventStoreError, InMemoryEventStore, InMemorySnapshotStore, SnapshotStore,
-    SnapshotStoreError,
+    EventStore, EventStoreError, InMemoryEventStore, InMemorySnapshotStore, Snapshot,
+    SnapshotStore, SnapshotStoreError,
 };
+pub use query::{EventQuery, RecordedEvent};
@@ -0,0 +1,167 @@
+//! Reading events across aggregates.
+//!
+//! Every stored event has a global position: 1 for the first event appended
+//! to the store, counting up across all aggregates in append order. Readers
+//! keep the position of the last event they processed as a checkpoint and
+//! resume after it.
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::types::EventEnvelope;
+
+/// An event as stored, with its position in the store
+#[derive(Debug, Clone)]
+pub struct RecordedEvent {
+    /// Global position of the event
+    pub position: u64,
+    /// Aggregate the event was appended to
+    pub aggregate_id: Uuid,
+    /// Version of the aggregate after the event
+    pub version: u64,
+    /// The event
+    pub envelope: EventEnvelope,
+}
+
+/// Filter of events read from a store
+///
+/// Criteria left unset match every event.
+#[derive(Debug, Clone, Default)]
+pub struct EventQuery {
+    /// First position to read
+    pub from_position: u64,
+    /// Event types to match, such as `StepCompleted`
+    pub event_types: Vec<String>,
+    /// Earliest timestamp to match
+    pub since: Option<DateTime<Utc>>,
+    /// Timestamp to match events before
+    pub until: Option<DateTime<Utc>>,
+    /// Source to match
+    pub source: Option<String>,
+    /// Correlation id to match
+    pub correlation_id: Option<Uuid>,
+    /// Maximum number of events returned
+    pub limit: Option<usize>,
+}
+
+impl EventQuery {
+    /// Creates a query matching every event
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads from a position on
+    pub fn starting_at(mut self, position: u64) -> Self {
+        self.from_position = position;
+        self
+    }
+
+    /// Matches events of a type, in addition to types already given
+    pub fn with_event_type(mut self, event_type: impl Into<String>) -> Self {
+        self.event_types.push(event_type.into());
+        self
+    }
+
+    /// Matches events at or after a time
+    pub fn since(mut self, since: DateTime<Utc>) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// Matches events before a time
+    pub fn until(mut self, until: DateTime<Utc>) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    /// Matches events from a source
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Matches events with a correlation id
+    pub fn with_correlation(mut self, correlation_id: Uuid) -> Self {
+        self.correlation_id = Some(correlation_id);
+        self
+    }
+
+    /// Returns at most `limit` events
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Returns true if the event matches every criterion but the limit
+    pub fn matches(&self, event: &RecordedEvent) -> bool {
+        let metadata = &event.envelope.metadata;
+        event.position >= self.from_position
+            && (self.event_types.is_empty()
+                || self
+                    .event_types
+                    .iter()
+                    .any(|t| t == event.envelope.event.event_type()))
+            && self.since.is_none_or(|since| metadata.timestamp >= since)
+            && self.until.is_none_or(|until| metadata.timestamp < until)
+            && self
+                .source
+                .as_ref()
+                .is_none_or(|source| *source == metadata.source)
+            && self
+                .correlation_id
+                .is_none_or(|id| metadata.correlation_id == Some(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AnyEvent, EventMetadata, PipelineEvent};
+
+    #[test]
+    fn test_query_matches() {
+        let pipeline_id = Uuid::new_v4();
+        let execution_id = Uuid::new_v4();
+        let event = RecordedEvent {
+            position: 5,
+            aggregate_id: pipeline_id,
+            version: 1,
+            envelope: EventEnvelope::new(
+                AnyEvent::Pipeline(PipelineEvent::Completed {
+                    pipeline_id,
+                    execution_id,
+                    result: "SUCCESS".to_string(),
+                }),
+                EventMetadata::new("executor").with_correlation(execution_id),
+            ),
+        };
+        let timestamp = event.envelope.metadata.timestamp;
+
+        assert!(EventQuery::new().matches(&event));
+        assert!(
+            EventQuery::new()
+                .starting_at(5)
+                .with_event_type("StageStarted")
+                .with_event_type("PipelineCompleted")
+                .since(timestamp)
+                .until(timestamp + chrono::Duration::seconds(1))
+                .with_source("executor")
+                .with_correlation(execution_id)
+                .matches(&event)
+        );
+
+        assert!(!EventQuery::new().starting_at(6).matches(&event));
+        assert!(
+            !EventQuery::new()
+                .with_event_type("StageStarted")
+                .matches(&event)
+        );
+        assert!(!EventQuery::new().until(timestamp).matches(&event));
+        assert!(!EventQuery::new().with_source("worker").matches(&event));
+        assert!(
+            !EventQuery::new()
+                .with_correlation(Uuid::new_v4())
+                .matches(&event)
+        );
+    }
+}
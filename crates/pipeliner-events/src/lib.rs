@@ -8,6 +8,7 @@
 //!
 //! - `event_store`: Persistent storage for events
 //! - `event_bus`: Pub/sub communication for event distribution
+//! - `projection`: Read models folded from the event log
 //! - `types`: Base event types and domain-specific events
 //!
 //! ## Example
@@ -24,12 +25,25 @@
 
 pub mod event_bus;
 pub mod event_store;
+pub mod projection;
 pub mod types;
 
-pub use event_bus::{EventBus, EventHandler, LocalEventBus};
-pub use event_store::{EventStore, InMemoryEventStore, InMemorySnapshotStore, SnapshotStore};
+pub use event_bus::{
+    Backpressure, DeadLetter, EventBus, EventBusConfig, EventHandler, HandlerStats, LocalEventBus,
+    Subscription,
+};
+pub use event_store::{
+    CatchUpSubscription, EventQuery, EventStore, FileEventStore, FileEventStoreConfig,
+    FileSnapshotStore, FsyncPolicy, InMemoryEventStore, InMemorySnapshotStore, RecordedEvent,
+    SnapshotStore,
+};
+pub use projection::{
+    ExecutionStatusProjection, Projection, ProjectionError, Projector, RunStatus,
+    StageTimelineProjection, WorkerUtilisationProjection,
+};
 pub use types::{
-    AnyEvent, EventEnvelope, EventMetadata, InfrastructureEvent, PipelineEvent, WorkerEvent,
+    AnyEvent, CURRENT_SCHEMA_VERSION, EventEnvelope, EventMetadata, InfrastructureEvent,
+    PipelineEvent, UpcastError, UpcasterRegistry, WorkerEvent,
 };
 
 /// Event errors
@@ -0,0 +1,264 @@
+//! Status of pipeline runs.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{Projection, RunStatus};
+use crate::event_store::RecordedEvent;
+use crate::types::{AnyEvent, PipelineEvent};
+
+/// A pipeline run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionView {
+    /// ID of the run
+    pub execution_id: Uuid,
+    /// Pipeline that ran
+    pub pipeline_id: Uuid,
+    /// Name of the pipeline, once its creation was seen
+    pub pipeline_name: Option<String>,
+    /// Status of the run
+    pub status: RunStatus,
+    /// Stage started last and not completed yet
+    pub current_stage: Option<String>,
+    /// When the run started
+    pub started_at: DateTime<Utc>,
+    /// When the run finished
+    pub completed_at: Option<DateTime<Utc>>,
+    /// Why the run failed or was cancelled
+    pub error: Option<String>,
+}
+
+/// Read model of [`ExecutionStatusProjection`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionStatusView {
+    /// Names of the pipelines by ID
+    pub pipelines: HashMap<Uuid, String>,
+    /// Runs by execution ID
+    pub executions: HashMap<Uuid, ExecutionView>,
+}
+
+impl ExecutionStatusView {
+    /// Returns the runs of a pipeline, most recent first
+    pub fn executions_of(&self, pipeline_id: &Uuid) -> Vec<&ExecutionView> {
+        let mut executions: Vec<_> = self
+            .executions
+            .values()
+            .filter(|e| e.pipeline_id == *pipeline_id)
+            .collect();
+        executions.sort_by_key(|e| std::cmp::Reverse(e.started_at));
+        executions
+    }
+
+    /// Returns the runs that have not finished
+    pub fn running(&self) -> impl Iterator<Item = &ExecutionView> {
+        self.executions.values().filter(|e| !e.status.is_finished())
+    }
+
+    /// Returns the run, recording it as started at `timestamp` if unknown
+    fn execution(
+        &mut self,
+        pipeline_id: Uuid,
+        execution_id: Uuid,
+        timestamp: DateTime<Utc>,
+    ) -> &mut ExecutionView {
+        let pipeline_name = self.pipelines.get(&pipeline_id).cloned();
+        self.executions
+            .entry(execution_id)
+            .or_insert_with(|| ExecutionView {
+                execution_id,
+                pipeline_id,
+                pipeline_name,
+                status: RunStatus::Running,
+                current_stage: None,
+                started_at: timestamp,
+                completed_at: None,
+                error: None,
+            })
+    }
+
+    fn finish(
+        &mut self,
+        pipeline_id: Uuid,
+        execution_id: Uuid,
+        timestamp: DateTime<Utc>,
+        status: RunStatus,
+        error: Option<String>,
+    ) {
+        let execution = self.execution(pipeline_id, execution_id, timestamp);
+        execution.status = status;
+        execution.current_stage = None;
+        execution.completed_at = Some(timestamp);
+        execution.error = error;
+    }
+}
+
+/// Projection of the status of every pipeline run
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecutionStatusProjection;
+
+impl Projection for ExecutionStatusProjection {
+    type State = ExecutionStatusView;
+
+    fn name(&self) -> &str {
+        "execution-status"
+    }
+
+    fn apply(&self, state: &mut ExecutionStatusView, event: &RecordedEvent) {
+        let AnyEvent::Pipeline(pipeline_event) = &event.envelope.event else {
+            return;
+        };
+        let timestamp = event.envelope.metadata.timestamp;
+
+        match pipeline_event {
+            PipelineEvent::Created { pipeline_id, name } => {
+                state.pipelines.insert(*pipeline_id, name.clone());
+                for execution in state.executions.values_mut() {
+                    if execution.pipeline_id == *pipeline_id {
+                        execution.pipeline_name = Some(name.clone());
+                    }
+                }
+            }
+            PipelineEvent::Started {
+                pipeline_id,
+                execution_id,
+                ..
+            } => {
+                state.execution(*pipeline_id, *execution_id, timestamp);
+            }
+            PipelineEvent::StageStarted {
+                pipeline_id,
+                execution_id,
+                stage_name,
+            } => {
+                state
+                    .execution(*pipeline_id, *execution_id, timestamp)
+                    .current_stage = Some(stage_name.clone());
+            }
+            PipelineEvent::StageCompleted {
+                pipeline_id,
+                execution_id,
+                stage_name,
+                ..
+            } => {
+                let execution = state.execution(*pipeline_id, *execution_id, timestamp);
+                if execution.current_stage.as_ref() == Some(stage_name) {
+                    execution.current_stage = None;
+                }
+            }
+            PipelineEvent::Completed {
+                pipeline_id,
+                execution_id,
+                result,
+            } => state.finish(
+                *pipeline_id,
+                *execution_id,
+                timestamp,
+                RunStatus::from_result(result),
+                None,
+            ),
+            PipelineEvent::Failed {
+                pipeline_id,
+                execution_id,
+                error,
+            } => state.finish(
+                *pipeline_id,
+                *execution_id,
+                timestamp,
+                RunStatus::Failure,
+                Some(error.clone()),
+            ),
+            PipelineEvent::Cancelled {
+                pipeline_id,
+                execution_id,
+                reason,
+            } => state.finish(
+                *pipeline_id,
+                *execution_id,
+                timestamp,
+                RunStatus::Aborted,
+                Some(reason.clone()),
+            ),
+            PipelineEvent::StepStarted { .. } | PipelineEvent::StepCompleted { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::projection::Projector;
+    use crate::types::{EventEnvelope, EventMetadata};
+
+    fn recorded(position: u64, event: PipelineEvent) -> RecordedEvent {
+        RecordedEvent {
+            position,
+            aggregate_id: *event.aggregate_id(),
+            version: position,
+            envelope: EventEnvelope::new(AnyEvent::Pipeline(event), EventMetadata::new("test")),
+        }
+    }
+
+    #[test]
+    fn test_execution_status() {
+        let pipeline_id = Uuid::new_v4();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let projector = Projector::new(ExecutionStatusProjection);
+
+        let events = [
+            PipelineEvent::Created {
+                pipeline_id,
+                name: "deploy".to_string(),
+            },
+            PipelineEvent::Started {
+                pipeline_id,
+                execution_id: first,
+                stage: String::new(),
+            },
+            PipelineEvent::StageStarted {
+                pipeline_id,
+                execution_id: first,
+                stage_name: "build".to_string(),
+            },
+            PipelineEvent::Started {
+                pipeline_id,
+                execution_id: second,
+                stage: String::new(),
+            },
+            PipelineEvent::Failed {
+                pipeline_id,
+                execution_id: second,
+                error: "exit code 1".to_string(),
+            },
+        ];
+        for (position, event) in (1..).zip(events) {
+            projector.apply(&recorded(position, event));
+        }
+
+        let view = projector.state();
+        let running = &view.executions[&first];
+        assert_eq!(running.status, RunStatus::Running);
+        assert_eq!(running.pipeline_name.as_deref(), Some("deploy"));
+        assert_eq!(running.current_stage.as_deref(), Some("build"));
+        let failed = &view.executions[&second];
+        assert_eq!(failed.status, RunStatus::Failure);
+        assert_eq!(failed.error.as_deref(), Some("exit code 1"));
+        assert!(failed.completed_at.is_some());
+
+        projector.apply(&recorded(
+            6,
+            PipelineEvent::Completed {
+                pipeline_id,
+                execution_id: first,
+                result: "UNSTABLE".to_string(),
+            },
+        ));
+        let view = projector.state();
+        assert_eq!(view.executions[&first].status, RunStatus::Unstable);
+        assert_eq!(view.executions[&first].current_stage, None);
+        assert_eq!(view.running().count(), 0);
+        assert_eq!(view.executions_of(&pipeline_id).len(), 2);
+    }
+}
@@ -0,0 +1,348 @@
+//! Projections building read models from the event log.
+//!
+//! A [`Projection`] folds events into a read model. A [`Projector`] holds
+//! the read model together with its checkpoint, the position of the last
+//! event folded in, and brings it up to date from an [`EventStore`]:
+//! replaying the log, following new events through a
+//! [`CatchUpSubscription`], or rebuilding from scratch. Saving the read
+//! model as a [`Snapshot`] lets a restarted process resume from the
+//! checkpoint instead of replaying everything.
+//!
+//! Built-in projections:
+//!
+//! - [`ExecutionStatusProjection`]: status of every pipeline run
+//! - [`StageTimelineProjection`]: stages and steps of every run over time
+//! - [`WorkerUtilisationProjection`]: jobs and busy time of every worker
+
+pub mod execution;
+pub mod timeline;
+pub mod worker;
+
+use std::sync::{Arc, RwLock};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::event_bus::Subscription;
+use crate::event_store::{CatchUpSubscription, EventStore, RecordedEvent, Snapshot, SnapshotStore};
+
+pub use execution::{ExecutionStatusProjection, ExecutionStatusView, ExecutionView};
+pub use timeline::{StageTimeline, StageTimelineProjection, StageTimelineView, StepTimeline};
+pub use worker::{WorkerState, WorkerUtilisationProjection, WorkerUtilisationView, WorkerView};
+
+/// Number of events read from the store at a time when catching up
+const BATCH_SIZE: usize = 256;
+
+/// Folds events into a read model
+pub trait Projection: Send + Sync {
+    /// Read model built by the projection
+    type State: Default + Clone + Serialize + DeserializeOwned + Send + Sync;
+
+    /// Returns the name of the projection
+    ///
+    /// Snapshots are stored under the name, so it must be unique and
+    /// stable.
+    fn name(&self) -> &str;
+
+    /// Folds an event into the read model
+    ///
+    /// Events are applied once each, in position order. Events the
+    /// projection has no use for are ignored.
+    fn apply(&self, state: &mut Self::State, event: &RecordedEvent);
+}
+
+/// Status of a pipeline run or stage in the read models
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RunStatus {
+    /// Started and not finished yet
+    Running,
+    /// Finished successfully
+    Success,
+    /// Finished with test failures or warnings
+    Unstable,
+    /// Finished with an error
+    Failure,
+    /// Stopped before finishing
+    Aborted,
+}
+
+impl RunStatus {
+    /// Parses the result recorded in completion events, such as `SUCCESS`
+    ///
+    /// Unknown results count as failures.
+    pub fn from_result(result: &str) -> Self {
+        match result {
+            "SUCCESS" => Self::Success,
+            "UNSTABLE" => Self::Unstable,
+            "ABORTED" | "NOT_BUILT" => Self::Aborted,
+            _ => Self::Failure,
+        }
+    }
+
+    /// Returns true once the run or stage has finished
+    pub fn is_finished(self) -> bool {
+        self != Self::Running
+    }
+}
+
+/// Projection errors
+#[derive(Debug, thiserror::Error)]
+pub enum ProjectionError {
+    /// The snapshot store failed
+    #[error("Snapshot store error: {0}")]
+    SnapshotStore(String),
+
+    /// The read model could not be serialized
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// Read model and the position of the last event folded into it
+#[derive(Debug, Default)]
+struct Projected<S> {
+    state: S,
+    checkpoint: u64,
+}
+
+/// Keeps the read model of a projection up to date
+#[derive(Debug)]
+pub struct Projector<P: Projection> {
+    projection: P,
+    projected: RwLock<Projected<P::State>>,
+}
+
+impl<P: Projection> Projector<P> {
+    /// Creates a projector with an empty read model
+    pub fn new(projection: P) -> Self {
+        Self {
+            projection,
+            projected: RwLock::new(Projected::default()),
+        }
+    }
+
+    /// Returns the projection
+    pub fn projection(&self) -> &P {
+        &self.projection
+    }
+
+    /// Returns a copy of the read model
+    pub fn state(&self) -> P::State {
+        self.projected.read().unwrap().state.clone()
+    }
+
+    /// Reads the read model without copying it
+    pub fn read<R>(&self, f: impl FnOnce(&P::State) -> R) -> R {
+        f(&self.projected.read().unwrap().state)
+    }
+
+    /// Returns the position of the last event folded into the read model
+    pub fn checkpoint(&self) -> u64 {
+        self.projected.read().unwrap().checkpoint
+    }
+
+    /// Folds an event into the read model
+    ///
+    /// Events at or before the checkpoint were already applied and are
+    /// skipped. Returns true if the event was applied.
+    pub fn apply(&self, event: &RecordedEvent) -> bool {
+        let mut projected = self.projected.write().unwrap();
+        if event.position <= projected.checkpoint {
+            return false;
+        }
+        self.projection.apply(&mut projected.state, event);
+        projected.checkpoint = event.position;
+        true
+    }
+
+    /// Applies the events stored after the checkpoint
+    ///
+    /// Returns the number of events applied.
+    pub async fn catch_up<S: EventStore + ?Sized>(&self, store: &S) -> Result<usize, S::Error> {
+        let mut applied = 0;
+        loop {
+            let batch = store.read_all(self.checkpoint() + 1, BATCH_SIZE).await?;
+            if batch.is_empty() {
+                return Ok(applied);
+            }
+            for event in &batch {
+                if self.apply(event) {
+                    applied += 1;
+                }
+            }
+        }
+    }
+
+    /// Empties the read model and replays the whole store
+    pub async fn rebuild<S: EventStore + ?Sized>(&self, store: &S) -> Result<usize, S::Error> {
+        *self.projected.write().unwrap() = Projected::default();
+        self.catch_up(store).await
+    }
+
+    /// Catches up, then applies new events until the bus closes
+    ///
+    /// `live` is a subscription to the bus the store's events are published
+    /// on.
+    pub async fn follow<S: EventStore>(
+        &self,
+        store: Arc<S>,
+        live: Subscription,
+    ) -> Result<(), S::Error> {
+        let mut subscription = CatchUpSubscription::new(store, live, self.checkpoint());
+        while let Some(event) = subscription.next().await? {
+            self.apply(&event);
+        }
+        Ok(())
+    }
+
+    /// Returns the ID the projection's snapshots are stored under
+    pub fn snapshot_id(&self) -> Uuid {
+        Uuid::new_v5(
+            &Uuid::NAMESPACE_OID,
+            format!("pipeliner.projection.{}", self.projection.name()).as_bytes(),
+        )
+    }
+
+    /// Saves the read model and its checkpoint as a snapshot
+    pub async fn save_snapshot<N: SnapshotStore + ?Sized>(
+        &self,
+        snapshots: &N,
+    ) -> Result<(), ProjectionError> {
+        let snapshot = {
+            let projected = self.projected.read().unwrap();
+            Snapshot {
+                aggregate_id: self.snapshot_id(),
+                aggregate_type: self.projection.name().to_string(),
+                version: projected.checkpoint,
+                state: serde_json::to_value(&projected.state)?,
+                created_at: chrono::Utc::now(),
+            }
+        };
+        snapshots
+            .save(&snapshot)
+            .await
+            .map_err(|e| ProjectionError::SnapshotStore(e.to_string()))
+    }
+
+    /// Restores the read model and checkpoint from the last snapshot
+    ///
+    /// Returns false, leaving the read model as it is, if there is no
+    /// snapshot or it no longer matches the read model, in which case the
+    /// projection has to catch up from the start.
+    pub async fn restore<N: SnapshotStore + ?Sized>(
+        &self,
+        snapshots: &N,
+    ) -> Result<bool, ProjectionError> {
+        let Some(snapshot) = snapshots
+            .load(&self.snapshot_id())
+            .await
+            .map_err(|e| ProjectionError::SnapshotStore(e.to_string()))?
+        else {
+            return Ok(false);
+        };
+
+        match serde_json::from_value(snapshot.state) {
+            Ok(state) => {
+                *self.projected.write().unwrap() = Projected {
+                    state,
+                    checkpoint: snapshot.version,
+                };
+                Ok(true)
+            }
+            Err(e) => {
+                warn!(
+                    "Ignoring snapshot of projection {}: {}",
+                    self.projection.name(),
+                    e
+                );
+                Ok(false)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_store::{InMemoryEventStore, InMemorySnapshotStore};
+    use crate::types::{AnyEvent, EventEnvelope, EventMetadata, PipelineEvent};
+
+    /// Counts the pipelines created
+    struct CreatedCount;
+
+    impl Projection for CreatedCount {
+        type State = u64;
+
+        fn name(&self) -> &str {
+            "created-count"
+        }
+
+        fn apply(&self, state: &mut u64, event: &RecordedEvent) {
+            if let AnyEvent::Pipeline(PipelineEvent::Created { .. }) = event.envelope.event {
+                *state += 1;
+            }
+        }
+    }
+
+    async fn create_pipelines(store: &InMemoryEventStore, count: usize) {
+        for _ in 0..count {
+            let id = Uuid::new_v4();
+            let event = EventEnvelope::new(
+                AnyEvent::Pipeline(PipelineEvent::Created {
+                    pipeline_id: id,
+                    name: "pipeline".to_string(),
+                }),
+                EventMetadata::new("test"),
+            );
+            store.append(&id, None, &[event]).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_projector_catches_up_and_rebuilds() {
+        let store = InMemoryEventStore::new();
+        create_pipelines(&store, 300).await;
+
+        let projector = Projector::new(CreatedCount);
+        assert_eq!(projector.catch_up(&store).await.unwrap(), 300);
+        assert_eq!(projector.state(), 300);
+        assert_eq!(projector.checkpoint(), 300);
+
+        let replayed = store.read_all(1, 1).await.unwrap();
+        assert!(!projector.apply(&replayed[0]));
+
+        create_pipelines(&store, 2).await;
+        assert_eq!(projector.catch_up(&store).await.unwrap(), 2);
+        assert_eq!(projector.rebuild(&store).await.unwrap(), 302);
+        assert_eq!(projector.state(), 302);
+    }
+
+    #[tokio::test]
+    async fn test_projector_resumes_from_snapshot() {
+        let store = InMemoryEventStore::new();
+        let snapshots = InMemorySnapshotStore::new();
+        create_pipelines(&store, 3).await;
+
+        let projector = Projector::new(CreatedCount);
+        assert!(!projector.restore(&snapshots).await.unwrap());
+        projector.catch_up(&store).await.unwrap();
+        projector.save_snapshot(&snapshots).await.unwrap();
+        create_pipelines(&store, 2).await;
+
+        let restarted = Projector::new(CreatedCount);
+        assert!(restarted.restore(&snapshots).await.unwrap());
+        assert_eq!(restarted.checkpoint(), 3);
+        assert_eq!(restarted.catch_up(&store).await.unwrap(), 2);
+        assert_eq!(restarted.state(), 5);
+    }
+
+    #[test]
+    fn test_run_status_from_result() {
+        assert_eq!(RunStatus::from_result("SUCCESS"), RunStatus::Success);
+        assert_eq!(RunStatus::from_result("UNSTABLE"), RunStatus::Unstable);
+        assert_eq!(RunStatus::from_result("ABORTED"), RunStatus::Aborted);
+        assert_eq!(RunStatus::from_result("FAILURE"), RunStatus::Failure);
+        assert!(!RunStatus::Running.is_finished());
+    }
+}
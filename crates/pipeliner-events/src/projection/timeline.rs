@@ -0,0 +1,253 @@
+//! Stages and steps of pipeline runs over time.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{Projection, RunStatus};
+use crate::event_store::RecordedEvent;
+use crate::types::{AnyEvent, PipelineEvent};
+
+/// A step of a stage
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StepTimeline {
+    /// Name of the step
+    pub name: String,
+    /// When the step started
+    pub started_at: DateTime<Utc>,
+    /// When the step finished
+    pub completed_at: Option<DateTime<Utc>>,
+    /// Output of the step, or its error if it failed
+    pub output: Option<String>,
+}
+
+impl StepTimeline {
+    /// Returns how long the step ran, once it finished
+    pub fn duration(&self) -> Option<Duration> {
+        Some(self.completed_at? - self.started_at)
+    }
+}
+
+/// A stage of a run
+///
+/// A stage that is retried appears once per attempt.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StageTimeline {
+    /// Name of the stage
+    pub name: String,
+    /// Status of the stage
+    pub status: RunStatus,
+    /// When the stage started
+    pub started_at: DateTime<Utc>,
+    /// When the stage finished
+    pub completed_at: Option<DateTime<Utc>>,
+    /// Steps of the stage, in start order
+    pub steps: Vec<StepTimeline>,
+}
+
+impl StageTimeline {
+    /// Returns how long the stage ran, once it finished
+    pub fn duration(&self) -> Option<Duration> {
+        Some(self.completed_at? - self.started_at)
+    }
+}
+
+/// Read model of [`StageTimelineProjection`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StageTimelineView {
+    /// Stages of each run by execution ID, in start order
+    pub executions: HashMap<Uuid, Vec<StageTimeline>>,
+}
+
+impl StageTimelineView {
+    /// Returns the stages of a run, in start order
+    pub fn stages(&self, execution_id: &Uuid) -> &[StageTimeline] {
+        self.executions.get(execution_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the latest attempt of a stage that has not finished
+    fn running_stage(&mut self, execution_id: &Uuid, name: &str) -> Option<&mut StageTimeline> {
+        self.executions
+            .get_mut(execution_id)?
+            .iter_mut()
+            .rev()
+            .find(|s| s.name == name && s.completed_at.is_none())
+    }
+}
+
+/// Projection of the stages and steps of every pipeline run
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StageTimelineProjection;
+
+impl Projection for StageTimelineProjection {
+    type State = StageTimelineView;
+
+    fn name(&self) -> &str {
+        "stage-timeline"
+    }
+
+    fn apply(&self, state: &mut StageTimelineView, event: &RecordedEvent) {
+        let AnyEvent::Pipeline(pipeline_event) = &event.envelope.event else {
+            return;
+        };
+        let timestamp = event.envelope.metadata.timestamp;
+
+        match pipeline_event {
+            PipelineEvent::StageStarted {
+                execution_id,
+                stage_name,
+                ..
+            } => state
+                .executions
+                .entry(*execution_id)
+                .or_default()
+                .push(StageTimeline {
+                    name: stage_name.clone(),
+                    status: RunStatus::Running,
+                    started_at: timestamp,
+                    completed_at: None,
+                    steps: Vec::new(),
+                }),
+            PipelineEvent::StageCompleted {
+                execution_id,
+                stage_name,
+                result,
+                ..
+            } => {
+                if let Some(stage) = state.running_stage(execution_id, stage_name) {
+                    stage.status = RunStatus::from_result(result);
+                    stage.completed_at = Some(timestamp);
+                }
+            }
+            PipelineEvent::StepStarted {
+                execution_id,
+                stage_name,
+                step_name,
+                ..
+            } => {
+                if let Some(stage) = state.running_stage(execution_id, stage_name) {
+                    stage.steps.push(StepTimeline {
+                        name: step_name.clone(),
+                        started_at: timestamp,
+                        completed_at: None,
+                        output: None,
+                    });
+                }
+            }
+            PipelineEvent::StepCompleted {
+                execution_id,
+                stage_name,
+                step_name,
+                output,
+                ..
+            } => {
+                let step = state
+                    .running_stage(execution_id, stage_name)
+                    .and_then(|stage| {
+                        stage
+                            .steps
+                            .iter_mut()
+                            .rev()
+                            .find(|s| s.name == *step_name && s.completed_at.is_none())
+                    });
+                if let Some(step) = step {
+                    step.completed_at = Some(timestamp);
+                    step.output.clone_from(output);
+                }
+            }
+            // Stages still running when the run ends never finished
+            PipelineEvent::Completed { execution_id, .. }
+            | PipelineEvent::Failed { execution_id, .. }
+            | PipelineEvent::Cancelled { execution_id, .. } => {
+                for stage in state.executions.get_mut(execution_id).into_iter().flatten() {
+                    if stage.completed_at.is_none() {
+                        stage.status = RunStatus::Aborted;
+                        stage.completed_at = Some(timestamp);
+                    }
+                }
+            }
+            PipelineEvent::Created { .. } | PipelineEvent::Started { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::projection::Projector;
+    use crate::types::{EventEnvelope, EventMetadata};
+
+    #[test]
+    fn test_stage_timeline() {
+        let pipeline_id = Uuid::new_v4();
+        let execution_id = Uuid::new_v4();
+        let stage = |stage_name: &str| PipelineEvent::StageStarted {
+            pipeline_id,
+            execution_id,
+            stage_name: stage_name.to_string(),
+        };
+        let stage_done = |stage_name: &str, result: &str| PipelineEvent::StageCompleted {
+            pipeline_id,
+            execution_id,
+            stage_name: stage_name.to_string(),
+            result: result.to_string(),
+        };
+        let step = |step_name: &str| PipelineEvent::StepStarted {
+            pipeline_id,
+            execution_id,
+            stage_name: "build".to_string(),
+            step_name: step_name.to_string(),
+        };
+        let step_done = |step_name: &str| PipelineEvent::StepCompleted {
+            pipeline_id,
+            execution_id,
+            stage_name: "build".to_string(),
+            step_name: step_name.to_string(),
+            output: Some("ok".to_string()),
+        };
+
+        let events = [
+            stage("build"),
+            step("sh"),
+            step_done("sh"),
+            stage_done("build", "FAILURE"),
+            stage("build"),
+            step("sh"),
+            stage_done("build", "SUCCESS"),
+            stage("deploy"),
+            PipelineEvent::Cancelled {
+                pipeline_id,
+                execution_id,
+                reason: "stopped".to_string(),
+            },
+        ];
+        let projector = Projector::new(StageTimelineProjection);
+        for (position, event) in (1..).zip(events) {
+            projector.apply(&RecordedEvent {
+                position,
+                aggregate_id: pipeline_id,
+                version: position,
+                envelope: EventEnvelope::new(AnyEvent::Pipeline(event), EventMetadata::new("test")),
+            });
+        }
+
+        let view = projector.state();
+        let stages = view.stages(&execution_id);
+        let summary: Vec<_> = stages.iter().map(|s| (s.name.as_str(), s.status)).collect();
+        assert_eq!(
+            summary,
+            [
+                ("build", RunStatus::Failure),
+                ("build", RunStatus::Success),
+                ("deploy", RunStatus::Aborted),
+            ]
+        );
+        assert_eq!(stages[0].steps[0].output.as_deref(), Some("ok"));
+        assert!(stages[0].steps[0].duration().is_some());
+        assert_eq!(stages[1].steps.len(), 1);
+        assert!(stages.iter().all(|s| s.duration().is_some()));
+        assert!(view.stages(&Uuid::new_v4()).is_empty());
+    }
+}
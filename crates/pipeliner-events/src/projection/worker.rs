@@ -0,0 +1,266 @@
+//! Jobs and busy time of workers.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::Projection;
+use crate::event_store::RecordedEvent;
+use crate::types::{AnyEvent, WorkerEvent};
+
+/// What a worker is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WorkerState {
+    /// Waiting for a job
+    Idle,
+    /// Running a job
+    Busy,
+    /// Shut down
+    Stopped,
+}
+
+impl WorkerState {
+    /// Returns the state as a lowercase string
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Idle => "idle",
+            Self::Busy => "busy",
+            Self::Stopped => "stopped",
+        }
+    }
+}
+
+/// A worker and the jobs it ran
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkerView {
+    /// ID of the worker
+    pub worker_id: String,
+    /// What the worker is doing
+    pub state: WorkerState,
+    /// Job assigned to or running on the worker
+    pub current_job: Option<Uuid>,
+    /// Number of jobs that succeeded
+    pub jobs_completed: usize,
+    /// Number of jobs that failed
+    pub jobs_failed: usize,
+    /// When the worker started
+    pub started_at: DateTime<Utc>,
+    /// When the worker stopped
+    pub stopped_at: Option<DateTime<Utc>>,
+    /// When the running job started
+    pub busy_since: Option<DateTime<Utc>>,
+    /// Time spent on finished jobs, in milliseconds
+    pub busy_millis: i64,
+    /// When the worker last sent a heartbeat
+    pub last_heartbeat: Option<DateTime<Utc>>,
+}
+
+impl WorkerView {
+    fn new(worker_id: &str, started_at: DateTime<Utc>) -> Self {
+        Self {
+            worker_id: worker_id.to_string(),
+            state: WorkerState::Idle,
+            current_job: None,
+            jobs_completed: 0,
+            jobs_failed: 0,
+            started_at,
+            stopped_at: None,
+            busy_since: None,
+            busy_millis: 0,
+            last_heartbeat: None,
+        }
+    }
+
+    /// Returns the time spent on jobs up to `now`, including a running job
+    pub fn busy_time(&self, now: DateTime<Utc>) -> Duration {
+        let running = self.busy_since.map_or(Duration::zero(), |since| {
+            (now - since).max(Duration::zero())
+        });
+        Duration::milliseconds(self.busy_millis) + running
+    }
+
+    /// Returns the fraction of its uptime the worker spent on jobs, up to
+    /// `now`
+    pub fn utilisation(&self, now: DateTime<Utc>) -> f64 {
+        let end = self.stopped_at.unwrap_or(now);
+        let uptime = (end - self.started_at).num_milliseconds();
+        if uptime <= 0 {
+            return 0.0;
+        }
+        let busy = self.busy_time(end).num_milliseconds();
+        (busy as f64 / uptime as f64).clamp(0.0, 1.0)
+    }
+
+    /// Ends the running job, adding its time to the busy time
+    fn finish_job(&mut self, timestamp: DateTime<Utc>) {
+        if let Some(since) = self.busy_since.take() {
+            self.busy_millis += (timestamp - since).num_milliseconds().max(0);
+        }
+        self.current_job = None;
+        if self.state == WorkerState::Busy {
+            self.state = WorkerState::Idle;
+        }
+    }
+}
+
+/// Read model of [`WorkerUtilisationProjection`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorkerUtilisationView {
+    /// Workers by ID
+    pub workers: HashMap<String, WorkerView>,
+}
+
+impl WorkerUtilisationView {
+    /// Returns the worker, recording it as started at `timestamp` if unknown
+    fn worker(&mut self, worker_id: &str, timestamp: DateTime<Utc>) -> &mut WorkerView {
+        self.workers
+            .entry(worker_id.to_string())
+            .or_insert_with(|| WorkerView::new(worker_id, timestamp))
+    }
+}
+
+/// Projection of the jobs and busy time of every worker
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WorkerUtilisationProjection;
+
+impl Projection for WorkerUtilisationProjection {
+    type State = WorkerUtilisationView;
+
+    fn name(&self) -> &str {
+        "worker-utilisation"
+    }
+
+    fn apply(&self, state: &mut WorkerUtilisationView, event: &RecordedEvent) {
+        let AnyEvent::Worker(worker_event) = &event.envelope.event else {
+            return;
+        };
+        let timestamp = event.envelope.metadata.timestamp;
+
+        match worker_event {
+            WorkerEvent::WorkerStarted { worker_id } => {
+                state
+                    .workers
+                    .insert(worker_id.clone(), WorkerView::new(worker_id, timestamp));
+            }
+            WorkerEvent::WorkerStopped { worker_id, .. } => {
+                let worker = state.worker(worker_id, timestamp);
+                worker.finish_job(timestamp);
+                worker.state = WorkerState::Stopped;
+                worker.stopped_at = Some(timestamp);
+            }
+            WorkerEvent::JobAssigned { worker_id, job_id } => {
+                state.worker(worker_id, timestamp).current_job = Some(*job_id);
+            }
+            WorkerEvent::JobStarted { worker_id, job_id } => {
+                let worker = state.worker(worker_id, timestamp);
+                worker.finish_job(timestamp);
+                worker.state = WorkerState::Busy;
+                worker.current_job = Some(*job_id);
+                worker.busy_since = Some(timestamp);
+            }
+            WorkerEvent::JobCompleted { worker_id, .. } => {
+                let worker = state.worker(worker_id, timestamp);
+                worker.finish_job(timestamp);
+                worker.jobs_completed += 1;
+            }
+            WorkerEvent::JobFailed { worker_id, .. } => {
+                let worker = state.worker(worker_id, timestamp);
+                worker.finish_job(timestamp);
+                worker.jobs_failed += 1;
+            }
+            WorkerEvent::Heartbeat { worker_id } => {
+                state.worker(worker_id, timestamp).last_heartbeat = Some(timestamp);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::projection::Projector;
+    use crate::types::{EventEnvelope, EventMetadata};
+
+    #[test]
+    fn test_worker_utilisation() {
+        let start = Utc::now();
+        let job = Uuid::new_v4();
+        let worker_id = "worker-1".to_string();
+        let events = [
+            (
+                0,
+                WorkerEvent::WorkerStarted {
+                    worker_id: worker_id.clone(),
+                },
+            ),
+            (
+                10,
+                WorkerEvent::JobStarted {
+                    worker_id: worker_id.clone(),
+                    job_id: job,
+                },
+            ),
+            (
+                40,
+                WorkerEvent::JobCompleted {
+                    worker_id: worker_id.clone(),
+                    job_id: job,
+                    result: "SUCCESS".to_string(),
+                },
+            ),
+            (
+                50,
+                WorkerEvent::JobStarted {
+                    worker_id: worker_id.clone(),
+                    job_id: job,
+                },
+            ),
+            (
+                60,
+                WorkerEvent::JobFailed {
+                    worker_id: worker_id.clone(),
+                    job_id: job,
+                    error: "killed".to_string(),
+                },
+            ),
+            (
+                70,
+                WorkerEvent::Heartbeat {
+                    worker_id: worker_id.clone(),
+                },
+            ),
+            (
+                80,
+                WorkerEvent::JobStarted {
+                    worker_id: worker_id.clone(),
+                    job_id: job,
+                },
+            ),
+        ];
+
+        let projector = Projector::new(WorkerUtilisationProjection);
+        for (position, (seconds, event)) in (1..).zip(events) {
+            let mut metadata = EventMetadata::new("worker");
+            metadata.timestamp = start + Duration::seconds(seconds);
+            projector.apply(&RecordedEvent {
+                position,
+                aggregate_id: Uuid::nil(),
+                version: position,
+                envelope: EventEnvelope::new(AnyEvent::Worker(event), metadata),
+            });
+        }
+
+        let view = projector.state();
+        let worker = &view.workers[&worker_id];
+        assert_eq!(worker.state, WorkerState::Busy);
+        assert_eq!(worker.current_job, Some(job));
+        assert_eq!((worker.jobs_completed, worker.jobs_failed), (1, 1));
+        assert_eq!(worker.last_heartbeat, Some(start + Duration::seconds(70)));
+
+        let now = start + Duration::seconds(100);
+        assert_eq!(worker.busy_time(now), Duration::seconds(60));
+        assert!((worker.utilisation(now) - 0.6).abs() < 1e-9);
+    }
+}
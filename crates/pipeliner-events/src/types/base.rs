@@ -7,6 +7,8 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use super::upcast::{CURRENT_SCHEMA_VERSION, FIRST_SCHEMA_VERSION};
+
 /// Event metadata
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EventMetadata {
@@ -83,14 +85,28 @@ impl AnyEvent {
 pub struct EventEnvelope {
     pub event: AnyEvent,
     pub metadata: EventMetadata,
+    /// Version of the event schema the envelope was written with
+    ///
+    /// Envelopes written before the schema was versioned have none and are
+    /// version 1.
+    #[serde(default = "first_schema_version")]
+    pub schema_version: u32,
 }
 
 impl EventEnvelope {
     pub fn new(event: AnyEvent, metadata: EventMetadata) -> Self {
-        Self { event, metadata }
+        Self {
+            event,
+            metadata,
+            schema_version: CURRENT_SCHEMA_VERSION,
+        }
     }
 }
 
+fn first_schema_version() -> u32 {
+    FIRST_SCHEMA_VERSION
+}
+
 /// Pipeline events
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PipelineEvent {
@@ -1,7 +1,9 @@
 //! Event types module.
 
 pub mod base;
+pub mod upcast;
 
 pub use base::{
     AnyEvent, EventEnvelope, EventMetadata, InfrastructureEvent, PipelineEvent, WorkerEvent,
 };
+pub use upcast::{CURRENT_SCHEMA_VERSION, UpcastError, UpcasterRegistry};
@@ -0,0 +1,270 @@
+//! Versioning of the event schema.
+//!
+//! Every envelope records the schema version it was written with. Changing
+//! the shape of a stored event type, such as renaming a variant or a field,
+//! takes three steps:
+//!
+//! 1. bump [`CURRENT_SCHEMA_VERSION`];
+//! 2. register in [`UpcasterRegistry::default`] an upcaster from the
+//!    previous version that rewrites old JSON into the new shape;
+//! 3. add fixtures written with the previous version under
+//!    `tests/fixtures/events/`, which must keep deserializing.
+//!
+//! Stores read envelopes through an [`UpcasterRegistry`], which runs the
+//! upcasters from the envelope's version up to the current one before
+//! deserializing it. Versions without upcasters only added to the schema
+//! and need no migration.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde_json::Value;
+
+use super::base::EventEnvelope;
+
+/// Schema version of envelopes written before versioning
+pub const FIRST_SCHEMA_VERSION: u32 = 1;
+
+/// Schema version of the event types in this crate
+pub const CURRENT_SCHEMA_VERSION: u32 = 1;
+
+/// Rewrites the JSON of an envelope into the shape of the next version
+type Upcaster = Box<dyn Fn(&mut Value) -> Result<(), String> + Send + Sync>;
+
+/// Upcaster errors
+#[derive(Debug, thiserror::Error)]
+pub enum UpcastError {
+    /// The envelope was written by a newer version
+    #[error("event schema version {version} is newer than the supported version {supported}")]
+    UnsupportedVersion {
+        /// Version of the envelope
+        version: u32,
+        /// Latest version the registry reads
+        supported: u32,
+    },
+
+    /// The envelope's version is not a number, or the envelope not an object
+    #[error("invalid event envelope: {0}")]
+    InvalidEnvelope(String),
+
+    /// An upcaster rejected the envelope
+    #[error("cannot upcast event from schema version {version}: {reason}")]
+    Failed {
+        /// Version the upcaster migrates from
+        version: u32,
+        /// Why the upcaster failed
+        reason: String,
+    },
+
+    /// The upcast envelope does not match the event types
+    #[error("invalid event: {0}")]
+    Deserialization(#[from] serde_json::Error),
+}
+
+/// Upcasters migrating stored envelopes to the current schema
+pub struct UpcasterRegistry {
+    target_version: u32,
+    upcasters: BTreeMap<u32, Vec<Upcaster>>,
+}
+
+impl Default for UpcasterRegistry {
+    /// Returns the registry with the upcasters of this crate's schema
+    /// history
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UpcasterRegistry {
+    /// Creates a registry without upcasters, migrating to the current
+    /// version
+    pub fn new() -> Self {
+        Self::targeting(CURRENT_SCHEMA_VERSION)
+    }
+
+    /// Creates a registry without upcasters, migrating to `target_version`
+    ///
+    /// Lets upcasters be tried out ahead of a version bump.
+    pub fn targeting(target_version: u32) -> Self {
+        Self {
+            target_version,
+            upcasters: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the version envelopes are migrated to
+    pub fn target_version(&self) -> u32 {
+        self.target_version
+    }
+
+    /// Registers an upcaster from `from_version` to the next version
+    ///
+    /// The upcaster receives the JSON of a whole envelope, so it can
+    /// migrate the event and its metadata. Upcasters of the same version
+    /// run in registration order and must leave envelopes they do not
+    /// migrate unchanged.
+    pub fn register(
+        mut self,
+        from_version: u32,
+        upcaster: impl Fn(&mut Value) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.upcasters
+            .entry(from_version)
+            .or_default()
+            .push(Box::new(upcaster));
+        self
+    }
+
+    /// Migrates the JSON of an envelope to the target version
+    pub fn upcast(&self, mut envelope: Value) -> Result<Value, UpcastError> {
+        let mut version = match envelope.get("schema_version") {
+            None => FIRST_SCHEMA_VERSION,
+            Some(version) => version
+                .as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .ok_or_else(|| UpcastError::InvalidEnvelope(format!("schema version {version}")))?,
+        };
+        if version > self.target_version {
+            return Err(UpcastError::UnsupportedVersion {
+                version,
+                supported: self.target_version,
+            });
+        }
+
+        while version < self.target_version {
+            for upcaster in self.upcasters.get(&version).into_iter().flatten() {
+                upcaster(&mut envelope)
+                    .map_err(|reason| UpcastError::Failed { version, reason })?;
+            }
+            version += 1;
+            envelope
+                .as_object_mut()
+                .ok_or_else(|| UpcastError::InvalidEnvelope("not an object".to_string()))?
+                .insert("schema_version".to_string(), version.into());
+        }
+        Ok(envelope)
+    }
+
+    /// Migrates and deserializes the JSON of an envelope
+    pub fn deserialize(&self, envelope: Value) -> Result<EventEnvelope, UpcastError> {
+        Ok(serde_json::from_value(self.upcast(envelope)?)?)
+    }
+
+    /// Migrates and deserializes an envelope from JSON bytes
+    pub fn from_slice(&self, bytes: &[u8]) -> Result<EventEnvelope, UpcastError> {
+        self.deserialize(serde_json::from_slice(bytes)?)
+    }
+}
+
+impl fmt::Debug for UpcasterRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UpcasterRegistry")
+            .field("target_version", &self.target_version)
+            .field(
+                "upcasters",
+                &self
+                    .upcasters
+                    .iter()
+                    .map(|(version, upcasters)| (version, upcasters.len()))
+                    .collect::<BTreeMap<_, _>>(),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AnyEvent, PipelineEvent};
+    use std::path::Path;
+
+    /// Every envelope of the fixtures, with the file it comes from
+    fn fixtures() -> Vec<(String, Value)> {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/events");
+        let mut files: Vec<_> = std::fs::read_dir(&root)
+            .unwrap()
+            .flat_map(|version| std::fs::read_dir(version.unwrap().path()).unwrap())
+            .map(|file| file.unwrap().path())
+            .collect();
+        files.sort();
+
+        let mut envelopes = Vec::new();
+        for file in files {
+            let name = file.strip_prefix(&root).unwrap().display().to_string();
+            let data = std::fs::read_to_string(&file).unwrap();
+            let fixtures: Vec<Value> = serde_json::from_str(&data).unwrap();
+            envelopes.extend(fixtures.into_iter().map(|e| (name.clone(), e)));
+        }
+        envelopes
+    }
+
+    #[test]
+    fn test_fixtures_of_past_versions_deserialize() {
+        let registry = UpcasterRegistry::default();
+        let fixtures = fixtures();
+        assert!(fixtures.len() >= 23, "fixtures are missing");
+
+        for (file, fixture) in fixtures {
+            let envelope = registry
+                .deserialize(fixture)
+                .unwrap_or_else(|e| panic!("{file}: {e}"));
+            assert_eq!(envelope.schema_version, CURRENT_SCHEMA_VERSION, "{file}");
+
+            let written = serde_json::to_value(&envelope).unwrap();
+            let reread = registry.deserialize(written).unwrap();
+            assert_eq!(reread.event.event_type(), envelope.event.event_type());
+        }
+    }
+
+    #[test]
+    fn test_upcasters_run_in_version_order() {
+        let registry = UpcasterRegistry::targeting(3)
+            .register(2, |envelope| {
+                let created = envelope["event"]
+                    .get_mut("Created")
+                    .ok_or("not a Created event")?;
+                let title = created["title"].take();
+                created["name"] = title;
+                Ok(())
+            })
+            .register(1, |envelope| {
+                if let Some(created) = envelope["event"].get_mut("Created") {
+                    created["title"] = created["name"].take();
+                }
+                Ok(())
+            });
+
+        let (_, created) = fixtures()
+            .into_iter()
+            .find(|(_, e)| e["event"].get("Created").is_some())
+            .unwrap();
+        let envelope = registry.deserialize(created.clone()).unwrap();
+        assert_eq!(envelope.schema_version, 3);
+        let AnyEvent::Pipeline(PipelineEvent::Created { name, .. }) = envelope.event else {
+            panic!("expected a Created event");
+        };
+        assert_eq!(name, "deploy");
+
+        let mut started = created;
+        started["event"] = serde_json::json!({"event_type": "Pipeline", "Started": {}});
+        let error = registry.upcast(started).unwrap_err();
+        assert!(matches!(error, UpcastError::Failed { version: 2, .. }));
+    }
+
+    #[test]
+    fn test_newer_versions_are_rejected() {
+        let (_, mut fixture) = fixtures().remove(0);
+        fixture["schema_version"] = (CURRENT_SCHEMA_VERSION + 1).into();
+        assert!(matches!(
+            UpcasterRegistry::default().deserialize(fixture),
+            Err(UpcastError::UnsupportedVersion { .. })
+        ));
+
+        let (_, mut fixture) = fixtures().remove(0);
+        fixture["schema_version"] = "one".into();
+        assert!(matches!(
+            UpcasterRegistry::default().upcast(fixture),
+            Err(UpcastError::InvalidEnvelope(_))
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+//! Cancellation of running executions.
+//!
+//! A [`CancellationToken`] is shared by an execution and whoever may stop
+//! it. Strategies stop scheduling stages once it is cancelled, running
+//! commands are killed, and the execution finishes as
+//! [`ExecutionStatus::Aborted`](crate::ExecutionStatus::Aborted).
+
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Token used to cancel a running execution
+///
+/// Clones share the same state, so any clone can cancel the execution.
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    reason: Arc<watch::Sender<Option<String>>>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            reason: Arc::new(watch::Sender::new(None)),
+        }
+    }
+
+    /// Cancels the execution
+    ///
+    /// Returns false if it was already cancelled, in which case the first
+    /// reason is kept.
+    pub fn cancel(&self, reason: impl Into<String>) -> bool {
+        let reason = reason.into();
+        self.reason.send_if_modified(|current| {
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(reason);
+            true
+        })
+    }
+
+    /// Returns true once the execution has been cancelled
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.reason.borrow().is_some()
+    }
+
+    /// Returns why the execution was cancelled
+    #[must_use]
+    pub fn reason(&self) -> Option<String> {
+        self.reason.borrow().clone()
+    }
+
+    /// Waits until the execution is cancelled and returns the reason
+    pub async fn cancelled(&self) -> String {
+        // The sender lives as long as `self`, so waiting cannot fail
+        let mut receiver = self.reason.subscribe();
+        receiver
+            .wait_for(Option::is_some)
+            .await
+            .map(|reason| reason.clone().unwrap_or_default())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_cancel_keeps_first_reason() {
+        let token = CancellationToken::new();
+        assert!(!token.is_cancelled());
+
+        assert!(token.clone().cancel("first"));
+        assert!(!token.cancel("second"));
+
+        assert!(token.is_cancelled());
+        assert_eq!(token.reason().as_deref(), Some("first"));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_wakes_waiters() {
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            canceller.cancel("stop");
+        });
+
+        let reason = tokio::time::timeout(Duration::from_secs(5), token.cancelled())
+            .await
+            .unwrap();
+        assert_eq!(reason, "stop");
+
+        // Already cancelled tokens return immediately
+        assert_eq!(token.cancelled().await, "stop");
+    }
+}
@@ -10,7 +10,33 @@ use std::sync::Arc;
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
-use pipeliner_core::{Environment, VariableResolver};
+use pipeliner_core::credentials::EnvCredentialsProvider;
+use pipeliner_core::{
+    CredentialsProvider, Environment, ExpressionContext, SecretMasker, VariableResolver,
+};
+
+use crate::listener::{EventEmitter, ExecutionEvent};
+use crate::{CancellationToken, ExecutionStatus};
+
+/// How an executor schedules the stages of a pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StrategyKind {
+    /// Matrix when the pipeline has a matrix, sequential otherwise
+    #[default]
+    Auto,
+    /// One stage after the other, stopping at the first failure
+    Sequential,
+    /// Stages side by side
+    Parallel {
+        /// Maximum number of stages running at once
+        max_concurrent: usize,
+    },
+    /// Every stage once per matrix cell
+    Matrix {
+        /// Maximum number of cells running at once
+        max_concurrent: usize,
+    },
+}
 
 /// Execution configuration
 #[derive(Debug, Clone)]
@@ -35,6 +61,10 @@ pub struct ExecutionConfig {
     pub quiet: bool,
     /// Write results to file
     pub output_file: Option<PathBuf>,
+    /// How stages are scheduled
+    pub strategy: StrategyKind,
+    /// Status of the previous build, for `changed` post conditions
+    pub previous_status: Option<ExecutionStatus>,
 }
 
 impl Default for ExecutionConfig {
@@ -50,6 +80,8 @@ impl Default for ExecutionConfig {
             colors: std::io::IsTerminal::is_terminal(&std::io::stdout()),
             quiet: false,
             output_file: None,
+            strategy: StrategyKind::Auto,
+            previous_status: None,
         }
     }
 }
@@ -81,6 +113,16 @@ pub struct ExecutionContext {
     pub parameters: HashMap<String, String>,
     /// Custom metadata
     pub metadata: HashMap<String, String>,
+    /// Cancels the execution, shared by every clone of this context
+    pub cancellation: CancellationToken,
+    /// Secrets masked in output, shared by every clone of this context
+    pub masker: SecretMasker,
+    /// Source of credentials for references and `withCredentials` steps
+    pub credentials: Arc<dyn CredentialsProvider>,
+    /// Receives the execution events, shared by every clone of this context
+    pub events: EventEmitter,
+    /// Status of the previous build, for `changed` post conditions
+    pub previous_status: Option<ExecutionStatus>,
 }
 
 impl Default for ExecutionContext {
@@ -106,6 +148,11 @@ impl ExecutionContext {
             dir_stack: Vec::new(),
             parameters: HashMap::new(),
             metadata: HashMap::new(),
+            cancellation: CancellationToken::new(),
+            masker: SecretMasker::new(),
+            credentials: Arc::new(EnvCredentialsProvider::new()),
+            events: EventEmitter::new(),
+            previous_status: None,
         }
     }
 
@@ -133,6 +180,32 @@ impl ExecutionContext {
         }
     }
 
+    /// Returns a copy of this context that ignores cancellation
+    ///
+    /// Used to run the post conditions of an aborted stage.
+    #[must_use]
+    pub fn detach_cancellation(&self) -> Self {
+        Self {
+            cancellation: CancellationToken::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Replaces the secrets in scope with `****`
+    ///
+    /// Every output, log line, event or error message that may contain
+    /// environment values goes through this before leaving the executor.
+    #[must_use]
+    pub fn mask(&self, text: &str) -> String {
+        self.masker.add_environment(&self.environment);
+        self.masker.mask(text)
+    }
+
+    /// Sends an event to the execution's listener, masking secrets
+    pub async fn emit(&self, event: ExecutionEvent) {
+        self.events.emit(event, self).await;
+    }
+
     /// Gets the current directory
     #[must_use]
     pub fn cwd(&self) -> &PathBuf {
@@ -170,6 +243,29 @@ impl ExecutionContext {
         results.push(result);
     }
 
+    /// Returns the variables seen by `when` conditions and expressions
+    ///
+    /// The build result is the worst result of the stages recorded so far,
+    /// or `None` while they all succeeded.
+    pub async fn expression_context(&self) -> ExpressionContext {
+        let mut context = ExpressionContext::new().with_environment(&self.environment);
+        context.params.clone_from(&self.parameters);
+
+        let mut worst = ExecutionStatus::Success;
+        for result in self.stage_results.lock().await.iter() {
+            context
+                .stage_results
+                .insert(result.name.clone(), result.status.result_name().to_string());
+            if severity(result.status) > severity(worst) {
+                worst = result.status;
+            }
+        }
+        if worst != ExecutionStatus::Success {
+            context.build_result = Some(worst.result_name().to_string());
+        }
+        context
+    }
+
     /// Sets a build parameter
     pub fn set_parameter(&mut self, name: impl Into<String>, value: impl Into<String>) {
         self.parameters.insert(name.into(), value.into());
@@ -224,6 +320,16 @@ impl VariableResolver for ExecutionContext {
     }
 }
 
+/// Orders statuses from best to worst build result
+fn severity(status: ExecutionStatus) -> u8 {
+    match status {
+        ExecutionStatus::Success | ExecutionStatus::Pending | ExecutionStatus::Running => 0,
+        ExecutionStatus::Unstable => 1,
+        ExecutionStatus::Failure | ExecutionStatus::Timeout => 2,
+        ExecutionStatus::Aborted => 3,
+    }
+}
+
 /// Stage execution result
 #[derive(Debug, Clone)]
 pub struct StageResult {
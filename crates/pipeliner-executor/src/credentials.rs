@@ -0,0 +1,385 @@
+//! Credentials bindings of `withCredentials` steps.
+//!
+//! A [`CredentialsScope`] looks the bound credentials up through the
+//! context's provider and exposes them to the nested steps: values as
+//! environment variables, keys and secret files as files in a private
+//! temporary directory whose path goes into the variable. Every secret is
+//! registered with the context's masker. The scope borrows the context the
+//! nested steps run on; dropping it, such as when a timeout cuts the steps
+//! short, restores the previous values of the bound variables and deletes
+//! the files. Other changes the steps make to the context are kept.
+
+use std::path::{Path, PathBuf};
+
+use pipeliner_core::credentials::{Credentials, CredentialsError};
+use pipeliner_core::environment::{EnvVarValue, SecretValue};
+use pipeliner_core::pipeline::CredentialsBinding;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{ExecutionContext, ExecutorResult};
+
+/// Credentials bound for the duration of a `withCredentials` step
+///
+/// Dereferences to the context the credentials are bound into.
+#[derive(Debug)]
+pub struct CredentialsScope<'a> {
+    /// Context the credentials are bound into
+    context: &'a mut ExecutionContext,
+
+    /// Bound variables with their previous values, in binding order
+    previous: Vec<(String, Option<EnvVarValue>)>,
+
+    /// Directory holding the bound files, once one was written
+    dir: Option<PathBuf>,
+
+    /// Number of files written, used to keep equal file names apart
+    files: usize,
+}
+
+impl<'a> CredentialsScope<'a> {
+    /// Binds credentials into `context`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if credentials cannot be found, have the wrong type
+    /// or cannot be written. Anything bound so far is released first.
+    pub async fn bind(
+        bindings: &[CredentialsBinding],
+        context: &'a mut ExecutionContext,
+    ) -> ExecutorResult<Self> {
+        let mut scope = Self {
+            context,
+            previous: Vec::new(),
+            dir: None,
+            files: 0,
+        };
+        for binding in bindings {
+            scope.bind_one(binding).await?;
+        }
+        Ok(scope)
+    }
+
+    /// Restores the bound variables and deletes the bound files
+    pub async fn release(mut self) {
+        if let Some(dir) = self.dir.take() {
+            debug!("Removing credentials files in {}", dir.display());
+            if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+                warn!("Cannot remove credentials files {}: {}", dir.display(), e);
+            }
+        }
+    }
+
+    async fn bind_one(&mut self, binding: &CredentialsBinding) -> ExecutorResult<()> {
+        let id = binding.credentials_id();
+        let credentials = self.context.credentials.get(id).await?;
+        for secret in credentials.secrets() {
+            self.context.masker.add(secret);
+        }
+
+        match (binding, credentials) {
+            (
+                CredentialsBinding::UsernamePassword {
+                    username_variable,
+                    password_variable,
+                    ..
+                },
+                Credentials::UsernamePassword { username, password },
+            ) => {
+                self.set(username_variable, EnvVarValue::Value(username));
+                self.set(password_variable, secret(password));
+            }
+            (
+                CredentialsBinding::String { variable, .. },
+                Credentials::SecretText { secret: text },
+            ) => {
+                self.set(variable, secret(text));
+            }
+            (
+                CredentialsBinding::SshUserPrivateKey {
+                    key_file_variable,
+                    username_variable,
+                    passphrase_variable,
+                    ..
+                },
+                Credentials::SshKey {
+                    username,
+                    private_key,
+                    passphrase,
+                },
+            ) => {
+                let path = self.write_file(&format!("{id}.key"), &private_key).await?;
+                self.set(key_file_variable, path_value(&path));
+                if let Some(variable) = username_variable {
+                    self.set(variable, EnvVarValue::Value(username));
+                }
+                if let Some(variable) = passphrase_variable {
+                    self.set(variable, secret(passphrase.unwrap_or_default()));
+                }
+            }
+            (
+                CredentialsBinding::File { variable, .. },
+                Credentials::File { file_name, content },
+            ) => {
+                let path = self.write_file(&file_name, &content).await?;
+                self.set(variable, path_value(&path));
+            }
+            (binding, credentials) => {
+                return Err(CredentialsError::WrongType {
+                    id: id.to_string(),
+                    expected: expected_kind(binding),
+                    actual: credentials.kind(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets a variable, remembering its previous value
+    fn set(&mut self, name: &str, value: EnvVarValue) {
+        let previous = self.context.environment.0.insert(name.to_string(), value);
+        self.previous.push((name.to_string(), previous));
+    }
+
+    /// Writes a file only the current user can read
+    async fn write_file(&mut self, file_name: &str, content: &str) -> ExecutorResult<PathBuf> {
+        if self.dir.is_none() {
+            let dir =
+                std::env::temp_dir().join(format!("pipeliner-credentials-{}", Uuid::new_v4()));
+            tokio::fs::create_dir_all(&dir).await?;
+            restrict(&dir, 0o700).await?;
+            self.dir = Some(dir);
+        }
+        let dir = self.dir.clone().unwrap_or_default();
+
+        // Never let a file name escape the directory
+        let file_name = Path::new(file_name)
+            .file_name()
+            .map_or_else(|| "credentials".into(), |name| name.to_string_lossy());
+        let file_dir = dir.join(self.files.to_string());
+        self.files += 1;
+        tokio::fs::create_dir_all(&file_dir).await?;
+        restrict(&file_dir, 0o700).await?;
+
+        let path = file_dir.join(file_name.as_ref());
+        tokio::fs::write(&path, content).await?;
+        restrict(&path, 0o600).await?;
+        Ok(path)
+    }
+}
+
+impl std::ops::Deref for CredentialsScope<'_> {
+    type Target = ExecutionContext;
+
+    fn deref(&self) -> &ExecutionContext {
+        self.context
+    }
+}
+
+impl std::ops::DerefMut for CredentialsScope<'_> {
+    fn deref_mut(&mut self) -> &mut ExecutionContext {
+        self.context
+    }
+}
+
+impl Drop for CredentialsScope<'_> {
+    fn drop(&mut self) {
+        for (name, previous) in std::mem::take(&mut self.previous).into_iter().rev() {
+            match previous {
+                Some(value) => self.context.environment.0.insert(name, value),
+                None => self.context.environment.0.remove(&name),
+            };
+        }
+
+        if let Some(dir) = self.dir.take() {
+            debug!("Removing credentials files in {}", dir.display());
+            if let Err(e) = std::fs::remove_dir_all(&dir) {
+                warn!("Cannot remove credentials files {}: {}", dir.display(), e);
+            }
+        }
+    }
+}
+
+/// Returns a masked secret value
+fn secret(value: String) -> EnvVarValue {
+    EnvVarValue::Secret(SecretValue {
+        value,
+        masked: true,
+    })
+}
+
+/// Returns the value of a variable holding a path
+fn path_value(path: &Path) -> EnvVarValue {
+    EnvVarValue::Value(path.display().to_string())
+}
+
+/// Returns the credentials type a binding needs
+fn expected_kind(binding: &CredentialsBinding) -> &'static str {
+    match binding {
+        CredentialsBinding::UsernamePassword { .. } => "usernamePassword",
+        CredentialsBinding::String { .. } => "secretText",
+        CredentialsBinding::SshUserPrivateKey { .. } => "sshKey",
+        CredentialsBinding::File { .. } => "file",
+    }
+}
+
+/// Sets the permissions of a file or directory
+#[cfg(unix)]
+async fn restrict(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await
+}
+
+/// Sets the permissions of a file or directory
+///
+/// Only Unix permissions are supported; elsewhere files keep the defaults.
+#[cfg(not(unix))]
+async fn restrict(_path: &Path, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pipeliner_core::credentials::EnvCredentialsProvider;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn context_with(vars: &[(&str, &str)]) -> ExecutionContext {
+        let mut context = ExecutionContext::new();
+        context.credentials = Arc::new(EnvCredentialsProvider::from_vars(
+            vars.iter()
+                .map(|(name, value)| ((*name).to_string(), (*value).to_string()))
+                .collect::<HashMap<_, _>>(),
+        ));
+        context
+    }
+
+    #[tokio::test]
+    async fn test_bind_and_release_variables() {
+        let mut context = context_with(&[
+            ("PIPELINER_CREDENTIALS_REGISTRY_USERNAME", "deploy"),
+            (
+                "PIPELINER_CREDENTIALS_REGISTRY_PASSWORD",
+                "hunter2-password",
+            ),
+        ]);
+        context.environment.insert("USER", "previous");
+
+        let bindings = vec![CredentialsBinding::UsernamePassword {
+            credentials_id: "registry".to_string(),
+            username_variable: "USER".to_string(),
+            password_variable: "PASS".to_string(),
+        }];
+        let scope = CredentialsScope::bind(&bindings, &mut context)
+            .await
+            .unwrap();
+
+        assert_eq!(scope.environment.get("USER").unwrap().value(), "deploy");
+        assert_eq!(
+            scope.environment.get("PASS").unwrap().value(),
+            "hunter2-password"
+        );
+        assert_eq!(scope.mask("pw=hunter2-password"), "pw=****");
+
+        scope.release().await;
+        assert_eq!(context.environment.get("USER").unwrap().value(), "previous");
+        assert!(context.environment.get("PASS").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_scope_restores_only_bound_variables() {
+        let mut context = context_with(&[
+            ("PIPELINER_CREDENTIALS_TOKEN", "abc123"),
+            ("PIPELINER_CREDENTIALS_KUBE_FILE", "/dev/null"),
+        ]);
+        context.environment.insert("TOKEN", "previous");
+        let bindings = vec![
+            CredentialsBinding::String {
+                credentials_id: "token".to_string(),
+                variable: "TOKEN".to_string(),
+            },
+            CredentialsBinding::File {
+                credentials_id: "kube".to_string(),
+                variable: "KUBECONFIG".to_string(),
+            },
+        ];
+
+        let mut scope = CredentialsScope::bind(&bindings, &mut context)
+            .await
+            .unwrap();
+        let path = PathBuf::from(scope.environment.get("KUBECONFIG").unwrap().value());
+        scope.environment.insert("BUILT", "yes");
+        scope
+            .metadata
+            .insert("stage".to_string(), "deploy".to_string());
+        drop(scope);
+
+        assert!(!path.exists());
+        assert_eq!(
+            context.environment.get("TOKEN").unwrap().value(),
+            "previous"
+        );
+        assert!(context.environment.get("KUBECONFIG").is_none());
+        assert_eq!(context.environment.get("BUILT").unwrap().value(), "yes");
+        assert_eq!(context.metadata["stage"], "deploy");
+    }
+
+    #[tokio::test]
+    async fn test_key_files_are_private_and_removed() {
+        let mut context = context_with(&[
+            (
+                "PIPELINER_CREDENTIALS_DEPLOY_KEY_PRIVATE_KEY",
+                "-----KEY-----",
+            ),
+            ("PIPELINER_CREDENTIALS_DEPLOY_KEY_USERNAME", "git"),
+        ]);
+        let bindings = vec![CredentialsBinding::SshUserPrivateKey {
+            credentials_id: "deploy-key".to_string(),
+            key_file_variable: "KEY_FILE".to_string(),
+            username_variable: Some("KEY_USER".to_string()),
+            passphrase_variable: None,
+        }];
+        let scope = CredentialsScope::bind(&bindings, &mut context)
+            .await
+            .unwrap();
+
+        let path = PathBuf::from(scope.environment.get("KEY_FILE").unwrap().value());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "-----KEY-----");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        scope.release().await;
+        assert!(!path.exists());
+        assert!(context.environment.get("KEY_FILE").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_wrong_type_releases_earlier_bindings() {
+        let mut context = context_with(&[
+            ("PIPELINER_CREDENTIALS_TOKEN", "abc123"),
+            ("PIPELINER_CREDENTIALS_OTHER", "def456"),
+        ]);
+        let bindings = vec![
+            CredentialsBinding::String {
+                credentials_id: "token".to_string(),
+                variable: "TOKEN".to_string(),
+            },
+            CredentialsBinding::File {
+                credentials_id: "other".to_string(),
+                variable: "OTHER_FILE".to_string(),
+            },
+        ];
+
+        let error = CredentialsScope::bind(&bindings, &mut context)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("expected file"));
+        assert!(context.environment.get("TOKEN").is_none());
+    }
+}
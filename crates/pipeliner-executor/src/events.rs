@@ -0,0 +1,422 @@
+//! Bridge from execution events to the event sourcing infrastructure.
+//!
+//! An [`EventStreamListener`] turns the executor's [`ExecutionEvent`]s into
+//! [`PipelineEvent`] envelopes, appends them to an [`EventStore`] and
+//! publishes them on an [`EventBus`], so every run leaves a replayable
+//! stream.
+//!
+//! Every envelope of a run carries the execution ID as its correlation ID.
+//! Causation IDs link each event to the one that led to it: a stage start
+//! to the pipeline start, a step start to its stage's or enclosing step's
+//! start, and a completion to the matching start.
+//!
+//! | Execution event     | Pipeline event                         |
+//! |---------------------|----------------------------------------|
+//! | `PipelineStarted`   | `Started`                              |
+//! | `PipelineCompleted` | `Completed`                            |
+//! | `PipelineFailed`    | `Failed`                               |
+//! | `PipelineAborted`   | `Cancelled`                            |
+//! | `StageStarted`      | `StageStarted`                         |
+//! | `StageCompleted`    | `StageCompleted`                       |
+//! | `StageFailed`       | `StageCompleted` with result `FAILURE` |
+//! | `StepStarted`       | `StepStarted`                          |
+//! | `StepCompleted`     | `StepCompleted` with the step's output |
+//! | `StepFailed`        | `StepCompleted` with the error         |
+//!
+//! Log output is collected into the `StepCompleted` event of its step.
+//! Artifact and stash events have no pipeline counterpart and are dropped.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use tracing::warn;
+use uuid::Uuid;
+
+use pipeliner_events::{
+    AnyEvent, EventBus, EventEnvelope, EventMetadata, EventStore, PipelineEvent,
+};
+
+use crate::listener::{ExecutionEvent, ExecutionListener};
+use crate::{ExecutionContext, ExecutionStatus};
+
+/// Source recorded in the metadata of bridged events
+pub const EVENT_SOURCE: &str = "pipeliner-executor";
+
+/// Listener that records execution events in an event store and bus
+pub struct EventStreamListener<S, B> {
+    pipeline_id: Uuid,
+    store: Arc<S>,
+    bus: Arc<B>,
+    runs: Mutex<HashMap<Uuid, Run>>,
+}
+
+/// Event IDs of a run, used as causation IDs of later events
+#[derive(Debug, Default)]
+struct Run {
+    /// ID of the `Started` event
+    started: Option<Uuid>,
+    /// IDs of the `StageStarted` events by stage
+    stages: HashMap<String, Uuid>,
+    /// Running steps by stage, innermost last
+    steps: HashMap<String, Vec<RunningStep>>,
+}
+
+/// A step that started and has not finished yet
+#[derive(Debug)]
+struct RunningStep {
+    /// ID of the `StepStarted` event
+    started: Uuid,
+    /// Output logged so far
+    output: String,
+}
+
+impl<S, B> std::fmt::Debug for EventStreamListener<S, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventStreamListener")
+            .field("pipeline_id", &self.pipeline_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: EventStore, B: EventBus> EventStreamListener<S, B> {
+    /// Creates a listener recording the events of `pipeline_id`
+    ///
+    /// The pipeline ID is the aggregate the events are appended to.
+    #[must_use]
+    pub fn new(pipeline_id: Uuid, store: Arc<S>, bus: Arc<B>) -> Self {
+        Self {
+            pipeline_id,
+            store,
+            bus,
+            runs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the aggregate the events are appended to
+    #[must_use]
+    pub fn pipeline_id(&self) -> Uuid {
+        self.pipeline_id
+    }
+
+    /// Converts an execution event into an envelope, or `None` for events
+    /// that have no pipeline counterpart
+    fn envelope(&self, event: &ExecutionEvent, execution_id: Uuid) -> Option<EventEnvelope> {
+        let metadata = EventMetadata::new(EVENT_SOURCE).with_correlation(execution_id);
+        let ids = Ids {
+            pipeline: self.pipeline_id,
+            execution: execution_id,
+            event: metadata.event_id,
+        };
+
+        let mut runs = self.runs.lock().unwrap();
+        let run = runs.entry(execution_id).or_default();
+        let (event, cause) = run
+            .pipeline_event(event, &ids)
+            .or_else(|| run.step_event(event, &ids))?;
+        if matches!(
+            event,
+            PipelineEvent::Completed { .. }
+                | PipelineEvent::Failed { .. }
+                | PipelineEvent::Cancelled { .. }
+        ) {
+            runs.remove(&execution_id);
+        }
+
+        let metadata = match cause {
+            Some(cause) => metadata.with_causation(cause),
+            None => metadata,
+        };
+        Some(EventEnvelope::new(AnyEvent::Pipeline(event), metadata))
+    }
+}
+
+/// IDs a converted event needs
+struct Ids {
+    pipeline: Uuid,
+    execution: Uuid,
+    event: Uuid,
+}
+
+impl Run {
+    /// Converts a pipeline or stage event, returning it with its cause
+    fn pipeline_event(
+        &mut self,
+        event: &ExecutionEvent,
+        ids: &Ids,
+    ) -> Option<(PipelineEvent, Option<Uuid>)> {
+        let Ids {
+            pipeline: pipeline_id,
+            execution: execution_id,
+            event: event_id,
+        } = *ids;
+        let converted = match event {
+            ExecutionEvent::PipelineStarted { .. } => {
+                self.started = Some(event_id);
+                let event = PipelineEvent::Started {
+                    pipeline_id,
+                    execution_id,
+                    stage: String::new(),
+                };
+                (event, None)
+            }
+            ExecutionEvent::PipelineCompleted { result, .. } => {
+                let event = PipelineEvent::Completed {
+                    pipeline_id,
+                    execution_id,
+                    result: result.status.result_name().to_string(),
+                };
+                (event, self.started)
+            }
+            ExecutionEvent::PipelineFailed { error, .. } => {
+                let event = PipelineEvent::Failed {
+                    pipeline_id,
+                    execution_id,
+                    error: error.clone(),
+                };
+                (event, self.started)
+            }
+            ExecutionEvent::PipelineAborted { reason, .. } => {
+                let event = PipelineEvent::Cancelled {
+                    pipeline_id,
+                    execution_id,
+                    reason: reason.clone(),
+                };
+                (event, self.started)
+            }
+            ExecutionEvent::StageStarted { stage_name, .. } => {
+                self.stages.insert(stage_name.clone(), event_id);
+                let event = PipelineEvent::StageStarted {
+                    pipeline_id,
+                    execution_id,
+                    stage_name: stage_name.clone(),
+                };
+                (event, self.started)
+            }
+            ExecutionEvent::StageCompleted {
+                stage_name, status, ..
+            } => {
+                let event = PipelineEvent::StageCompleted {
+                    pipeline_id,
+                    execution_id,
+                    stage_name: stage_name.clone(),
+                    result: status.result_name().to_string(),
+                };
+                (event, self.stages.get(stage_name).copied())
+            }
+            ExecutionEvent::StageFailed { stage_name, .. } => {
+                let event = PipelineEvent::StageCompleted {
+                    pipeline_id,
+                    execution_id,
+                    stage_name: stage_name.clone(),
+                    result: ExecutionStatus::Failure.result_name().to_string(),
+                };
+                (event, self.stages.get(stage_name).copied())
+            }
+            _ => return None,
+        };
+        Some(converted)
+    }
+
+    /// Converts a step event, returning it with its cause
+    ///
+    /// Log output is kept for the step's completion and returns `None`.
+    fn step_event(
+        &mut self,
+        event: &ExecutionEvent,
+        ids: &Ids,
+    ) -> Option<(PipelineEvent, Option<Uuid>)> {
+        let Ids {
+            pipeline: pipeline_id,
+            execution: execution_id,
+            event: event_id,
+        } = *ids;
+        let converted = match event {
+            ExecutionEvent::StepStarted {
+                stage_name,
+                step_name,
+            } => {
+                let steps = self.steps.entry(stage_name.clone()).or_default();
+                let cause = steps
+                    .last()
+                    .map(|step| step.started)
+                    .or_else(|| self.stages.get(stage_name).copied());
+                steps.push(RunningStep {
+                    started: event_id,
+                    output: String::new(),
+                });
+                let event = PipelineEvent::StepStarted {
+                    pipeline_id,
+                    execution_id,
+                    stage_name: stage_name.clone(),
+                    step_name: step_name.clone(),
+                };
+                (event, cause)
+            }
+            ExecutionEvent::StepCompleted {
+                stage_name,
+                step_name,
+                ..
+            } => {
+                let step = self.steps.get_mut(stage_name).and_then(Vec::pop);
+                let event = PipelineEvent::StepCompleted {
+                    pipeline_id,
+                    execution_id,
+                    stage_name: stage_name.clone(),
+                    step_name: step_name.clone(),
+                    output: step
+                        .as_ref()
+                        .map(|step| step.output.clone())
+                        .filter(|output| !output.is_empty()),
+                };
+                (event, step.map(|step| step.started))
+            }
+            ExecutionEvent::StepFailed {
+                stage_name,
+                step_name,
+                error,
+            } => {
+                let step = self.steps.get_mut(stage_name).and_then(Vec::pop);
+                let event = PipelineEvent::StepCompleted {
+                    pipeline_id,
+                    execution_id,
+                    stage_name: stage_name.clone(),
+                    step_name: step_name.clone(),
+                    output: Some(error.clone()),
+                };
+                (event, step.map(|step| step.started))
+            }
+            ExecutionEvent::LogOutput {
+                stage_name, output, ..
+            } => {
+                let steps = self.steps.get_mut(stage_name);
+                if let Some(step) = steps.and_then(|steps| steps.last_mut()) {
+                    step.output.push_str(output);
+                }
+                return None;
+            }
+            _ => return None,
+        };
+        Some(converted)
+    }
+}
+
+#[async_trait]
+impl<S: EventStore, B: EventBus> ExecutionListener for EventStreamListener<S, B> {
+    async fn on_event(&self, event: &ExecutionEvent, context: &ExecutionContext) {
+        let event = event.masked(&context.masker);
+        let Some(envelope) = self.envelope(&event, context.execution_id) else {
+            return;
+        };
+
+        if let Err(e) = self
+            .store
+            .append(&self.pipeline_id, None, std::slice::from_ref(&envelope))
+            .await
+        {
+            warn!("Cannot append {} event: {}", envelope.event.event_type(), e);
+        }
+        if let Err(e) = self.bus.publish(envelope).await {
+            warn!("Cannot publish event: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pipeliner_core::{Pipeline, Stage, Step, agent::AgentType};
+    use pipeliner_events::InMemoryEventStore;
+    use pipeliner_events::event_bus::{EventBusError, EventHandler};
+
+    /// Bus keeping the published events
+    #[derive(Default)]
+    struct RecordingBus {
+        events: Mutex<Vec<EventEnvelope>>,
+    }
+
+    #[async_trait]
+    impl EventBus for RecordingBus {
+        type Error = EventBusError;
+
+        async fn publish(&self, event: EventEnvelope) -> Result<(), Self::Error> {
+            self.events.lock().unwrap().push(event);
+            Ok(())
+        }
+
+        async fn subscribe(&self, _handler: Arc<dyn EventHandler>) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn unsubscribe(&self, _handler_id: &Uuid) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_leaves_correlated_event_stream() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let pipeline = Pipeline::new()
+            .with_agent(AgentType::any())
+            .with_stage(Stage::new("Build").with_step(Step::echo("compiling")))
+            .with_stage(Stage::new("Test").with_step(Step::shell("false")));
+        let pipeline_id = Uuid::new_v4();
+        let store = Arc::new(InMemoryEventStore::new());
+        let bus = Arc::new(RecordingBus::default());
+        let listener = EventStreamListener::new(pipeline_id, Arc::clone(&store), Arc::clone(&bus));
+
+        let config = crate::ExecutionConfig {
+            working_dir: temp_dir.path().to_path_buf(),
+            ..crate::ExecutionConfig::default()
+        };
+        let mut executor = crate::Executor::new(pipeline, config).with_listener(listener);
+        let result = executor.run().await.unwrap();
+        assert!(result.is_failure());
+
+        let events = store.get_events(&pipeline_id).await.unwrap();
+        let types: Vec<_> = events.iter().map(|e| e.event.event_type()).collect();
+        assert_eq!(
+            types,
+            vec![
+                "PipelineStarted",
+                "StageStarted",
+                "StepStarted",
+                "StepCompleted",
+                "StageCompleted",
+                "StageStarted",
+                "StepStarted",
+                "StepCompleted",
+                "StageCompleted",
+                "PipelineFailed",
+            ]
+        );
+        assert_eq!(bus.events.lock().unwrap().len(), events.len());
+
+        let execution_id = executor.context().execution_id;
+        assert!(
+            events
+                .iter()
+                .all(|e| e.metadata.correlation_id == Some(execution_id))
+        );
+
+        let id = |i: usize| Some(events[i].metadata.event_id);
+        let cause = |i: usize| events[i].metadata.causation_id;
+        assert_eq!(cause(0), None);
+        assert_eq!(cause(1), id(0));
+        assert_eq!(cause(2), id(1));
+        assert_eq!(cause(3), id(2));
+        assert_eq!(cause(4), id(1));
+        assert_eq!(cause(9), id(0));
+
+        let AnyEvent::Pipeline(PipelineEvent::StepCompleted { output, .. }) = &events[3].event
+        else {
+            panic!("expected a completed step");
+        };
+        assert_eq!(output.as_deref(), Some("compiling"));
+        let AnyEvent::Pipeline(PipelineEvent::StageCompleted { result, .. }) = &events[8].event
+        else {
+            panic!("expected a completed stage");
+        };
+        assert_eq!(result, "FAILURE");
+    }
+}
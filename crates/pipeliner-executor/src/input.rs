@@ -0,0 +1,365 @@
+//! Approval gate for `input` steps.
+//!
+//! An input is answered, in order of precedence, by:
+//!
+//! 1. an environment variable `PIPELINER_ANSWER_<ID>`
+//! 2. an entry in a JSON answers file (`PIPELINER_INPUT_ANSWERS` or
+//!    [`InputConfig::answers_file`])
+//! 3. a prompt, when stdin is a terminal
+//! 4. the pending-input registry, a directory another process answers through
+//!
+//! Answers are checked against the step's submitter allow-list. A registry
+//! wait that times out falls back to the step default, or aborts without one.
+//! The requests, answers and the registry itself live in
+//! [`pipeliner_core::input`]; this module resolves them asynchronously.
+
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+pub use pipeliner_core::input::{
+    ANSWER_VAR_PREFIX, ANSWERS_FILE_VAR, INPUT_DIR_VAR, InputAnswer, InputError, InputRegistry,
+    InputRequest, SUBMITTER_VAR,
+};
+
+use crate::{ExecutionContext, ExecutorErrorKind, ExecutorResult};
+
+/// How input steps obtain their answers
+#[derive(Debug, Clone)]
+pub struct InputConfig {
+    /// Answers file, overriding `PIPELINER_INPUT_ANSWERS`
+    pub answers_file: Option<PathBuf>,
+    /// Whether to prompt; `None` prompts only when stdin is a terminal
+    pub interactive: Option<bool>,
+    /// Registry directory; defaults to `PIPELINER_INPUT_DIR` or
+    /// `.pipeliner/inputs` in the working directory
+    pub registry_dir: Option<PathBuf>,
+    /// How long to wait on the registry; `None` waits indefinitely
+    pub timeout: Option<Duration>,
+    /// Interval between registry polls
+    pub poll_interval: Duration,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            answers_file: None,
+            interactive: None,
+            registry_dir: None,
+            timeout: None,
+            poll_interval: Duration::from_millis(200),
+        }
+    }
+}
+
+impl InputConfig {
+    /// Returns the registry used for executions in `context`
+    #[must_use]
+    pub fn registry(&self, context: &ExecutionContext) -> InputRegistry {
+        let dir = self
+            .registry_dir
+            .clone()
+            .or_else(|| std::env::var_os(INPUT_DIR_VAR).map(PathBuf::from))
+            .unwrap_or_else(|| context.cwd().join(".pipeliner").join("inputs"));
+        InputRegistry::new(dir)
+    }
+
+    /// Resolves the answer to an input request
+    ///
+    /// `timeout` overrides the configured registry timeout. Returns the
+    /// accepted answer with its value and parameters filled in from defaults.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InputAborted` if the input was aborted or timed out without a
+    /// default, `InputRejected` if a pre-supplied or prompted answer comes
+    /// from a user outside the submitter list, or an I/O error on registry
+    /// failures.
+    pub async fn resolve(
+        &self,
+        request: &InputRequest,
+        context: &ExecutionContext,
+        timeout: Option<Duration>,
+    ) -> ExecutorResult<InputAnswer> {
+        let default_submitter =
+            env_value(context, SUBMITTER_VAR).or_else(|| env_value(context, "USER"));
+
+        let answer = if let Some(answer) = self.preset_answer(request, context)? {
+            answer
+        } else if self
+            .interactive
+            .unwrap_or_else(|| std::io::stdin().is_terminal())
+        {
+            let request = request.clone();
+            tokio::task::spawn_blocking(move || prompt(&request))
+                .await
+                .map_err(|e| ExecutorErrorKind::StepFailed {
+                    reason: format!("input prompt failed: {e}"),
+                })??
+        } else {
+            return self.wait(request, context, timeout.or(self.timeout)).await;
+        };
+
+        let answer = InputAnswer {
+            submitter: answer.submitter.or(default_submitter),
+            ..answer
+        };
+        Ok(request.accept(answer)?)
+    }
+
+    /// Looks up an answer from the environment or the answers file
+    fn preset_answer(
+        &self,
+        request: &InputRequest,
+        context: &ExecutionContext,
+    ) -> ExecutorResult<Option<InputAnswer>> {
+        if let Some(value) = env_value(context, &request.answer_var()) {
+            return Ok(Some(InputAnswer::proceed(Some(value))));
+        }
+
+        let Some(path) = self
+            .answers_file
+            .clone()
+            .or_else(|| env_value(context, ANSWERS_FILE_VAR).map(PathBuf::from))
+        else {
+            return Ok(None);
+        };
+
+        Ok(InputAnswer::from_file(&path, &request.id)?)
+    }
+
+    /// Waits on the registry until an allowed answer arrives or time runs out
+    async fn wait(
+        &self,
+        request: &InputRequest,
+        context: &ExecutionContext,
+        timeout: Option<Duration>,
+    ) -> ExecutorResult<InputAnswer> {
+        let registry = self.registry(context);
+        registry.register(request)?;
+        info!(
+            "Waiting for input '{}' in {}: {}",
+            request.key(),
+            registry.dir().display(),
+            request.message
+        );
+
+        let poll = async {
+            loop {
+                match registry.take_answer(request)? {
+                    Some(answer) if request.allows(answer.submitter.as_deref()) => {
+                        return Ok(request.accept(answer)?);
+                    }
+                    Some(answer) => warn!(
+                        "Ignoring answer to input '{}' from '{}': not an allowed submitter",
+                        request.id,
+                        answer.submitter.as_deref().unwrap_or("<unknown>")
+                    ),
+                    None => {}
+                }
+                tokio::time::sleep(self.poll_interval).await;
+            }
+        };
+
+        let result = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, poll).await {
+                Ok(result) => result,
+                Err(_) => match request.default {
+                    Some(ref default) => {
+                        Ok(request.accept(InputAnswer::proceed(Some(default.clone())))?)
+                    }
+                    None => Err(ExecutorErrorKind::InputAborted {
+                        id: request.id.clone(),
+                        reason: format!("no answer within {timeout:?}"),
+                    }
+                    .into()),
+                },
+            },
+            None => poll.await,
+        };
+
+        registry.withdraw(request)?;
+        result
+    }
+}
+
+/// Prompts on the terminal; an empty line proceeds and `abort` aborts
+fn prompt(request: &InputRequest) -> ExecutorResult<InputAnswer> {
+    let mut stderr = std::io::stderr();
+    match request.default {
+        Some(ref default) => write!(
+            stderr,
+            "{} [{default}] (Enter to proceed, 'abort' to abort): ",
+            request.message
+        )?,
+        None => write!(
+            stderr,
+            "{} (Enter to proceed, 'abort' to abort): ",
+            request.message
+        )?,
+    }
+    stderr.flush()?;
+
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line)?;
+
+    Ok(match line.trim() {
+        "abort" => InputAnswer::abort(),
+        "" => InputAnswer::proceed(None),
+        value => InputAnswer::proceed(Some(value.to_string())),
+    })
+}
+
+/// Reads a variable from the execution environment, then the process
+fn env_value(context: &ExecutionContext, key: &str) -> Option<String> {
+    context
+        .environment
+        .get(key)
+        .map(ToString::to_string)
+        .or_else(|| std::env::var(key).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pipeliner_core::pipeline::StepParameter;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn config(dir: &Path) -> InputConfig {
+        InputConfig {
+            interactive: Some(false),
+            registry_dir: Some(dir.join("inputs")),
+            timeout: Some(Duration::from_millis(200)),
+            poll_interval: Duration::from_millis(10),
+            ..InputConfig::default()
+        }
+    }
+
+    fn request(submitter: Option<&str>) -> InputRequest {
+        InputRequest::new(
+            "Deploy to production?",
+            None,
+            &[StepParameter::Choice {
+                name: "TARGET".to_string(),
+                description: String::new(),
+                choices: vec!["staging".to_string(), "prod".to_string()],
+            }],
+            None,
+            submitter.map(String::from).as_ref(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_answer_from_environment() {
+        let dir = TempDir::new().unwrap();
+        let mut context = ExecutionContext::new();
+        context
+            .environment
+            .insert("PIPELINER_ANSWER_DEPLOY_TO_PRODUCTION", "yes");
+        context.environment.insert("PIPELINER_SUBMITTER", "alice");
+
+        let answer = config(dir.path())
+            .resolve(&request(Some("alice")), &context, None)
+            .await
+            .unwrap();
+
+        assert_eq!(answer.value.as_deref(), Some("yes"));
+        assert_eq!(
+            answer.parameters.get("TARGET").map(String::as_str),
+            Some("staging")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_submitter_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let mut context = ExecutionContext::new();
+        context
+            .environment
+            .insert("PIPELINER_ANSWER_DEPLOY_TO_PRODUCTION", "yes");
+        context.environment.insert("PIPELINER_SUBMITTER", "mallory");
+
+        let result = config(dir.path())
+            .resolve(&request(Some("alice")), &context, None)
+            .await;
+
+        assert!(matches!(
+            result.unwrap_err().0,
+            ExecutorErrorKind::InputRejected { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_answers_file_with_invalid_choice() {
+        let dir = TempDir::new().unwrap();
+        let answers = dir.path().join("answers.json");
+        std::fs::write(
+            &answers,
+            r#"{"deploy-to-production": {"parameters": {"TARGET": "moon"}}}"#,
+        )
+        .unwrap();
+        let config = InputConfig {
+            answers_file: Some(answers),
+            ..config(dir.path())
+        };
+
+        let result = config
+            .resolve(&request(None), &ExecutionContext::new(), None)
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_registry_answers_each_execution() {
+        let dir = TempDir::new().unwrap();
+        let config = InputConfig {
+            timeout: Some(Duration::from_secs(10)),
+            ..config(dir.path())
+        };
+        let contexts = [ExecutionContext::new(), ExecutionContext::new()];
+        let requests = contexts.each_ref().map(|context| {
+            request(Some("alice")).with_execution_id(context.execution_id.to_string())
+        });
+        let registry = config.registry(&contexts[0]);
+
+        let keys = requests.each_ref().map(InputRequest::key);
+        let answerer = tokio::spawn(async move {
+            while registry.pending().unwrap().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            for (key, target) in keys.iter().zip(["staging", "prod"]) {
+                let answer = InputAnswer::proceed(None)
+                    .with_submitter("alice")
+                    .with_parameter("TARGET", target);
+                registry.answer(key, &answer).unwrap();
+            }
+        });
+
+        let (first, second) = tokio::join!(
+            config.resolve(&requests[0], &contexts[0], None),
+            config.resolve(&requests[1], &contexts[1], None)
+        );
+        answerer.await.unwrap();
+
+        assert_eq!(first.unwrap().parameters["TARGET"], "staging");
+        assert_eq!(second.unwrap().parameters["TARGET"], "prod");
+        assert!(config.registry(&contexts[0]).pending().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_registry_timeout_without_default_aborts() {
+        let dir = TempDir::new().unwrap();
+
+        let result = config(dir.path())
+            .resolve(&request(None), &ExecutionContext::new(), None)
+            .await;
+
+        assert!(matches!(
+            result.unwrap_err().0,
+            ExecutorErrorKind::InputAborted { .. }
+        ));
+    }
+}
@@ -11,6 +11,10 @@
 //! - `runtime`: Runtime for executing steps
 //! - `strategy`: Execution strategies (sequential, parallel, matrix)
 //! - `listener`: Event listeners for execution events
+//! - `input`: Approval gate for `input` steps
+//! - `cancel`: Cancellation of running executions
+//! - `credentials`: Credentials bindings of `withCredentials` steps
+//! - `events`: Recording of execution events in an event store and bus
 //!
 //! ## Example
 //!
@@ -21,24 +25,41 @@
 //! let pipeline = Pipeline::new().with_name("Example");
 //! let config = ExecutionConfig::default();
 //! let mut executor = Executor::new(pipeline, config);
-//! let result = executor.run().await;
+//! let result = executor.run().await?;
 //! ```
 
 #![warn(missing_docs)]
 #![warn(unused)]
 #![warn(clippy::pedantic)]
 
+pub mod cancel;
 pub mod context;
+pub mod credentials;
+pub mod events;
+pub mod input;
 pub mod listener;
 pub mod local;
 pub mod runtime;
 pub mod strategy;
 
-pub use context::{ExecutionConfig, ExecutionContext};
+pub use cancel::CancellationToken;
+pub use context::{ExecutionConfig, ExecutionContext, StageResult, StepResult, StrategyKind};
+pub use credentials::CredentialsScope;
+pub use events::EventStreamListener;
+pub use input::{InputAnswer, InputConfig, InputRegistry, InputRequest};
 pub use listener::ExecutionListener;
 pub use local::{LocalExecutor, LocalResult};
 pub use runtime::StepExecutor;
-pub use strategy::{ExecutionStrategy, ParallelStrategy, SequentialStrategy};
+pub use strategy::{ExecutionStrategy, MatrixStrategy, ParallelStrategy, SequentialStrategy};
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::Utc;
+use pipeliner_core::options::{Retry, Timeout};
+use tracing::{info, warn};
+
+use crate::listener::ExecutionEvent;
 
 /// Re-exports
 pub use pipeliner_core::{Pipeline, Stage, Step, StepType, Validate, ValidationError};
@@ -74,6 +95,28 @@ pub enum ExecutorErrorKind {
 
     #[error("unexpected termination: {reason}")]
     UnexpectedTermination { reason: String },
+
+    #[error("input '{id}' aborted: {reason}")]
+    InputAborted { id: String, reason: String },
+
+    #[error("input '{id}' cannot be answered by '{submitter}'")]
+    InputRejected { id: String, submitter: String },
+
+    #[error("execution aborted: {reason}")]
+    Aborted { reason: String },
+
+    #[error("credentials unavailable: {reason}")]
+    Credentials {
+        reason: pipeliner_core::CredentialsError,
+    },
+
+    #[error("invalid expression: {reason}")]
+    Expression {
+        reason: pipeliner_core::ExpressionError,
+    },
+
+    #[error("invalid pipeline: {reason}")]
+    InvalidPipeline { reason: ValidationError },
 }
 
 impl From<std::io::Error> for ExecutorError {
@@ -82,6 +125,34 @@ impl From<std::io::Error> for ExecutorError {
     }
 }
 
+impl From<pipeliner_core::CredentialsError> for ExecutorError {
+    fn from(e: pipeliner_core::CredentialsError) -> Self {
+        Self(ExecutorErrorKind::Credentials { reason: e })
+    }
+}
+
+impl From<pipeliner_core::InputError> for ExecutorError {
+    fn from(e: pipeliner_core::InputError) -> Self {
+        use pipeliner_core::InputError;
+
+        Self(match e {
+            InputError::Aborted { id, reason } => ExecutorErrorKind::InputAborted { id, reason },
+            InputError::Rejected { id, submitter } => {
+                ExecutorErrorKind::InputRejected { id, submitter }
+            }
+            e => ExecutorErrorKind::StepFailed {
+                reason: e.to_string(),
+            },
+        })
+    }
+}
+
+impl From<pipeliner_core::ExpressionError> for ExecutorError {
+    fn from(e: pipeliner_core::ExpressionError) -> Self {
+        Self(ExecutorErrorKind::Expression { reason: e })
+    }
+}
+
 /// Execution status
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ExecutionStatus {
@@ -122,6 +193,18 @@ impl ExecutionStatus {
             ExecutionStatus::Failure | ExecutionStatus::Timeout | ExecutionStatus::Aborted
         )
     }
+
+    /// Returns the build result name seen by expressions, such as `SUCCESS`
+    #[must_use]
+    pub fn result_name(&self) -> &'static str {
+        match self {
+            ExecutionStatus::Pending | ExecutionStatus::Running => "NOT_BUILT",
+            ExecutionStatus::Success => "SUCCESS",
+            ExecutionStatus::Failure | ExecutionStatus::Timeout => "FAILURE",
+            ExecutionStatus::Aborted => "ABORTED",
+            ExecutionStatus::Unstable => "UNSTABLE",
+        }
+    }
 }
 
 /// Execution result
@@ -137,6 +220,8 @@ pub struct ExecutionResult {
     pub steps_executed: usize,
     /// Error message if failed
     pub error: Option<String>,
+    /// Results of the stages that ran, in the order they finished
+    pub stages: Vec<StageResult>,
 }
 
 impl Default for ExecutionResult {
@@ -147,6 +232,7 @@ impl Default for ExecutionResult {
             stages_executed: 0,
             steps_executed: 0,
             error: None,
+            stages: Vec::new(),
         }
     }
 }
@@ -161,6 +247,7 @@ impl ExecutionResult {
             stages_executed: stages,
             steps_executed: steps,
             error: None,
+            stages: Vec::new(),
         }
     }
 
@@ -178,6 +265,25 @@ impl ExecutionResult {
             stages_executed: stages,
             steps_executed: steps,
             error: Some(error.into()),
+            stages: Vec::new(),
+        }
+    }
+
+    /// Creates an aborted result
+    #[must_use]
+    pub fn aborted(
+        stages: usize,
+        steps: usize,
+        duration: chrono::Duration,
+        reason: impl Into<String>,
+    ) -> Self {
+        Self {
+            status: ExecutionStatus::Aborted,
+            duration,
+            stages_executed: stages,
+            steps_executed: steps,
+            error: Some(reason.into()),
+            stages: Vec::new(),
         }
     }
 
@@ -192,6 +298,12 @@ impl ExecutionResult {
     pub fn is_failure(&self) -> bool {
         matches!(self.status, ExecutionStatus::Failure)
     }
+
+    /// Returns true if the execution was aborted
+    #[must_use]
+    pub fn is_aborted(&self) -> bool {
+        matches!(self.status, ExecutionStatus::Aborted)
+    }
 }
 
 /// Main executor entry point
@@ -200,6 +312,7 @@ pub struct Executor {
     pipeline: Pipeline,
     config: ExecutionConfig,
     context: ExecutionContext,
+    parameters: HashMap<String, String>,
 }
 
 impl Executor {
@@ -210,18 +323,276 @@ impl Executor {
             pipeline,
             config,
             context: ExecutionContext::new(),
+            parameters: HashMap::new(),
         }
     }
 
+    /// Sets the listener receiving the execution events
+    #[must_use]
+    pub fn with_listener<L: ExecutionListener + 'static>(self, listener: L) -> Self {
+        self.context.events.set_listener(listener);
+        self
+    }
+
+    /// Sets the value of a build parameter, overriding its default
+    #[must_use]
+    pub fn with_parameter(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.parameters.insert(name.into(), value.into());
+        self
+    }
+
+    /// Returns the execution context
+    ///
+    /// After `run` it holds the final environment and the stage and step
+    /// results.
+    #[must_use]
+    pub fn context(&self) -> &ExecutionContext {
+        &self.context
+    }
+
+    /// Returns the token that cancels this execution
+    ///
+    /// Clone it before calling `run` to cancel the execution from elsewhere.
+    #[must_use]
+    pub fn cancellation(&self) -> CancellationToken {
+        self.context.cancellation.clone()
+    }
+
     /// Runs the pipeline execution
+    ///
+    /// Parameters take their defaults unless set with
+    /// [`with_parameter`](Self::with_parameter), and the configuration's
+    /// environment overrides the pipeline's. The pipeline's `timeout` and
+    /// `retry` options, or the configuration's, apply to the whole run.
+    /// Every event goes to the listener, ending with exactly one of
+    /// `PipelineCompleted`, `PipelineFailed` or `PipelineAborted`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the pipeline is invalid. Failing stages and
+    /// steps are reported in the result instead.
     pub async fn run(&mut self) -> ExecutorResult<ExecutionResult> {
-        todo!()
+        self.validate()
+            .map_err(|reason| ExecutorErrorKind::InvalidPipeline { reason })?;
+        self.prepare_context();
+
+        let pipeline_name = self
+            .pipeline
+            .name
+            .clone()
+            .unwrap_or_else(|| "unnamed".to_string());
+        let execution_id = self.context.execution_id.to_string();
+        let start_time = Utc::now();
+        self.context.start_time = start_time;
+        self.context
+            .emit(ExecutionEvent::PipelineStarted {
+                pipeline_name: pipeline_name.clone(),
+                execution_id: execution_id.clone(),
+                start_time,
+            })
+            .await;
+
+        let timer = self.timeout().map(|timeout| {
+            let token = self.context.cancellation.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(timeout).await;
+                token.cancel(format!("pipeline timeout of {timeout:?} exceeded"))
+            })
+        });
+
+        let mut result = self.run_attempts().await;
+
+        let timed_out = match timer {
+            Some(timer) if timer.is_finished() => timer.await.unwrap_or(false),
+            Some(timer) => {
+                timer.abort();
+                false
+            }
+            None => false,
+        };
+        if timed_out && result.is_aborted() {
+            result.status = ExecutionStatus::Timeout;
+        }
+        result.duration = Utc::now().signed_duration_since(start_time);
+        result
+            .stages
+            .clone_from(&*self.context.stage_results.lock().await);
+
+        let end_time = Utc::now();
+        let event = match result.status {
+            ExecutionStatus::Aborted => ExecutionEvent::PipelineAborted {
+                pipeline_name,
+                execution_id,
+                reason: result.error.clone().unwrap_or_default(),
+                end_time,
+            },
+            status if status.is_failure() => ExecutionEvent::PipelineFailed {
+                pipeline_name,
+                execution_id,
+                error: result.error.clone().unwrap_or_default(),
+                end_time,
+            },
+            _ => ExecutionEvent::PipelineCompleted {
+                pipeline_name,
+                execution_id,
+                result: result.clone(),
+                end_time,
+            },
+        };
+        self.context.emit(event).await;
+
+        Ok(result)
     }
 
     /// Validates the pipeline before execution
     pub fn validate(&self) -> Result<(), ValidationError> {
         self.pipeline.validate()
     }
+
+    /// Sets up the working directory, parameters and environment
+    ///
+    /// Parameters are visible as variables too, below the pipeline's
+    /// environment, which is below the configuration's.
+    fn prepare_context(&mut self) {
+        let context = &mut self.context;
+        context.working_dir.clone_from(&self.config.working_dir);
+        context.previous_status = self.config.previous_status;
+
+        let mut parameters: HashMap<String, String> = HashMap::new();
+        let mut secrets = Vec::new();
+        if let Some(declared) = &self.pipeline.parameters {
+            for parameter in declared.iter() {
+                let name = parameter.name();
+                let value = self
+                    .parameters
+                    .get(name)
+                    .cloned()
+                    .or_else(|| parameter.default_value());
+                if let Some(value) = value {
+                    if parameter.parameter_type() == pipeliner_core::ParameterType::Password {
+                        secrets.push(name.to_string());
+                    }
+                    parameters.insert(name.to_string(), value);
+                }
+            }
+        }
+        for (name, value) in &self.parameters {
+            parameters
+                .entry(name.clone())
+                .or_insert_with(|| value.clone());
+        }
+
+        for (name, value) in parameters {
+            if secrets.contains(&name) {
+                context.masker.add(&value);
+                context
+                    .environment
+                    .insert_secret(name.clone(), value.clone());
+            } else {
+                context.environment.insert(name.clone(), value.clone());
+            }
+            context.set_parameter(name, value);
+        }
+        for environment in [&self.pipeline.environment, &self.config.environment] {
+            for (name, value) in environment.iter() {
+                context
+                    .environment
+                    .0
+                    .insert(name.to_string(), value.clone());
+            }
+        }
+    }
+
+    /// Returns the shorter of the configured and the pipeline's timeout
+    fn timeout(&self) -> Option<Duration> {
+        let pipeline = self
+            .pipeline
+            .options
+            .as_ref()
+            .and_then(|options| options.timeout.as_ref())
+            .map(|timeout| match timeout {
+                Timeout::Duration { duration } | Timeout::Activity { duration } => *duration,
+            });
+        match (self.config.global_timeout, pipeline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Returns how many times a failed run is retried, and the delay
+    fn retries(&self) -> (usize, Duration) {
+        match self
+            .pipeline
+            .options
+            .as_ref()
+            .and_then(|options| options.retry.as_ref())
+        {
+            Some(Retry::Count(count)) => (*count, Duration::ZERO),
+            Some(Retry::CountWithDelay { count, delay }) => (*count, *delay),
+            None if self.config.retry_on_failure => {
+                (self.config.max_retries, self.config.retry_delay)
+            }
+            None => (0, Duration::ZERO),
+        }
+    }
+
+    /// Returns the strategy scheduling the stages
+    fn strategy(&self) -> Box<dyn ExecutionStrategy> {
+        match self.config.strategy {
+            StrategyKind::Auto if self.pipeline.matrix.is_some() => {
+                Box::new(MatrixStrategy::new(1))
+            }
+            StrategyKind::Auto | StrategyKind::Sequential => Box::new(SequentialStrategy::new()),
+            StrategyKind::Parallel { max_concurrent } => {
+                Box::new(ParallelStrategy::new(max_concurrent.max(1)))
+            }
+            StrategyKind::Matrix { max_concurrent } => {
+                Box::new(MatrixStrategy::new(max_concurrent.max(1)))
+            }
+        }
+    }
+
+    /// Runs the strategy, again after a failure while retries are left
+    ///
+    /// Each attempt starts without the stage and step results of the
+    /// previous one. Aborted runs are not retried.
+    async fn run_attempts(&mut self) -> ExecutionResult {
+        let strategy = self.strategy();
+        let (retries, delay) = self.retries();
+        let mut attempt = 0;
+        loop {
+            let start_time = Utc::now();
+            let result = match strategy.execute(&self.pipeline, &mut self.context).await {
+                Ok(result) => result,
+                Err(e) => ExecutionResult::failure(
+                    0,
+                    0,
+                    Utc::now().signed_duration_since(start_time),
+                    self.context.mask(&e.to_string()),
+                ),
+            };
+            if result.is_success()
+                || result.is_aborted()
+                || attempt >= retries
+                || self.context.cancellation.is_cancelled()
+            {
+                return result;
+            }
+
+            attempt += 1;
+            warn!(
+                "Pipeline failed, retrying in {:?} (attempt {}/{})",
+                delay, attempt, retries
+            );
+            tokio::select! {
+                () = tokio::time::sleep(delay) => {}
+                _ = self.context.cancellation.cancelled() => return result,
+            }
+            info!("Retrying pipeline");
+            self.context.stage_results.lock().await.clear();
+            self.context.step_results.lock().await.clear();
+        }
+    }
 }
 
 #[cfg(test)]
@@ -276,6 +647,228 @@ mod tests {
         assert!(result.error.is_some());
     }
 
+    #[test]
+    fn test_execution_result_aborted() {
+        let result = ExecutionResult::aborted(1, 2, chrono::Duration::zero(), "interrupted");
+        assert!(result.is_aborted());
+        assert!(!result.is_success());
+        assert_eq!(result.error.as_deref(), Some("interrupted"));
+    }
+
+    #[test]
+    fn test_executor_cancellation_is_shared() {
+        let executor = Executor::new(create_test_pipeline(), ExecutionConfig::default());
+        executor.cancellation().cancel("stop");
+        assert!(executor.context.cancellation.is_cancelled());
+    }
+
+    fn shell_stage(name: &str, command: &str) -> Stage {
+        Stage::new(name).with_step(Step::shell(command))
+    }
+
+    fn config_in(dir: &TempDir) -> ExecutionConfig {
+        ExecutionConfig {
+            working_dir: dir.path().to_path_buf(),
+            ..ExecutionConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_emits_events_and_applies_parameters() {
+        use crate::listener::{BufferListener, ExecutionEvent};
+        use pipeliner_core::parameters::Parameter;
+
+        let temp_dir = TempDir::new().unwrap();
+        let pipeline = create_test_pipeline()
+            .with_parameters(pipeliner_core::Parameters::from_vec(vec![
+                Parameter::String {
+                    name: "TARGET".to_string(),
+                    description: String::new(),
+                    default_value: Some("staging".to_string()),
+                    trim: false,
+                },
+                Parameter::Boolean {
+                    name: "DRY_RUN".to_string(),
+                    description: String::new(),
+                    default_value: true,
+                },
+            ]))
+            .with_stage(shell_stage("Deploy", "touch ${TARGET}-${DRY_RUN}"));
+        let listener = BufferListener::new();
+
+        let mut executor = Executor::new(pipeline, config_in(&temp_dir))
+            .with_listener(listener.clone())
+            .with_parameter("TARGET", "production");
+        let result = executor.run().await.unwrap();
+
+        assert!(result.is_success(), "{:?}", result.error);
+        assert!(temp_dir.path().join("production-true").exists());
+        assert_eq!(result.stages.len(), 2);
+        assert_eq!(executor.context().get_parameter("DRY_RUN"), Some("true"));
+
+        let events = listener.get_events();
+        assert!(matches!(
+            events.first(),
+            Some(ExecutionEvent::PipelineStarted { .. })
+        ));
+        assert!(matches!(
+            events.last(),
+            Some(ExecutionEvent::PipelineCompleted { .. })
+        ));
+        let started = |name: &str| {
+            events.iter().any(|event| {
+                matches!(event, ExecutionEvent::StageStarted { stage_name, .. } if stage_name == name)
+            })
+        };
+        assert!(started("Test Stage") && started("Deploy"));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            ExecutionEvent::LogOutput { output, .. } if output == "Hello"
+        )));
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| matches!(event, ExecutionEvent::StepCompleted { .. }))
+                .count(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_applies_stage_retry_and_timeout() {
+        use pipeliner_core::pipeline::StageOptions;
+
+        let temp_dir = TempDir::new().unwrap();
+        let mut flaky = shell_stage(
+            "Flaky",
+            "sh -c 'echo attempt >> attempts; test $(wc -l < attempts) -ge 3'",
+        );
+        flaky.options = Some(StageOptions {
+            retry: Some(2),
+            ..StageOptions::default()
+        });
+        let mut slow = shell_stage("Slow", "sleep 30");
+        slow.options = Some(StageOptions {
+            timeout: Some(std::time::Duration::from_millis(100)),
+            ..StageOptions::default()
+        });
+        let pipeline = create_test_pipeline().with_stage(flaky).with_stage(slow);
+
+        let start = std::time::Instant::now();
+        let result = Executor::new(pipeline, config_in(&temp_dir))
+            .run()
+            .await
+            .unwrap();
+
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        assert!(result.is_failure());
+        let statuses: Vec<_> = result
+            .stages
+            .iter()
+            .map(|stage| (stage.name.as_str(), stage.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("Test Stage", ExecutionStatus::Success),
+                ("Flaky", ExecutionStatus::Success),
+                ("Slow", ExecutionStatus::Timeout),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_global_timeout() {
+        use crate::listener::{BufferListener, ExecutionEvent};
+
+        let temp_dir = TempDir::new().unwrap();
+        let pipeline = create_test_pipeline().with_stage(shell_stage("Slow", "sleep 30"));
+        let config = ExecutionConfig {
+            global_timeout: Some(std::time::Duration::from_millis(100)),
+            ..config_in(&temp_dir)
+        };
+        let listener = BufferListener::new();
+
+        let result = Executor::new(pipeline, config)
+            .with_listener(listener.clone())
+            .run()
+            .await
+            .unwrap();
+
+        assert_eq!(result.status, ExecutionStatus::Timeout);
+        assert!(result.error.unwrap().contains("timeout"));
+        assert!(matches!(
+            listener.get_events().last(),
+            Some(ExecutionEvent::PipelineFailed { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_run_parallel_fail_fast_stops_siblings() {
+        use pipeliner_core::pipeline::StageOptions;
+
+        let temp_dir = TempDir::new().unwrap();
+        let mut failing = shell_stage("Lint", "false");
+        failing.options = Some(StageOptions {
+            fail_fast: true,
+            ..StageOptions::default()
+        });
+        let pipeline = Pipeline::new()
+            .with_agent(AgentType::any())
+            .with_stage(shell_stage("Tests", "sleep 30"))
+            .with_stage(failing);
+        let config = ExecutionConfig {
+            strategy: StrategyKind::Parallel { max_concurrent: 2 },
+            ..config_in(&temp_dir)
+        };
+
+        let start = std::time::Instant::now();
+        let result = Executor::new(pipeline, config).run().await.unwrap();
+
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        assert!(result.is_failure());
+        let tests = result.stages.iter().find(|stage| stage.name == "Tests");
+        assert_eq!(tests.unwrap().status, ExecutionStatus::Aborted);
+    }
+
+    #[tokio::test]
+    async fn test_run_changed_post_condition() {
+        use pipeliner_core::pipeline::PostCondition;
+
+        let temp_dir = TempDir::new().unwrap();
+        let mut stage = shell_stage("Build", "true");
+        stage.post = Some(PostCondition {
+            changed: vec![Step::shell("touch changed")],
+            ..PostCondition::default()
+        });
+        let pipeline = Pipeline::new()
+            .with_agent(AgentType::any())
+            .with_stage(stage);
+
+        let config = ExecutionConfig {
+            previous_status: Some(ExecutionStatus::Success),
+            ..config_in(&temp_dir)
+        };
+        let mut executor = Executor::new(pipeline.clone(), config);
+        assert!(executor.run().await.unwrap().is_success());
+        assert!(!temp_dir.path().join("changed").exists());
+
+        let config = ExecutionConfig {
+            previous_status: Some(ExecutionStatus::Failure),
+            ..config_in(&temp_dir)
+        };
+        let mut executor = Executor::new(pipeline, config);
+        assert!(executor.run().await.unwrap().is_success());
+        assert!(temp_dir.path().join("changed").exists());
+    }
+
+    #[tokio::test]
+    async fn test_run_rejects_invalid_pipeline() {
+        let mut executor = Executor::new(Pipeline::new(), ExecutionConfig::default());
+        let error = executor.run().await.unwrap_err();
+        assert!(error.to_string().starts_with("invalid pipeline"));
+    }
+
     #[test]
     fn test_execution_status() {
         assert_eq!(ExecutionStatus::Pending, ExecutionStatus::Pending);
@@ -8,7 +8,7 @@ use chrono::{DateTime, Utc};
 use std::io::Write;
 use tracing::{Level, event};
 
-use pipeliner_core::Step;
+use pipeliner_core::{SecretMasker, Step};
 
 use crate::{ExecutionContext, ExecutionResult, ExecutionStatus};
 
@@ -35,6 +35,13 @@ pub enum ExecutionEvent {
         error: String,
         end_time: DateTime<Utc>,
     },
+    /// Pipeline aborted by cancellation
+    PipelineAborted {
+        pipeline_name: String,
+        execution_id: String,
+        reason: String,
+        end_time: DateTime<Utc>,
+    },
     /// Stage started
     StageStarted {
         stage_name: String,
@@ -83,6 +90,113 @@ pub enum ExecutionEvent {
     StashRestored { name: String, path: String },
 }
 
+impl ExecutionEvent {
+    /// Returns a copy of this event with every text field masked
+    ///
+    /// Names are masked too, since stages and steps may be named after
+    /// values that turn out to be secrets.
+    #[must_use]
+    pub fn masked(&self, masker: &SecretMasker) -> Self {
+        let mask = |text: &mut String| *text = masker.mask(text);
+        let mut event = self.clone();
+        match &mut event {
+            ExecutionEvent::PipelineStarted {
+                pipeline_name,
+                execution_id,
+                ..
+            } => {
+                mask(pipeline_name);
+                mask(execution_id);
+            }
+            ExecutionEvent::PipelineCompleted {
+                pipeline_name,
+                execution_id,
+                result,
+                ..
+            } => {
+                mask(pipeline_name);
+                mask(execution_id);
+                if let Some(error) = result.error.as_mut() {
+                    mask(error);
+                }
+                for stage in &mut result.stages {
+                    mask(&mut stage.name);
+                    if let Some(error) = stage.error.as_mut() {
+                        mask(error);
+                    }
+                }
+            }
+            ExecutionEvent::PipelineFailed {
+                pipeline_name,
+                execution_id,
+                error: message,
+                ..
+            }
+            | ExecutionEvent::PipelineAborted {
+                pipeline_name,
+                execution_id,
+                reason: message,
+                ..
+            } => {
+                mask(pipeline_name);
+                mask(execution_id);
+                mask(message);
+            }
+            ExecutionEvent::StageStarted {
+                stage_name,
+                execution_id,
+            } => {
+                mask(stage_name);
+                mask(execution_id);
+            }
+            ExecutionEvent::StageCompleted { stage_name, .. } => mask(stage_name),
+            ExecutionEvent::StageFailed { stage_name, error } => {
+                mask(stage_name);
+                mask(error);
+            }
+            ExecutionEvent::StepStarted {
+                stage_name,
+                step_name,
+            }
+            | ExecutionEvent::StepCompleted {
+                stage_name,
+                step_name,
+                ..
+            } => {
+                mask(stage_name);
+                mask(step_name);
+            }
+            ExecutionEvent::StepFailed {
+                stage_name,
+                step_name,
+                error: text,
+            }
+            | ExecutionEvent::LogOutput {
+                stage_name,
+                step_name,
+                output: text,
+            } => {
+                mask(stage_name);
+                mask(step_name);
+                mask(text);
+            }
+            ExecutionEvent::ArtifactArchived {
+                stage_name,
+                artifact,
+            } => {
+                mask(stage_name);
+                mask(artifact);
+            }
+            ExecutionEvent::StashCreated { name, path }
+            | ExecutionEvent::StashRestored { name, path } => {
+                mask(name);
+                mask(path);
+            }
+        }
+        event
+    }
+}
+
 /// Listener trait for execution events
 #[async_trait]
 pub trait ExecutionListener: Send + Sync {
@@ -134,8 +248,8 @@ pub struct TracingListener;
 
 #[async_trait]
 impl ExecutionListener for TracingListener {
-    async fn on_event(&self, event: &ExecutionEvent, _context: &ExecutionContext) {
-        match event {
+    async fn on_event(&self, event: &ExecutionEvent, context: &ExecutionContext) {
+        match &event.masked(&context.masker) {
             ExecutionEvent::PipelineStarted {
                 pipeline_name,
                 execution_id,
@@ -157,6 +271,13 @@ impl ExecutionListener for TracingListener {
             } => {
                 event!(Level::ERROR, pipeline = %pipeline_name, error = %error, "Pipeline failed");
             }
+            ExecutionEvent::PipelineAborted {
+                pipeline_name,
+                reason,
+                ..
+            } => {
+                event!(Level::WARN, pipeline = %pipeline_name, reason = %reason, "Pipeline aborted");
+            }
             ExecutionEvent::StageStarted { stage_name, .. } => {
                 event!(Level::INFO, stage = %stage_name, "Stage started");
             }
@@ -224,7 +345,10 @@ impl ExecutionListener for NoopListener {
 }
 
 /// Buffer listener that stores events in memory
-#[derive(Debug, Default)]
+///
+/// Clones share the buffer, so a clone handed to an executor can be read
+/// through the original.
+#[derive(Debug, Default, Clone)]
 pub struct BufferListener {
     events: std::sync::Arc<std::sync::Mutex<Vec<ExecutionEvent>>>,
 }
@@ -278,7 +402,8 @@ impl TextFileListener {
 
 #[async_trait]
 impl ExecutionListener for TextFileListener {
-    async fn on_event(&self, event: &ExecutionEvent, _context: &ExecutionContext) {
+    async fn on_event(&self, event: &ExecutionEvent, context: &ExecutionContext) {
+        let event = event.masked(&context.masker);
         let mut file = self.file.lock().unwrap();
         if file.is_none() {
             let f = std::fs::OpenOptions::new()
@@ -296,8 +421,12 @@ impl ExecutionListener for TextFileListener {
 }
 
 /// Event emitter for execution events
+///
+/// Clones share the listener, so every clone of an execution context
+/// reports to the same place.
+#[derive(Clone)]
 pub struct EventEmitter {
-    listener: std::sync::Arc<std::sync::Mutex<Option<Box<dyn ExecutionListener>>>>,
+    listener: std::sync::Arc<std::sync::Mutex<Option<std::sync::Arc<dyn ExecutionListener>>>>,
 }
 
 impl Default for EventEmitter {
@@ -324,13 +453,18 @@ impl EventEmitter {
     /// Sets the listener
     pub fn set_listener<L: ExecutionListener + 'static>(&self, listener: L) {
         let mut guard = self.listener.lock().unwrap();
-        *guard = Some(Box::new(listener));
+        *guard = Some(std::sync::Arc::new(listener));
     }
 
     /// Emits an event
+    ///
+    /// Secrets in scope of `context` are masked before listeners see it.
     pub async fn emit(&self, event: ExecutionEvent, context: &ExecutionContext) {
-        let guard = self.listener.lock().unwrap();
-        if let Some(listener) = guard.as_ref() {
+        context.masker.add_environment(&context.environment);
+        let event = event.masked(&context.masker);
+        // Not holding the lock while listeners run keeps emitting `Send`
+        let listener = self.listener.lock().unwrap().clone();
+        if let Some(listener) = listener {
             listener.on_event(&event, context).await;
         }
     }
@@ -403,4 +537,144 @@ mod tests {
         // Should not panic even without a listener
         emitter.emit(event, &context).await;
     }
+
+    #[tokio::test]
+    async fn test_emitted_events_are_masked() {
+        let listener = BufferListener::new();
+        let events = Arc::clone(&listener.events);
+        let emitter = EventEmitter::new();
+        emitter.set_listener(listener);
+
+        let mut context = ExecutionContext::new();
+        context.environment.insert_secret("TOKEN", "s3cr3t-token");
+        let event = ExecutionEvent::LogOutput {
+            stage_name: "Deploy".to_string(),
+            step_name: "push".to_string(),
+            output: "Authorization: s3cr3t-token".to_string(),
+        };
+        emitter.emit(event, &context).await;
+
+        let events = events.lock().unwrap();
+        match &events[0] {
+            ExecutionEvent::LogOutput { output, .. } => {
+                assert_eq!(output, "Authorization: ****");
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_masked_covers_every_event() {
+        let secret = "s3cr3t-token";
+        let text = format!("value {secret}");
+        let stage = crate::StageResult {
+            name: text.clone(),
+            status: ExecutionStatus::Failure,
+            duration: chrono::Duration::zero(),
+            error: Some(text.clone()),
+        };
+        let result = ExecutionResult {
+            error: Some(text.clone()),
+            stages: vec![stage],
+            ..ExecutionResult::default()
+        };
+        let events = vec![
+            ExecutionEvent::PipelineStarted {
+                pipeline_name: text.clone(),
+                execution_id: text.clone(),
+                start_time: Utc::now(),
+            },
+            ExecutionEvent::PipelineCompleted {
+                pipeline_name: text.clone(),
+                execution_id: text.clone(),
+                result,
+                end_time: Utc::now(),
+            },
+            ExecutionEvent::PipelineFailed {
+                pipeline_name: text.clone(),
+                execution_id: text.clone(),
+                error: text.clone(),
+                end_time: Utc::now(),
+            },
+            ExecutionEvent::PipelineAborted {
+                pipeline_name: text.clone(),
+                execution_id: text.clone(),
+                reason: text.clone(),
+                end_time: Utc::now(),
+            },
+            ExecutionEvent::StageStarted {
+                stage_name: text.clone(),
+                execution_id: text.clone(),
+            },
+            ExecutionEvent::StageCompleted {
+                stage_name: text.clone(),
+                status: ExecutionStatus::Success,
+                duration: chrono::Duration::zero(),
+            },
+            ExecutionEvent::StageFailed {
+                stage_name: text.clone(),
+                error: text.clone(),
+            },
+            ExecutionEvent::StepStarted {
+                stage_name: text.clone(),
+                step_name: text.clone(),
+            },
+            ExecutionEvent::StepCompleted {
+                stage_name: text.clone(),
+                step_name: text.clone(),
+                status: ExecutionStatus::Success,
+                duration: chrono::Duration::zero(),
+            },
+            ExecutionEvent::StepFailed {
+                stage_name: text.clone(),
+                step_name: text.clone(),
+                error: text.clone(),
+            },
+            ExecutionEvent::LogOutput {
+                stage_name: text.clone(),
+                step_name: text.clone(),
+                output: text.clone(),
+            },
+            ExecutionEvent::ArtifactArchived {
+                stage_name: text.clone(),
+                artifact: text.clone(),
+            },
+            ExecutionEvent::StashCreated {
+                name: text.clone(),
+                path: text.clone(),
+            },
+            ExecutionEvent::StashRestored {
+                name: text.clone(),
+                path: text.clone(),
+            },
+        ];
+
+        let masker = SecretMasker::new();
+        masker.add(secret);
+        for event in events {
+            let shown = format!("{:?}", event.masked(&masker));
+            assert!(!shown.contains(secret), "secret left in {shown}");
+            assert!(shown.contains("value ****"), "nothing masked in {shown}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_text_file_listener_masks_events() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("events.log");
+        let listener = TextFileListener::new(&path);
+
+        let context = ExecutionContext::new();
+        context.masker.add("hunter2-password");
+        let event = ExecutionEvent::StepFailed {
+            stage_name: "Deploy".to_string(),
+            step_name: "login".to_string(),
+            error: "login failed for hunter2-password".to_string(),
+        };
+        listener.on_event(&event, &context).await;
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(log.contains("login failed for ****"));
+        assert!(!log.contains("hunter2-password"));
+    }
 }
@@ -7,13 +7,18 @@ use async_trait::async_trait;
 use std::path::PathBuf;
 use std::process::{Output, Stdio};
 use std::time::Duration;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 use tokio::process::Command;
 use tracing::{debug, error, info, warn};
 
+use pipeliner_core::pipeline::CredentialsBinding;
 use pipeliner_core::{Step, StepType};
 
-use crate::{ExecutionContext, ExecutionStatus, ExecutorResult};
+use crate::context::StepResult;
+use crate::credentials::CredentialsScope;
+use crate::input::{InputConfig, InputRequest};
+use crate::listener::ExecutionEvent;
+use crate::{ExecutionContext, ExecutionStatus, ExecutorErrorKind, ExecutorResult};
 
 /// Step executor trait
 #[async_trait]
@@ -28,13 +33,22 @@ pub trait StepExecutorTrait: Send + Sync {
 
 /// Built-in step executor
 #[derive(Debug, Default)]
-pub struct StepExecutor;
+pub struct StepExecutor {
+    input: InputConfig,
+}
 
 impl StepExecutor {
     /// Creates a new step executor
     #[must_use]
     pub fn new() -> Self {
-        Self
+        Self::default()
+    }
+
+    /// Sets how input steps obtain their answers
+    #[must_use]
+    pub fn with_input_config(mut self, input: InputConfig) -> Self {
+        self.input = input;
+        self
     }
 }
 
@@ -46,11 +60,104 @@ impl StepExecutorTrait for StepExecutor {
         context: &mut ExecutionContext,
     ) -> ExecutorResult<ExecutionStatus> {
         let step_name = step.name.clone().unwrap_or_else(|| "unnamed".to_string());
+        if let Some(reason) = context.cancellation.reason() {
+            debug!("Not starting step {}: {}", step_name, reason);
+            return Ok(ExecutionStatus::Aborted);
+        }
         context.set_current_step(&step_name);
+        let stage_name = context.current_stage.clone().unwrap_or_default();
+        context
+            .emit(ExecutionEvent::StepStarted {
+                stage_name: stage_name.clone(),
+                step_name: step_name.clone(),
+            })
+            .await;
 
         debug!("Executing step: {}", step_name);
 
-        let result = match &step.step_type {
+        let start_time = chrono::Utc::now();
+        let result = match self.dispatch(step, context).await {
+            Err(e) if matches!(e.0, ExecutorErrorKind::Aborted { .. }) => {
+                warn!("Step {} {}", step_name, e);
+                Ok(ExecutionStatus::Aborted)
+            }
+            result => result,
+        };
+        context.clear_current_step();
+
+        let duration = chrono::Utc::now().signed_duration_since(start_time);
+        let error = result.as_ref().err().map(|e| context.mask(&e.to_string()));
+        let status = if let Ok(status) = &result {
+            context
+                .emit(ExecutionEvent::StepCompleted {
+                    stage_name: stage_name.clone(),
+                    step_name,
+                    status: *status,
+                    duration,
+                })
+                .await;
+            *status
+        } else {
+            context
+                .emit(ExecutionEvent::StepFailed {
+                    stage_name: stage_name.clone(),
+                    step_name,
+                    error: error.clone().unwrap_or_default(),
+                })
+                .await;
+            ExecutionStatus::Failure
+        };
+        context
+            .record_step_result(StepResult {
+                stage: stage_name,
+                name: step.name.clone(),
+                status,
+                duration,
+                error,
+                output: None,
+            })
+            .await;
+        result
+    }
+}
+
+/// Process group of a running command, killed when dropped
+///
+/// Commands start in their own process group, so that killing the group
+/// also stops the processes they started, such as the children of
+/// `sh -c`.
+struct ProcessGroup(Option<u32>);
+
+impl ProcessGroup {
+    /// Kills every process of the group
+    fn kill(&mut self) {
+        let Some(pgid) = self.0.take() else {
+            return;
+        };
+        #[cfg(unix)]
+        if let Ok(pgid) = libc::pid_t::try_from(pgid) {
+            // SAFETY: kill(2) has no memory-safety preconditions
+            unsafe { libc::kill(-pgid, libc::SIGKILL) };
+        }
+        #[cfg(not(unix))]
+        let _ = pgid;
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+impl StepExecutor {
+    /// Runs a step according to its type
+    async fn dispatch(
+        &self,
+        step: &Step,
+        context: &mut ExecutionContext,
+    ) -> ExecutorResult<ExecutionStatus> {
+        match &step.step_type {
             StepType::Shell { command } => self.execute_shell(command, step, context).await,
             StepType::Echo { message } => self.execute_echo(message, step, context).await,
             StepType::Retry { count, step: inner } => {
@@ -73,7 +180,22 @@ impl StepExecutorTrait for StepExecutor {
                     .await
             }
             StepType::Unstash { name } => self.execute_unstash(name, step, context).await,
-            StepType::Input { message, .. } => self.execute_input(message, step, context).await,
+            StepType::Input {
+                message,
+                default,
+                parameters,
+                id,
+                submitter,
+            } => {
+                let request = InputRequest::new(
+                    message,
+                    default.as_ref(),
+                    parameters,
+                    id.as_ref(),
+                    submitter.as_ref(),
+                );
+                self.execute_input(request, step, context).await
+            }
             StepType::Dir { path, steps } => self.execute_dir(path, steps, step, context).await,
             StepType::Script { content } => self.execute_script(content, step, context).await,
             StepType::Archive {
@@ -84,17 +206,16 @@ impl StepExecutorTrait for StepExecutor {
                 self.execute_archive(artifacts, excludes, *fingerprint, step, context)
                     .await
             }
+            StepType::WithCredentials { bindings, steps } => {
+                self.execute_with_credentials(bindings, steps, step, context)
+                    .await
+            }
             StepType::Custom { name, config } => {
                 self.execute_custom(name, config, step, context).await
             }
-        };
-
-        context.clear_current_step();
-        result
+        }
     }
-}
 
-impl StepExecutor {
     async fn execute_shell(
         &self,
         command: &str,
@@ -103,7 +224,7 @@ impl StepExecutor {
     ) -> ExecutorResult<ExecutionStatus> {
         let resolved_command = self.resolve_variables(command, context);
 
-        info!("Executing shell: {}", resolved_command);
+        info!("Executing shell: {}", context.mask(&resolved_command));
 
         let output = self.run_command(&resolved_command, context).await?;
 
@@ -123,7 +244,8 @@ impl StepExecutor {
         context: &mut ExecutionContext,
     ) -> ExecutorResult<ExecutionStatus> {
         let resolved_message = self.resolve_variables(message, context);
-        info!("{}", resolved_message);
+        info!("{}", context.mask(&resolved_message));
+        log_output(context, resolved_message.as_bytes()).await;
         Ok(ExecutionStatus::Success)
     }
 
@@ -193,6 +315,12 @@ impl StepExecutor {
             self.copy_files(pattern, &stash_path, excludes).await?;
         }
 
+        context
+            .emit(ExecutionEvent::StashCreated {
+                name: name.to_string(),
+                path: stash_path.display().to_string(),
+            })
+            .await;
         context.stash(name, stash_path).await;
         Ok(ExecutionStatus::Success)
     }
@@ -205,6 +333,12 @@ impl StepExecutor {
     ) -> ExecutorResult<ExecutionStatus> {
         if let Some(path) = context.unstash(name).await {
             self.copy_all(&path, context.cwd()).await?;
+            context
+                .emit(ExecutionEvent::StashRestored {
+                    name: name.to_string(),
+                    path: path.display().to_string(),
+                })
+                .await;
             return Ok(ExecutionStatus::Success);
         }
 
@@ -214,11 +348,41 @@ impl StepExecutor {
 
     async fn execute_input(
         &self,
-        message: &str,
-        _step: &Step,
-        _context: &mut ExecutionContext,
+        mut request: InputRequest,
+        step: &Step,
+        context: &mut ExecutionContext,
     ) -> ExecutorResult<ExecutionStatus> {
-        warn!("Input step requires interactive input: {}", message);
+        request.execution_id = context.execution_id.to_string();
+
+        let answer = match self.input.resolve(&request, context, step.timeout).await {
+            Ok(answer) => answer,
+            Err(e) if matches!(e.0, ExecutorErrorKind::InputAborted { .. }) => {
+                warn!("{}", e);
+                return Ok(ExecutionStatus::Aborted);
+            }
+            Err(e) => return Err(e),
+        };
+
+        info!(
+            "Input '{}' approved by {}",
+            request.id,
+            answer.submitter.as_deref().unwrap_or("<unknown>")
+        );
+
+        let key = request.env_key();
+        if let Some(submitter) = answer.submitter {
+            context
+                .environment
+                .insert(format!("{key}_SUBMITTER"), submitter);
+        }
+        context
+            .environment
+            .insert(key, answer.value.unwrap_or_else(|| "true".to_string()));
+        for (name, value) in answer.parameters {
+            context.environment.insert(name.clone(), value.clone());
+            context.set_parameter(name, value);
+        }
+
         Ok(ExecutionStatus::Success)
     }
 
@@ -237,6 +401,24 @@ impl StepExecutor {
         result
     }
 
+    /// Runs steps with credentials bound
+    ///
+    /// The steps run on the context itself. Only the bound variables are
+    /// restored afterwards, also when the run is cut short, such as by a
+    /// timeout, so other changes the steps make are kept.
+    async fn execute_with_credentials(
+        &self,
+        bindings: &[CredentialsBinding],
+        steps: &[Step],
+        _step: &Step,
+        context: &mut ExecutionContext,
+    ) -> ExecutorResult<ExecutionStatus> {
+        let mut scope = CredentialsScope::bind(bindings, context).await?;
+        let result = self.execute_steps(steps, &mut scope).await;
+        scope.release().await;
+        result
+    }
+
     async fn execute_script(
         &self,
         content: &str,
@@ -268,8 +450,16 @@ impl StepExecutor {
         let archive_dir = context.cwd().join(".pipeliner").join("archive");
         tokio::fs::create_dir_all(&archive_dir).await?;
 
+        let stage_name = context.current_stage.clone().unwrap_or_default();
         for pattern in artifacts {
-            self.copy_files(pattern, &archive_dir, excludes).await?;
+            for artifact in self.copy_files(pattern, &archive_dir, excludes).await? {
+                context
+                    .emit(ExecutionEvent::ArtifactArchived {
+                        stage_name: stage_name.clone(),
+                        artifact: artifact.display().to_string(),
+                    })
+                    .await;
+            }
         }
 
         Ok(ExecutionStatus::Success)
@@ -301,6 +491,11 @@ impl StepExecutor {
         Ok(ExecutionStatus::Success)
     }
 
+    /// Runs a command, forwarding its stdout to the listener as it arrives
+    ///
+    /// Output is forwarded and masked a line at a time, so a secret is
+    /// masked even if the process writes it in pieces. A line is held back
+    /// until it is complete.
     async fn run_command(
         &self,
         command: &str,
@@ -327,37 +522,59 @@ impl StepExecutor {
             .current_dir(context.cwd())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
-            .stdin(Stdio::null());
+            .stdin(Stdio::null())
+            .kill_on_drop(true);
+        #[cfg(unix)]
+        cmd.process_group(0);
 
         for (key, value) in context.environment.iter() {
-            cmd.env(key, value.to_string());
+            cmd.env(key, value.value());
         }
 
-        let mut child = cmd.spawn().map_err(|e| {
-            crate::ExecutorError::from(crate::ExecutorErrorKind::IoError { reason: e })
-        })?;
+        // Secrets never leave the executor through a step's output
+        context.masker.add_environment(&context.environment);
 
-        let status = child.wait().await.map_err(|e| {
+        let mut child = cmd.spawn().map_err(|e| {
             crate::ExecutorError::from(crate::ExecutorErrorKind::IoError { reason: e })
         })?;
+        // Killed if the step is cut short, by a timeout or a cancellation
+        let mut group = ProcessGroup(child.id());
+
+        // Both pipes are drained while the process runs, so output larger
+        // than a pipe buffer cannot block it
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        let run = async {
+            tokio::join!(
+                child.wait(),
+                read_lines(stdout, context, true),
+                read_lines(stderr, context, false),
+            )
+        };
 
-        let mut stdout_buf = Vec::new();
-        if let Some(mut stdout) = child.stdout {
-            let _ = stdout.read_to_end(&mut stdout_buf).await;
-        }
-
-        let mut stderr_buf = Vec::new();
-        if let Some(mut stderr) = child.stderr {
-            let _ = stderr.read_to_end(&mut stderr_buf).await;
-        }
+        let (status, stdout_buf, stderr_buf) = tokio::select! {
+            (status, stdout, stderr) = run => {
+                let status = status.map_err(|e| {
+                    crate::ExecutorError::from(crate::ExecutorErrorKind::IoError { reason: e })
+                })?;
+                (status, stdout, stderr)
+            }
+            reason = context.cancellation.cancelled() => {
+                warn!("Killing '{}': execution cancelled", parts[0]);
+                group.kill();
+                let _ = child.kill().await;
+                return Err(crate::ExecutorError::from(
+                    crate::ExecutorErrorKind::Aborted { reason },
+                ));
+            }
+        };
+        group.0 = None;
 
-        let output = Output {
+        Ok(Output {
             status,
             stdout: stdout_buf,
             stderr: stderr_buf,
-        };
-
-        Ok(output)
+        })
     }
 
     fn resolve_variables(&self, input: &str, context: &ExecutionContext) -> String {
@@ -365,7 +582,7 @@ impl StepExecutor {
 
         for (key, value) in context.environment.iter() {
             let placeholder = format!("${{{}}}", key);
-            result = result.replace(&placeholder, &value.to_string());
+            result = result.replace(&placeholder, &value.value());
         }
 
         result
@@ -376,7 +593,8 @@ impl StepExecutor {
         pattern: &str,
         dest: &PathBuf,
         excludes: &[String],
-    ) -> ExecutorResult<()> {
+    ) -> ExecutorResult<Vec<PathBuf>> {
+        let mut copied = Vec::new();
         let matches = glob::glob(pattern).map_err(|e| {
             crate::ExecutorError::from(crate::ExecutorErrorKind::StepFailed {
                 reason: format!("Glob pattern error: {}", e),
@@ -391,10 +609,11 @@ impl StepExecutor {
             if path.is_file() {
                 let dest_path = dest.join(path.file_name().unwrap_or_default());
                 tokio::fs::copy(&path, &dest_path).await?;
+                copied.push(path);
             }
         }
 
-        Ok(())
+        Ok(copied)
     }
 
     async fn copy_all(&self, from: &PathBuf, to: &PathBuf) -> ExecutorResult<()> {
@@ -417,6 +636,49 @@ impl StepExecutor {
     }
 }
 
+/// Reads a child's pipe until it is closed, masking it line by line
+///
+/// With `forward` set every line is also sent to the listener as soon as
+/// it is complete.
+async fn read_lines(
+    pipe: Option<impl AsyncRead + Unpin>,
+    context: &ExecutionContext,
+    forward: bool,
+) -> Vec<u8> {
+    let mut output = Vec::new();
+    let Some(pipe) = pipe else {
+        return output;
+    };
+    let mut reader = BufReader::new(pipe);
+    let mut line = Vec::new();
+    while let Ok(read) = reader.read_until(b'\n', &mut line).await {
+        if read == 0 {
+            break;
+        }
+        let masked = context.masker.mask_bytes(&line);
+        if forward {
+            log_output(context, &masked).await;
+        }
+        output.extend_from_slice(&masked);
+        line.clear();
+    }
+    output
+}
+
+/// Sends the output of the current step to the execution's listener
+async fn log_output(context: &ExecutionContext, output: &[u8]) {
+    if output.is_empty() {
+        return;
+    }
+    context
+        .emit(ExecutionEvent::LogOutput {
+            stage_name: context.current_stage.clone().unwrap_or_default(),
+            step_name: context.current_step.clone().unwrap_or_default(),
+            output: String::from_utf8_lossy(output).into_owned(),
+        })
+        .await;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -471,4 +733,304 @@ mod tests {
         let result = executor.resolve_variables("${FOO}", &context);
         assert_eq!(result, "bar");
     }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_with_credentials_binds_for_nested_steps() {
+        let mut context = ExecutionContext::new();
+        context.credentials = std::sync::Arc::new(
+            pipeliner_core::credentials::EnvCredentialsProvider::from_vars(
+                [(
+                    "PIPELINER_CREDENTIALS_NPM".to_string(),
+                    "npm-s3cr3t".to_string(),
+                )]
+                .into(),
+            ),
+        );
+        let step = Step::credentials(
+            vec![CredentialsBinding::String {
+                credentials_id: "npm".to_string(),
+                variable: "NPM_TOKEN".to_string(),
+            }],
+            vec![Step::shell("sh -c 'test \"$NPM_TOKEN\" = npm-s3cr3t'")],
+        );
+        let executor = StepExecutor::new();
+
+        let status = executor.execute(&step, &mut context).await.unwrap();
+        assert_eq!(status, ExecutionStatus::Success);
+        assert!(context.environment.get("NPM_TOKEN").is_none());
+
+        let missing = Step::credentials(
+            vec![CredentialsBinding::String {
+                credentials_id: "unknown".to_string(),
+                variable: "TOKEN".to_string(),
+            }],
+            vec![Step::echo("unreachable")],
+        );
+        let error = executor.execute(&missing, &mut context).await.unwrap_err();
+        assert!(error.to_string().contains("'unknown' not found"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_with_credentials_released_on_timeout() {
+        let dir = TempDir::new().unwrap();
+        let kubeconfig = dir.path().join("kubeconfig");
+        std::fs::write(&kubeconfig, "apiVersion: v1").unwrap();
+        let mut context = ExecutionContext::with_working_dir(dir.path().to_path_buf());
+        context.credentials = std::sync::Arc::new(
+            pipeliner_core::credentials::EnvCredentialsProvider::from_vars(
+                [
+                    (
+                        "PIPELINER_CREDENTIALS_NPM".to_string(),
+                        "npm-s3cr3t".to_string(),
+                    ),
+                    (
+                        "PIPELINER_CREDENTIALS_KUBE_FILE".to_string(),
+                        kubeconfig.display().to_string(),
+                    ),
+                ]
+                .into(),
+            ),
+        );
+        let credentials = Step::credentials(
+            vec![
+                CredentialsBinding::String {
+                    credentials_id: "npm".to_string(),
+                    variable: "NPM_TOKEN".to_string(),
+                },
+                CredentialsBinding::File {
+                    credentials_id: "kube".to_string(),
+                    variable: "KUBECONFIG".to_string(),
+                },
+            ],
+            vec![Step::shell("sh -c 'echo $KUBECONFIG > bound; sleep 5'")],
+        );
+        let step = Step {
+            step_type: StepType::Timeout {
+                duration: Duration::from_millis(500),
+                step: Box::new(credentials),
+            },
+            name: None,
+            timeout: None,
+            retry: None,
+        };
+        let executor = StepExecutor::new();
+
+        let status = executor.execute(&step, &mut context).await.unwrap();
+        assert_eq!(status, ExecutionStatus::Timeout);
+        assert!(context.environment.get("NPM_TOKEN").is_none());
+        assert!(context.environment.get("KUBECONFIG").is_none());
+        let bound = std::fs::read_to_string(dir.path().join("bound")).unwrap();
+        assert!(bound.contains("pipeliner-credentials-"), "{bound}");
+        assert!(!std::path::Path::new(bound.trim()).exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_output_larger_than_pipe_buffer() {
+        let context = ExecutionContext::new();
+        let executor = StepExecutor::new();
+
+        let output = tokio::time::timeout(
+            Duration::from_secs(10),
+            executor.run_command(
+                "sh -c 'head -c 200000 /dev/zero; head -c 100000 /dev/zero >&2'",
+                &context,
+            ),
+        )
+        .await
+        .expect("command blocked on a full pipe")
+        .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout.len(), 200_000);
+        assert_eq!(output.stderr.len(), 100_000);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_output_is_forwarded_line_by_line() {
+        let listener = crate::listener::BufferListener::new();
+        let context = ExecutionContext::new();
+        context.events.set_listener(listener.clone());
+        context.masker.add("s3cr3t-token");
+        let executor = StepExecutor::new();
+
+        // The secret is written in two pieces, but masked within its line
+        executor
+            .run_command(
+                "sh -c 'echo first; printf s3cr3t; sleep 0.1; echo -token; printf last'",
+                &context,
+            )
+            .await
+            .unwrap();
+
+        let lines: Vec<String> = listener
+            .get_events()
+            .into_iter()
+            .filter_map(|event| match event {
+                ExecutionEvent::LogOutput { output, .. } => Some(output),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(lines, ["first\n", "****\n", "last"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_secrets_reach_process_but_not_output() {
+        let mut context = ExecutionContext::new();
+        context.environment.insert_secret("TOKEN", "s3cr3t-token");
+        let executor = StepExecutor::new();
+
+        // The process sees the real value, its output only the mask
+        let output = executor
+            .run_command(
+                "sh -c 'test \"$TOKEN\" = s3cr3t-token && echo $TOKEN'",
+                &context,
+            )
+            .await
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "****\n");
+
+        let output = executor
+            .run_command("sh -c 'printf %s ${TOKEN} | base64 >&2'", &context)
+            .await
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "****\n");
+    }
+
+    #[tokio::test]
+    async fn test_input_answer_is_stored_in_environment() {
+        let temp_dir = TempDir::new().unwrap();
+        let step = Step::approval("deploy", "Deploy?", "alice");
+        let mut context = ExecutionContext::new();
+        context.environment.insert("PIPELINER_ANSWER_DEPLOY", "v2");
+        context.environment.insert("PIPELINER_SUBMITTER", "alice");
+
+        let executor = StepExecutor::new().with_input_config(crate::InputConfig {
+            interactive: Some(false),
+            registry_dir: Some(temp_dir.path().to_path_buf()),
+            ..crate::InputConfig::default()
+        });
+        let result = executor.execute(&step, &mut context).await.unwrap();
+
+        assert_eq!(result, ExecutionStatus::Success);
+        assert_eq!(
+            context.environment.get("INPUT_DEPLOY").unwrap().to_string(),
+            "v2"
+        );
+        assert_eq!(
+            context
+                .environment
+                .get("INPUT_DEPLOY_SUBMITTER")
+                .unwrap()
+                .to_string(),
+            "alice"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_input_timeout_aborts() {
+        let temp_dir = TempDir::new().unwrap();
+        let step = Step::approval("deploy", "Deploy?", "alice")
+            .with_timeout(std::time::Duration::from_millis(50));
+        let mut context = ExecutionContext::new();
+
+        let executor = StepExecutor::new().with_input_config(crate::InputConfig {
+            interactive: Some(false),
+            registry_dir: Some(temp_dir.path().to_path_buf()),
+            poll_interval: std::time::Duration::from_millis(10),
+            ..crate::InputConfig::default()
+        });
+        let result = executor.execute(&step, &mut context).await.unwrap();
+
+        assert_eq!(result, ExecutionStatus::Aborted);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_kills_running_command() {
+        let step = Step {
+            step_type: StepType::Shell {
+                command: "sleep 30".to_string(),
+            },
+            name: Some("slow".to_string()),
+            timeout: None,
+            retry: None,
+        };
+        let mut context = ExecutionContext::new();
+        let token = context.cancellation.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            token.cancel("stopped by test");
+        });
+
+        let start = std::time::Instant::now();
+        let result = StepExecutor::new().execute(&step, &mut context).await;
+
+        assert_eq!(result.unwrap(), ExecutionStatus::Aborted);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // Nothing starts once the execution is cancelled
+        let result = StepExecutor::new()
+            .execute(&create_test_step(), &mut context)
+            .await;
+        assert_eq!(result.unwrap(), ExecutionStatus::Aborted);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_cancel_and_timeout_kill_process_group() {
+        /// Waits until the process whose PID is in `file` has exited
+        async fn exited(file: &std::path::Path) -> bool {
+            let pid: libc::pid_t = std::fs::read_to_string(file)
+                .unwrap()
+                .trim()
+                .parse()
+                .unwrap();
+            for _ in 0..100 {
+                // A killed process stays a zombie until it is reaped
+                let zombie = std::fs::read_to_string(format!("/proc/{pid}/stat"))
+                    .is_ok_and(|stat| stat.contains(") Z "));
+                // SAFETY: kill(2) with signal 0 only checks the process exists
+                if zombie || unsafe { libc::kill(pid, 0) } != 0 {
+                    return true;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            false
+        }
+
+        let dir = TempDir::new().unwrap();
+        let background =
+            |file: &str| Step::shell(format!("sh -c 'sleep 30 & echo $! > {file}; wait'"));
+
+        let mut context = ExecutionContext::with_working_dir(dir.path().to_path_buf());
+        let token = context.cancellation.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            token.cancel("stopped by test");
+        });
+        let result = StepExecutor::new()
+            .execute(&background("aborted"), &mut context)
+            .await;
+        assert_eq!(result.unwrap(), ExecutionStatus::Aborted);
+        assert!(exited(&dir.path().join("aborted")).await);
+
+        let mut context = ExecutionContext::with_working_dir(dir.path().to_path_buf());
+        let step = Step {
+            step_type: StepType::Timeout {
+                duration: Duration::from_millis(300),
+                step: Box::new(background("timed-out")),
+            },
+            name: None,
+            timeout: None,
+            retry: None,
+        };
+        let result = StepExecutor::new().execute(&step, &mut context).await;
+        assert_eq!(result.unwrap(), ExecutionStatus::Timeout);
+        assert!(exited(&dir.path().join("timed-out")).await);
+    }
 }
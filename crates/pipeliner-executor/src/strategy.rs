@@ -2,14 +2,26 @@
 //!
 //! This module provides different execution strategies for running
 //! pipeline stages, including sequential and parallel execution.
+//!
+//! Every strategy stops scheduling stages once the context's cancellation
+//! token is cancelled and reports the execution as aborted. Stages whose
+//! `when` condition does not hold are skipped. A stage's options retry its
+//! steps and bound them with a timeout; its post conditions run once
+//! afterwards.
 
 use async_trait::async_trait;
 use tokio::task;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
+use pipeliner_core::pipeline::PostCondition;
 use pipeliner_core::{Pipeline, Stage};
 
-use crate::{ExecutionContext, ExecutionResult, ExecutionStatus, ExecutorResult};
+use crate::context::StageResult;
+use crate::listener::ExecutionEvent;
+use crate::runtime::{StepExecutor, StepExecutorTrait};
+use crate::{
+    CancellationToken, ExecutionContext, ExecutionResult, ExecutionStatus, ExecutorResult,
+};
 
 /// Execution strategy trait
 #[async_trait]
@@ -49,8 +61,18 @@ impl ExecutionStrategy for SequentialStrategy {
             "Starting sequential execution of pipeline: {:?}",
             pipeline.name
         );
+        prepare_context(pipeline, context).await?;
 
         for stage in &pipeline.stages {
+            if let Some(reason) = context.cancellation.reason() {
+                let duration = chrono::Utc::now().signed_duration_since(start_time);
+                return Ok(ExecutionResult::aborted(
+                    stages_executed,
+                    steps_executed,
+                    duration,
+                    reason,
+                ));
+            }
             context.set_current_stage(&stage.name);
 
             let result = execute_stage(stage, context).await;
@@ -65,6 +87,19 @@ impl ExecutionStrategy for SequentialStrategy {
                 Ok(ExecutionStatus::Unstable) => {
                     debug!("Stage '{}' completed with unstable status", stage.name);
                 }
+                Ok(ExecutionStatus::Aborted) => {
+                    let duration = chrono::Utc::now().signed_duration_since(start_time);
+                    let reason = context
+                        .cancellation
+                        .reason()
+                        .unwrap_or_else(|| format!("Stage '{}' aborted", stage.name));
+                    return Ok(ExecutionResult::aborted(
+                        stages_executed,
+                        steps_executed,
+                        duration,
+                        reason,
+                    ));
+                }
                 Ok(status) => {
                     let duration = chrono::Utc::now().signed_duration_since(start_time);
                     return Ok(ExecutionResult::failure(
@@ -80,7 +115,7 @@ impl ExecutionStrategy for SequentialStrategy {
                         stages_executed,
                         steps_executed,
                         duration,
-                        format!("Stage '{}' error: {}", stage.name, e),
+                        context.mask(&format!("Stage '{}' error: {}", stage.name, e)),
                     ));
                 }
             }
@@ -127,18 +162,35 @@ impl ExecutionStrategy for ParallelStrategy {
             "Starting parallel execution of pipeline: {:?} (max {} concurrent)",
             pipeline.name, self.max_concurrent
         );
+        prepare_context(pipeline, context).await?;
 
         let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(self.max_concurrent));
         let mut handles = Vec::new();
 
+        // Stages share a token of their own, so a fail-fast stage can stop
+        // its siblings without aborting the whole execution
+        let siblings = CancellationToken::new();
+        let watcher = {
+            let parent = context.cancellation.clone();
+            let siblings = siblings.clone();
+            tokio::spawn(async move {
+                let reason = parent.cancelled().await;
+                siblings.cancel(reason);
+            })
+        };
+
         for stage in &pipeline.stages {
             let permit = semaphore.clone().acquire_owned().await.unwrap();
+            if context.cancellation.is_cancelled() || siblings.is_cancelled() {
+                break;
+            }
             let stage = stage.clone();
             let mut context = context.clone();
+            context.cancellation = siblings.clone();
 
             let handle = tokio::spawn(async move {
                 let _permit = permit;
-                execute_stage(&stage, &mut context).await
+                execute_sibling_stage(&stage, &mut context).await
             });
 
             handles.push(handle);
@@ -146,9 +198,14 @@ impl ExecutionStrategy for ParallelStrategy {
 
         let mut has_failure = false;
         let mut failure_reason = None;
+        let mut aborted = None;
 
         for handle in handles {
             match handle.await {
+                Ok(Ok(ExecutionStatus::Aborted)) => {
+                    stages_executed += 1;
+                    aborted = Some("One or more stages aborted".to_string());
+                }
                 Ok(Ok(status)) => {
                     stages_executed += 1;
                     if !status.is_success() && !matches!(status, ExecutionStatus::Unstable) {
@@ -157,7 +214,7 @@ impl ExecutionStrategy for ParallelStrategy {
                 }
                 Ok(Err(e)) => {
                     has_failure = true;
-                    failure_reason = Some(e.to_string());
+                    failure_reason = Some(context.mask(&e.to_string()));
                 }
                 Err(e) => {
                     has_failure = true;
@@ -166,12 +223,23 @@ impl ExecutionStrategy for ParallelStrategy {
             }
         }
 
+        watcher.abort();
         let duration = chrono::Utc::now().signed_duration_since(start_time);
 
         for stage in &pipeline.stages {
             steps_executed += stage.steps.len();
         }
 
+        if let Some(reason) = context.cancellation.reason() {
+            return Ok(ExecutionResult::aborted(
+                stages_executed,
+                steps_executed,
+                duration,
+                reason,
+            ));
+        }
+
+        // Siblings stopped by a fail-fast stage make the execution fail
         if has_failure {
             return Ok(ExecutionResult::failure(
                 stages_executed,
@@ -181,6 +249,15 @@ impl ExecutionStrategy for ParallelStrategy {
             ));
         }
 
+        if let Some(reason) = aborted {
+            return Ok(ExecutionResult::aborted(
+                stages_executed,
+                steps_executed,
+                duration,
+                reason,
+            ));
+        }
+
         Ok(ExecutionResult::success(
             stages_executed,
             steps_executed,
@@ -218,6 +295,7 @@ impl ExecutionStrategy for MatrixStrategy {
             }
         };
 
+        let matrix = matrix.resolve_expressions(&context.expression_context().await)?;
         let cells = matrix.generate_cells();
         let start_time = chrono::Utc::now();
 
@@ -227,6 +305,15 @@ impl ExecutionStrategy for MatrixStrategy {
         let mut cells_failed = 0;
 
         for cell in &cells {
+            if let Some(reason) = context.cancellation.reason() {
+                let duration = chrono::Utc::now().signed_duration_since(start_time);
+                return Ok(ExecutionResult::aborted(
+                    cells_executed,
+                    cells_failed,
+                    duration,
+                    reason,
+                ));
+            }
             let cell_values = cell.values.clone();
             let cell_name = cell.name.clone();
 
@@ -237,7 +324,13 @@ impl ExecutionStrategy for MatrixStrategy {
             }
 
             match SequentialStrategy::new().execute(pipeline, context).await {
-                Ok(_) => cells_executed += 1,
+                Ok(result) if result.is_aborted() => return Ok(result),
+                Ok(result) => {
+                    cells_executed += 1;
+                    if !result.is_success() {
+                        cells_failed += 1;
+                    }
+                }
                 Err(_) => cells_failed += 1,
             }
         }
@@ -261,34 +354,227 @@ impl ExecutionStrategy for MatrixStrategy {
     }
 }
 
-/// Executes a single stage
+/// Executes a single stage unless its `when` condition does not hold, and
+/// records its result
 async fn execute_stage(
     stage: &Stage,
     context: &mut ExecutionContext,
 ) -> ExecutorResult<ExecutionStatus> {
-    use crate::runtime::{StepExecutor, StepExecutorTrait};
+    if let Some(when) = &stage.when
+        && !when.evaluate(&context.expression_context().await)?
+    {
+        info!("Skipping stage '{}': when condition not met", stage.name);
+        return Ok(ExecutionStatus::Success);
+    }
+
+    context.set_current_stage(&stage.name);
+    context
+        .emit(ExecutionEvent::StageStarted {
+            stage_name: stage.name.clone(),
+            execution_id: context.execution_id.to_string(),
+        })
+        .await;
+
+    let start_time = chrono::Utc::now();
+    let result = execute_stage_steps(stage, context).await;
+    let duration = chrono::Utc::now().signed_duration_since(start_time);
+    let error = result.as_ref().err().map(|e| context.mask(&e.to_string()));
+
+    match &result {
+        Ok(status) => {
+            context
+                .emit(ExecutionEvent::StageCompleted {
+                    stage_name: stage.name.clone(),
+                    status: *status,
+                    duration,
+                })
+                .await;
+        }
+        Err(_) => {
+            context
+                .emit(ExecutionEvent::StageFailed {
+                    stage_name: stage.name.clone(),
+                    error: error.clone().unwrap_or_default(),
+                })
+                .await;
+        }
+    }
+    context
+        .record_stage_result(StageResult {
+            name: stage.name.clone(),
+            status: result
+                .as_ref()
+                .map_or(ExecutionStatus::Failure, |status| *status),
+            duration,
+            error,
+        })
+        .await;
+    result
+}
+
+/// Executes a stage running next to others, cancelling them if it fails
+/// with `fail_fast` set
+async fn execute_sibling_stage(
+    stage: &Stage,
+    context: &mut ExecutionContext,
+) -> ExecutorResult<ExecutionStatus> {
+    let result = execute_stage(stage, context).await;
+    if has_failed(&result) && stage.options.as_ref().is_some_and(|o| o.fail_fast) {
+        info!("Stage '{}' failed, stopping its siblings", stage.name);
+        context
+            .cancellation
+            .cancel(format!("Stage '{}' failed", stage.name));
+    }
+    result
+}
 
+/// Returns true if a stage or its steps failed rather than being aborted
+fn has_failed(result: &ExecutorResult<ExecutionStatus>) -> bool {
+    result.as_ref().map_or(true, |status| {
+        status.is_failure() && *status != ExecutionStatus::Aborted
+    })
+}
+
+/// Executes the steps of a stage followed by its post conditions
+///
+/// The stage's `retry` option runs the steps again while they fail and its
+/// `timeout` bounds all attempts together. Post conditions are not retried.
+async fn execute_stage_steps(
+    stage: &Stage,
+    context: &mut ExecutionContext,
+) -> ExecutorResult<ExecutionStatus> {
     let executor = StepExecutor::new();
+    context.masker.add_environment(&stage.environment);
+
+    let options = stage.options.clone().unwrap_or_default();
+    let attempts = execute_attempts(&executor, stage, options.retry.unwrap_or(0), context);
+    let result = match options.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, attempts)
+            .await
+            .unwrap_or_else(|_| {
+                warn!("Stage '{}' timed out after {:?}", stage.name, timeout);
+                Ok(ExecutionStatus::Timeout)
+            }),
+        None => attempts.await,
+    };
+
+    if let Some(post) = &stage.post {
+        let status = result
+            .as_ref()
+            .map_or(ExecutionStatus::Failure, |status| *status);
+        let post = execute_post(&executor, post, status, context).await;
+        let status = result?;
+        post?;
+        return Ok(status);
+    }
 
-    for step in &stage.steps {
-        match executor.execute(step, context).await {
-            Ok(ExecutionStatus::Success) => continue,
-            Ok(status) => return Ok(status),
-            Err(e) => return Err(e),
+    result
+}
+
+/// Runs the steps of a stage up to `retries` more times while they fail
+async fn execute_attempts(
+    executor: &StepExecutor,
+    stage: &Stage,
+    retries: usize,
+    context: &mut ExecutionContext,
+) -> ExecutorResult<ExecutionStatus> {
+    let mut attempt = 0;
+    loop {
+        let result = execute_steps(executor, &stage.steps, context).await;
+        if !has_failed(&result) || attempt >= retries || context.cancellation.is_cancelled() {
+            return result;
         }
+        attempt += 1;
+        info!(
+            "Retrying stage '{}' (attempt {}/{})",
+            stage.name, attempt, retries
+        );
     }
+}
 
+/// Executes steps in order, stopping at the first that does not succeed
+async fn execute_steps(
+    executor: &StepExecutor,
+    steps: &[pipeliner_core::Step],
+    context: &mut ExecutionContext,
+) -> ExecutorResult<ExecutionStatus> {
+    for step in steps {
+        match executor.execute(step, context).await {
+            Ok(ExecutionStatus::Success) => {}
+            outcome => return outcome,
+        }
+    }
     Ok(ExecutionStatus::Success)
 }
 
+/// Resolves credentials references and expressions, and registers the
+/// pipeline's secrets
+async fn prepare_context(
+    pipeline: &Pipeline,
+    context: &mut ExecutionContext,
+) -> ExecutorResult<()> {
+    let provider = std::sync::Arc::clone(&context.credentials);
+    context
+        .environment
+        .resolve_credentials(provider.as_ref())
+        .await?;
+    let variables = context.expression_context().await;
+    context.environment.resolve_expressions(&variables)?;
+    context.masker.add_environment(&pipeline.environment);
+    Ok(())
+}
+
+/// Executes the post conditions that apply to a stage status
+///
+/// `always` runs first and `cleanup` last. `changed` runs after `always`
+/// when the previous build is known and finished with another status. Post
+/// conditions of an aborted stage run even though the execution is
+/// cancelled.
+async fn execute_post(
+    executor: &StepExecutor,
+    post: &PostCondition,
+    status: ExecutionStatus,
+    context: &mut ExecutionContext,
+) -> ExecutorResult<()> {
+    let mut detached;
+    let context = if status == ExecutionStatus::Aborted {
+        detached = context.detach_cancellation();
+        &mut detached
+    } else {
+        context
+    };
+
+    let conditional = match status {
+        ExecutionStatus::Success => &post.success,
+        ExecutionStatus::Failure | ExecutionStatus::Timeout => &post.failure,
+        ExecutionStatus::Unstable => &post.unstable,
+        ExecutionStatus::Aborted => &post.aborted,
+        ExecutionStatus::Pending | ExecutionStatus::Running => &Vec::new(),
+    };
+
+    let changed = match context.previous_status {
+        Some(previous) if previous.result_name() != status.result_name() => &post.changed,
+        _ => &Vec::new(),
+    };
+
+    for step in post
+        .always
+        .iter()
+        .chain(changed)
+        .chain(conditional)
+        .chain(&post.cleanup)
+    {
+        executor.execute(step, context).await?;
+    }
+    Ok(())
+}
+
 /// Executes pipeline for a matrix cell
 async fn execute_pipeline_for_cell(
     pipeline: &Pipeline,
     _cell_values: &std::collections::HashMap<String, String>,
     context: &mut ExecutionContext,
 ) -> ExecutorResult<ExecutionStatus> {
-    use crate::runtime::{StepExecutor, StepExecutorTrait};
-
     let executor = StepExecutor::new();
 
     for stage in &pipeline.stages {
@@ -382,4 +668,120 @@ mod tests {
         let strategy = ParallelStrategy::new(5);
         assert_eq!(strategy.max_concurrent, 5);
     }
+
+    fn shell(command: String) -> Step {
+        Step {
+            step_type: StepType::Shell { command },
+            name: None,
+            timeout: None,
+            retry: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_aborts_sequential_execution() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let marker = |name: &str| temp_dir.path().join(name).display().to_string();
+        let post = PostCondition {
+            aborted: vec![shell(format!("touch {}", marker("aborted")))],
+            failure: vec![shell(format!("touch {}", marker("failure")))],
+            cleanup: vec![shell(format!("touch {}", marker("cleanup")))],
+            ..PostCondition::default()
+        };
+        let mut pipeline = create_test_pipeline();
+        pipeline.stages[0].steps = vec![shell("sleep 30".to_string())];
+        pipeline.stages[0].post = Some(post);
+        pipeline.stages[1].steps = vec![shell(format!("touch {}", marker("deployed")))];
+
+        let mut context = ExecutionContext::with_working_dir(temp_dir.path().to_path_buf());
+        let token = context.cancellation.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            token.cancel("interrupted");
+        });
+
+        let result = SequentialStrategy::new()
+            .execute(&pipeline, &mut context)
+            .await
+            .unwrap();
+
+        assert!(result.is_aborted());
+        assert_eq!(result.stages_executed, 1);
+        assert_eq!(result.error.as_deref(), Some("interrupted"));
+        assert!(temp_dir.path().join("aborted").exists());
+        assert!(temp_dir.path().join("cleanup").exists());
+        assert!(!temp_dir.path().join("failure").exists());
+        assert!(!temp_dir.path().join("deployed").exists());
+    }
+
+    #[tokio::test]
+    async fn test_when_conditions_skip_stages() {
+        use pipeliner_core::environment::{EnvVarValue, ExpressionValue};
+        use pipeliner_core::pipeline::WhenCondition;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let marker = |name: &str| temp_dir.path().join(name).display().to_string();
+        let when = |expression: &str| WhenCondition {
+            expression: Some(expression.to_string()),
+            ..WhenCondition::default()
+        };
+
+        let mut pipeline = create_test_pipeline();
+        pipeline.stages[0].when = Some(when("env.TARGET == 'production'"));
+        pipeline.stages[0].steps = vec![shell(format!("touch {}", marker("skipped")))];
+        pipeline.stages[1].when = Some(when(
+            "params.DEPLOY == true && stages.Stage1 == null && currentBuild.result == null",
+        ));
+        pipeline.stages[1].steps = vec![shell(format!("touch {}-${{TARGET}}", marker("ran")))];
+
+        let mut context = ExecutionContext::with_working_dir(temp_dir.path().to_path_buf());
+        context.set_parameter("DEPLOY", "true");
+        context.environment.insert("BRANCH_NAME", "release/1.2");
+        context.environment.0.insert(
+            "TARGET".to_string(),
+            EnvVarValue::Expression(ExpressionValue {
+                expression: "env.BRANCH_NAME.startsWith('release/') ? 'x' : 'y'".to_string(),
+            }),
+        );
+
+        let error = SequentialStrategy::new()
+            .execute(&pipeline, &mut context)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("at column"));
+
+        context.environment.0.insert(
+            "TARGET".to_string(),
+            EnvVarValue::Expression(ExpressionValue {
+                expression: "'staging-' + split(env.BRANCH_NAME, '/')[1]".to_string(),
+            }),
+        );
+        let result = SequentialStrategy::new()
+            .execute(&pipeline, &mut context)
+            .await
+            .unwrap();
+
+        assert!(result.is_success());
+        assert!(!temp_dir.path().join("skipped").exists());
+        assert!(temp_dir.path().join("ran-staging-1.2").exists());
+
+        let results = context.stage_results.lock().await;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].name, "Stage2");
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_parallel_execution_is_aborted() {
+        let pipeline = create_test_pipeline();
+        let mut context = ExecutionContext::new();
+        context.cancellation.cancel("interrupted");
+
+        let result = ParallelStrategy::new(2)
+            .execute(&pipeline, &mut context)
+            .await
+            .unwrap();
+
+        assert!(result.is_aborted());
+        assert_eq!(result.stages_executed, 0);
+    }
 }
@@ -0,0 +1,122 @@
+//! Worker capabilities.
+//!
+//! Workers advertise labels describing what they can run: their operating
+//! system and architecture, the container runtimes they have, and free-form
+//! flags such as `gpu`. Jobs only run on workers whose labels match their
+//! [label expression](pipeliner_core::label).
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// What a worker can run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkerCapabilities {
+    /// Operating system, such as `linux` or `macos`
+    pub os: String,
+    /// CPU architecture, such as `x86_64` or `aarch64`
+    pub arch: String,
+    /// Docker is available
+    pub docker: bool,
+    /// Podman is available
+    pub podman: bool,
+    /// Free-form capability flags, such as `gpu`
+    pub flags: BTreeSet<String>,
+}
+
+impl Default for WorkerCapabilities {
+    fn default() -> Self {
+        Self::detect()
+    }
+}
+
+impl WorkerCapabilities {
+    /// Detects the capabilities of this host
+    ///
+    /// Container runtimes count as available when their command is on the
+    /// `PATH`.
+    #[must_use]
+    pub fn detect() -> Self {
+        Self {
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            docker: on_path("docker"),
+            podman: on_path("podman"),
+            flags: BTreeSet::new(),
+        }
+    }
+
+    /// Adds a capability flag
+    #[must_use]
+    pub fn with_flag(mut self, flag: impl Into<String>) -> Self {
+        self.flags.insert(flag.into());
+        self
+    }
+
+    /// Returns the labels the capabilities stand for
+    #[must_use]
+    pub fn labels(&self) -> BTreeSet<String> {
+        let mut labels = BTreeSet::from([self.os.clone(), self.arch.clone()]);
+        if self.docker {
+            labels.insert("docker".to_string());
+        }
+        if self.podman {
+            labels.insert("podman".to_string());
+        }
+        labels.extend(self.flags.iter().cloned());
+        labels
+    }
+}
+
+/// Returns true if `command` is an executable file on the `PATH`
+fn on_path(command: &str) -> bool {
+    let Some(paths) = std::env::var_os("PATH") else {
+        return false;
+    };
+    let file = format!("{command}{}", std::env::consts::EXE_SUFFIX);
+    std::env::split_paths(&paths).any(|dir| is_executable(&dir.join(&file)))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_labels() {
+        let capabilities = WorkerCapabilities {
+            os: "linux".to_string(),
+            arch: "x86_64".to_string(),
+            docker: true,
+            podman: false,
+            flags: BTreeSet::new(),
+        }
+        .with_flag("gpu");
+
+        assert_eq!(
+            capabilities.labels().into_iter().collect::<Vec<_>>(),
+            ["docker", "gpu", "linux", "x86_64"]
+        );
+    }
+
+    #[test]
+    fn test_capabilities_detect_host() {
+        let capabilities = WorkerCapabilities::detect();
+        assert_eq!(capabilities.os, std::env::consts::OS);
+        assert_eq!(capabilities.arch, std::env::consts::ARCH);
+        assert!(capabilities.flags.is_empty());
+        assert!(on_path("sh"));
+        assert!(!on_path("pipeliner-no-such-command"));
+    }
+}
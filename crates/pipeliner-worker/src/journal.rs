@@ -0,0 +1,435 @@
+//! Write-ahead log of job queue transitions.
+//!
+//! A persistent [`JobQueue`](crate::JobQueue) writes every transition of a
+//! job to the journal before it returns: entering the queue, being taken by
+//! a worker or updated while processing, completing, being cancelled and
+//! going to the dead-letter queue.
+//! Each entry is one line of JSON holding the whole job in its new state,
+//! so replaying the journal in order rebuilds the queue.
+//!
+//! Once enough entries were appended, the journal is compacted: rewritten
+//! with one entry per job, to a temporary file that is then renamed over
+//! it. Only the most recent finished jobs are kept, so the journal does not
+//! grow with every job ever run. A line at the end of the journal that is
+//! cut short is a torn write left by a crash and is truncated when the
+//! journal is opened. Damage anywhere else is reported rather than
+//! repaired.
+//!
+//! A journal is only opened by one queue at a time: opening it takes an
+//! exclusive lock on a `.lock` file next to it, and fails while another
+//! queue holds it.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::{Job, WorkerErrorKind, WorkerResult};
+
+/// What happens to the jobs that were processing when the queue stopped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecoveryPolicy {
+    /// The jobs are enqueued again, to run from the start
+    #[default]
+    Requeue,
+    /// The jobs are set aside as [orphaned](crate::JobStatus::Orphaned)
+    /// until they are requeued explicitly
+    Orphan,
+}
+
+/// Configuration of a persistent job queue
+#[derive(Debug, Clone)]
+pub struct JournalConfig {
+    /// File of the journal
+    pub path: PathBuf,
+    /// What happens to the jobs that were processing when the queue stopped
+    pub recovery: RecoveryPolicy,
+    /// Number of entries appended after which the journal is compacted
+    pub compact_after: usize,
+    /// Number of finished jobs, completed or cancelled, kept when the
+    /// journal is compacted; older ones are forgotten
+    pub retain_finished: usize,
+    /// Syncs every entry to disk before the transition returns
+    pub fsync: bool,
+}
+
+impl JournalConfig {
+    /// Creates the configuration of a journal stored in `path`
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            recovery: RecoveryPolicy::default(),
+            compact_after: 10_000,
+            retain_finished: 1_000,
+            fsync: true,
+        }
+    }
+
+    /// Sets what happens to the jobs that were processing
+    #[must_use]
+    pub fn with_recovery(mut self, recovery: RecoveryPolicy) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// Sets the number of entries appended after which the journal is
+    /// compacted
+    #[must_use]
+    pub fn with_compact_after(mut self, entries: usize) -> Self {
+        self.compact_after = entries;
+        self
+    }
+
+    /// Sets the number of finished jobs kept when the journal is compacted
+    #[must_use]
+    pub fn with_retain_finished(mut self, jobs: usize) -> Self {
+        self.retain_finished = jobs;
+        self
+    }
+}
+
+/// A job and the state it entered
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub(crate) enum JournalEntry {
+    /// The job entered the queue
+    Pending {
+        job: Job,
+        enqueued_at: DateTime<Utc>,
+    },
+    /// The job was taken by a worker, or updated while processing
+    Processing { job: Job },
+    /// The job finished, successfully or not
+    Completed { job: Job },
+    /// The job was cancelled
+    Cancelled { job: Job },
+    /// The job was processing when the queue stopped
+    Orphaned { job: Job },
+    /// The job exhausted its retries
+    DeadLettered { job: Job },
+}
+
+impl JournalEntry {
+    pub(crate) fn job(&self) -> &Job {
+        match self {
+            Self::Pending { job, .. }
+            | Self::Processing { job }
+            | Self::Completed { job }
+            | Self::Cancelled { job }
+            | Self::Orphaned { job }
+            | Self::DeadLettered { job } => job,
+        }
+    }
+}
+
+/// Open journal of a persistent queue
+#[derive(Debug)]
+pub(crate) struct Journal {
+    config: JournalConfig,
+    file: File,
+    /// Entries appended since the journal was last compacted
+    appended: usize,
+    /// Lock file, locked for as long as the journal is open
+    _lock: File,
+}
+
+impl Journal {
+    /// Opens the journal, creating it if needed, and returns its entries
+    ///
+    /// Fails if another queue has the journal open.
+    pub(crate) fn open(config: JournalConfig) -> WorkerResult<(Self, Vec<JournalEntry>)> {
+        if let Some(dir) = config.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| journal_error(&config.path, &e))?;
+        }
+        let lock = lock(&config.path)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&config.path)
+            .map_err(|e| journal_error(&config.path, &e))?;
+        let entries = recover(&config.path, &file)?;
+        let journal = Self {
+            config,
+            file,
+            appended: 0,
+            _lock: lock,
+        };
+        Ok((journal, entries))
+    }
+
+    /// Returns the configuration
+    pub(crate) fn config(&self) -> &JournalConfig {
+        &self.config
+    }
+
+    /// Appends an entry
+    pub(crate) fn append(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        if self.config.fsync {
+            self.file.sync_data()?;
+        }
+        self.appended += 1;
+        Ok(())
+    }
+
+    /// Returns true once enough entries were appended to compact
+    pub(crate) fn needs_compaction(&self) -> bool {
+        self.appended >= self.config.compact_after
+    }
+
+    /// Replaces the journal with `entries`
+    pub(crate) fn rewrite(
+        &mut self,
+        entries: impl IntoIterator<Item = JournalEntry>,
+    ) -> io::Result<()> {
+        let path = &self.config.path;
+        let temporary = path.with_extension("compacting");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        for entry in entries {
+            serde_json::to_writer(&mut writer, &entry)?;
+            writer.write_all(b"\n")?;
+        }
+        writer
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?
+            .sync_all()?;
+        fs::rename(&temporary, path)?;
+        sync_dir(path)?;
+
+        self.file = OpenOptions::new().read(true).append(true).open(path)?;
+        self.appended = 0;
+        Ok(())
+    }
+}
+
+/// Reads the entries of the journal in `path` without opening it
+///
+/// Nothing is written, so the journal may be open in another queue; a
+/// write cut short at its end is skipped.
+pub(crate) fn read(path: &Path) -> WorkerResult<Vec<JournalEntry>> {
+    let file = File::open(path).map_err(|e| journal_error(path, &e))?;
+    Ok(read_entries(path, &file)?.0)
+}
+
+/// Takes the lock of the journal in `path`
+fn lock(path: &Path) -> WorkerResult<File> {
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.with_extension("lock"))
+        .map_err(|e| journal_error(path, &e))?;
+    match lock.try_lock() {
+        Ok(()) => Ok(lock),
+        Err(TryLockError::WouldBlock) => {
+            Err(journal_error(path, &"already opened by another queue"))
+        }
+        Err(TryLockError::Error(e)) => Err(journal_error(path, &e)),
+    }
+}
+
+/// Reads the entries of the journal
+///
+/// A damaged last line is a torn write and is truncated.
+fn recover(path: &Path, file: &File) -> WorkerResult<Vec<JournalEntry>> {
+    let (entries, torn) = read_entries(path, file)?;
+    if let Some((valid_len, torn_len)) = torn {
+        warn!(
+            "Truncating torn write of {} bytes at {}:{}",
+            torn_len,
+            path.display(),
+            valid_len
+        );
+        file.set_len(valid_len)
+            .map_err(|e| journal_error(path, &e))?;
+        file.sync_all().map_err(|e| journal_error(path, &e))?;
+    }
+    Ok(entries)
+}
+
+/// Offset and length of a torn write at the end of a journal
+type TornWrite = (u64, usize);
+
+/// Reads the entries of the journal, and the torn write at its end
+fn read_entries(path: &Path, file: &File) -> WorkerResult<(Vec<JournalEntry>, Option<TornWrite>)> {
+    let mut entries = Vec::new();
+    let mut reader = BufReader::new(file);
+    let mut valid_len = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_line(&mut line)
+            .map_err(|e| journal_error(path, &e))?;
+        if read == 0 {
+            return Ok((entries, None));
+        }
+        let entry = serde_json::from_str(&line)
+            .ok()
+            .filter(|_| line.ends_with('\n'));
+        if let Some(entry) = entry {
+            entries.push(entry);
+            valid_len += line.len() as u64;
+            continue;
+        }
+
+        let mut rest = String::new();
+        reader
+            .read_line(&mut rest)
+            .map_err(|e| journal_error(path, &e))?;
+        if !rest.is_empty() {
+            return Err(journal_error(
+                path,
+                &format!("corrupt entry at byte {valid_len}"),
+            ));
+        }
+        return Ok((entries, Some((valid_len, line.len()))));
+    }
+}
+
+fn journal_error(path: &Path, error: &impl std::fmt::Display) -> crate::WorkerError {
+    WorkerErrorKind::Journal {
+        reason: format!("{}: {error}", path.display()),
+    }
+    .into()
+}
+
+/// Syncs the directory of a file so that renaming the file survives a crash
+fn sync_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn pending(job: Job) -> JournalEntry {
+        JournalEntry::Pending {
+            job,
+            enqueued_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_journal_reopens_with_entries() {
+        let dir = TempDir::new().unwrap();
+        let config = JournalConfig::new(dir.path().join("queue").join("journal.log"));
+        let job = Job::new();
+
+        let (mut journal, entries) = Journal::open(config.clone()).unwrap();
+        assert!(entries.is_empty());
+        journal.append(&pending(job.clone())).unwrap();
+        journal
+            .append(&JournalEntry::Processing { job: job.clone() })
+            .unwrap();
+        drop(journal);
+
+        let (_, entries) = Journal::open(config).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(matches!(&entries[1], JournalEntry::Processing { job: j } if j.id == job.id));
+    }
+
+    #[test]
+    fn test_journal_truncates_torn_write() {
+        let dir = TempDir::new().unwrap();
+        let config = JournalConfig::new(dir.path().join("journal.log"));
+        let (mut journal, _) = Journal::open(config.clone()).unwrap();
+        journal.append(&pending(Job::new())).unwrap();
+        let len = fs::metadata(&config.path).unwrap().len();
+        journal
+            .file
+            .write_all(br#"{"state":"pending","job":{"#)
+            .unwrap();
+        drop(journal);
+
+        let (mut journal, entries) = Journal::open(config.clone()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(fs::metadata(&config.path).unwrap().len(), len);
+        journal.append(&pending(Job::new())).unwrap();
+        drop(journal);
+        assert_eq!(Journal::open(config).unwrap().1.len(), 2);
+    }
+
+    #[test]
+    fn test_journal_reports_corrupt_entry() {
+        let dir = TempDir::new().unwrap();
+        let config = JournalConfig::new(dir.path().join("journal.log"));
+        let (mut journal, _) = Journal::open(config.clone()).unwrap();
+        journal.append(&pending(Job::new())).unwrap();
+        journal.file.write_all(b"garbage\n").unwrap();
+        journal.append(&pending(Job::new())).unwrap();
+        drop(journal);
+
+        let error = Journal::open(config).unwrap_err().to_string();
+        assert!(error.contains("corrupt entry at byte"), "{error}");
+    }
+
+    #[test]
+    fn test_journal_read_while_open() {
+        let dir = TempDir::new().unwrap();
+        let config = JournalConfig::new(dir.path().join("journal.log"));
+        let (mut journal, _) = Journal::open(config.clone()).unwrap();
+        journal.append(&pending(Job::new())).unwrap();
+        journal.file.write_all(br#"{"state":"#).unwrap();
+        let len = fs::metadata(&config.path).unwrap().len();
+
+        assert_eq!(read(&config.path).unwrap().len(), 1);
+        assert_eq!(fs::metadata(&config.path).unwrap().len(), len);
+        assert!(read(&dir.path().join("missing.log")).is_err());
+    }
+
+    #[test]
+    fn test_journal_is_opened_once() {
+        let dir = TempDir::new().unwrap();
+        let config = JournalConfig::new(dir.path().join("journal.log"));
+        let (journal, _) = Journal::open(config.clone()).unwrap();
+
+        let error = Journal::open(config.clone()).unwrap_err().to_string();
+        assert!(error.contains("already opened by another queue"), "{error}");
+        drop(journal);
+        assert!(Journal::open(config).is_ok());
+    }
+
+    #[test]
+    fn test_journal_rewrite_replaces_entries() {
+        let dir = TempDir::new().unwrap();
+        let config = JournalConfig::new(dir.path().join("journal.log")).with_compact_after(2);
+        let (mut journal, _) = Journal::open(config.clone()).unwrap();
+        let job = Job::new();
+        journal.append(&pending(job.clone())).unwrap();
+        assert!(!journal.needs_compaction());
+        journal
+            .append(&JournalEntry::Completed { job: job.clone() })
+            .unwrap();
+        assert!(journal.needs_compaction());
+
+        journal
+            .rewrite([JournalEntry::Completed { job: job.clone() }])
+            .unwrap();
+        assert!(!journal.needs_compaction());
+        journal.append(&pending(Job::new())).unwrap();
+        drop(journal);
+
+        let (_, entries) = Journal::open(config).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(matches!(&entries[0], JournalEntry::Completed { job: j } if j.id == job.id));
+        assert!(!dir.path().join("journal.compacting").exists());
+    }
+}
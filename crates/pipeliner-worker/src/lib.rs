@@ -8,34 +8,42 @@
 //! The worker is organized around:
 //!
 //! - `queue`: Job queue implementation
+//! - `journal`: Write-ahead log of persistent queues
 //! - `pool`: Worker pool management
 //! - `state`: Execution state tracking
 //! - `scheduler`: Job scheduling logic
+//! - `retry`: Retry policies of failed jobs
+//! - `capabilities`: Labels and capabilities workers advertise
 //!
 //! ## Example
 //!
 //! ```rust,ignore
-//! use pipeliner_worker::{Worker, JobQueue, WorkerConfig};
+//! use pipeliner_worker::{Job, JobQueue, WorkerConfig, WorkerPool};
 //!
-//! let queue = JobQueue::new();
-//! let config = WorkerConfig::default();
-//! let worker = Worker::new(queue.clone(), config);
-//! worker.start().await;
+//! let mut pool = WorkerPool::new(WorkerConfig::default(), JobQueue::new());
+//! pool.start().await;
+//! pool.submit(Job::from_pipeline(pipeline).with_label("linux && docker"))?;
 //! ```
 
 #![warn(missing_docs)]
 #![warn(unused)]
 #![warn(clippy::pedantic)]
 
+pub mod capabilities;
+pub mod journal;
 pub mod pool;
 pub mod queue;
+pub mod retry;
 pub mod scheduler;
 pub mod state;
 
-pub use pool::{Worker, WorkerConfig, WorkerPool};
+pub use capabilities::WorkerCapabilities;
+pub use journal::{JournalConfig, RecoveryPolicy};
+pub use pool::{Worker, WorkerConfig, WorkerId, WorkerPool};
 pub use queue::{Job, JobPriority, JobQueue, JobStatus};
-pub use scheduler::{Scheduler, SchedulingStrategy};
-pub use state::ExecutionState;
+pub use retry::{RetryOn, RetryPolicy};
+pub use scheduler::{Scheduler, SchedulingStrategy, WaitTimeStats};
+pub use state::{ExecutionState, JobOutcome, StepLog};
 
 /// Re-exports
 pub use pipeliner_core::{Pipeline, Stage, Step, pipeline};
@@ -54,8 +62,8 @@ pub enum WorkerErrorKind {
     #[error("job cancelled: {id}")]
     JobCancelled { id: String },
 
-    #[error("worker unavailable")]
-    WorkerUnavailable,
+    #[error("worker unavailable: {reason}")]
+    WorkerUnavailable { reason: String },
 
     #[error("queue full")]
     QueueFull,
@@ -65,6 +73,12 @@ pub enum WorkerErrorKind {
 
     #[error("execution failed: {reason}")]
     ExecutionFailed { reason: String },
+
+    #[error("invalid label expression: {reason}")]
+    InvalidLabel { reason: String },
+
+    #[error("journal error: {reason}")]
+    Journal { reason: String },
 }
 
 /// Worker result type
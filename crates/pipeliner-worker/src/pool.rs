@@ -1,15 +1,40 @@
 //! Worker pool management.
 //!
 //! This module provides the worker pool for parallel job execution.
+//! Workers take jobs from the queue and run their pipelines through the
+//! executor, each job in its own workspace under
+//! [`WorkerConfig::workspace_root`]. Step output and results are recorded
+//! in the pool's [`ExecutionState`].
+//!
+//! Every worker advertises the labels of its [`WorkerCapabilities`] and
+//! [`WorkerConfig::labels`], and only takes the jobs whose label expression
+//! they match.
+//!
+//! Jobs failing because of the infrastructure, such as a workspace that
+//! cannot be created or a run exceeding the timeout, are retried according
+//! to their [`RetryPolicy`](crate::RetryPolicy).
 
+use chrono::Utc;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::Duration;
-use tokio::sync::{mpsc, oneshot};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
 use tokio::task;
-use tracing::{debug, error, info, warn};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use async_trait::async_trait;
+use pipeliner_executor::listener::ExecutionEvent;
+use pipeliner_executor::{
+    ExecutionConfig, ExecutionContext, ExecutionListener, ExecutionResult, ExecutionStatus,
+    Executor,
+};
 
-use crate::{Job, JobQueue, WorkerResult};
+use crate::capabilities::WorkerCapabilities;
+use crate::state::{JobOutcome, StepLog};
+use crate::{ExecutionState, Job, JobQueue, JobStatus, WorkerErrorKind, WorkerResult};
 
 /// Worker identifier
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -28,6 +53,24 @@ pub struct WorkerConfig {
     pub job_timeout: Option<Duration>,
     pub heartbeat_interval: Duration,
     pub shutdown_timeout: Duration,
+    /// Directory holding the workspace of each job
+    pub workspace_root: PathBuf,
+    /// Removes the workspace of a job once its run finishes
+    pub clean_workspace: bool,
+    /// Labels the workers advertise besides those of their capabilities
+    pub labels: BTreeSet<String>,
+    /// What the workers can run
+    pub capabilities: WorkerCapabilities,
+}
+
+impl WorkerConfig {
+    /// Returns every label the workers advertise
+    #[must_use]
+    pub fn advertised_labels(&self) -> BTreeSet<String> {
+        let mut labels = self.capabilities.labels();
+        labels.extend(self.labels.iter().cloned());
+        labels
+    }
 }
 
 impl Default for WorkerConfig {
@@ -37,6 +80,10 @@ impl Default for WorkerConfig {
             job_timeout: Some(Duration::from_secs(3600)),
             heartbeat_interval: Duration::from_secs(30),
             shutdown_timeout: Duration::from_secs(60),
+            workspace_root: std::env::temp_dir().join("pipeliner").join("workspaces"),
+            clean_workspace: true,
+            labels: BTreeSet::new(),
+            capabilities: WorkerCapabilities::detect(),
         }
     }
 }
@@ -47,49 +94,55 @@ pub struct Worker {
     id: WorkerId,
     config: WorkerConfig,
     queue: JobQueue,
+    state: ExecutionState,
+    labels: BTreeSet<String>,
     rx: mpsc::Receiver<WorkerMessage>,
     active_jobs: Arc<AtomicUsize>,
 }
 
+#[derive(Debug)]
 enum WorkerMessage {
-    Job(Job),
     Stop,
 }
 
 impl Worker {
-    #[must_use]
-    pub fn new(
+    fn new(
         id: WorkerId,
         config: WorkerConfig,
         queue: JobQueue,
+        state: ExecutionState,
         rx: mpsc::Receiver<WorkerMessage>,
     ) -> Self {
         Self {
             id,
+            labels: config.advertised_labels(),
             config,
             queue,
+            state,
             rx,
             active_jobs: Arc::new(AtomicUsize::new(0)),
         }
     }
 
+    /// Runs jobs from the queue until the worker is stopped
+    ///
+    /// Only jobs matching the worker's labels are taken. A stop request is
+    /// handled between jobs, so a running job finishes first.
     pub async fn run(&mut self) {
         info!("Worker {} starting", self.id);
 
         loop {
             tokio::select! {
-                Some(msg) = self.rx.recv() => {
-                    match msg {
-                        WorkerMessage::Job(job) => {
-                            self.execute_job(job).await;
-                        }
-                        WorkerMessage::Stop => {
-                            info!("Worker {} stopping", self.id);
-                            break;
-                        }
-                    }
+                biased;
+                // A stop request, or the pool is gone
+                _ = self.rx.recv() => {
+                    info!("Worker {} stopping", self.id);
+                    break;
                 }
-                _ = tokio::time::sleep(self.config.heartbeat_interval) => {
+                job = self.queue.next_for(&self.labels) => {
+                    self.execute_job(job).await;
+                }
+                () = tokio::time::sleep(self.config.heartbeat_interval) => {
                     // Heartbeat
                 }
             }
@@ -108,46 +161,194 @@ impl Worker {
         }
 
         job.start();
-
-        let result = tokio::time::timeout(
-            self.config.job_timeout.unwrap_or(Duration::MAX),
-            self.run_pipeline(&job),
-        )
-        .await;
-
-        match result {
-            Ok(Ok(())) => {
+        self.queue.update(&job);
+        self.state.update_job(&job);
+        self.state.reset_run(&job.id);
+        self.state.mark_active(&job.id);
+
+        match self.run_pipeline(&job).await {
+            Ok(result)
+                if matches!(
+                    result.status,
+                    ExecutionStatus::Success | ExecutionStatus::Unstable
+                ) =>
+            {
                 job.complete();
-                self.queue.complete(&job.id);
+                self.finish(&job);
+                self.state.mark_completed(&job.id);
                 info!("Job {} completed successfully", job.id);
             }
-            Ok(Err(e)) => {
-                job.fail(e.to_string());
-
-                if job.retry() {
-                    warn!(
-                        "Job {} failed, retrying ({}/{})",
-                        job.id, job.retries, job.max_retries
-                    );
-                    self.queue.enqueue(job);
-                } else {
-                    self.queue.complete(&job.id);
-                    error!("Job {} failed after retries", job.id);
-                }
+            Ok(result) if result.is_aborted() => {
+                job.cancel();
+                self.queue.update(&job);
+                self.queue.cancel(&job.id);
+                self.state.update_job(&job);
+                self.state.mark_failed(&job.id);
+                warn!("Job {} was aborted", job.id);
             }
-            Err(_) => {
-                job.fail("timeout");
-                self.queue.complete(&job.id);
-                error!("Job {} timed out", job.id);
+            Ok(result) => {
+                let reason = result
+                    .error
+                    .unwrap_or_else(|| result.status.result_name().to_string());
+                self.fail_or_retry(job, &WorkerErrorKind::ExecutionFailed { reason });
             }
+            Err(e) => self.fail_or_retry(job, &e.0),
         }
 
         self.active_jobs.fetch_sub(1, Ordering::SeqCst);
     }
 
-    async fn run_pipeline(&self, _job: &Job) -> WorkerResult<()> {
-        tokio::time::sleep(Duration::from_millis(100)).await;
-        Ok(())
+    /// Fails the job
+    ///
+    /// A job failing with an error its retry policy retries on is enqueued
+    /// again after a backoff while retries are left, and goes to the
+    /// dead-letter queue once they are exhausted.
+    fn fail_or_retry(&self, mut job: Job, error: &WorkerErrorKind) {
+        job.fail(error.to_string());
+
+        if !job.retry_policy.retries(error) {
+            self.finish(&job);
+            self.state.mark_failed(&job.id);
+            error!("Job {} failed: {}", job.id, error);
+        } else if job.retry() {
+            let backoff = job.retry_policy.backoff(job.retries);
+            warn!(
+                "Job {} failed, retrying in {:?} ({}/{}): {}",
+                job.id, backoff, job.retries, job.max_retries, error
+            );
+            job.status = JobStatus::Pending;
+            job.retry_at = chrono::Duration::from_std(backoff)
+                .ok()
+                .and_then(|backoff| Utc::now().checked_add_signed(backoff));
+            self.state.update_job(&job);
+            self.queue.enqueue(job);
+        } else {
+            self.queue.update(&job);
+            self.queue.dead_letter(&job.id);
+            self.state.update_job(&job);
+            self.state.mark_failed(&job.id);
+            error!(
+                "Job {} failed after {} retries, moved to the dead-letter queue: {}",
+                job.id, job.retries, error
+            );
+        }
+    }
+
+    /// Stores the final state of a job
+    fn finish(&self, job: &Job) {
+        self.queue.update(job);
+        self.queue.complete(&job.id);
+        self.state.update_job(job);
+    }
+
+    /// Runs the job's pipeline in its workspace
+    ///
+    /// The job timeout applies to the whole run, which is cancelled once
+    /// it is exceeded. Step output and the outcome of the run are recorded
+    /// in the execution state.
+    ///
+    /// A run cut short by the job timeout is a [`WorkerErrorKind::Timeout`],
+    /// while one exceeding the pipeline's own `timeout` option is a failed
+    /// build like any other.
+    async fn run_pipeline(&self, job: &Job) -> WorkerResult<ExecutionResult> {
+        let pipeline = job
+            .pipeline
+            .clone()
+            .ok_or_else(|| WorkerErrorKind::ExecutionFailed {
+                reason: "job has no pipeline".to_string(),
+            })?;
+
+        let workspace = self.config.workspace_root.join(job.id.to_string());
+        tokio::fs::create_dir_all(&workspace).await.map_err(|e| {
+            WorkerErrorKind::WorkerUnavailable {
+                reason: format!("cannot create workspace {}: {e}", workspace.display()),
+            }
+        })?;
+        let config = ExecutionConfig {
+            working_dir: workspace.clone(),
+            colors: false,
+            quiet: true,
+            ..ExecutionConfig::default()
+        };
+
+        let mut executor = Executor::new(pipeline, config).with_listener(StateListener {
+            job_id: job.id,
+            state: self.state.clone(),
+        });
+        // The worker enforces the job timeout itself rather than through the
+        // executor, which would not tell it apart from the pipeline's
+        let timer = self.config.job_timeout.map(|timeout| {
+            let token = executor.cancellation();
+            tokio::spawn(async move {
+                tokio::time::sleep(timeout).await;
+                token.cancel(format!("job timeout of {timeout:?} exceeded"))
+            })
+        });
+        let result = executor.run().await;
+        let timed_out = match timer {
+            Some(timer) if timer.is_finished() => timer.await.unwrap_or(false),
+            Some(timer) => {
+                timer.abort();
+                false
+            }
+            None => false,
+        };
+
+        if self.config.clean_workspace
+            && let Err(e) = tokio::fs::remove_dir_all(&workspace).await
+        {
+            warn!("Cannot remove workspace {}: {}", workspace.display(), e);
+        }
+
+        let mut result = result.map_err(|e| WorkerErrorKind::ExecutionFailed {
+            reason: e.to_string(),
+        })?;
+        let timed_out = timed_out && result.is_aborted();
+        if timed_out {
+            result.status = ExecutionStatus::Timeout;
+        }
+        let steps = executor.context().step_results.lock().await.clone();
+        self.state.record_outcome(
+            &job.id,
+            JobOutcome {
+                result: result.clone(),
+                steps,
+            },
+        );
+        if timed_out {
+            return Err(WorkerErrorKind::Timeout {
+                reason: result.error.unwrap_or_else(|| "job timeout".to_string()),
+            }
+            .into());
+        }
+        Ok(result)
+    }
+}
+
+/// Records the step output of a job's run in the execution state
+struct StateListener {
+    job_id: Uuid,
+    state: ExecutionState,
+}
+
+#[async_trait]
+impl ExecutionListener for StateListener {
+    async fn on_event(&self, event: &ExecutionEvent, _context: &ExecutionContext) {
+        if let ExecutionEvent::LogOutput {
+            stage_name,
+            step_name,
+            output,
+        } = event
+        {
+            self.state.append_log(
+                &self.job_id,
+                StepLog {
+                    stage: stage_name.clone(),
+                    step: step_name.clone(),
+                    output: output.clone(),
+                },
+            );
+        }
     }
 }
 
@@ -156,7 +357,8 @@ impl Worker {
 pub struct WorkerPool {
     config: WorkerConfig,
     queue: JobQueue,
-    workers: Vec<task::JoinHandle<()>>,
+    state: ExecutionState,
+    workers: Vec<(mpsc::Sender<WorkerMessage>, task::JoinHandle<()>)>,
 }
 
 impl WorkerPool {
@@ -165,6 +367,7 @@ impl WorkerPool {
         Self {
             config,
             queue,
+            state: ExecutionState::new(),
             workers: Vec::new(),
         }
     }
@@ -174,12 +377,20 @@ impl WorkerPool {
             let (tx, rx) = mpsc::channel(100);
             let worker_id = WorkerId(i);
 
-            let mut worker = Worker::new(worker_id, self.config.clone(), self.queue.clone(), rx);
+            self.queue
+                .register_worker(worker_id, self.config.advertised_labels());
+            let mut worker = Worker::new(
+                worker_id,
+                self.config.clone(),
+                self.queue.clone(),
+                self.state.clone(),
+                rx,
+            );
             let handle = task::spawn(async move {
                 worker.run().await;
             });
 
-            self.workers.push(handle);
+            self.workers.push((tx, handle));
         }
 
         info!(
@@ -188,15 +399,39 @@ impl WorkerPool {
         );
     }
 
-    pub fn submit(&self, job: Job) {
+    /// Submits a job to the queue
+    ///
+    /// A job no worker matches waits in the queue as blocked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, without enqueueing the job, if its label expression
+    /// or the label of one of its agents is malformed.
+    pub fn submit(&self, job: Job) -> WorkerResult {
+        job.label_expression()?;
+        self.state.add_job(job.clone());
         self.queue.enqueue(job);
+        Ok(())
     }
 
+    /// Stops the workers
+    ///
+    /// Running jobs get the shutdown timeout to finish, after which their
+    /// workers are aborted.
     pub async fn stop(&mut self) {
         info!("Stopping worker pool...");
 
-        for worker in self.workers.drain(..) {
-            worker.abort();
+        for (tx, _) in &self.workers {
+            let _ = tx.send(WorkerMessage::Stop).await;
+        }
+        let deadline = Instant::now() + self.config.shutdown_timeout;
+        for (i, (_, mut handle)) in self.workers.drain(..).enumerate() {
+            self.queue.unregister_worker(WorkerId(i));
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if tokio::time::timeout(remaining, &mut handle).await.is_err() {
+                warn!("Worker did not stop in time, aborting it");
+                handle.abort();
+            }
         }
 
         info!("Worker pool stopped");
@@ -211,11 +446,20 @@ impl WorkerPool {
     pub fn queue_len(&self) -> usize {
         self.queue.len()
     }
+
+    /// Returns the state of the jobs submitted to the pool
+    #[must_use]
+    pub fn state(&self) -> &ExecutionState {
+        &self.state
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use pipeliner_core::agent::AgentType;
+    use pipeliner_core::{Stage, Step};
+    use tempfile::TempDir;
 
     #[test]
     fn test_worker_config_default() {
@@ -228,4 +472,227 @@ mod tests {
         let id = WorkerId(1);
         assert_eq!(id.to_string(), "worker-1");
     }
+
+    fn pipeline(commands: &[&str]) -> pipeliner_core::Pipeline {
+        let mut stage = Stage::new("Build").with_step(Step::echo("building"));
+        for command in commands {
+            stage = stage.with_step(Step::shell(*command));
+        }
+        pipeliner_core::Pipeline::new()
+            .with_name("Test")
+            .with_agent(AgentType::any())
+            .with_stage(stage)
+    }
+
+    fn config_in(dir: &TempDir) -> WorkerConfig {
+        WorkerConfig {
+            max_concurrent: 2,
+            workspace_root: dir.path().to_path_buf(),
+            clean_workspace: false,
+            ..WorkerConfig::default()
+        }
+    }
+
+    /// Submits a job and waits until its run has finished
+    async fn run_job(pool: &WorkerPool, job: Job) -> Job {
+        let id = job.id;
+        pool.submit(job).unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let job = pool.queue.get(&id).unwrap();
+                if matches!(job.status, JobStatus::Completed | JobStatus::Failed)
+                    && pool.state().active_count() == 0
+                {
+                    return job;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pool_runs_job_pipeline() {
+        let dir = TempDir::new().unwrap();
+        let mut pool = WorkerPool::new(config_in(&dir), JobQueue::new());
+        pool.start().await;
+
+        let job = run_job(&pool, Job::from_pipeline(pipeline(&["touch built"]))).await;
+        assert_eq!(job.status, JobStatus::Completed);
+        assert!(dir.path().join(job.id.to_string()).join("built").exists());
+
+        let state = pool.state();
+        assert_eq!(state.get_job(&job.id).unwrap().status, JobStatus::Completed);
+        assert_eq!(state.completed_count(), 1);
+        let logs = state.logs(&job.id);
+        assert!(logs.iter().any(|log| log.output == "building"));
+        let outcome = state.outcome(&job.id).unwrap();
+        assert!(outcome.result.is_success());
+        assert_eq!(outcome.steps.len(), 2);
+
+        pool.stop().await;
+        assert_eq!(pool.worker_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_pool_does_not_retry_build_failure() {
+        let dir = TempDir::new().unwrap();
+        let mut pool = WorkerPool::new(config_in(&dir), JobQueue::new());
+        pool.start().await;
+
+        let job =
+            Job::from_pipeline(pipeline(&["sh -c 'echo run >> runs; false'"])).with_max_retries(1);
+        let job = run_job(&pool, job).await;
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.retries, 0);
+        assert!(job.error.unwrap().starts_with("execution failed"));
+        let runs = std::fs::read_to_string(dir.path().join(job.id.to_string()).join("runs"));
+        assert_eq!(runs.unwrap().lines().count(), 1);
+        assert!(pool.queue.dead_letters().is_empty());
+        let outcome = pool.state().outcome(&job.id).unwrap();
+        assert_eq!(outcome.result.status, ExecutionStatus::Failure);
+        assert_eq!(pool.state().failed_count(), 1);
+
+        let invalid = run_job(&pool, Job::new().with_max_retries(0)).await;
+        assert_eq!(invalid.status, JobStatus::Failed);
+        assert!(invalid.error.unwrap().contains("no pipeline"));
+
+        pool.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_pool_routes_jobs_by_label() {
+        let dir = TempDir::new().unwrap();
+        let config = WorkerConfig {
+            labels: BTreeSet::from(["deploy".to_string()]),
+            capabilities: WorkerCapabilities::detect().with_flag("fast-disk"),
+            ..config_in(&dir)
+        };
+        let mut pool = WorkerPool::new(config, JobQueue::new());
+        pool.start().await;
+
+        let gpu = Job::from_pipeline(pipeline(&[])).with_label("gpu");
+        pool.submit(gpu.clone()).unwrap();
+        let job = Job::from_pipeline(pipeline(&[]))
+            .with_label(format!("deploy && fast-disk && {}", std::env::consts::OS));
+        let job = run_job(&pool, job).await;
+        assert_eq!(job.status, JobStatus::Completed);
+
+        let blocked = pool.queue.blocked();
+        assert_eq!(blocked.len(), 1);
+        assert_eq!(blocked[0].id, gpu.id);
+        assert_eq!(
+            blocked[0].blocked_reason.as_deref(),
+            Some("no worker matches 'gpu'")
+        );
+
+        let invalid = Job::from_pipeline(pipeline(&[])).with_label("deploy ||");
+        let error = pool.submit(invalid.clone()).unwrap_err();
+        assert!(matches!(
+            error,
+            crate::WorkerError(WorkerErrorKind::InvalidLabel { .. })
+        ));
+        assert!(pool.state().get_job(&invalid.id).is_none());
+
+        pool.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_pool_timeout_cancels_run() {
+        let dir = TempDir::new().unwrap();
+        let config = WorkerConfig {
+            job_timeout: Some(Duration::from_millis(200)),
+            clean_workspace: true,
+            ..config_in(&dir)
+        };
+        let mut pool = WorkerPool::new(config, JobQueue::new());
+        pool.start().await;
+
+        let start = Instant::now();
+        let job = Job::from_pipeline(pipeline(&["sleep 30"])).with_max_retries(0);
+        let job = run_job(&pool, job).await;
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.retries, 0);
+        assert!(job.error.unwrap().starts_with("timeout"));
+        let outcome = pool.state().outcome(&job.id).unwrap();
+        assert_eq!(outcome.result.status, ExecutionStatus::Timeout);
+        assert!(!dir.path().join(job.id.to_string()).exists());
+        assert_eq!(pool.queue.dead_letters().len(), 1);
+
+        pool.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_pool_does_not_retry_pipeline_timeout() {
+        use pipeliner_core::{PipelineOptions, Timeout};
+
+        let dir = TempDir::new().unwrap();
+        let config = WorkerConfig {
+            job_timeout: Some(Duration::from_secs(30)),
+            ..config_in(&dir)
+        };
+        let mut pool = WorkerPool::new(config, JobQueue::new());
+        pool.start().await;
+
+        let options = PipelineOptions::new().with_timeout(Timeout::Duration {
+            duration: Duration::from_millis(200),
+        });
+        let pipeline = pipeline(&["sh -c 'echo run >> runs; sleep 30'"]).with_options(options);
+        let job = run_job(&pool, Job::from_pipeline(pipeline).with_max_retries(1)).await;
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.retries, 0);
+        assert!(job.error.unwrap().starts_with("execution failed"));
+        let runs = std::fs::read_to_string(dir.path().join(job.id.to_string()).join("runs"));
+        assert_eq!(runs.unwrap().lines().count(), 1);
+        assert!(pool.queue.dead_letters().is_empty());
+        let outcome = pool.state().outcome(&job.id).unwrap();
+        assert_eq!(outcome.result.status, ExecutionStatus::Timeout);
+
+        pool.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_pool_retries_timeout_then_dead_letters() {
+        let dir = TempDir::new().unwrap();
+        let config = WorkerConfig {
+            job_timeout: Some(Duration::from_millis(200)),
+            ..config_in(&dir)
+        };
+        let mut pool = WorkerPool::new(config, JobQueue::new());
+        pool.start().await;
+
+        let policy = crate::RetryPolicy::default()
+            .with_backoff(Duration::from_millis(50), Duration::from_millis(50));
+        let job = Job::from_pipeline(pipeline(&[
+            "sh -c 'echo run >> runs; test -f ready || sleep 30'",
+        ]))
+        .with_max_retries(1)
+        .with_retry_policy(policy);
+        let job = run_job(&pool, job).await;
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.retries, 1);
+        let workspace = dir.path().join(job.id.to_string());
+        let runs = std::fs::read_to_string(workspace.join("runs")).unwrap();
+        assert_eq!(runs.lines().count(), 2);
+        let dead_letters = pool.queue.dead_letters();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].id, job.id);
+
+        std::fs::write(workspace.join("ready"), "").unwrap();
+        assert!(pool.queue.replay_dead_letter(&job.id));
+        assert!(!pool.queue.replay_dead_letter(&job.id));
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while pool.queue.get(&job.id).unwrap().status != JobStatus::Completed {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(pool.queue.dead_letters().is_empty());
+        assert_eq!(pool.queue.get(&job.id).unwrap().retries, 0);
+
+        pool.stop().await;
+    }
 }
@@ -1,18 +1,43 @@
 //! Job queue implementation.
 //!
 //! This module provides a thread-safe job queue for pipeline executions.
+//!
+//! Workers register the labels they advertise with the queue and only
+//! dequeue jobs whose label expression they match. A pending job that no
+//! registered worker matches is [blocked](JobStatus::Blocked) until one
+//! registers.
+//!
+//! A failed job waiting to be [retried](crate::retry) is only dequeued
+//! once its backoff has passed. A job that exhausted its retries goes to
+//! the dead-letter queue, from which it can be replayed.
+//!
+//! A queue opened with [`JobQueue::open`] is persistent: it records every
+//! transition in a [journal](crate::journal) and rebuilds itself from it
+//! when opened again.
 
 use chrono::{DateTime, Utc};
 use dashmap::DashMap;
+use pipeliner_core::LabelExpression;
+use pipeliner_core::agent::AgentType;
 use serde::{Deserialize, Serialize};
-use std::collections::BinaryHeap;
-use std::sync::{Arc, Mutex};
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::Notify;
+use tracing::error;
 use uuid::Uuid;
 
-use crate::WorkerResult;
+use crate::journal::{self, Journal, JournalConfig, JournalEntry, RecoveryPolicy};
+use crate::pool::WorkerId;
+use crate::retry::RetryPolicy;
+use crate::scheduler::{PendingJob, Scheduler, SchedulingStrategy, WaitTimeStats};
+use crate::{WorkerErrorKind, WorkerResult};
 use pipeliner_core::pipeline;
 
 /// Thread-safe job queue
+///
+/// The queue's [`Scheduler`] picks which pending job is dequeued next.
 #[derive(Debug, Clone)]
 pub struct JobQueue {
     inner: Arc<JobQueueInner>,
@@ -20,53 +45,502 @@ pub struct JobQueue {
 
 #[derive(Debug)]
 struct JobQueueInner {
-    pending: Arc<Mutex<BinaryHeap<JobEntry>>>,
+    pending: Arc<Mutex<Pending>>,
     processing: Arc<DashMap<Uuid, Job>>,
     completed: Arc<DashMap<Uuid, Job>>,
     cancelled: Arc<DashMap<Uuid, Job>>,
+    orphaned: Arc<DashMap<Uuid, Job>>,
+    dead_letters: Arc<DashMap<Uuid, Job>>,
+    available: Notify,
+}
+
+/// Pending jobs in enqueue order, the scheduler choosing among them and
+/// the workers taking them
+#[derive(Debug)]
+struct Pending {
+    jobs: Vec<PendingJob>,
+    scheduler: Scheduler,
+    /// Labels advertised by each registered worker
+    workers: HashMap<WorkerId, BTreeSet<String>>,
+    /// Journal recording the transitions of a persistent queue
+    journal: Option<Journal>,
+}
+
+impl Pending {
+    /// Blocks the jobs no registered worker can run and unblocks the others
+    fn refresh_blocked(&mut self) {
+        for pending in &mut self.jobs {
+            refresh_blocked(&self.workers, pending);
+        }
+    }
+
+    /// Removes the job at `index`, recording its dispatch
+    fn take(&mut self, index: usize, now: DateTime<Utc>) -> Job {
+        let mut next = self.jobs.remove(index);
+        self.scheduler.record_dispatch(&next, now);
+        next.job.status = JobStatus::Pending;
+        next.job.blocked_reason = None;
+        next.job.retry_at = None;
+        next.job
+    }
+}
+
+/// Sets whether a pending job is blocked, given the registered workers
+fn refresh_blocked(workers: &HashMap<WorkerId, BTreeSet<String>>, pending: &mut PendingJob) {
+    let reason = match &pending.requirement {
+        Err(reason) => Some(reason.clone()),
+        Ok(Some(expression)) if !workers.values().any(|labels| expression.matches(labels)) => {
+            Some(format!("no worker matches '{expression}'"))
+        }
+        Ok(_) => None,
+    };
+    pending.job.status = if reason.is_some() {
+        JobStatus::Blocked
+    } else {
+        JobStatus::Pending
+    };
+    pending.job.blocked_reason = reason;
 }
 
 impl JobQueue {
-    /// Creates a new job queue
+    /// Creates a new job queue, scheduling jobs by priority
     #[must_use]
     pub fn new() -> Self {
+        Self::with_strategy(SchedulingStrategy::Priority)
+    }
+
+    /// Creates a job queue scheduling jobs with `strategy`
+    #[must_use]
+    pub fn with_strategy(strategy: SchedulingStrategy) -> Self {
+        Self::with_scheduler(Scheduler::new(strategy))
+    }
+
+    /// Creates a job queue scheduling jobs with `scheduler`
+    #[must_use]
+    pub fn with_scheduler(scheduler: Scheduler) -> Self {
         Self {
             inner: Arc::new(JobQueueInner {
-                pending: Arc::new(Mutex::new(BinaryHeap::new())),
+                pending: Arc::new(Mutex::new(Pending {
+                    jobs: Vec::new(),
+                    scheduler,
+                    workers: HashMap::new(),
+                    journal: None,
+                })),
                 processing: Arc::new(DashMap::new()),
                 completed: Arc::new(DashMap::new()),
                 cancelled: Arc::new(DashMap::new()),
+                orphaned: Arc::new(DashMap::new()),
+                dead_letters: Arc::new(DashMap::new()),
+                available: Notify::new(),
             }),
         }
     }
 
-    /// Enqueues a job
-    pub fn enqueue(&self, job: Job) {
-        let entry = JobEntry {
-            priority: job.priority,
-            id: job.id,
-            created_at: job.created_at,
+    /// Opens a persistent job queue, scheduling jobs by priority
+    ///
+    /// See [`JobQueue::open_with_scheduler`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be read or is corrupt.
+    pub fn open(config: JournalConfig) -> WorkerResult<Self> {
+        Self::open_with_scheduler(config, Scheduler::new(SchedulingStrategy::Priority))
+    }
+
+    /// Opens a persistent job queue scheduling jobs with `scheduler`
+    ///
+    /// The queue is rebuilt from its journal, and the jobs that were
+    /// processing are requeued or orphaned following the recovery policy.
+    /// The journal is then compacted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be read or is corrupt.
+    pub fn open_with_scheduler(config: JournalConfig, scheduler: Scheduler) -> WorkerResult<Self> {
+        let recovery = config.recovery;
+        let (journal, entries) = Journal::open(config)?;
+        let queue = Self::with_scheduler(scheduler);
+        {
+            let mut pending = queue.inner.pending.lock().unwrap();
+            queue.replay(&mut pending, entries, recovery);
+            pending.journal = Some(journal);
+            queue
+                .compact_locked(&mut pending)
+                .map_err(|e| WorkerErrorKind::Journal {
+                    reason: e.to_string(),
+                })?;
+        }
+        Ok(queue)
+    }
+
+    /// Rebuilds the queue from the entries of its journal
+    fn replay(&self, pending: &mut Pending, entries: Vec<JournalEntry>, recovery: RecoveryPolicy) {
+        // Only the last entry of each job counts, in the order they were
+        // written
+        let mut latest = HashMap::new();
+        for (sequence, entry) in entries.into_iter().enumerate() {
+            latest.insert(entry.job().id, (sequence, entry));
+        }
+        let mut latest: Vec<_> = latest.into_values().collect();
+        latest.sort_unstable_by_key(|(sequence, _)| *sequence);
+
+        let now = Utc::now();
+        for (_, entry) in latest {
+            match entry {
+                JournalEntry::Pending { job, enqueued_at } => {
+                    pending.jobs.push(PendingJob::new(job, enqueued_at));
+                }
+                JournalEntry::Processing { mut job } => match recovery {
+                    RecoveryPolicy::Requeue => {
+                        job.status = JobStatus::Pending;
+                        job.started_at = None;
+                        pending.jobs.push(PendingJob::new(job, now));
+                    }
+                    RecoveryPolicy::Orphan => {
+                        job.status = JobStatus::Orphaned;
+                        self.inner.orphaned.insert(job.id, job);
+                    }
+                },
+                JournalEntry::Completed { job } => {
+                    self.inner.completed.insert(job.id, job);
+                }
+                JournalEntry::Cancelled { job } => {
+                    self.inner.cancelled.insert(job.id, job);
+                }
+                JournalEntry::Orphaned { job } => {
+                    self.inner.orphaned.insert(job.id, job);
+                }
+                JournalEntry::DeadLettered { job } => {
+                    self.inner.dead_letters.insert(job.id, job);
+                }
+            }
+        }
+        pending.refresh_blocked();
+    }
+
+    /// Records a transition in the journal of a persistent queue
+    ///
+    /// The queue keeps working in memory if the journal cannot be written.
+    fn record(&self, pending: &mut Pending, entry: impl FnOnce() -> JournalEntry) {
+        let Some(journal) = pending.journal.as_mut() else {
+            return;
         };
+        if let Err(e) = journal.append(&entry()) {
+            error!(
+                "Cannot write queue journal {}: {}",
+                journal.config().path.display(),
+                e
+            );
+        }
+        if journal.needs_compaction()
+            && let Err(e) = self.compact_locked(pending)
+        {
+            error!("Cannot compact queue journal: {}", e);
+        }
+    }
+
+    /// Compacts the journal of a persistent queue, rewriting it with one
+    /// entry per job
+    ///
+    /// Only the [most recent](JournalConfig::retain_finished) completed and
+    /// cancelled jobs are kept, in the journal and in the queue.
+    ///
+    /// Does nothing if the queue is not persistent.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be rewritten.
+    pub fn compact(&self) -> WorkerResult {
         let mut pending = self.inner.pending.lock().unwrap();
-        pending.push(entry);
+        self.compact_locked(&mut pending)
+            .map_err(|e| WorkerErrorKind::Journal {
+                reason: e.to_string(),
+            })?;
+        Ok(())
     }
 
-    /// Dequeues the next job
+    fn compact_locked(&self, pending: &mut Pending) -> io::Result<()> {
+        let Pending { jobs, journal, .. } = pending;
+        let Some(journal) = journal else {
+            return Ok(());
+        };
+        self.forget_finished(journal.config().retain_finished);
+        let inner = &self.inner;
+        let entries = jobs
+            .iter()
+            .map(|p| JournalEntry::Pending {
+                job: p.job.clone(),
+                enqueued_at: p.enqueued_at,
+            })
+            .chain(inner.processing.iter().map(|job| JournalEntry::Processing {
+                job: job.value().clone(),
+            }))
+            .chain(inner.completed.iter().map(|job| JournalEntry::Completed {
+                job: job.value().clone(),
+            }))
+            .chain(inner.cancelled.iter().map(|job| JournalEntry::Cancelled {
+                job: job.value().clone(),
+            }))
+            .chain(inner.orphaned.iter().map(|job| JournalEntry::Orphaned {
+                job: job.value().clone(),
+            }))
+            .chain(
+                inner
+                    .dead_letters
+                    .iter()
+                    .map(|job| JournalEntry::DeadLettered {
+                        job: job.value().clone(),
+                    }),
+            );
+        journal.rewrite(entries)
+    }
+
+    /// Forgets the oldest completed and cancelled jobs, keeping `retain`
+    fn forget_finished(&self, retain: usize) {
+        let inner = &self.inner;
+        let mut finished: Vec<_> = inner
+            .completed
+            .iter()
+            .chain(inner.cancelled.iter())
+            .map(|job| (job.completed_at, job.id))
+            .collect();
+        if finished.len() <= retain {
+            return;
+        }
+        finished.sort_unstable_by(|a, b| b.cmp(a));
+        for (_, id) in &finished[retain..] {
+            inner.completed.remove(id);
+            inner.cancelled.remove(id);
+        }
+    }
+
+    /// Enqueues a job
+    ///
+    /// A processing job that is enqueued again, to be retried, leaves
+    /// processing. A job with a malformed label expression, or one no
+    /// registered worker matches, waits as blocked.
+    pub fn enqueue(&self, job: Job) {
+        let pending = self.inner.pending.lock().unwrap();
+        self.inner.processing.remove(&job.id);
+        self.push(pending, job);
+    }
+
+    /// Adds a job to the pending jobs and wakes up the waiting workers
+    fn push(&self, mut pending: MutexGuard<'_, Pending>, job: Job) {
+        let mut next = PendingJob::new(job, Utc::now());
+        refresh_blocked(&pending.workers, &mut next);
+        self.record(&mut pending, || JournalEntry::Pending {
+            job: next.job.clone(),
+            enqueued_at: next.enqueued_at,
+        });
+        pending.jobs.push(next);
+        drop(pending);
+        self.inner.available.notify_waiters();
+    }
+
+    /// Dequeues the job the scheduler picks, moving it to processing
+    ///
+    /// The job's labels are not checked, except that jobs with a malformed
+    /// label expression are never dequeued. Jobs waiting to be retried are
+    /// skipped until their backoff has passed.
     pub fn dequeue(&self) -> Option<Job> {
+        self.dequeue_where(|p| p.requirement.is_ok())
+    }
+
+    /// Dequeues the job the scheduler picks among those a worker with
+    /// `labels` can run, moving it to processing
+    pub fn dequeue_for(&self, labels: &BTreeSet<String>) -> Option<Job> {
+        self.dequeue_where(|p| p.runs_on(labels))
+    }
+
+    fn dequeue_where(&self, eligible: impl Fn(&PendingJob) -> bool) -> Option<Job> {
+        let mut pending = self.inner.pending.lock().unwrap();
+        let now = Utc::now();
+        let index = pending.scheduler.select(&pending.jobs, now, |p| {
+            p.job.retry_at.is_none_or(|at| at <= now) && eligible(p)
+        })?;
+        let job = pending.take(index, now);
+        self.inner.processing.insert(job.id, job.clone());
+        self.record(&mut pending, || JournalEntry::Processing {
+            job: job.clone(),
+        });
+        Some(job)
+    }
+
+    /// Waits for a job and dequeues it
+    pub async fn next(&self) -> Job {
+        self.wait_for(|p| p.requirement.is_ok()).await
+    }
+
+    /// Waits for a job a worker with `labels` can run and dequeues it
+    pub async fn next_for(&self, labels: &BTreeSet<String>) -> Job {
+        self.wait_for(|p| p.runs_on(labels)).await
+    }
+
+    async fn wait_for(&self, eligible: impl Fn(&PendingJob) -> bool) -> Job {
+        loop {
+            let available = self.inner.available.notified();
+            if let Some(job) = self.dequeue_where(&eligible) {
+                return job;
+            }
+            match self.next_retry_at(&eligible) {
+                Some(retry_at) => {
+                    let backoff = (retry_at - Utc::now()).to_std().unwrap_or_default();
+                    tokio::select! {
+                        () = available => {}
+                        () = tokio::time::sleep(backoff) => {}
+                    }
+                }
+                None => available.await,
+            }
+        }
+    }
+
+    /// Returns when the first eligible job waiting to be retried is due
+    fn next_retry_at(&self, eligible: impl Fn(&PendingJob) -> bool) -> Option<DateTime<Utc>> {
+        let pending = self.inner.pending.lock().unwrap();
+        pending
+            .jobs
+            .iter()
+            .filter(|p| eligible(p))
+            .filter_map(|p| p.job.retry_at)
+            .min()
+    }
+
+    /// Registers the labels a worker advertises, unblocking the jobs it
+    /// can run
+    pub fn register_worker(&self, id: WorkerId, labels: BTreeSet<String>) {
+        let mut pending = self.inner.pending.lock().unwrap();
+        pending.workers.insert(id, labels);
+        pending.refresh_blocked();
+        drop(pending);
+        self.inner.available.notify_waiters();
+    }
+
+    /// Unregisters a worker, blocking the jobs only it could run
+    pub fn unregister_worker(&self, id: WorkerId) {
+        let mut pending = self.inner.pending.lock().unwrap();
+        if pending.workers.remove(&id).is_some() {
+            pending.refresh_blocked();
+        }
+    }
+
+    /// Returns the jobs that were processing when a persistent queue
+    /// stopped, and were orphaned when it was opened again
+    #[must_use]
+    pub fn orphaned(&self) -> Vec<Job> {
+        let mut jobs: Vec<Job> = self
+            .inner
+            .orphaned
+            .iter()
+            .map(|job| job.value().clone())
+            .collect();
+        jobs.sort_by_key(|job| job.created_at);
+        jobs
+    }
+
+    /// Enqueues an orphaned job again
+    ///
+    /// Returns false if the job is not orphaned.
+    pub fn requeue(&self, id: &Uuid) -> bool {
+        let pending = self.inner.pending.lock().unwrap();
+        let Some((_, mut job)) = self.inner.orphaned.remove(id) else {
+            return false;
+        };
+        job.status = JobStatus::Pending;
+        job.started_at = None;
+        self.push(pending, job);
+        true
+    }
+
+    /// Moves a processing job that exhausted its retries to the
+    /// dead-letter queue
+    pub fn dead_letter(&self, id: &Uuid) {
         let mut pending = self.inner.pending.lock().unwrap();
-        pending.pop().map(|entry| {
-            self.inner.processing.insert(entry.id, Job::default());
-            self.inner
-                .processing
-                .get(&entry.id)
-                .unwrap()
-                .value()
-                .clone()
-        })
+        if let Some((_, job)) = self.inner.processing.remove(id) {
+            self.record(&mut pending, || JournalEntry::DeadLettered {
+                job: job.clone(),
+            });
+            self.inner.dead_letters.insert(job.id, job);
+        }
+    }
+
+    /// Returns the jobs in the dead-letter queue, oldest failure first
+    #[must_use]
+    pub fn dead_letters(&self) -> Vec<Job> {
+        let mut jobs: Vec<Job> = self
+            .inner
+            .dead_letters
+            .iter()
+            .map(|job| job.value().clone())
+            .collect();
+        jobs.sort_by_key(|job| job.completed_at);
+        jobs
+    }
+
+    /// Reads the dead-letter queue from the journal of a persistent queue,
+    /// oldest failure first
+    ///
+    /// The journal is only read, so this is safe while a queue has it open.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be read or is corrupt.
+    pub fn read_dead_letters(path: impl AsRef<Path>) -> WorkerResult<Vec<Job>> {
+        let mut latest = HashMap::new();
+        for entry in journal::read(path.as_ref())? {
+            latest.insert(entry.job().id, entry);
+        }
+        let mut jobs: Vec<Job> = latest
+            .into_values()
+            .filter_map(|entry| match entry {
+                JournalEntry::DeadLettered { job } => Some(job),
+                _ => None,
+            })
+            .collect();
+        jobs.sort_by_key(|job| job.completed_at);
+        Ok(jobs)
+    }
+
+    /// Enqueues a job of the dead-letter queue again, with its retries
+    /// reset
+    ///
+    /// Returns false if the job is not in the dead-letter queue.
+    pub fn replay_dead_letter(&self, id: &Uuid) -> bool {
+        let pending = self.inner.pending.lock().unwrap();
+        let Some((_, mut job)) = self.inner.dead_letters.remove(id) else {
+            return false;
+        };
+        job.status = JobStatus::Pending;
+        job.started_at = None;
+        job.completed_at = None;
+        job.error = None;
+        job.retries = 0;
+        job.retry_at = None;
+        self.push(pending, job);
+        true
+    }
+
+    /// Returns the pending jobs no registered worker can run, in enqueue
+    /// order
+    #[must_use]
+    pub fn blocked(&self) -> Vec<Job> {
+        let pending = self.inner.pending.lock().unwrap();
+        pending
+            .jobs
+            .iter()
+            .filter(|p| p.job.status == JobStatus::Blocked)
+            .map(|p| p.job.clone())
+            .collect()
     }
 
     /// Gets a job by ID
     pub fn get(&self, id: &Uuid) -> Option<Job> {
+        let pending = self.inner.pending.lock().unwrap();
+        if let Some(pending) = pending.jobs.iter().find(|p| p.job.id == *id) {
+            return Some(pending.job.clone());
+        }
+        drop(pending);
         if let Some(job) = self.inner.processing.get(id) {
             return Some(job.value().clone());
         }
@@ -76,28 +550,81 @@ impl JobQueue {
         if let Some(job) = self.inner.cancelled.get(id) {
             return Some(job.value().clone());
         }
+        if let Some(job) = self.inner.orphaned.get(id) {
+            return Some(job.value().clone());
+        }
+        if let Some(job) = self.inner.dead_letters.get(id) {
+            return Some(job.value().clone());
+        }
         None
     }
 
+    /// Stores the current state of a processing job
+    pub fn update(&self, job: &Job) {
+        let mut pending = self.inner.pending.lock().unwrap();
+        let Some(mut processing) = self.inner.processing.get_mut(&job.id) else {
+            return;
+        };
+        processing.clone_from(job);
+        drop(processing);
+        self.record(&mut pending, || JournalEntry::Processing {
+            job: job.clone(),
+        });
+    }
+
     /// Marks a job as completed
     pub fn complete(&self, id: &Uuid) {
+        let mut pending = self.inner.pending.lock().unwrap();
         if let Some((_, job)) = self.inner.processing.remove(id) {
+            self.record(&mut pending, || JournalEntry::Completed {
+                job: job.clone(),
+            });
             self.inner.completed.insert(job.id, job);
         }
     }
 
     /// Marks a job as cancelled
     pub fn cancel(&self, id: &Uuid) {
-        if let Some((_, job)) = self.inner.processing.remove(id) {
+        let mut pending = self.inner.pending.lock().unwrap();
+        let removed = match pending.jobs.iter().position(|p| p.job.id == *id) {
+            Some(index) => Some(pending.jobs.remove(index).job),
+            None => self.inner.processing.remove(id).map(|(_, job)| job),
+        };
+        if let Some(mut job) = removed {
+            job.cancel();
+            self.record(&mut pending, || JournalEntry::Cancelled {
+                job: job.clone(),
+            });
             self.inner.cancelled.insert(job.id, job);
         }
     }
 
+    /// Returns the strategy picking the next job
+    #[must_use]
+    pub fn strategy(&self) -> SchedulingStrategy {
+        self.inner.pending.lock().unwrap().scheduler.strategy()
+    }
+
+    /// Switches to another strategy for the jobs dequeued from now on
+    pub fn set_strategy(&self, strategy: SchedulingStrategy) {
+        self.inner
+            .pending
+            .lock()
+            .unwrap()
+            .scheduler
+            .set_strategy(strategy);
+    }
+
+    /// Returns how long the jobs dequeued under each strategy waited
+    #[must_use]
+    pub fn wait_times(&self) -> HashMap<SchedulingStrategy, WaitTimeStats> {
+        self.inner.pending.lock().unwrap().scheduler.wait_times()
+    }
+
     /// Returns the number of pending jobs
     #[must_use]
     pub fn len(&self) -> usize {
-        let pending = self.inner.pending.lock().unwrap();
-        pending.len()
+        self.inner.pending.lock().unwrap().jobs.len()
     }
 
     /// Returns true if the queue is empty
@@ -125,30 +652,6 @@ impl Default for JobQueue {
     }
 }
 
-/// Job entry for the priority queue
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct JobEntry {
-    priority: JobPriority,
-    id: Uuid,
-    created_at: DateTime<Utc>,
-}
-
-impl Ord for JobEntry {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        // Reverse order for min-heap behavior (lower priority number = higher priority)
-        other
-            .priority
-            .cmp(&self.priority)
-            .then_with(|| other.created_at.cmp(&self.created_at))
-    }
-}
-
-impl PartialOrd for JobEntry {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
 /// A job in the queue
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Job {
@@ -174,6 +677,24 @@ pub struct Job {
     pub max_retries: u32,
     /// Job metadata
     pub metadata: std::collections::HashMap<String, String>,
+    /// Time by which the job should have run, for deadline scheduling
+    #[serde(default)]
+    pub deadline: Option<DateTime<Utc>>,
+    /// Tenant the job runs for, for fair scheduling
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Label expression the worker running the job must match
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Why no worker can run the job, while it is blocked
+    #[serde(default)]
+    pub blocked_reason: Option<String>,
+    /// When and how often the job is retried if it fails
+    #[serde(default)]
+    pub retry_policy: RetryPolicy,
+    /// Time before which a failed job is not retried
+    #[serde(default)]
+    pub retry_at: Option<DateTime<Utc>>,
 }
 
 impl Default for Job {
@@ -190,6 +711,12 @@ impl Default for Job {
             retries: 0,
             max_retries: 3,
             metadata: std::collections::HashMap::new(),
+            deadline: None,
+            tenant: None,
+            label: None,
+            blocked_reason: None,
+            retry_policy: RetryPolicy::default(),
+            retry_at: None,
         }
     }
 }
@@ -219,6 +746,81 @@ impl Job {
         self
     }
 
+    /// Sets the deadline
+    #[must_use]
+    pub fn with_deadline(mut self, deadline: DateTime<Utc>) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Sets the tenant
+    #[must_use]
+    pub fn with_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    /// Sets the label expression the worker running the job must match
+    #[must_use]
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Returns the labels a worker needs to run the job
+    ///
+    /// The worker must match the job's label expression as well as the
+    /// agents of its pipeline and stages. Returns `None` if any worker can
+    /// run the job.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a label expression is malformed.
+    pub fn label_expression(&self) -> WorkerResult<Option<LabelExpression>> {
+        let mut required = Vec::new();
+        if let Some(label) = &self.label {
+            let expression =
+                LabelExpression::parse(label).map_err(|e| WorkerErrorKind::InvalidLabel {
+                    reason: format!("job label '{label}': {e}"),
+                })?;
+            required.push(expression);
+        }
+
+        if let Some(pipeline) = &self.pipeline {
+            let agents = pipeline
+                .agent
+                .iter()
+                .map(|agent| ("pipeline agent".to_string(), agent))
+                .chain(pipeline.stages.iter().filter_map(|stage| {
+                    let agent = stage.agent.as_ref()?;
+                    Some((format!("agent of stage '{}'", stage.name), agent))
+                }));
+            for (owner, agent) in agents {
+                let expression =
+                    agent
+                        .label_expression()
+                        .map_err(|e| WorkerErrorKind::InvalidLabel {
+                            reason: format!("{owner} '{}': {e}", agent_label(agent)),
+                        })?;
+                required.extend(expression);
+            }
+        }
+
+        Ok(required
+            .into_iter()
+            .reduce(|all, next| LabelExpression::And(Box::new(all), Box::new(next))))
+    }
+
+    /// Returns what fair scheduling takes turns between: the tenant, or
+    /// else the name of the pipeline
+    #[must_use]
+    pub fn fairness_key(&self) -> &str {
+        self.tenant
+            .as_deref()
+            .or_else(|| self.pipeline.as_ref()?.name.as_deref())
+            .unwrap_or_default()
+    }
+
     /// Sets the maximum retries
     #[must_use]
     pub fn with_max_retries(mut self, max: u32) -> Self {
@@ -226,6 +828,13 @@ impl Job {
         self
     }
 
+    /// Sets the retry policy
+    #[must_use]
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
     /// Marks the job as running
     pub fn start(&mut self) {
         self.status = JobStatus::Running;
@@ -262,6 +871,14 @@ impl Job {
     }
 }
 
+/// Returns the label expression of an agent, for error messages
+fn agent_label(agent: &AgentType) -> &str {
+    match agent {
+        AgentType::Label { label } | AgentType::Custom { label } => label,
+        _ => "",
+    }
+}
+
 /// Job priority levels
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum JobPriority {
@@ -288,6 +905,10 @@ impl Default for JobPriority {
 pub enum JobStatus {
     /// Job is pending
     Pending,
+    /// Job is pending, but no worker can run it
+    ///
+    /// [`Job::blocked_reason`] tells why.
+    Blocked,
     /// Job is running
     Running,
     /// Job completed successfully
@@ -296,6 +917,9 @@ pub enum JobStatus {
     Failed,
     /// Job was cancelled
     Cancelled,
+    /// Job was running when its persistent queue stopped, and waits to be
+    /// requeued
+    Orphaned,
 }
 
 impl Default for JobStatus {
@@ -324,6 +948,349 @@ mod tests {
         assert!(!queue.is_empty());
     }
 
+    #[test]
+    fn test_job_queue_dequeue_returns_job() {
+        let queue = JobQueue::new();
+        let low = Job::new().with_priority(JobPriority::Low);
+        let high = Job::from_pipeline(pipeliner_core::Pipeline::new().with_name("Test"))
+            .with_priority(JobPriority::High);
+        let cancelled = Job::new().with_priority(JobPriority::Critical);
+        queue.enqueue(low.clone());
+        queue.enqueue(high.clone());
+        queue.enqueue(cancelled.clone());
+        queue.cancel(&cancelled.id);
+        assert_eq!(queue.len(), 2);
+
+        let mut job = queue.dequeue().unwrap();
+        assert_eq!(job.id, high.id);
+        assert_eq!(job.pipeline.as_ref().unwrap().name.as_deref(), Some("Test"));
+        assert_eq!(queue.processing_count(), 1);
+
+        job.start();
+        queue.update(&job);
+        assert_eq!(queue.get(&job.id).unwrap().status, JobStatus::Running);
+        assert_eq!(queue.dequeue().unwrap().id, low.id);
+        assert!(queue.dequeue().is_none());
+        assert_eq!(
+            queue.get(&cancelled.id).unwrap().status,
+            JobStatus::Cancelled
+        );
+    }
+
+    #[test]
+    fn test_job_queue_strategy() {
+        let queue = JobQueue::with_strategy(SchedulingStrategy::Fifo);
+        let low = Job::new().with_priority(JobPriority::Low);
+        let high = Job::new().with_priority(JobPriority::High);
+        queue.enqueue(low.clone());
+        queue.enqueue(high.clone());
+        queue.enqueue(Job::new());
+
+        assert_eq!(queue.dequeue().unwrap().id, low.id);
+        queue.set_strategy(SchedulingStrategy::Priority);
+        assert_eq!(queue.strategy(), SchedulingStrategy::Priority);
+        assert_eq!(queue.dequeue().unwrap().id, high.id);
+
+        let wait_times = queue.wait_times();
+        assert_eq!(wait_times[&SchedulingStrategy::Fifo].dispatched, 1);
+        assert_eq!(wait_times[&SchedulingStrategy::Priority].dispatched, 1);
+    }
+
+    #[tokio::test]
+    async fn test_job_queue_next_waits_for_job() {
+        let queue = JobQueue::new();
+        let waiting = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.next().await }
+        });
+        tokio::task::yield_now().await;
+
+        let job = Job::new();
+        queue.enqueue(job.clone());
+        assert_eq!(waiting.await.unwrap().id, job.id);
+    }
+
+    fn labels(labels: &[&str]) -> BTreeSet<String> {
+        labels.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_job_queue_routes_by_labels() {
+        let queue = JobQueue::new();
+        let gpu = Job::new()
+            .with_label("linux && gpu")
+            .with_priority(JobPriority::Critical);
+        let any = Job::new();
+        queue.enqueue(gpu.clone());
+        queue.enqueue(any.clone());
+
+        let blocked = queue.blocked();
+        assert_eq!(blocked.len(), 1);
+        assert_eq!(blocked[0].status, JobStatus::Blocked);
+        assert_eq!(
+            blocked[0].blocked_reason.as_deref(),
+            Some("no worker matches 'linux && gpu'")
+        );
+
+        let linux = labels(&["linux"]);
+        queue.register_worker(WorkerId(0), linux.clone());
+        assert_eq!(queue.blocked().len(), 1);
+        queue.register_worker(WorkerId(1), labels(&["linux", "gpu"]));
+        assert!(queue.blocked().is_empty());
+        assert_eq!(queue.get(&gpu.id).unwrap().status, JobStatus::Pending);
+
+        assert_eq!(queue.dequeue_for(&linux).unwrap().id, any.id);
+        assert!(queue.dequeue_for(&linux).is_none());
+
+        queue.unregister_worker(WorkerId(1));
+        assert_eq!(queue.blocked()[0].id, gpu.id);
+        let invalid = Job::new().with_label("gpu &&");
+        queue.enqueue(invalid.clone());
+        let reason = queue.get(&invalid.id).unwrap().blocked_reason.unwrap();
+        assert!(reason.contains("job label 'gpu &&'"), "{reason}");
+
+        // Without labels, blocked jobs are dequeued unless malformed
+        let dequeued = queue.dequeue().unwrap();
+        assert_eq!(dequeued.id, gpu.id);
+        assert_eq!(dequeued.status, JobStatus::Pending);
+        assert_eq!(dequeued.blocked_reason, None);
+        assert!(queue.dequeue().is_none());
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_job_queue_next_for_skips_unmatched_jobs() {
+        let queue = JobQueue::new();
+        queue.register_worker(WorkerId(0), labels(&["linux"]));
+        let waiting = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.next_for(&labels(&["linux"])).await }
+        });
+        tokio::task::yield_now().await;
+
+        queue.enqueue(Job::new().with_label("windows"));
+        let linux = Job::new().with_label("linux || macos");
+        queue.enqueue(linux.clone());
+        assert_eq!(waiting.await.unwrap().id, linux.id);
+        assert_eq!(queue.blocked().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_job_queue_waits_for_retry_backoff() {
+        let queue = JobQueue::new();
+        let mut retried = Job::new().with_priority(JobPriority::Critical);
+        retried.retry_at = Some(Utc::now() + chrono::Duration::milliseconds(100));
+        queue.enqueue(retried.clone());
+        let next = Job::new();
+        queue.enqueue(next.clone());
+
+        assert_eq!(queue.dequeue().unwrap().id, next.id);
+        assert!(queue.dequeue().is_none());
+        let start = std::time::Instant::now();
+        let job = queue.next().await;
+        assert_eq!(job.id, retried.id);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(50));
+        assert_eq!(job.retry_at, None);
+    }
+
+    #[test]
+    fn test_persistent_queue_replays_dead_letters() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let config = JournalConfig::new(dir.path().join("queue.log"));
+        let queue = JobQueue::open(config.clone()).unwrap();
+        let job = Job::new().with_max_retries(1);
+        queue.enqueue(job.clone());
+        let mut failed = queue.dequeue().unwrap();
+        assert!(failed.retry());
+        failed.fail("timeout: job timeout");
+        queue.update(&failed);
+        queue.dead_letter(&job.id);
+        assert!(queue.is_empty());
+        drop(queue);
+
+        let queue = JobQueue::open(config.clone()).unwrap();
+        let dead_letters = queue.dead_letters();
+        assert_eq!(dead_letters.len(), 1);
+        let read = JobQueue::read_dead_letters(&config.path).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].id, job.id);
+        assert_eq!(dead_letters[0].status, JobStatus::Failed);
+        assert_eq!(dead_letters[0].retries, 1);
+        assert_eq!(queue.get(&job.id).unwrap().status, JobStatus::Failed);
+        queue.compact().unwrap();
+        drop(queue);
+
+        let queue = JobQueue::open(config).unwrap();
+        assert!(queue.replay_dead_letter(&job.id));
+        assert!(!queue.replay_dead_letter(&job.id));
+        assert!(queue.dead_letters().is_empty());
+        let replayed = queue.dequeue().unwrap();
+        assert_eq!(replayed.id, job.id);
+        assert_eq!(replayed.retries, 0);
+        assert_eq!(replayed.error, None);
+    }
+
+    #[test]
+    fn test_persistent_queue_survives_restart() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let config = JournalConfig::new(dir.path().join("queue.log"));
+        let (done, running, cancelled) = (Job::new(), Job::new(), Job::new());
+        let waiting = Job::new().with_label("gpu");
+        let next = Job::new();
+
+        let queue = JobQueue::open(config.clone()).unwrap();
+        for job in [&done, &running, &cancelled, &waiting, &next] {
+            queue.enqueue(job.clone());
+        }
+        queue.cancel(&cancelled.id);
+        let mut job = queue.dequeue().unwrap();
+        job.start();
+        job.complete();
+        queue.update(&job);
+        queue.complete(&job.id);
+        let mut job = queue.dequeue().unwrap();
+        assert_eq!(job.id, running.id);
+        job.start();
+        queue.update(&job);
+        drop(queue);
+
+        let queue = JobQueue::open(config.clone()).unwrap();
+        assert_eq!(queue.get(&done.id).unwrap().status, JobStatus::Completed);
+        assert_eq!(
+            queue.get(&cancelled.id).unwrap().status,
+            JobStatus::Cancelled
+        );
+        assert_eq!(queue.blocked()[0].id, waiting.id);
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.completed_count(), 1);
+        // The interrupted job is requeued and runs after those that waited
+        let ids: Vec<_> = std::iter::from_fn(|| queue.dequeue_for(&labels(&["gpu"])))
+            .map(|job| job.id)
+            .collect();
+        assert_eq!(ids, [waiting.id, next.id, running.id]);
+        assert_eq!(queue.get(&running.id).unwrap().started_at, None);
+    }
+
+    #[test]
+    fn test_persistent_queue_orphans_processing_jobs() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let config =
+            JournalConfig::new(dir.path().join("queue.log")).with_recovery(RecoveryPolicy::Orphan);
+        let queue = JobQueue::open(config.clone()).unwrap();
+        let job = Job::new();
+        queue.enqueue(job.clone());
+        queue.dequeue().unwrap();
+        drop(queue);
+
+        let queue = JobQueue::open(config.clone()).unwrap();
+        assert!(queue.is_empty());
+        let orphaned = queue.orphaned();
+        assert_eq!(orphaned.len(), 1);
+        assert_eq!(orphaned[0].status, JobStatus::Orphaned);
+        assert_eq!(queue.get(&job.id).unwrap().status, JobStatus::Orphaned);
+        drop(queue);
+
+        let queue = JobQueue::open(config).unwrap();
+        assert_eq!(queue.orphaned().len(), 1);
+        assert!(queue.requeue(&job.id));
+        assert!(!queue.requeue(&job.id));
+        assert!(queue.orphaned().is_empty());
+        assert_eq!(queue.dequeue().unwrap().id, job.id);
+    }
+
+    #[test]
+    fn test_persistent_queue_compacts_journal() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("queue.log");
+        let config = JournalConfig::new(&path).with_compact_after(50);
+        let lines = || std::fs::read_to_string(&path).unwrap().lines().count();
+
+        let queue = JobQueue::open(config.clone()).unwrap();
+        for _ in 0..40 {
+            queue.enqueue(Job::new());
+            let job = queue.dequeue().unwrap();
+            queue.update(&job);
+            queue.complete(&job.id);
+        }
+        // Four entries per job, compacted to one each time 50 were written
+        assert!(lines() < 50, "{} entries", lines());
+        queue.enqueue(Job::new());
+        queue.compact().unwrap();
+        assert_eq!(lines(), 41);
+        drop(queue);
+
+        let queue = JobQueue::open(config).unwrap();
+        assert_eq!(queue.completed_count(), 40);
+        assert_eq!(queue.len(), 1);
+        assert!(JobQueue::new().compact().is_ok());
+    }
+
+    #[test]
+    fn test_persistent_queue_forgets_old_finished_jobs() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("queue.log");
+        let config = JournalConfig::new(&path)
+            .with_compact_after(20)
+            .with_retain_finished(5);
+
+        let queue = JobQueue::open(config.clone()).unwrap();
+        let mut finished = Vec::new();
+        for _ in 0..30 {
+            queue.enqueue(Job::new());
+            let mut job = queue.dequeue().unwrap();
+            job.complete();
+            queue.update(&job);
+            queue.complete(&job.id);
+            finished.push(job.id);
+        }
+        let cancelled = Job::new();
+        queue.enqueue(cancelled.clone());
+        queue.cancel(&cancelled.id);
+        queue.compact().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 5);
+        drop(queue);
+
+        let queue = JobQueue::open(config).unwrap();
+        assert_eq!(queue.completed_count(), 4);
+        assert!(queue.get(&cancelled.id).is_some());
+        assert!(queue.get(&finished[29]).is_some());
+        assert!(queue.get(&finished[25]).is_none());
+        assert!(queue.get(&finished[0]).is_none());
+    }
+
+    #[test]
+    fn test_job_label_expression() {
+        use pipeliner_core::{Stage, Step};
+
+        let pipeline = pipeliner_core::Pipeline::new()
+            .with_agent(AgentType::label("linux"))
+            .with_stage(Stage::new("Build").with_step(Step::echo("building")))
+            .with_stage(
+                Stage::new("Image")
+                    .with_agent(AgentType::docker("rust:1.75"))
+                    .with_step(Step::echo("packaging")),
+            );
+        let job = Job::from_pipeline(pipeline.clone()).with_label("arm64 || aarch64");
+        assert_eq!(
+            job.label_expression().unwrap().unwrap().to_string(),
+            "(arm64 || aarch64) && linux && docker"
+        );
+        assert_eq!(Job::new().label_expression().unwrap(), None);
+
+        let invalid = Job::from_pipeline(
+            pipeline.with_stage(
+                Stage::new("Deploy")
+                    .with_agent(AgentType::label("linux &&"))
+                    .with_step(Step::echo("deploying")),
+            ),
+        );
+        assert_eq!(
+            invalid.label_expression().unwrap_err().to_string(),
+            "invalid label expression: agent of stage 'Deploy' 'linux &&': \
+             expected a label at column 9"
+        );
+    }
+
     #[test]
     fn test_job_from_pipeline() {
         let pipeline = pipeliner_core::Pipeline::new().with_name("Test");
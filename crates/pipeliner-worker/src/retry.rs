@@ -0,0 +1,157 @@
+//! Retry policies of failed jobs.
+//!
+//! A job that fails because of the infrastructure, such as a workspace that
+//! cannot be set up or a run exceeding the job timeout, is retried after an
+//! exponential backoff with jitter, up to [`Job::max_retries`](crate::Job)
+//! times. Jobs whose pipeline fails are not retried: running the same build
+//! again would fail the same way. A job that exhausts its retries goes to
+//! the queue's dead-letter queue, from which it can be replayed.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use crate::WorkerErrorKind;
+
+/// Kind of failure a job is retried on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum RetryOn {
+    /// No worker could run the job
+    WorkerUnavailable,
+    /// The run exceeded the job timeout
+    Timeout,
+}
+
+/// When and how often a failed job is retried
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Delay before the first retry
+    pub initial_backoff: Duration,
+    /// Longest delay between retries
+    pub max_backoff: Duration,
+    /// Factor the delay grows by with every retry
+    pub multiplier: f64,
+    /// Fraction of the delay, between 0 and 1, randomly taken off it so
+    /// that jobs failing together are not retried together
+    pub jitter: f64,
+    /// Failures the job is retried on
+    pub retry_on: BTreeSet<RetryOn>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_secs(10),
+            max_backoff: Duration::from_mins(10),
+            multiplier: 2.0,
+            jitter: 0.2,
+            retry_on: BTreeSet::from([RetryOn::WorkerUnavailable, RetryOn::Timeout]),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Sets the delay before the first retry and the longest delay
+    #[must_use]
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Sets the factor the delay grows by with every retry
+    #[must_use]
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Sets the fraction of the delay randomly taken off it
+    #[must_use]
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the failures the job is retried on
+    #[must_use]
+    pub fn retrying_on(mut self, retry_on: impl IntoIterator<Item = RetryOn>) -> Self {
+        self.retry_on = retry_on.into_iter().collect();
+        self
+    }
+
+    /// Returns true if a job failing with `error` is retried
+    #[must_use]
+    pub fn retries(&self, error: &WorkerErrorKind) -> bool {
+        let kind = match error {
+            WorkerErrorKind::WorkerUnavailable { .. } => RetryOn::WorkerUnavailable,
+            WorkerErrorKind::Timeout { .. } => RetryOn::Timeout,
+            _ => return false,
+        };
+        self.retry_on.contains(&kind)
+    }
+
+    /// Returns the delay before retry number `attempt`, counting from 1
+    #[must_use]
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let delay = (self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_backoff.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0) * fastrand::f64();
+        Duration::try_from_secs_f64(delay * (1.0 - jitter)).unwrap_or(self.max_backoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_exponentially() {
+        let policy = RetryPolicy::default()
+            .with_backoff(Duration::from_secs(1), Duration::from_secs(30))
+            .with_jitter(0.0);
+        let delays: Vec<_> = (1..=6).map(|attempt| policy.backoff(attempt)).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30].map(Duration::from_secs));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(30));
+
+        let linear = policy.with_multiplier(1.0);
+        assert_eq!(linear.backoff(5), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_backoff_jitter() {
+        let policy = RetryPolicy::default()
+            .with_backoff(Duration::from_secs(8), Duration::from_secs(8))
+            .with_jitter(0.5);
+        let delays: BTreeSet<_> = (0..50).map(|_| policy.backoff(3)).collect();
+        assert!(delays.len() > 1);
+        assert!(
+            delays
+                .iter()
+                .all(|delay| (Duration::from_secs(4)..=Duration::from_secs(8)).contains(delay))
+        );
+    }
+
+    #[test]
+    fn test_retries_infrastructure_errors_only() {
+        let unavailable = WorkerErrorKind::WorkerUnavailable {
+            reason: "disk full".to_string(),
+        };
+        let timeout = WorkerErrorKind::Timeout {
+            reason: "job timeout".to_string(),
+        };
+        let failure = WorkerErrorKind::ExecutionFailed {
+            reason: "exit code 1".to_string(),
+        };
+
+        let policy = RetryPolicy::default();
+        assert!(policy.retries(&unavailable));
+        assert!(policy.retries(&timeout));
+        assert!(!policy.retries(&failure));
+
+        let policy = policy.retrying_on([RetryOn::WorkerUnavailable]);
+        assert!(policy.retries(&unavailable));
+        assert!(!policy.retries(&timeout));
+    }
+}
@@ -1,72 +1,175 @@
 //! Job scheduling logic.
+//!
+//! A [`Scheduler`] decides which pending job of a [`JobQueue`](crate::JobQueue)
+//! runs next, following its [`SchedulingStrategy`], and keeps track of how
+//! long jobs waited in the queue under each strategy.
 
-use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use pipeliner_core::LabelExpression;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
 use std::time::Duration;
-use tokio::sync::RwLock;
-use tracing::{debug, info};
 
-use crate::{JobQueue, WorkerPool};
+use crate::Job;
 
-/// Scheduler for job execution
-#[derive(Debug)]
+/// Default time a job waits before its priority is raised by one level
+const DEFAULT_AGING_INTERVAL: Duration = Duration::from_mins(5);
+
+/// A job waiting in the queue
+#[derive(Debug, Clone)]
+pub(crate) struct PendingJob {
+    pub(crate) job: Job,
+    pub(crate) enqueued_at: DateTime<Utc>,
+    /// Labels a worker needs to run the job, or why no worker can
+    pub(crate) requirement: Result<Option<LabelExpression>, String>,
+}
+
+impl PendingJob {
+    pub(crate) fn new(job: Job, enqueued_at: DateTime<Utc>) -> Self {
+        let requirement = job.label_expression().map_err(|e| e.to_string());
+        Self {
+            job,
+            enqueued_at,
+            requirement,
+        }
+    }
+
+    /// Returns true if a worker with `labels` can run the job
+    pub(crate) fn runs_on(&self, labels: &BTreeSet<String>) -> bool {
+        match &self.requirement {
+            Ok(None) => true,
+            Ok(Some(expression)) => expression.matches(labels),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Picks the next job of a queue
+#[derive(Debug, Clone)]
 pub struct Scheduler {
-    queue: JobQueue,
-    pool: Arc<RwLock<WorkerPool>>,
     strategy: SchedulingStrategy,
-    interval: Duration,
+    aging_interval: Duration,
+    /// Number of jobs dispatched so far
+    dispatched: u64,
+    /// When each fairness key was last served, as a dispatch count
+    last_served: HashMap<String, u64>,
+    wait_times: HashMap<SchedulingStrategy, WaitTimeStats>,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(SchedulingStrategy::default())
+    }
 }
 
 impl Scheduler {
+    /// Creates a scheduler following `strategy`
     #[must_use]
-    pub fn new(queue: JobQueue, pool: Arc<RwLock<WorkerPool>>) -> Self {
+    pub fn new(strategy: SchedulingStrategy) -> Self {
         Self {
-            queue,
-            pool,
-            strategy: SchedulingStrategy::default(),
-            interval: Duration::from_millis(100),
+            strategy,
+            aging_interval: DEFAULT_AGING_INTERVAL,
+            dispatched: 0,
+            last_served: HashMap::new(),
+            wait_times: HashMap::new(),
         }
     }
 
+    /// Sets how long a job waits before the priority strategy raises its
+    /// priority by one level
+    ///
+    /// A zero interval disables aging.
     #[must_use]
-    pub fn with_interval(mut self, interval: Duration) -> Self {
-        self.interval = interval;
+    pub fn with_aging_interval(mut self, interval: Duration) -> Self {
+        self.aging_interval = interval;
         self
     }
 
-    pub async fn run(&self) {
-        info!("Scheduler starting with {:?}", self.strategy);
+    /// Returns the strategy
+    #[must_use]
+    pub fn strategy(&self) -> SchedulingStrategy {
+        self.strategy
+    }
+
+    /// Switches to another strategy
+    pub fn set_strategy(&mut self, strategy: SchedulingStrategy) {
+        self.strategy = strategy;
+    }
 
-        loop {
-            tokio::time::sleep(self.interval).await;
+    /// Returns the queue wait times of the jobs dispatched under each
+    /// strategy
+    #[must_use]
+    pub fn wait_times(&self) -> HashMap<SchedulingStrategy, WaitTimeStats> {
+        self.wait_times.clone()
+    }
 
-            let pool = self.pool.read().await;
-            if pool.queue_len() > 0 {
-                self.dispatch_jobs(&pool).await;
+    /// Returns the index of the job to run next among those `eligible`
+    ///
+    /// `pending` is in enqueue order, which breaks ties.
+    pub(crate) fn select(
+        &self,
+        pending: &[PendingJob],
+        now: DateTime<Utc>,
+        eligible: impl Fn(&PendingJob) -> bool,
+    ) -> Option<usize> {
+        let jobs = pending.iter().enumerate().filter(|(_, p)| eligible(p));
+        let selected = match self.strategy {
+            SchedulingStrategy::Fifo => jobs.min_by_key(|(_, p)| p.enqueued_at),
+            SchedulingStrategy::Priority => {
+                jobs.min_by_key(|(_, p)| self.effective_priority(p, now))
             }
-        }
+            SchedulingStrategy::Fair => jobs.min_by_key(|(_, p)| {
+                self.last_served
+                    .get(p.job.fairness_key())
+                    .copied()
+                    .unwrap_or(0)
+            }),
+            // Jobs without a deadline go after every job with one
+            SchedulingStrategy::Deadline => {
+                jobs.min_by_key(|(_, p)| (p.job.deadline.is_none(), p.job.deadline))
+            }
+        };
+        selected.map(|(index, _)| index)
     }
 
-    async fn dispatch_jobs(&self, pool: &WorkerPool) {
-        let capacity = pool.worker_count() * 4;
-        let available = capacity.saturating_sub(pool.queue_len());
+    /// Records that a job was taken from the queue
+    pub(crate) fn record_dispatch(&mut self, pending: &PendingJob, now: DateTime<Utc>) {
+        self.dispatched += 1;
+        self.last_served
+            .insert(pending.job.fairness_key().to_string(), self.dispatched);
 
-        for _ in 0..available {
-            if let Some(job) = self.queue.dequeue() {
-                debug!("Dispatching job {}", job.id);
-                pool.submit(job);
-            } else {
-                break;
-            }
+        let waited = (now - pending.enqueued_at).to_std().unwrap_or_default();
+        self.wait_times
+            .entry(self.strategy)
+            .or_default()
+            .record(waited);
+    }
+
+    /// Returns the priority level of a job, raised by one level for every
+    /// aging interval it waited
+    fn effective_priority(&self, pending: &PendingJob, now: DateTime<Utc>) -> u128 {
+        let level = pending.job.priority as u128;
+        if self.aging_interval.is_zero() {
+            return level;
         }
+        let waited = (now - pending.enqueued_at).to_std().unwrap_or_default();
+        level.saturating_sub(waited.as_nanos() / self.aging_interval.as_nanos())
     }
 }
 
 /// Scheduling strategy
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum SchedulingStrategy {
+    /// Jobs run in the order they were enqueued
     Fifo,
+    /// Jobs run by priority, oldest first within a priority
+    ///
+    /// Waiting raises the priority of a job, so low priority jobs cannot
+    /// starve.
     Priority,
+    /// Jobs of each tenant or pipeline take turns, oldest first within one
     Fair,
+    /// Jobs run by earliest deadline, jobs without one last
     Deadline,
 }
 
@@ -76,9 +179,58 @@ impl Default for SchedulingStrategy {
     }
 }
 
+/// Time jobs waited in a queue before running
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WaitTimeStats {
+    /// Number of jobs taken from the queue
+    pub dispatched: u64,
+    /// Sum of their wait times
+    pub total: Duration,
+    /// Longest wait time
+    pub max: Duration,
+}
+
+impl WaitTimeStats {
+    /// Returns the average wait time
+    #[must_use]
+    pub fn average(&self) -> Duration {
+        if self.dispatched == 0 {
+            return Duration::ZERO;
+        }
+        let nanos = self.total.as_nanos() / u128::from(self.dispatched);
+        Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+    }
+
+    fn record(&mut self, waited: Duration) {
+        self.dispatched += 1;
+        self.total += waited;
+        self.max = self.max.max(waited);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::JobPriority;
+
+    fn pending(job: Job, minutes_ago: i64, now: DateTime<Utc>) -> PendingJob {
+        PendingJob::new(job, now - chrono::Duration::minutes(minutes_ago))
+    }
+
+    /// Takes every job in the order the scheduler picks them
+    fn drain(scheduler: &mut Scheduler, mut jobs: Vec<PendingJob>, now: DateTime<Utc>) -> Vec<Job> {
+        let mut order = Vec::new();
+        while let Some(index) = scheduler.select(&jobs, now, |_| true) {
+            let next = jobs.remove(index);
+            scheduler.record_dispatch(&next, now);
+            order.push(next.job);
+        }
+        order
+    }
+
+    fn ids(jobs: &[Job]) -> Vec<uuid::Uuid> {
+        jobs.iter().map(|job| job.id).collect()
+    }
 
     #[test]
     fn test_scheduling_strategy_variants() {
@@ -89,4 +241,132 @@ mod tests {
     fn test_scheduling_strategy_default() {
         assert_eq!(SchedulingStrategy::default(), SchedulingStrategy::Fifo);
     }
+
+    #[test]
+    fn test_fifo_ignores_priority() {
+        let now = Utc::now();
+        let low = Job::new().with_priority(JobPriority::Low);
+        let critical = Job::new().with_priority(JobPriority::Critical);
+        let jobs = vec![
+            pending(low.clone(), 2, now),
+            pending(critical.clone(), 1, now),
+        ];
+
+        let order = drain(&mut Scheduler::new(SchedulingStrategy::Fifo), jobs, now);
+        assert_eq!(ids(&order), [low.id, critical.id]);
+    }
+
+    #[test]
+    fn test_priority_ages_waiting_jobs() {
+        let now = Utc::now();
+        let nightly = Job::new().with_priority(JobPriority::Background);
+        let hotfix = Job::new().with_priority(JobPriority::High);
+        let normal = Job::new();
+
+        let jobs = vec![
+            pending(nightly.clone(), 10, now),
+            pending(normal.clone(), 1, now),
+            pending(hotfix.clone(), 0, now),
+        ];
+        // Two aging intervals lift the background job to normal priority,
+        // where it is the oldest
+        let mut scheduler = Scheduler::new(SchedulingStrategy::Priority);
+        let order = drain(&mut scheduler, jobs.clone(), now);
+        assert_eq!(ids(&order), [hotfix.id, nightly.id, normal.id]);
+
+        // Waiting four aging intervals lifts a background job to the top
+        let mut scheduler = scheduler.with_aging_interval(Duration::from_secs(150));
+        let order = drain(&mut scheduler, jobs.clone(), now);
+        assert_eq!(ids(&order), [nightly.id, hotfix.id, normal.id]);
+
+        let mut scheduler = scheduler.with_aging_interval(Duration::ZERO);
+        let order = drain(&mut scheduler, jobs, now + chrono::Duration::days(1));
+        assert_eq!(ids(&order), [hotfix.id, normal.id, nightly.id]);
+    }
+
+    #[test]
+    fn test_fair_takes_turns() {
+        let now = Utc::now();
+        let tenant = |name: &str| Job::new().with_tenant(name);
+        let (a1, a2, a3) = (tenant("a"), tenant("a"), tenant("a"));
+        let (b1, b2) = (tenant("b"), tenant("b"));
+        let nightly = Job::from_pipeline(pipeliner_core::Pipeline::new().with_name("nightly"));
+
+        let jobs = vec![
+            pending(a1.clone(), 6, now),
+            pending(a2.clone(), 5, now),
+            pending(a3.clone(), 4, now),
+            pending(b1.clone(), 3, now),
+            pending(b2.clone(), 2, now),
+            pending(nightly.clone(), 1, now),
+        ];
+        let order = drain(&mut Scheduler::new(SchedulingStrategy::Fair), jobs, now);
+        assert_eq!(ids(&order), [a1.id, b1.id, nightly.id, a2.id, b2.id, a3.id]);
+    }
+
+    #[test]
+    fn test_deadline_runs_earliest_first() {
+        let now = Utc::now();
+        let due = |minutes| Job::new().with_deadline(now + chrono::Duration::minutes(minutes));
+        let (late, soon) = (due(60), due(5));
+        let whenever = Job::new();
+
+        let jobs = vec![
+            pending(whenever.clone(), 3, now),
+            pending(late.clone(), 2, now),
+            pending(soon.clone(), 1, now),
+        ];
+        let order = drain(&mut Scheduler::new(SchedulingStrategy::Deadline), jobs, now);
+        assert_eq!(ids(&order), [soon.id, late.id, whenever.id]);
+    }
+
+    #[test]
+    fn test_select_skips_ineligible_jobs() {
+        let now = Utc::now();
+        let gpu = Job::new()
+            .with_priority(JobPriority::Critical)
+            .with_label("gpu");
+        let invalid = Job::new().with_label("gpu &&");
+        let any = Job::new().with_priority(JobPriority::Low);
+        let jobs = vec![
+            pending(gpu.clone(), 2, now),
+            pending(invalid, 1, now),
+            pending(any.clone(), 0, now),
+        ];
+
+        let scheduler = Scheduler::new(SchedulingStrategy::Priority);
+        let linux = BTreeSet::from(["linux".to_string()]);
+        let index = scheduler.select(&jobs, now, |p| p.runs_on(&linux));
+        assert_eq!(jobs[index.unwrap()].job.id, any.id);
+        let with_gpu = BTreeSet::from(["linux".to_string(), "gpu".to_string()]);
+        let index = scheduler.select(&jobs, now, |p| p.runs_on(&with_gpu));
+        assert_eq!(jobs[index.unwrap()].job.id, gpu.id);
+        assert!(jobs[1].requirement.is_err());
+        assert!(scheduler.select(&jobs, now, |_| false).is_none());
+    }
+
+    #[test]
+    fn test_wait_times_per_strategy() {
+        let now = Utc::now();
+        let mut scheduler = Scheduler::new(SchedulingStrategy::Fifo);
+        drain(
+            &mut scheduler,
+            vec![pending(Job::new(), 1, now), pending(Job::new(), 3, now)],
+            now,
+        );
+        scheduler.set_strategy(SchedulingStrategy::Deadline);
+        drain(&mut scheduler, vec![pending(Job::new(), 10, now)], now);
+
+        let wait_times = scheduler.wait_times();
+        let fifo = wait_times[&SchedulingStrategy::Fifo];
+        assert_eq!(fifo.dispatched, 2);
+        assert_eq!(fifo.average(), Duration::from_mins(2));
+        assert_eq!(fifo.max, Duration::from_mins(3));
+        assert_eq!(
+            wait_times[&SchedulingStrategy::Deadline].total,
+            Duration::from_mins(10)
+        );
+        assert!(!wait_times.contains_key(&SchedulingStrategy::Priority));
+        assert_eq!(WaitTimeStats::default().average(), Duration::ZERO);
+    }
 }
@@ -9,6 +9,8 @@ use std::sync::Arc;
 use std::time::Instant;
 use uuid::Uuid;
 
+use pipeliner_executor::{ExecutionResult, StepResult};
+
 use crate::Job;
 
 /// Current execution state
@@ -28,6 +30,10 @@ pub struct ExecutionState {
     total_failed: Arc<std::sync::atomic::AtomicUsize>,
     /// Last job ID
     last_job_id: Arc<std::sync::RwLock<Option<Uuid>>>,
+    /// Step output of the last run of each job
+    logs: Arc<DashMap<Uuid, Vec<StepLog>>>,
+    /// Outcome of the last run of each job
+    outcomes: Arc<DashMap<Uuid, JobOutcome>>,
 }
 
 impl ExecutionState {
@@ -42,6 +48,8 @@ impl ExecutionState {
             total_processed: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
             total_failed: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
             last_job_id: Arc::new(std::sync::RwLock::new(None)),
+            logs: Arc::new(DashMap::new()),
+            outcomes: Arc::new(DashMap::new()),
         }
     }
 
@@ -57,6 +65,42 @@ impl ExecutionState {
         self.jobs.get(id).map(|j| j.value().clone())
     }
 
+    /// Stores the current state of a job
+    pub fn update_job(&self, job: &Job) {
+        self.jobs.insert(job.id, job.clone());
+    }
+
+    /// Forgets the step output and outcome of a job's previous run
+    pub fn reset_run(&self, id: &Uuid) {
+        self.logs.remove(id);
+        self.outcomes.remove(id);
+    }
+
+    /// Appends step output of a job
+    pub fn append_log(&self, id: &Uuid, log: StepLog) {
+        self.logs.entry(*id).or_default().push(log);
+    }
+
+    /// Returns the step output of a job's last run
+    #[must_use]
+    pub fn logs(&self, id: &Uuid) -> Vec<StepLog> {
+        self.logs
+            .get(id)
+            .map(|l| l.value().clone())
+            .unwrap_or_default()
+    }
+
+    /// Records the outcome of a job's run
+    pub fn record_outcome(&self, id: &Uuid, outcome: JobOutcome) {
+        self.outcomes.insert(*id, outcome);
+    }
+
+    /// Returns the outcome of a job's last run
+    #[must_use]
+    pub fn outcome(&self, id: &Uuid) -> Option<JobOutcome> {
+        self.outcomes.get(id).map(|o| o.value().clone())
+    }
+
     /// Marks a job as active
     pub fn mark_active(&self, id: &Uuid) {
         self.active.insert(*id, Instant::now());
@@ -134,6 +178,26 @@ impl ExecutionState {
     }
 }
 
+/// Output of a step of a job's pipeline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepLog {
+    /// Stage the step belongs to
+    pub stage: String,
+    /// Name of the step
+    pub step: String,
+    /// Output of the step
+    pub output: String,
+}
+
+/// Outcome of running a job's pipeline
+#[derive(Debug, Clone)]
+pub struct JobOutcome {
+    /// Result of the run, with the results of its stages
+    pub result: ExecutionResult,
+    /// Results of the steps that ran, in the order they finished
+    pub steps: Vec<StepResult>,
+}
+
 /// State statistics
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StateStats {
@@ -247,6 +311,32 @@ mod tests {
         assert_eq!(state.total_failed(), 1);
     }
 
+    #[test]
+    fn test_execution_state_logs_and_outcome() {
+        let state = ExecutionState::new();
+        let job = Job::new();
+        let log = StepLog {
+            stage: "Build".to_string(),
+            step: "echo".to_string(),
+            output: "hello".to_string(),
+        };
+
+        state.append_log(&job.id, log.clone());
+        state.record_outcome(
+            &job.id,
+            JobOutcome {
+                result: ExecutionResult::default(),
+                steps: Vec::new(),
+            },
+        );
+        assert_eq!(state.logs(&job.id), vec![log]);
+        assert!(state.outcome(&job.id).is_some());
+
+        state.reset_run(&job.id);
+        assert!(state.logs(&job.id).is_empty());
+        assert!(state.outcome(&job.id).is_none());
+    }
+
     #[test]
     fn test_state_stats_default() {
         let stats = StateStats::default();
@@ -0,0 +1,201 @@
+//! `rustline gc` - Apply build retention on demand
+//!
+//! Builds apply their pipeline's `BuildDiscarder` when they finish and store
+//! it with the job. This command re-applies the stored policies, optionally
+//! overridden from the command line, to one job or every job in the history.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! rustline gc
+//! rustline gc my-app --num-to-keep 10 --artifact-num-to-keep 3
+//! rustline gc my-app --pin 42
+//! rustline gc --dry-run
+//! ```
+
+use anyhow::Result;
+use rustline::executor::{BuildHistory, apply_retention};
+use rustline::pipeline::BuildDiscarder;
+use std::path::PathBuf;
+
+/// Retention limits given on the command line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Builds to keep
+    pub num_to_keep: Option<usize>,
+    /// Days to keep builds
+    pub days_to_keep: Option<usize>,
+    /// Builds to keep artifacts for
+    pub artifact_num_to_keep: Option<usize>,
+    /// Days to keep artifacts
+    pub artifact_days_to_keep: Option<usize>,
+}
+
+impl Limits {
+    /// Returns true if no limit was given
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Overrides the limits of a stored policy
+    fn apply(&self, mut discarder: BuildDiscarder) -> BuildDiscarder {
+        if let Some(num) = self.num_to_keep {
+            discarder.num_to_keep = num;
+        }
+        if self.days_to_keep.is_some() {
+            discarder.days_to_keep = self.days_to_keep;
+        }
+        if self.artifact_num_to_keep.is_some() {
+            discarder.artifact_num_to_keep = self.artifact_num_to_keep;
+        }
+        if self.artifact_days_to_keep.is_some() {
+            discarder.artifact_days_to_keep = self.artifact_days_to_keep;
+        }
+        discarder
+    }
+}
+
+/// Marks a build as kept forever, or releases it
+pub fn pin_build(dir: Option<PathBuf>, job: &str, number: u64, keep: bool) -> Result<String> {
+    history(dir).set_keep_forever(job, number, keep)?;
+    let state = if keep { "Pinned" } else { "Unpinned" };
+    Ok(format!("{state} {job} #{number}"))
+}
+
+/// Applies retention to `job`, or to every job, and describes what was removed
+///
+/// Jobs without a stored policy are skipped unless limits are given.
+pub fn collect_garbage(
+    dir: Option<PathBuf>,
+    job: Option<&str>,
+    limits: Limits,
+    dry_run: bool,
+) -> Result<String> {
+    let history = history(dir);
+    let jobs = match job {
+        Some(job) => vec![job.to_string()],
+        None => history.jobs()?,
+    };
+
+    let mut lines = Vec::new();
+    for job in &jobs {
+        let stored = history.discarder(job)?;
+        if stored.is_none() && limits.is_empty() {
+            lines.push(format!("{job}: no retention policy, skipped"));
+            continue;
+        }
+
+        let discarder = limits.apply(stored.unwrap_or_default());
+        lines.push(apply_retention(&history, job, &discarder, dry_run)?.to_string());
+    }
+
+    if lines.is_empty() {
+        return Ok("No builds recorded".to_string());
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Opens the history at `dir`, or the default history
+fn history(dir: Option<PathBuf>) -> BuildHistory {
+    dir.map_or_else(BuildHistory::from_env, BuildHistory::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustline::executor::BuildRecord;
+    use rustline::pipeline::StageResult;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn history_with_builds(count: usize) -> (TempDir, BuildHistory) {
+        let temp_dir = TempDir::new().unwrap();
+        let history = BuildHistory::new(temp_dir.path());
+        for _ in 0..count {
+            let mut build = BuildRecord::new("app", history.next_build_number("app").unwrap());
+            build.finish(StageResult::Failure, Duration::from_secs(1));
+            history.record(&build).unwrap();
+        }
+        (temp_dir, history)
+    }
+
+    #[test]
+    fn test_gc_uses_stored_policy_and_overrides() {
+        let (temp_dir, history) = history_with_builds(4);
+        let dir = Some(temp_dir.path().to_path_buf());
+
+        let output = collect_garbage(dir.clone(), None, Limits::default(), false).unwrap();
+        assert_eq!(output, "app: no retention policy, skipped");
+
+        history
+            .set_discarder("app", &BuildDiscarder::new(3))
+            .unwrap();
+        let output = collect_garbage(dir.clone(), None, Limits::default(), true).unwrap();
+        assert!(output.starts_with("app: would remove 1 build(s) #1"));
+        assert_eq!(history.builds("app").unwrap().len(), 4);
+
+        let limits = Limits {
+            num_to_keep: Some(1),
+            ..Limits::default()
+        };
+        collect_garbage(dir, Some("app"), limits, false).unwrap();
+        assert_eq!(history.builds("app").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_executor_and_gc_discard_old_builds() {
+        use rustline::executor::{LocalExecutor, PipelineExecutor};
+        use rustline::pipeline::{AgentType, Pipeline, PipelineOptions, Stage, Step};
+
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("history");
+        let history = BuildHistory::new(&dir);
+        let pipeline = Pipeline::builder()
+            .name("app")
+            .agent(AgentType::Any)
+            .options(PipelineOptions::default().with_build_discarder(BuildDiscarder::new(2)))
+            .stages(vec![Stage::new("Build", vec![Step::echo("build")])])
+            .build_unchecked();
+
+        let executor = LocalExecutor::new()
+            .with_cwd(temp_dir.path())
+            .with_history(history.clone());
+        for _ in 0..3 {
+            executor.execute(&pipeline).unwrap();
+        }
+
+        let numbers = || -> Vec<u64> {
+            let builds = history.builds("app").unwrap();
+            builds.iter().map(|b| b.number).collect()
+        };
+        assert_eq!(numbers(), vec![3, 2]);
+
+        let limits = Limits {
+            num_to_keep: Some(1),
+            ..Limits::default()
+        };
+        collect_garbage(Some(dir), Some("app"), limits, false).unwrap();
+        assert_eq!(numbers(), vec![3]);
+    }
+
+    #[test]
+    fn test_pinned_build_survives_gc() {
+        let (temp_dir, history) = history_with_builds(3);
+        let dir = Some(temp_dir.path().to_path_buf());
+
+        pin_build(dir.clone(), "app", 1, true).unwrap();
+        let limits = Limits {
+            num_to_keep: Some(1),
+            ..Limits::default()
+        };
+        collect_garbage(dir, Some("app"), limits, false).unwrap();
+
+        let remaining: Vec<u64> = history
+            .builds("app")
+            .unwrap()
+            .iter()
+            .map(|b| b.number)
+            .collect();
+        assert_eq!(remaining, vec![3, 1]);
+    }
+}
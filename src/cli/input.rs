@@ -0,0 +1,123 @@
+//! `rustline input` - Answer pending pipeline inputs
+//!
+//! Builds that reach an `input` step without a terminal or a pre-supplied
+//! answer wait in the pending-input registry. This command lists those
+//! inputs and answers them from another shell. Inputs are listed by key,
+//! `<pipeline>.<id>`; the bare id answers an input while only one build
+//! waits on it.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! rustline input list
+//! rustline input answer deploy --value v1.2 --submitter alice
+//! rustline input answer deploy --abort
+//! ```
+
+use anyhow::Result;
+use rustline::executor::{InputAnswer, InputRegistry};
+use std::path::PathBuf;
+
+/// Opens the registry at `dir`, or the default registry
+fn registry(dir: Option<PathBuf>) -> InputRegistry {
+    dir.map_or_else(InputRegistry::from_env, InputRegistry::new)
+}
+
+/// Formats the inputs waiting in the registry
+pub fn list_inputs(dir: Option<PathBuf>) -> Result<String> {
+    let pending = registry(dir).pending()?;
+
+    if pending.is_empty() {
+        return Ok("No pending inputs".to_string());
+    }
+
+    let lines: Vec<String> = pending
+        .iter()
+        .map(|request| {
+            let mut line = format!("{}: {}", request.key(), request.message);
+            if let Some(ref default) = request.default {
+                line.push_str(&format!(" [default: {default}]"));
+            }
+            if !request.submitters.is_empty() {
+                line.push_str(&format!(" (submitters: {})", request.submitters.join(", ")));
+            }
+            line
+        })
+        .collect();
+
+    Ok(lines.join("\n"))
+}
+
+/// Answers a pending input
+///
+/// The submitter defaults to the current user.
+pub fn answer_input(
+    dir: Option<PathBuf>,
+    id: &str,
+    value: Option<String>,
+    submitter: Option<String>,
+    abort: bool,
+) -> Result<()> {
+    let answer = if abort {
+        InputAnswer::abort()
+    } else {
+        InputAnswer::proceed(value)
+    };
+    let answer = match submitter.or_else(|| std::env::var("USER").ok()) {
+        Some(submitter) => answer.with_submitter(submitter),
+        None => answer,
+    };
+
+    let key = registry(dir).answer(id, &answer)?;
+    tracing::info!("Answered input: {}", key);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustline::executor::InputRequest;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_list_and_answer_inputs() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = Some(temp_dir.path().to_path_buf());
+
+        assert_eq!(list_inputs(dir.clone()).unwrap(), "No pending inputs");
+
+        let request = InputRequest::new(
+            "Deploy?",
+            None,
+            Some(&"deploy".to_string()),
+            Some(&"alice".to_string()),
+        );
+        InputRegistry::new(temp_dir.path())
+            .register(&request)
+            .unwrap();
+
+        let listing = list_inputs(dir.clone()).unwrap();
+        assert_eq!(listing, "deploy: Deploy? (submitters: alice)");
+
+        answer_input(dir, "deploy", None, Some("alice".to_string()), false).unwrap();
+        let answer = InputRegistry::new(temp_dir.path())
+            .take_answer(&request)
+            .unwrap()
+            .unwrap();
+        assert_eq!(answer.submitter.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn test_answer_unknown_input_fails() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let result = answer_input(
+            Some(temp_dir.path().to_path_buf()),
+            "missing",
+            None,
+            None,
+            false,
+        );
+        assert!(result.is_err());
+    }
+}
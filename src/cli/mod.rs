@@ -6,12 +6,16 @@
 //! - `doc`: Generate documentation from pipeline comments
 //! - `export`: Convert pipelines to CI/CD formats
 //! - `completions`: Generate shell completions
+//! - `input`: List and answer pending pipeline inputs
+//! - `gc`: Apply build retention to the build history
 //! - `run`: Execute pipelines via rust-script
 
 pub mod check;
 pub mod completions;
 pub mod doc;
 pub mod export;
+pub mod gc;
+pub mod input;
 pub mod lint;
 
 use anyhow::{Context, Result};
@@ -89,6 +93,65 @@ enum Command {
         #[arg(short, long)]
         output: Option<PathBuf>,
     },
+
+    /// List and answer pending pipeline inputs
+    Input {
+        #[command(subcommand)]
+        action: InputAction,
+        /// Pending-input registry directory
+        #[arg(long, global = true)]
+        dir: Option<PathBuf>,
+    },
+
+    /// Remove old builds and artifacts from the build history
+    Gc {
+        /// Job to collect (all jobs if not specified)
+        job: Option<String>,
+        /// Build history directory
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        /// Builds to keep, overriding the stored policy
+        #[arg(long)]
+        num_to_keep: Option<usize>,
+        /// Days to keep builds, overriding the stored policy
+        #[arg(long)]
+        days_to_keep: Option<usize>,
+        /// Builds to keep artifacts for, overriding the stored policy
+        #[arg(long)]
+        artifact_num_to_keep: Option<usize>,
+        /// Days to keep artifacts, overriding the stored policy
+        #[arg(long)]
+        artifact_days_to_keep: Option<usize>,
+        /// Mark a build of the job to be kept forever
+        #[arg(long, requires = "job", conflicts_with = "unpin")]
+        pin: Option<u64>,
+        /// Release a build of the job marked to be kept forever
+        #[arg(long, requires = "job")]
+        unpin: Option<u64>,
+        /// Report what would be removed without removing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum InputAction {
+    /// List inputs waiting for an answer
+    List,
+    /// Answer a pending input
+    Answer {
+        /// Input identifier
+        id: String,
+        /// Value to submit (the input default is used otherwise)
+        #[arg(long)]
+        value: Option<String>,
+        /// Submitter name (defaults to $USER)
+        #[arg(long)]
+        submitter: Option<String>,
+        /// Abort instead of proceeding
+        #[arg(long)]
+        abort: bool,
+    },
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
@@ -233,6 +296,46 @@ pub fn run() -> Result<()> {
                 println!("{}", completions);
             }
         }
+        Command::Input { action, dir } => match action {
+            InputAction::List => {
+                println!("{}", input::list_inputs(dir)?);
+            }
+            InputAction::Answer {
+                id,
+                value,
+                submitter,
+                abort,
+            } => {
+                input::answer_input(dir, &id, value, submitter, abort)?;
+            }
+        },
+        Command::Gc {
+            job,
+            dir,
+            num_to_keep,
+            days_to_keep,
+            artifact_num_to_keep,
+            artifact_days_to_keep,
+            pin,
+            unpin,
+            dry_run,
+        } => {
+            if let (Some(job), Some(number)) = (job.as_deref(), pin.or(unpin)) {
+                println!("{}", gc::pin_build(dir, job, number, pin.is_some())?);
+                return Ok(());
+            }
+
+            let limits = gc::Limits {
+                num_to_keep,
+                days_to_keep,
+                artifact_num_to_keep,
+                artifact_days_to_keep,
+            };
+            println!(
+                "{}",
+                gc::collect_garbage(dir, job.as_deref(), limits, dry_run)?
+            );
+        }
     }
 
     Ok(())
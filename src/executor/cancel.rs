@@ -0,0 +1,292 @@
+//! Build cancellation
+//!
+//! A `CancellationHandle` is shared between a running build and whoever may
+//! stop it. Cancelling records a reason, stops the executor from starting
+//! further stages and steps, and kills the process groups of running steps.
+//! The build then runs its `always`, `aborted` and `cleanup` post-conditions
+//! and finishes as `StageResult::Aborted`.
+//!
+//! Child handles stop part of a build, such as the sibling branches of a
+//! failed fail-fast branch. They are cancelled along with their parent, but
+//! cancelling a child leaves the rest of the build running.
+
+use super::process::{DEFAULT_GRACE_PERIOD, KillReason, ProcessScope};
+use crate::pipeline::{PipelineError, StageResult};
+use parking_lot::{Condvar, Mutex};
+use std::sync::{Arc, Weak};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// State shared by every clone of a handle
+#[derive(Debug)]
+struct CancelState {
+    /// Why the build was cancelled, once it has been
+    reason: Mutex<Option<String>>,
+
+    /// Wakes threads waiting in `wait_timeout`
+    cancelled: Condvar,
+
+    /// Process groups of the build's running steps
+    processes: Arc<ProcessScope>,
+
+    /// Time between SIGTERM and SIGKILL for the build's processes
+    grace: Duration,
+
+    /// Handle whose cancellation also cancels this one
+    parent: Option<CancellationHandle>,
+
+    /// Child handles woken when this one is cancelled
+    children: Mutex<Vec<Weak<CancelState>>>,
+}
+
+impl CancelState {
+    /// Wakes the threads waiting on this state and on its children
+    fn notify(&self) {
+        // Taking the lock orders the wake-up after a waiter's last check
+        drop(self.reason.lock());
+        self.cancelled.notify_all();
+        for child in self.children.lock().iter().filter_map(Weak::upgrade) {
+            child.notify();
+        }
+    }
+}
+
+/// Handle used to cancel a running build
+///
+/// Clones share the same state, so any clone can cancel the build.
+#[derive(Debug, Clone)]
+pub struct CancellationHandle {
+    state: Arc<CancelState>,
+}
+
+impl CancellationHandle {
+    /// Creates a handle that kills processes after `DEFAULT_GRACE_PERIOD`
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_grace_period(DEFAULT_GRACE_PERIOD)
+    }
+
+    /// Creates a handle whose processes get `grace` to exit before SIGKILL
+    #[must_use]
+    pub fn with_grace_period(grace: Duration) -> Self {
+        Self {
+            state: Arc::new(CancelState {
+                reason: Mutex::new(None),
+                cancelled: Condvar::new(),
+                processes: Arc::new(ProcessScope::new()),
+                grace,
+                parent: None,
+                children: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Creates a handle that is also cancelled when this one is
+    ///
+    /// The child gets a child process scope, so cancelling it only kills
+    /// the processes started under it.
+    #[must_use]
+    pub fn child(&self) -> Self {
+        let child = Self {
+            state: Arc::new(CancelState {
+                reason: Mutex::new(None),
+                cancelled: Condvar::new(),
+                processes: self.state.processes.child(),
+                grace: self.state.grace,
+                parent: Some(self.clone()),
+                children: Mutex::new(Vec::new()),
+            }),
+        };
+
+        let mut children = self.state.children.lock();
+        children.retain(|child| child.strong_count() > 0);
+        children.push(Arc::downgrade(&child.state));
+        child
+    }
+
+    /// Cancels the build
+    ///
+    /// Running steps are killed in the background, so this returns
+    /// immediately. Returns false if the build was already cancelled, in
+    /// which case the first reason is kept.
+    pub fn cancel(&self, reason: impl Into<String>) -> bool {
+        let reason = reason.into();
+        {
+            let mut current = self.state.reason.lock();
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(reason.clone());
+        }
+        self.state.notify();
+        tracing::warn!(reason = %reason, "Build cancellation requested");
+
+        let state = Arc::clone(&self.state);
+        std::thread::spawn(move || {
+            state
+                .processes
+                .kill(KillReason::Aborted(reason), state.grace);
+        });
+        true
+    }
+
+    /// Returns true once this handle or one of its parents is cancelled
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.state.reason.lock().is_some() || self.parent_cancelled()
+    }
+
+    /// Returns why the build was cancelled
+    #[must_use]
+    pub fn reason(&self) -> Option<String> {
+        let reason = self.state.reason.lock().clone();
+        reason.or_else(|| self.state.parent.as_ref().and_then(Self::reason))
+    }
+
+    /// Returns true once a parent of this handle is cancelled
+    fn parent_cancelled(&self) -> bool {
+        self.state
+            .parent
+            .as_ref()
+            .is_some_and(CancellationHandle::is_cancelled)
+    }
+
+    /// Sleeps for `timeout` unless the build is cancelled first
+    ///
+    /// Returns true if the build was cancelled.
+    #[must_use]
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let mut reason = self.state.reason.lock();
+        if reason.is_none() && !self.parent_cancelled() {
+            self.state.cancelled.wait_for(&mut reason, timeout);
+        }
+        drop(reason);
+        self.is_cancelled()
+    }
+
+    /// Returns the process scope killed on cancellation
+    #[must_use]
+    pub fn processes(&self) -> Arc<ProcessScope> {
+        Arc::clone(&self.state.processes)
+    }
+}
+
+impl Default for CancellationHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A build running on its own thread
+#[derive(Debug)]
+pub struct BuildRun {
+    /// Handle that cancels the build
+    cancellation: CancellationHandle,
+
+    /// Thread executing the build
+    thread: JoinHandle<Result<StageResult, PipelineError>>,
+}
+
+impl BuildRun {
+    /// Creates a run from its cancellation handle and thread
+    pub(crate) fn new(
+        cancellation: CancellationHandle,
+        thread: JoinHandle<Result<StageResult, PipelineError>>,
+    ) -> Self {
+        Self {
+            cancellation,
+            thread,
+        }
+    }
+
+    /// Returns the handle that cancels this build
+    #[must_use]
+    pub fn cancellation(&self) -> &CancellationHandle {
+        &self.cancellation
+    }
+
+    /// Cancels the build, see `CancellationHandle::cancel`
+    pub fn cancel(&self, reason: impl Into<String>) -> bool {
+        self.cancellation.cancel(reason)
+    }
+
+    /// Returns true once the build has finished
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Waits for the build to finish and returns its result
+    ///
+    /// # Errors
+    ///
+    /// Returns the error that failed the build.
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic if the build thread panicked.
+    pub fn wait(self) -> Result<StageResult, PipelineError> {
+        self.thread
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_cancel_keeps_first_reason() {
+        let handle = CancellationHandle::new();
+        assert!(!handle.is_cancelled());
+
+        assert!(handle.clone().cancel("first"));
+        assert!(!handle.cancel("second"));
+
+        assert!(handle.is_cancelled());
+        assert_eq!(handle.reason().as_deref(), Some("first"));
+    }
+
+    #[test]
+    fn test_wait_timeout_wakes_on_cancel() {
+        let handle = CancellationHandle::new();
+        assert!(!handle.wait_timeout(Duration::from_millis(10)));
+
+        let canceller = handle.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            canceller.cancel("stop");
+        });
+
+        let start = Instant::now();
+        assert!(handle.wait_timeout(Duration::from_secs(10)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_child_is_cancelled_with_parent() {
+        let parent = CancellationHandle::new();
+        let child = parent.child();
+
+        assert!(child.cancel("branch failed"));
+        assert!(child.is_cancelled());
+        assert!(!parent.is_cancelled());
+
+        let other = parent.child();
+        let canceller = parent.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            canceller.cancel("stop");
+        });
+
+        let start = Instant::now();
+        assert!(other.wait_timeout(Duration::from_secs(10)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(other.reason().as_deref(), Some("stop"));
+        assert_eq!(child.reason().as_deref(), Some("branch failed"));
+        thread.join().unwrap();
+    }
+}
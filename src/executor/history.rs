@@ -0,0 +1,615 @@
+//! Persistent build history
+//!
+//! Every job gets a directory in the history home holding a
+//! `nextBuildNumber` counter and one JSON record per build:
+//!
+//! ```text
+//! <home>/<job>/nextBuildNumber
+//! <home>/<job>/builds/<number>.json
+//! ```
+//!
+//! Logs and archived artifacts of a build live in `builds/<number>/`, and
+//! `discarder.json` holds the job's retention policy once one is set.
+//!
+//! Build numbers are claimed by creating the record file exclusively, so
+//! concurrent builds of the same job never share a number. A claimed build
+//! has an empty record until it finishes and is ignored when looking up the
+//! previous result.
+
+use crate::pipeline::{BuildDiscarder, PipelineError, StageResult};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Environment variable overriding the history home
+pub const HISTORY_DIR_VAR: &str = "RUSTLINE_HISTORY_DIR";
+
+/// Name of the per-job build counter file
+const NEXT_BUILD_NUMBER_FILE: &str = "nextBuildNumber";
+
+/// Name of the per-job retention policy file
+const DISCARDER_FILE: &str = "discarder.json";
+
+/// Result of a single stage within a recorded build
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageRecord {
+    /// Stage name
+    pub name: String,
+
+    /// Stage result
+    pub result: StageResult,
+
+    /// Stage duration in milliseconds
+    pub duration_ms: u64,
+
+    /// Per-branch results of a parallel or matrix stage, in branch order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub branches: Vec<BranchRecord>,
+}
+
+impl StageRecord {
+    /// Returns the result of a branch, if it ran in this stage
+    #[must_use]
+    pub fn branch_result(&self, branch: &str) -> Option<StageResult> {
+        self.branches
+            .iter()
+            .find(|record| record.name == branch)
+            .map(|record| record.result)
+    }
+}
+
+/// Result of a single branch of a parallel or matrix stage
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BranchRecord {
+    /// Branch name, or the cell name given by `MatrixCell::name`
+    pub name: String,
+
+    /// Branch result
+    pub result: StageResult,
+
+    /// Branch duration in milliseconds
+    pub duration_ms: u64,
+}
+
+impl BranchRecord {
+    /// Creates the record of a finished branch
+    #[must_use]
+    pub fn new(name: impl Into<String>, result: StageResult, duration: Duration) -> Self {
+        Self {
+            name: name.into(),
+            result,
+            duration_ms: duration_ms(duration),
+        }
+    }
+}
+
+/// A finished build as stored in the history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildRecord {
+    /// Job the build belongs to
+    pub job: String,
+
+    /// Build number, unique and increasing per job
+    pub number: u64,
+
+    /// Final build result
+    pub result: StageResult,
+
+    /// Per-stage results, in execution order
+    #[serde(default)]
+    pub stages: Vec<StageRecord>,
+
+    /// Start time in seconds since the Unix epoch
+    pub started_at: u64,
+
+    /// Build duration in milliseconds
+    pub duration_ms: u64,
+
+    /// Parameters the build ran with
+    #[serde(default)]
+    pub parameters: HashMap<String, String>,
+
+    /// Whether retention must never discard this build
+    #[serde(default)]
+    pub keep_forever: bool,
+}
+
+impl BuildRecord {
+    /// Creates a record for a build that started now
+    #[must_use]
+    pub fn new(job: impl Into<String>, number: u64) -> Self {
+        Self {
+            job: job.into(),
+            number,
+            result: StageResult::Success,
+            stages: Vec::new(),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            duration_ms: 0,
+            parameters: HashMap::new(),
+            keep_forever: false,
+        }
+    }
+
+    /// Records the result of a stage and returns its record
+    pub fn add_stage(
+        &mut self,
+        name: impl Into<String>,
+        result: StageResult,
+        duration: Duration,
+    ) -> &mut StageRecord {
+        self.stages.push(StageRecord {
+            name: name.into(),
+            result,
+            duration_ms: duration_ms(duration),
+            branches: Vec::new(),
+        });
+        let index = self.stages.len() - 1;
+        &mut self.stages[index]
+    }
+
+    /// Returns the result of a stage, if it ran in this build
+    #[must_use]
+    pub fn stage_result(&self, name: &str) -> Option<StageResult> {
+        self.stages
+            .iter()
+            .find(|stage| stage.name == name)
+            .map(|stage| stage.result)
+    }
+
+    /// Returns the result of a branch, if its stage ran in this build
+    #[must_use]
+    pub fn branch_result(&self, stage: &str, branch: &str) -> Option<StageResult> {
+        self.stages
+            .iter()
+            .find(|record| record.name == stage)
+            .and_then(|record| record.branch_result(branch))
+    }
+
+    /// Sets the final result and duration
+    pub fn finish(&mut self, result: StageResult, duration: Duration) {
+        self.result = result;
+        self.duration_ms = duration_ms(duration);
+    }
+}
+
+/// On-disk store of build records, one directory per job
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildHistory {
+    /// History home directory
+    dir: PathBuf,
+}
+
+impl BuildHistory {
+    /// Creates a history store rooted at `dir`
+    #[must_use]
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Creates the history store from `RUSTLINE_HISTORY_DIR`, falling back
+    /// to `builds/` under the configured cache home
+    #[must_use]
+    pub fn from_env() -> Self {
+        let dir = std::env::var_os(HISTORY_DIR_VAR).map_or_else(
+            || {
+                crate::infrastructure::Config::default()
+                    .cache_home()
+                    .join("builds")
+            },
+            PathBuf::from,
+        );
+        Self::new(dir)
+    }
+
+    /// Returns the history home directory
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the directory holding a job's builds
+    #[must_use]
+    pub fn job_dir(&self, job: &str) -> PathBuf {
+        self.dir.join(job)
+    }
+
+    /// Returns the directory holding a build's log and artifacts
+    #[must_use]
+    pub fn build_dir(&self, job: &str, number: u64) -> PathBuf {
+        self.builds_dir(job).join(number.to_string())
+    }
+
+    /// Returns the directory holding a build's archived artifacts
+    #[must_use]
+    pub fn artifacts_dir(&self, job: &str, number: u64) -> PathBuf {
+        self.build_dir(job, number).join("archive")
+    }
+
+    /// Returns the path of a build's log
+    #[must_use]
+    pub fn log_path(&self, job: &str, number: u64) -> PathBuf {
+        self.build_dir(job, number).join("log")
+    }
+
+    /// Claims the next build number for a job
+    ///
+    /// Numbers keep increasing even after old builds are deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if the job directory cannot be written.
+    pub fn next_build_number(&self, job: &str) -> Result<u64, PipelineError> {
+        validate_job(job)?;
+        let builds = self.builds_dir(job);
+        fs::create_dir_all(&builds)?;
+
+        let counter = self.job_dir(job).join(NEXT_BUILD_NUMBER_FILE);
+        let stored = fs::read_to_string(&counter)
+            .ok()
+            .and_then(|n| n.trim().parse::<u64>().ok())
+            .unwrap_or(1);
+        let mut number = stored.max(self.latest_number(job)?.map_or(1, |n| n + 1));
+
+        loop {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(record_path(&builds, number))
+            {
+                Ok(_) => break,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => number += 1,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        write_atomic(&counter, (number + 1).to_string().as_bytes())?;
+        Ok(number)
+    }
+
+    /// Stores a finished build
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if the record cannot be written.
+    pub fn record(&self, build: &BuildRecord) -> Result<(), PipelineError> {
+        validate_job(&build.job)?;
+        let builds = self.builds_dir(&build.job);
+        fs::create_dir_all(&builds)?;
+
+        let json = serde_json::to_vec_pretty(build)
+            .map_err(|e| PipelineError::Io(format!("Failed to serialize build: {e}")))?;
+        write_atomic(&record_path(&builds, build.number), &json)
+    }
+
+    /// Loads a build, if it exists and has finished
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if the record exists but cannot be read.
+    pub fn get(&self, job: &str, number: u64) -> Result<Option<BuildRecord>, PipelineError> {
+        validate_job(job)?;
+        read_record(&record_path(&self.builds_dir(job), number))
+    }
+
+    /// Lists a job's finished builds, newest first
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if the job directory cannot be read.
+    pub fn builds(&self, job: &str) -> Result<Vec<BuildRecord>, PipelineError> {
+        let mut builds = Vec::new();
+        for number in self.numbers(job)?.into_iter().rev() {
+            if let Some(build) = self.get(job, number)? {
+                builds.push(build);
+            }
+        }
+        Ok(builds)
+    }
+
+    /// Returns the most recent finished build of a job
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if the job directory cannot be read.
+    pub fn last_build(&self, job: &str) -> Result<Option<BuildRecord>, PipelineError> {
+        for number in self.numbers(job)?.into_iter().rev() {
+            if let Some(build) = self.get(job, number)? {
+                return Ok(Some(build));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Marks a build to be kept forever, or releases it
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if the build does not exist or cannot be
+    /// rewritten.
+    pub fn set_keep_forever(
+        &self,
+        job: &str,
+        number: u64,
+        keep: bool,
+    ) -> Result<(), PipelineError> {
+        let mut build = self
+            .get(job, number)?
+            .ok_or_else(|| PipelineError::Io(format!("Build {job} #{number} not found")))?;
+        build.keep_forever = keep;
+        self.record(&build)
+    }
+
+    /// Deletes a build together with its log and artifacts
+    ///
+    /// Returns the number of bytes freed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if the files cannot be removed.
+    pub fn delete(&self, job: &str, number: u64) -> Result<u64, PipelineError> {
+        validate_job(job)?;
+        let record = record_path(&self.builds_dir(job), number);
+        let freed = disk_usage(&record)? + disk_usage(&self.build_dir(job, number))?;
+
+        remove_all(&self.build_dir(job, number))?;
+        remove_all(&record)?;
+        Ok(freed)
+    }
+
+    /// Deletes a build's archived artifacts, keeping the build itself
+    ///
+    /// Returns the number of bytes freed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if the artifacts cannot be removed.
+    pub fn delete_artifacts(&self, job: &str, number: u64) -> Result<u64, PipelineError> {
+        validate_job(job)?;
+        let artifacts = self.artifacts_dir(job, number);
+        let freed = disk_usage(&artifacts)?;
+        remove_all(&artifacts)?;
+        Ok(freed)
+    }
+
+    /// Stores the retention policy of a job
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if the policy cannot be written.
+    pub fn set_discarder(
+        &self,
+        job: &str,
+        discarder: &BuildDiscarder,
+    ) -> Result<(), PipelineError> {
+        validate_job(job)?;
+        let job_dir = self.job_dir(job);
+        fs::create_dir_all(&job_dir)?;
+
+        let json = serde_json::to_vec_pretty(discarder)
+            .map_err(|e| PipelineError::Io(format!("Failed to serialize discarder: {e}")))?;
+        write_atomic(&job_dir.join(DISCARDER_FILE), &json)
+    }
+
+    /// Returns the retention policy of a job, if one was stored
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if the policy exists but cannot be read.
+    pub fn discarder(&self, job: &str) -> Result<Option<BuildDiscarder>, PipelineError> {
+        validate_job(job)?;
+        let path = self.job_dir(job).join(DISCARDER_FILE);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| PipelineError::Io(format!("Corrupt discarder {}: {e}", path.display())))
+    }
+
+    /// Lists the names of all jobs with a history
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if the history home cannot be read.
+    pub fn jobs(&self) -> Result<Vec<String>, PipelineError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut jobs = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                jobs.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        jobs.sort();
+        Ok(jobs)
+    }
+
+    /// Returns the directory holding a job's build records
+    fn builds_dir(&self, job: &str) -> PathBuf {
+        self.job_dir(job).join("builds")
+    }
+
+    /// Returns the highest claimed build number of a job
+    fn latest_number(&self, job: &str) -> Result<Option<u64>, PipelineError> {
+        Ok(self.numbers(job)?.last().copied())
+    }
+
+    /// Returns every claimed build number of a job, in ascending order
+    fn numbers(&self, job: &str) -> Result<Vec<u64>, PipelineError> {
+        validate_job(job)?;
+        let entries = match fs::read_dir(self.builds_dir(job)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut numbers = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            if let Some(number) = name
+                .to_str()
+                .and_then(|n| n.strip_suffix(".json"))
+                .and_then(|n| n.parse::<u64>().ok())
+            {
+                numbers.push(number);
+            }
+        }
+        numbers.sort_unstable();
+        Ok(numbers)
+    }
+}
+
+/// Rejects job names that would escape the history home
+fn validate_job(job: &str) -> Result<(), PipelineError> {
+    if job.is_empty() || job.contains(['/', '\\']) || job == "." || job == ".." {
+        return Err(PipelineError::Io(format!("Invalid job name: '{job}'")));
+    }
+    Ok(())
+}
+
+/// Returns the record path of a build
+fn record_path(builds: &Path, number: u64) -> PathBuf {
+    builds.join(format!("{number}.json"))
+}
+
+/// Reads a build record, treating empty (unfinished) records as absent
+fn read_record(path: &Path) -> Result<Option<BuildRecord>, PipelineError> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if content.trim().is_empty() {
+        return Ok(None);
+    }
+
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|e| PipelineError::Io(format!("Corrupt build record {}: {e}", path.display())))
+}
+
+/// Returns the size in bytes of a file or directory tree, 0 if missing
+fn disk_usage(path: &Path) -> std::io::Result<u64> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut total = 0;
+    for entry in fs::read_dir(path)? {
+        total += disk_usage(&entry?.path())?;
+    }
+    Ok(total)
+}
+
+/// Removes a file or directory tree, ignoring it if missing
+fn remove_all(path: &Path) -> std::io::Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+/// Writes a file through a temporary sibling so readers never see it partial
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), PipelineError> {
+    let partial = path.with_extension(format!("{}.partial", uuid::Uuid::new_v4()));
+    fs::write(&partial, contents)?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// Converts a duration to whole milliseconds, saturating
+fn duration_ms(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_build_numbers_increase_per_job() {
+        let temp_dir = TempDir::new().unwrap();
+        let history = BuildHistory::new(temp_dir.path());
+
+        assert_eq!(history.next_build_number("app").unwrap(), 1);
+        assert_eq!(history.next_build_number("app").unwrap(), 2);
+        assert_eq!(history.next_build_number("lib").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_numbers_survive_deleted_builds() {
+        let temp_dir = TempDir::new().unwrap();
+        let history = BuildHistory::new(temp_dir.path());
+
+        let first = history.next_build_number("app").unwrap();
+        let second = history.next_build_number("app").unwrap();
+        fs::remove_file(record_path(&history.builds_dir("app"), first)).unwrap();
+        fs::remove_file(record_path(&history.builds_dir("app"), second)).unwrap();
+
+        assert_eq!(history.next_build_number("app").unwrap(), 3);
+    }
+
+    #[test]
+    fn test_record_roundtrip_and_last_build() {
+        let temp_dir = TempDir::new().unwrap();
+        let history = BuildHistory::new(temp_dir.path());
+
+        let mut build = BuildRecord::new("app", history.next_build_number("app").unwrap());
+        build.add_stage("Build", StageResult::Success, Duration::from_millis(5));
+        build.add_stage("Test", StageResult::Failure, Duration::from_millis(7));
+        build
+            .parameters
+            .insert("TARGET".to_string(), "prod".to_string());
+        build.finish(StageResult::Failure, Duration::from_millis(12));
+        history.record(&build).unwrap();
+
+        // A build in progress has no record yet and is not the last build
+        history.next_build_number("app").unwrap();
+
+        let last = history.last_build("app").unwrap().unwrap();
+        assert_eq!(last, build);
+        assert_eq!(last.stage_result("Test"), Some(StageResult::Failure));
+        assert_eq!(history.builds("app").unwrap().len(), 1);
+        assert_eq!(history.jobs().unwrap(), vec!["app".to_string()]);
+    }
+
+    #[test]
+    fn test_unknown_job_has_no_history() {
+        let temp_dir = TempDir::new().unwrap();
+        let history = BuildHistory::new(temp_dir.path());
+
+        assert!(history.last_build("missing").unwrap().is_none());
+        assert!(history.jobs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_job_name() {
+        let temp_dir = TempDir::new().unwrap();
+        let history = BuildHistory::new(temp_dir.path());
+
+        assert!(history.next_build_number("../escape").is_err());
+    }
+}
@@ -0,0 +1,755 @@
+//! Approval gate for `input` steps
+//!
+//! An input is answered, in order of precedence, by:
+//!
+//! 1. an environment variable `RUSTLINE_ANSWER_<ID>`
+//! 2. an entry in a JSON answers file (`RUSTLINE_INPUT_ANSWERS` or
+//!    [`InputConfig::answers_file`])
+//! 3. a prompt, when stdin is a terminal
+//! 4. the pending-input registry, a directory another process answers through
+//!    (for example `rustline input answer <id>`)
+//!
+//! Answers are checked against the step's submitter allow-list. A registry
+//! wait that times out falls back to the step default, or aborts without one.
+//! Registry entries are keyed by the build waiting on them as well as the
+//! input id, so builds waiting on the same input are answered separately.
+
+use crate::pipeline::PipelineError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Prefix of environment variables that pre-answer an input
+pub const ANSWER_VAR_PREFIX: &str = "RUSTLINE_ANSWER_";
+
+/// Environment variable pointing at a JSON answers file
+pub const ANSWERS_FILE_VAR: &str = "RUSTLINE_INPUT_ANSWERS";
+
+/// Environment variable overriding the pending-input registry directory
+pub const INPUT_DIR_VAR: &str = "RUSTLINE_INPUT_DIR";
+
+/// Environment variable naming the submitter of non-interactive answers
+pub const SUBMITTER_VAR: &str = "RUSTLINE_SUBMITTER";
+
+/// A pending request for input
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputRequest {
+    /// Input identifier
+    pub id: String,
+
+    /// Message shown to the submitter
+    pub message: String,
+
+    /// Value used when no explicit value is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+
+    /// Users allowed to answer; empty means anyone
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub submitters: Vec<String>,
+
+    /// Pipeline waiting for the answer
+    #[serde(default)]
+    pub pipeline_id: String,
+}
+
+impl InputRequest {
+    /// Creates a request from the fields of an `input` step
+    ///
+    /// The id defaults to a slug of the message and the submitter list is
+    /// split on commas.
+    #[must_use]
+    pub fn new(
+        message: &str,
+        default: Option<&String>,
+        id: Option<&String>,
+        submitter: Option<&String>,
+    ) -> Self {
+        Self {
+            id: id.cloned().unwrap_or_else(|| slug(message)),
+            message: message.to_string(),
+            default: default.cloned(),
+            submitters: submitter
+                .map(|s| {
+                    s.split(',')
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            pipeline_id: String::new(),
+        }
+    }
+
+    /// Registry key of this request, `<pipeline>.<id>`
+    #[must_use]
+    pub fn key(&self) -> String {
+        if self.pipeline_id.is_empty() {
+            slug(&self.id)
+        } else {
+            format!("{}.{}", slug(&self.pipeline_id), slug(&self.id))
+        }
+    }
+
+    /// Returns whether `submitter` may answer this request
+    #[must_use]
+    pub fn allows(&self, submitter: Option<&str>) -> bool {
+        self.submitters.is_empty()
+            || submitter.is_some_and(|s| self.submitters.iter().any(|allowed| allowed == s))
+    }
+
+    /// Environment variable name that carries this input's answer
+    #[must_use]
+    pub fn env_key(&self) -> String {
+        format!("INPUT_{}", env_suffix(&self.id))
+    }
+}
+
+/// An answer to an input request
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputAnswer {
+    /// Submitted value; the request default is used when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+
+    /// User who answered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submitter: Option<String>,
+
+    /// Whether the submitter aborted instead of proceeding
+    #[serde(default)]
+    pub abort: bool,
+}
+
+impl InputAnswer {
+    /// Creates an answer that proceeds with an optional value
+    #[must_use]
+    pub fn proceed(value: Option<String>) -> Self {
+        Self {
+            value,
+            ..Self::default()
+        }
+    }
+
+    /// Creates an answer that aborts the input
+    #[must_use]
+    pub fn abort() -> Self {
+        Self {
+            abort: true,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the submitter of this answer
+    #[must_use]
+    pub fn with_submitter(mut self, submitter: impl Into<String>) -> Self {
+        self.submitter = Some(submitter.into());
+        self
+    }
+}
+
+/// Entry of an answers file: either a bare value or a full answer
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum AnswerEntry {
+    Value(String),
+    Answer(InputAnswer),
+}
+
+/// Directory-backed registry of inputs waiting for an answer
+///
+/// Each pending input is a `<key>.request.json` file; answering writes a
+/// matching `<key>.answer.json` that the waiting build picks up.
+#[derive(Debug, Clone)]
+pub struct InputRegistry {
+    /// Registry directory
+    dir: PathBuf,
+}
+
+impl InputRegistry {
+    /// Creates a registry rooted at `dir`
+    #[must_use]
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Creates the registry named by `RUSTLINE_INPUT_DIR`, or the `inputs`
+    /// directory of the default cache home
+    #[must_use]
+    pub fn from_env() -> Self {
+        let dir = std::env::var_os(INPUT_DIR_VAR).map_or_else(
+            || {
+                crate::infrastructure::Config::default()
+                    .cache_home()
+                    .join("inputs")
+            },
+            PathBuf::from,
+        );
+        Self::new(dir)
+    }
+
+    /// Gets the registry directory
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Registers a pending request, discarding any stale answer to it
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if the request cannot be written.
+    pub fn register(&self, request: &InputRequest) -> Result<(), PipelineError> {
+        fs::create_dir_all(&self.dir)?;
+        let key = request.key();
+        remove_if_exists(&self.answer_path(&key))?;
+        write_json(&self.request_path(&key), request)
+    }
+
+    /// Lists pending requests
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if the registry cannot be read.
+    pub fn pending(&self) -> Result<Vec<InputRequest>, PipelineError> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut requests = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.to_string_lossy().ends_with(".request.json") {
+                requests.push(read_json(&path)?);
+            }
+        }
+        requests.sort_by_key(InputRequest::key);
+        Ok(requests)
+    }
+
+    /// Answers the pending request with key or id `target`
+    ///
+    /// An id only names a request while a single build waits on it.
+    /// Returns the key of the answered request.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if no request or several match, or the
+    /// answer cannot be written.
+    pub fn answer(&self, target: &str, answer: &InputAnswer) -> Result<String, PipelineError> {
+        let mut keys: Vec<String> = self
+            .pending()?
+            .iter()
+            .filter(|request| request.id == target || request.key() == target)
+            .map(InputRequest::key)
+            .collect();
+
+        match keys.len() {
+            0 => Err(PipelineError::Io(format!("No pending input '{target}'"))),
+            1 => {
+                let key = keys.remove(0);
+                write_json(&self.answer_path(&key), answer)?;
+                Ok(key)
+            }
+            _ => Err(PipelineError::Io(format!(
+                "Input '{target}' is pending in several builds, answer one of: {}",
+                keys.join(", ")
+            ))),
+        }
+    }
+
+    /// Takes the answer to `request`, if one has been written
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if the answer exists but cannot be read.
+    pub fn take_answer(
+        &self,
+        request: &InputRequest,
+    ) -> Result<Option<InputAnswer>, PipelineError> {
+        let path = self.answer_path(&request.key());
+        if !path.exists() {
+            return Ok(None);
+        }
+        let answer = read_json(&path)?;
+        remove_if_exists(&path)?;
+        Ok(Some(answer))
+    }
+
+    /// Removes a request and any answer to it
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if the files cannot be removed.
+    pub fn withdraw(&self, request: &InputRequest) -> Result<(), PipelineError> {
+        let key = request.key();
+        remove_if_exists(&self.request_path(&key))?;
+        remove_if_exists(&self.answer_path(&key))
+    }
+
+    fn request_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.request.json"))
+    }
+
+    fn answer_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.answer.json"))
+    }
+}
+
+/// How input steps obtain their answers
+#[derive(Debug, Clone)]
+pub struct InputConfig {
+    /// Answers file, overriding `RUSTLINE_INPUT_ANSWERS`
+    pub answers_file: Option<PathBuf>,
+
+    /// Whether to prompt; `None` prompts only when stdin is a terminal
+    pub interactive: Option<bool>,
+
+    /// Registry used when no other answer source applies
+    pub registry: InputRegistry,
+
+    /// How long to wait on the registry; `None` waits indefinitely
+    pub timeout: Option<Duration>,
+
+    /// Interval between registry polls
+    pub poll_interval: Duration,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            answers_file: None,
+            interactive: None,
+            registry: InputRegistry::from_env(),
+            timeout: None,
+            poll_interval: Duration::from_millis(200),
+        }
+    }
+}
+
+impl InputConfig {
+    /// Resolves the answer to an input request
+    ///
+    /// `env` is the pipeline environment, consulted for pre-supplied
+    /// answers. `timeout` overrides the configured registry timeout.
+    /// Returns the accepted answer with its value filled in from the default.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::InputAborted` if the input was aborted or timed
+    /// out without a default, `PipelineError::InputRejected` if a
+    /// pre-supplied or prompted answer comes from a user outside the
+    /// submitter list, or `PipelineError::Io` on registry failures.
+    pub fn resolve(
+        &self,
+        request: &InputRequest,
+        env: &HashMap<String, String>,
+        timeout: Option<Duration>,
+    ) -> Result<InputAnswer, PipelineError> {
+        let default_submitter = env.get(SUBMITTER_VAR).or_else(|| env.get("USER")).cloned();
+
+        let answer = if let Some(answer) = self.preset_answer(request, env)? {
+            answer
+        } else if self
+            .interactive
+            .unwrap_or_else(|| std::io::stdin().is_terminal())
+        {
+            prompt(request)?
+        } else {
+            return self.wait(request, timeout.or(self.timeout));
+        };
+
+        let answer = InputAnswer {
+            submitter: answer.submitter.or(default_submitter),
+            ..answer
+        };
+        accept(request, answer)
+    }
+
+    /// Looks up an answer from the environment or the answers file
+    fn preset_answer(
+        &self,
+        request: &InputRequest,
+        env: &HashMap<String, String>,
+    ) -> Result<Option<InputAnswer>, PipelineError> {
+        let var = format!("{ANSWER_VAR_PREFIX}{}", env_suffix(&request.id));
+        if let Some(value) = env.get(&var) {
+            return Ok(Some(InputAnswer::proceed(Some(value.clone()))));
+        }
+
+        let Some(path) = self
+            .answers_file
+            .clone()
+            .or_else(|| env.get(ANSWERS_FILE_VAR).map(PathBuf::from))
+        else {
+            return Ok(None);
+        };
+
+        let mut answers: HashMap<String, AnswerEntry> = read_json(&path)?;
+        Ok(answers.remove(&request.id).map(|entry| match entry {
+            AnswerEntry::Value(value) => InputAnswer::proceed(Some(value)),
+            AnswerEntry::Answer(answer) => answer,
+        }))
+    }
+
+    /// Waits on the registry until an allowed answer arrives or time runs out
+    fn wait(
+        &self,
+        request: &InputRequest,
+        timeout: Option<Duration>,
+    ) -> Result<InputAnswer, PipelineError> {
+        let registry = &self.registry;
+        registry.register(request)?;
+        tracing::info!(
+            input = %request.key(),
+            registry = %registry.dir().display(),
+            "Waiting for input: {}",
+            request.message
+        );
+
+        let start = Instant::now();
+        let result = loop {
+            match registry.take_answer(request) {
+                Ok(Some(answer)) if request.allows(answer.submitter.as_deref()) => {
+                    break accept(request, answer);
+                }
+                Ok(Some(answer)) => {
+                    tracing::warn!(
+                        input = %request.id,
+                        submitter = answer.submitter.as_deref().unwrap_or("<unknown>"),
+                        "Ignoring answer from user outside the submitter list"
+                    );
+                }
+                Ok(None) => {}
+                Err(e) => break Err(e),
+            }
+
+            if let Some(timeout) = timeout
+                && start.elapsed() >= timeout
+            {
+                break match request.default {
+                    Some(ref default) => Ok(InputAnswer::proceed(Some(default.clone()))),
+                    None => Err(PipelineError::InputAborted {
+                        id: request.id.clone(),
+                        reason: format!("no answer within {timeout:?}"),
+                    }),
+                };
+            }
+
+            std::thread::sleep(self.poll_interval);
+        };
+
+        registry.withdraw(request)?;
+        result
+    }
+}
+
+/// Checks an answer against the request and fills in the default value
+fn accept(request: &InputRequest, answer: InputAnswer) -> Result<InputAnswer, PipelineError> {
+    if !request.allows(answer.submitter.as_deref()) {
+        return Err(PipelineError::InputRejected {
+            id: request.id.clone(),
+            submitter: answer.submitter.unwrap_or_default(),
+        });
+    }
+    if answer.abort {
+        return Err(PipelineError::InputAborted {
+            id: request.id.clone(),
+            reason: format!(
+                "aborted by {}",
+                answer.submitter.as_deref().unwrap_or("<unknown>")
+            ),
+        });
+    }
+
+    Ok(InputAnswer {
+        value: answer.value.or_else(|| request.default.clone()),
+        ..answer
+    })
+}
+
+/// Prompts on the terminal; an empty line proceeds and `abort` aborts
+fn prompt(request: &InputRequest) -> Result<InputAnswer, PipelineError> {
+    let mut stderr = std::io::stderr();
+    match request.default {
+        Some(ref default) => write!(
+            stderr,
+            "{} [{default}] (Enter to proceed, 'abort' to abort): ",
+            request.message
+        )?,
+        None => write!(
+            stderr,
+            "{} (Enter to proceed, 'abort' to abort): ",
+            request.message
+        )?,
+    }
+    stderr.flush()?;
+
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line)?;
+    let line = line.trim();
+
+    Ok(match line {
+        "abort" => InputAnswer::abort(),
+        "" => InputAnswer::proceed(None),
+        value => InputAnswer::proceed(Some(value.to_string())),
+    })
+}
+
+/// Lowercase, dash-separated form of a message or id
+fn slug(value: &str) -> String {
+    value
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Uppercase, underscore-separated form of an id for variable names
+fn env_suffix(id: &str) -> String {
+    slug(id).replace('-', "_").to_uppercase()
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, PipelineError> {
+    let content = fs::read_to_string(path)?;
+    serde_json::from_str(&content)
+        .map_err(|e| PipelineError::Io(format!("Invalid JSON in {}: {e}", path.display())))
+}
+
+/// Writes JSON through a temp file so readers never see partial content
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), PipelineError> {
+    let content = serde_json::to_string_pretty(value)
+        .map_err(|e| PipelineError::Io(format!("Failed to serialize input: {e}")))?;
+    let partial = path.with_extension("partial");
+    fs::write(&partial, content)?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), PipelineError> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn config(dir: &Path) -> InputConfig {
+        InputConfig {
+            answers_file: None,
+            interactive: Some(false),
+            registry: InputRegistry::new(dir.join("inputs")),
+            timeout: Some(Duration::from_millis(200)),
+            poll_interval: Duration::from_millis(10),
+        }
+    }
+
+    fn request(submitter: Option<&str>, default: Option<&str>) -> InputRequest {
+        InputRequest::new(
+            "Deploy to production?",
+            default.map(String::from).as_ref(),
+            None,
+            submitter.map(String::from).as_ref(),
+        )
+    }
+
+    #[test]
+    fn test_request_id_and_env_key() {
+        let request = request(Some("alice, bob"), None);
+
+        assert_eq!(request.id, "deploy-to-production");
+        assert_eq!(request.env_key(), "INPUT_DEPLOY_TO_PRODUCTION");
+        assert_eq!(request.submitters, vec!["alice", "bob"]);
+        assert!(request.allows(Some("bob")));
+        assert!(!request.allows(Some("mallory")));
+        assert!(!request.allows(None));
+    }
+
+    #[test]
+    fn test_answer_from_env_var() {
+        let dir = TempDir::new().unwrap();
+        let env = HashMap::from([
+            (
+                "RUSTLINE_ANSWER_DEPLOY_TO_PRODUCTION".to_string(),
+                "v1.2".to_string(),
+            ),
+            ("RUSTLINE_SUBMITTER".to_string(), "alice".to_string()),
+        ]);
+
+        let answer = config(dir.path())
+            .resolve(&request(Some("alice"), None), &env, None)
+            .unwrap();
+
+        assert_eq!(answer.value.as_deref(), Some("v1.2"));
+        assert_eq!(answer.submitter.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn test_env_answer_from_disallowed_submitter_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let env = HashMap::from([
+            (
+                "RUSTLINE_ANSWER_DEPLOY_TO_PRODUCTION".to_string(),
+                "yes".to_string(),
+            ),
+            ("RUSTLINE_SUBMITTER".to_string(), "mallory".to_string()),
+        ]);
+
+        let result = config(dir.path()).resolve(&request(Some("alice"), None), &env, None);
+
+        assert_eq!(
+            result.unwrap_err(),
+            PipelineError::InputRejected {
+                id: "deploy-to-production".to_string(),
+                submitter: "mallory".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_answers_file() {
+        let dir = TempDir::new().unwrap();
+        let answers = dir.path().join("answers.json");
+        fs::write(
+            &answers,
+            r#"{"deploy-to-production": {"submitter": "alice", "abort": true}, "other": "x"}"#,
+        )
+        .unwrap();
+        let config = InputConfig {
+            answers_file: Some(answers),
+            ..config(dir.path())
+        };
+
+        let result = config.resolve(&request(None, None), &HashMap::new(), None);
+        assert!(matches!(result, Err(PipelineError::InputAborted { .. })));
+
+        let other = InputRequest::new("Other", None, Some(&"other".to_string()), None);
+        let answer = config.resolve(&other, &HashMap::new(), None).unwrap();
+        assert_eq!(answer.value.as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn test_registry_answer_from_another_thread() {
+        let dir = TempDir::new().unwrap();
+        let config = InputConfig {
+            timeout: Some(Duration::from_secs(10)),
+            ..config(dir.path())
+        };
+        let registry = config.registry.clone();
+
+        let answerer = std::thread::spawn(move || {
+            loop {
+                if !registry.pending().unwrap().is_empty() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            registry
+                .answer(
+                    "deploy-to-production",
+                    &InputAnswer::proceed(None).with_submitter("mallory"),
+                )
+                .unwrap();
+            // Wait until the rejected answer is consumed before answering again
+            while registry
+                .dir()
+                .join("deploy-to-production.answer.json")
+                .exists()
+            {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            registry
+                .answer(
+                    "deploy-to-production",
+                    &InputAnswer::proceed(Some("go".to_string())).with_submitter("alice"),
+                )
+                .unwrap();
+        });
+
+        let answer = config
+            .resolve(&request(Some("alice"), None), &HashMap::new(), None)
+            .unwrap();
+        answerer.join().unwrap();
+
+        assert_eq!(answer.value.as_deref(), Some("go"));
+        assert!(config.registry.pending().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_registry_keeps_builds_apart() {
+        let dir = TempDir::new().unwrap();
+        let registry = InputRegistry::new(dir.path());
+        let build = |pipeline_id: &str| InputRequest {
+            pipeline_id: pipeline_id.to_string(),
+            ..request(None, None)
+        };
+        let (first, second) = (build("1"), build("2"));
+        registry.register(&first).unwrap();
+        registry
+            .answer("1.deploy-to-production", &InputAnswer::abort())
+            .unwrap();
+
+        // Registering the second build keeps the answer to the first
+        registry.register(&second).unwrap();
+        assert!(
+            registry
+                .answer("deploy-to-production", &InputAnswer::proceed(None))
+                .is_err()
+        );
+
+        assert!(registry.take_answer(&first).unwrap().unwrap().abort);
+        assert_eq!(registry.take_answer(&second).unwrap(), None);
+
+        registry.withdraw(&first).unwrap();
+        assert_eq!(
+            registry
+                .answer("deploy-to-production", &InputAnswer::proceed(None))
+                .unwrap(),
+            "2.deploy-to-production"
+        );
+        assert!(!registry.take_answer(&second).unwrap().unwrap().abort);
+    }
+
+    #[test]
+    fn test_registry_timeout_uses_default() {
+        let dir = TempDir::new().unwrap();
+
+        let answer = config(dir.path())
+            .resolve(&request(None, Some("staging")), &HashMap::new(), None)
+            .unwrap();
+
+        assert_eq!(answer.value.as_deref(), Some("staging"));
+    }
+
+    #[test]
+    fn test_registry_timeout_without_default_aborts() {
+        let dir = TempDir::new().unwrap();
+        let config = config(dir.path());
+
+        let result = config.resolve(
+            &request(None, None),
+            &HashMap::new(),
+            Some(Duration::from_millis(30)),
+        );
+
+        assert!(matches!(result, Err(PipelineError::InputAborted { .. })));
+        assert!(config.registry.pending().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_answer_without_pending_request_fails() {
+        let dir = TempDir::new().unwrap();
+        let registry = InputRegistry::new(dir.path());
+
+        assert!(registry.answer("missing", &InputAnswer::abort()).is_err());
+    }
+}
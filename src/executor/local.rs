@@ -1,10 +1,22 @@
-use super::shell::{ShellCommand, ShellConfig};
+use super::cancel::{BuildRun, CancellationHandle};
+use super::history::{BranchRecord, BuildHistory, BuildRecord};
+use super::input::{InputConfig, InputRequest};
+use super::process::{DEFAULT_GRACE_PERIOD, KillReason};
+use super::retention::apply_retention;
+use super::shell::{ShellCommand, ShellConfig, expand_variables};
+use super::stash::StashStore;
 use super::traits::{ExecutorCapabilities, HealthStatus, PipelineContext, PipelineExecutor};
-use crate::pipeline::{Pipeline, Stage, StageResult, Step, StepType, Validate};
+use super::when::evaluate_when;
+use crate::pipeline::{
+    BuildDiscarder, MatrixCell, MatrixConfig, Pipeline, PostCondition, SharedRegistry, Stage,
+    StageResult, Step, StepType, Validate,
+};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::process::Command;
 use std::sync::Arc;
-use std::time::Instant;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 /// Local executor that runs commands on host system
 #[derive(Debug, Clone)]
@@ -14,7 +26,7 @@ pub struct LocalExecutor {
 }
 
 /// Configuration for local executor
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct ExecutorConfig {
     /// Current working directory
     pub cwd: std::path::PathBuf,
@@ -22,8 +34,49 @@ pub struct ExecutorConfig {
     /// Environment variables
     pub env: std::collections::HashMap<String, String>,
 
+    /// Names of the environment variables whose values are masked in output
+    pub secrets: std::collections::BTreeSet<String>,
+
     /// Shell to use (default: sh)
     pub shell: String,
+
+    /// How input steps obtain their answers
+    pub input: InputConfig,
+
+    /// Fail `dir` blocks whose directory does not exist instead of creating it
+    pub require_existing_dirs: bool,
+
+    /// Custom steps available to `custom` steps
+    pub custom_steps: SharedRegistry,
+
+    /// Build history that numbers builds and records their results
+    /// (default: `BuildHistory::from_env()`, `None` records nothing)
+    pub history: Option<BuildHistory>,
+
+    /// Time between SIGTERM and SIGKILL when killing a step's processes
+    /// (default: `DEFAULT_GRACE_PERIOD`)
+    pub kill_grace_period: Option<Duration>,
+
+    /// Maximum number of branches of a stage running at once
+    /// (default: the number of available CPUs)
+    pub max_parallel: Option<usize>,
+}
+
+impl Default for ExecutorConfig {
+    fn default() -> Self {
+        Self {
+            cwd: PathBuf::new(),
+            env: HashMap::new(),
+            secrets: std::collections::BTreeSet::new(),
+            shell: String::new(),
+            input: InputConfig::default(),
+            require_existing_dirs: false,
+            custom_steps: SharedRegistry::default(),
+            history: Some(BuildHistory::from_env()),
+            kill_grace_period: None,
+            max_parallel: None,
+        }
+    }
 }
 
 impl LocalExecutor {
@@ -49,22 +102,115 @@ impl LocalExecutor {
         self
     }
 
+    /// Adds an environment variable whose value, such as a credential, is
+    /// masked in output
+    #[must_use]
+    pub fn with_secret_env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let key = key.into();
+        self.config.env.insert(key.clone(), value.into());
+        self.config.secrets.insert(key);
+        self
+    }
+
     /// Sets shell to use
     #[must_use]
     pub fn with_shell(mut self, shell: impl Into<String>) -> Self {
         self.config.shell = shell.into();
         self
     }
-}
 
-impl Default for LocalExecutor {
-    fn default() -> Self {
-        Self::new()
+    /// Sets how input steps obtain their answers
+    #[must_use]
+    pub fn with_input_config(mut self, input: InputConfig) -> Self {
+        self.config.input = input;
+        self
     }
-}
 
-impl PipelineExecutor for LocalExecutor {
-    fn execute(&self, pipeline: &Pipeline) -> Result<StageResult, crate::pipeline::PipelineError> {
+    /// Sets whether `dir` blocks require their directory to exist
+    ///
+    /// By default missing directories are created.
+    #[must_use]
+    pub fn with_require_existing_dirs(mut self, require: bool) -> Self {
+        self.config.require_existing_dirs = require;
+        self
+    }
+
+    /// Sets the registry used to resolve custom steps
+    #[must_use]
+    pub fn with_custom_steps(mut self, registry: SharedRegistry) -> Self {
+        self.config.custom_steps = registry;
+        self
+    }
+
+    /// Records builds in a history store
+    ///
+    /// Builds are numbered per job and post-conditions such as `changed`
+    /// compare against the previous recorded build.
+    #[must_use]
+    pub fn with_history(mut self, history: BuildHistory) -> Self {
+        self.config.history = Some(history);
+        self
+    }
+
+    /// Runs builds without recording them
+    ///
+    /// Builds are not numbered and post-conditions such as `changed` see no
+    /// previous build.
+    #[must_use]
+    pub fn without_history(mut self) -> Self {
+        self.config.history = None;
+        self
+    }
+
+    /// Sets how long killed steps get to exit before SIGKILL
+    #[must_use]
+    pub fn with_kill_grace_period(mut self, grace: Duration) -> Self {
+        self.config.kill_grace_period = Some(grace);
+        self
+    }
+
+    /// Limits how many branches of a parallel or matrix stage run at once
+    ///
+    /// Stages can set a lower limit with `Stage::with_max_parallel`.
+    #[must_use]
+    pub fn with_max_parallel(mut self, max_parallel: usize) -> Self {
+        self.config.max_parallel = Some(max_parallel);
+        self
+    }
+
+    /// Returns the grace period for killed steps
+    fn kill_grace(&self) -> Duration {
+        self.config
+            .kill_grace_period
+            .unwrap_or(DEFAULT_GRACE_PERIOD)
+    }
+
+    /// Starts executing a pipeline on a new thread
+    ///
+    /// The returned run cancels the build or waits for its result.
+    #[must_use]
+    pub fn start(&self, pipeline: Pipeline) -> BuildRun {
+        let cancellation = CancellationHandle::with_grace_period(self.kill_grace());
+        let executor = self.clone();
+        let handle = cancellation.clone();
+        let thread = std::thread::spawn(move || executor.execute_cancellable(&pipeline, &handle));
+        BuildRun::new(cancellation, thread)
+    }
+
+    /// Executes a pipeline that can be cancelled through `cancellation`
+    ///
+    /// A cancelled build stops before its next stage or step, kills the
+    /// processes of running steps, runs its `always`, `aborted` and
+    /// `cleanup` post-conditions and returns `StageResult::Aborted`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error that failed the build.
+    pub fn execute_cancellable(
+        &self,
+        pipeline: &Pipeline,
+        cancellation: &CancellationHandle,
+    ) -> Result<StageResult, crate::pipeline::PipelineError> {
         let pipeline_id = pipeline
             .name
             .clone()
@@ -75,51 +221,136 @@ impl PipelineExecutor for LocalExecutor {
             "Starting pipeline execution"
         );
 
-        let mut context = PipelineContext::new();
+        let mut context = self.new_context(cancellation);
+        let job_name = job_name(&pipeline_id);
+
+        // Parameters fall back to their defaults unless set in the environment
+        let parameters = parameter_values(pipeline, &context);
+        for (key, value) in &parameters {
+            context.set_env(key, value);
+        }
+
+        let (previous, mut build) = self.start_build(&job_name, parameters, &mut context);
+
+        // Stashes live in the workspace temp area for the duration of the build
+        let build_id = context
+            .get_env("BUILD_NUMBER")
+            .cloned()
+            .unwrap_or_else(|| context.pipeline_id.clone());
+        context.stashes = Arc::new(StashStore::new(&context.cwd, &job_name, &build_id));
 
         // Set environment variables from pipeline
         for (key, value) in &pipeline.environment.vars {
-            context.set_env(key, value);
+            if pipeline.environment.is_secret(key) {
+                context.set_secret_env(key, value);
+            } else {
+                context.set_env(key, value);
+            }
         }
 
+        let build_start = Instant::now();
+        let mut result = StageResult::Success;
+        let mut error = None;
+
         // Execute each stage
         for stage in &pipeline.stages {
             let stage_name = stage.name.clone();
+
+            // Stop scheduling stages once the build is cancelled
+            if cancellation.is_cancelled() {
+                result = StageResult::Aborted;
+                break;
+            }
+
+            // Skip stages whose when condition does not hold, per cell for matrices
+            if stage.matrix.is_none()
+                && let Some(ref when) = stage.when
+                && !evaluate_when(when, &context)
+            {
+                tracing::info!(stage = %stage_name, "Stage skipped due to when condition");
+                context.record_stage_result(&stage_name, StageResult::Skipped);
+                if let Some(ref mut build) = build {
+                    build.add_stage(&stage_name, StageResult::Skipped, Duration::ZERO);
+                }
+                continue;
+            }
+
             tracing::info!(stage = %stage_name, "Executing stage");
 
             let start = Instant::now();
 
-            // Execute stage
-            let result = self.execute_stage(stage, &context)?;
+            // Execute stage, a stage error counts as a failed stage
+            let stage_result = match self.execute_stage(stage, &mut context, previous.as_ref()) {
+                Ok(stage_result) => stage_result,
+                Err(e) => {
+                    tracing::error!(stage = %stage_name, error = %e, "Stage error");
+                    error = Some(e);
+                    StageResult::Failure
+                }
+            };
 
-            let duration = start.elapsed();
-            tracing::info!(
-                stage = %stage_name,
-                result = %result,
-                duration_ms = duration.as_millis(),
-                "Stage completed"
+            // Record result
+            Self::record_stage(
+                &mut context,
+                &mut build,
+                &stage_name,
+                stage_result,
+                start.elapsed(),
             );
 
-            // Record result
-            context.record_stage_result(&stage_name, result);
+            if stage_result.is_aborted() {
+                result = StageResult::Aborted;
+                break;
+            } else if stage_result.is_failure() || error.is_some() {
+                result = StageResult::Failure;
+            } else if stage_result.is_unstable() {
+                result = StageResult::Unstable;
+            }
 
             // If stage failed and no retry, stop pipeline
-            if result.is_failure() && pipeline.options.retry.is_none() {
+            if error.is_some() || (stage_result.is_failure() && pipeline.options.retry.is_none()) {
                 tracing::error!(stage = %stage_name, "Stage failed, stopping pipeline");
-                return Ok(result);
+                break;
             }
         }
 
-        // Execute post-conditions
-        for post in &pipeline.post {
-            if let Some(last_result) = context.stage_results.values().last().copied()
-                && post.should_execute(last_result, None)
-            {
-                self.execute_steps(post.steps(), &context)?;
-            }
+        if result.is_aborted() {
+            tracing::warn!(
+                pipeline_id = %pipeline_id,
+                reason = cancellation.reason().as_deref().unwrap_or_default(),
+                "Build aborted"
+            );
         }
 
-        Ok(StageResult::Success)
+        // Execute post-conditions against the previous build's result
+        let previous_result = previous.as_ref().map(|b| b.result);
+        if let Err(e) = self.execute_post(&pipeline.post, result, previous_result, &mut context) {
+            result = StageResult::Failure;
+            error.get_or_insert(e);
+        }
+
+        if let (Some(history), Some(mut build)) = (self.config.history.as_ref(), build) {
+            build.finish(result, build_start.elapsed());
+            Self::record_build(history, &build, pipeline.options.build_discarder.as_ref());
+        }
+
+        if let Some(e) = error {
+            return Err(e);
+        }
+        Ok(result)
+    }
+}
+
+impl Default for LocalExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PipelineExecutor for LocalExecutor {
+    fn execute(&self, pipeline: &Pipeline) -> Result<StageResult, crate::pipeline::PipelineError> {
+        let cancellation = CancellationHandle::with_grace_period(self.kill_grace());
+        self.execute_cancellable(pipeline, &cancellation)
     }
 
     fn validate(&self, pipeline: &Pipeline) -> Result<(), crate::pipeline::ValidationError> {
@@ -189,210 +420,370 @@ impl PipelineExecutor for LocalExecutor {
 }
 
 impl LocalExecutor {
-    /// Executes a single stage
-    fn execute_stage(
-        &self,
-        stage: &Stage,
-        context: &PipelineContext,
-    ) -> Result<StageResult, crate::pipeline::PipelineError> {
-        // Handle matrix configuration - generate parallel branches
-        if let Some(ref matrix) = stage.matrix {
-            let combinations = matrix.generate_combinations();
-            let mut branches = Vec::new();
-
-            for combo in &combinations {
-                let branch_name = combo
-                    .iter()
-                    .map(|(k, v)| format!("{}_{}", k, v))
-                    .collect::<Vec<_>>()
-                    .join("-");
+    /// Creates the context of a build from the executor configuration
+    fn new_context(&self, cancellation: &CancellationHandle) -> PipelineContext {
+        let mut context = PipelineContext::new();
+        context.cancellation = cancellation.clone();
+        context.processes = cancellation.processes();
 
-                let mut branch_steps = stage.steps.clone();
+        if !self.config.cwd.as_os_str().is_empty() {
+            context.set_cwd(self.config.cwd.clone());
+        }
+        for (key, value) in &self.config.env {
+            if self.config.secrets.contains(key) {
+                context.set_secret_env(key, value);
+            } else {
+                context.set_env(key, value);
+            }
+        }
+        context
+    }
 
-                for (key, value) in combo {
-                    branch_steps.insert(0, Step::shell(&format!("export {}={}", key, value)));
-                }
+    /// Claims a build number and looks up the previous build
+    ///
+    /// Returns the previous build and the record of the new one, or nothing
+    /// without a history. A history that cannot be read or written is
+    /// logged and the build runs without being recorded.
+    fn start_build(
+        &self,
+        job_name: &str,
+        parameters: HashMap<String, String>,
+        context: &mut PipelineContext,
+    ) -> (Option<BuildRecord>, Option<BuildRecord>) {
+        let Some(ref history) = self.config.history else {
+            return (None, None);
+        };
 
-                branches.push(crate::pipeline::ParallelBranch {
-                    name: branch_name.clone(),
-                    stage: Stage::new(branch_name, branch_steps),
-                });
+        let claimed = history
+            .last_build(job_name)
+            .and_then(|previous| Ok((previous, history.next_build_number(job_name)?)));
+        let (previous, number) = match claimed {
+            Ok(claimed) => claimed,
+            Err(e) => {
+                tracing::warn!(job = %job_name, error = %e, "Build history unavailable, build is not recorded");
+                return (None, None);
             }
+        };
+        context.set_env("BUILD_NUMBER", number.to_string());
 
-            return self.execute_parallel_branches(&branches, context);
-        }
+        let mut build = BuildRecord::new(job_name, number);
+        build.parameters = parameters;
+        (previous, Some(build))
+    }
 
-        // Execute parallel branches if present
-        if !stage.parallel.is_empty() {
-            self.execute_parallel_branches(&stage.parallel, context)?;
+    /// Executes a single stage followed by its post-conditions
+    ///
+    /// Post-conditions see the result the stage had in the `previous` build.
+    /// Matrix stages run their post-conditions once per cell instead.
+    fn execute_stage(
+        &self,
+        stage: &Stage,
+        context: &mut PipelineContext,
+        previous: Option<&BuildRecord>,
+    ) -> Result<StageResult, crate::pipeline::PipelineError> {
+        if let Some(ref matrix) = stage.matrix {
+            return self.execute_matrix(stage, matrix, context, previous);
         }
 
-        // Execute steps (only if no parallel branches, or after parallel)
-        if !stage.parallel.is_empty() || !stage.steps.is_empty() {
-            let result = self.execute_steps(&stage.steps, context)?;
-
-            // Execute post-conditions
-            for post in &stage.post {
-                if post.should_execute(result, None) {
-                    self.execute_steps(post.steps(), context)?;
-                }
-            }
+        let outcome = self.execute_stage_body(stage, context, previous);
+        let previous_result = previous.and_then(|build| build.stage_result(&stage.name));
+        self.finish_stage(outcome, &stage.post, previous_result, context)
+    }
 
-            return Ok(result);
+    /// Runs the post-conditions of a stage body that finished with `outcome`
+    fn finish_stage(
+        &self,
+        mut outcome: Result<StageResult, crate::pipeline::PipelineError>,
+        post: &[PostCondition],
+        previous: Option<StageResult>,
+        context: &mut PipelineContext,
+    ) -> Result<StageResult, crate::pipeline::PipelineError> {
+        // Errors caused by a cancellation abort the stage instead of failing it
+        if context.cancellation.is_cancelled() {
+            outcome = Ok(StageResult::Aborted);
         }
 
-        // Execute post-conditions for parallel-only stage
-        let result = StageResult::Success;
-        for post in &stage.post {
-            if post.should_execute(result, None) {
-                self.execute_steps(post.steps(), context)?;
-            }
-        }
+        let result = outcome
+            .as_ref()
+            .map_or(StageResult::Failure, |result| *result);
+        let post = self.execute_post(post, result, previous, context);
 
+        let result = outcome?;
+        post?;
         Ok(result)
     }
 
-    /// Executes parallel branches concurrently
-    fn execute_parallel_branches(
+    /// Executes every cell of a matrix stage as a branch
+    ///
+    /// Each cell runs the stage's `when`, steps and post-conditions with its
+    /// axis values set as environment variables.
+    fn execute_matrix(
         &self,
-        branches: &[crate::pipeline::ParallelBranch],
-        context: &PipelineContext,
+        stage: &Stage,
+        matrix: &MatrixConfig,
+        context: &mut PipelineContext,
+        previous: Option<&BuildRecord>,
     ) -> Result<StageResult, crate::pipeline::PipelineError> {
-        use std::sync::{Arc, Mutex};
-        use std::thread;
-
-        let results = Arc::new(Mutex::new(Vec::new()));
-        let context = Arc::new(context.clone());
-
-        let handles: Vec<_> = branches
-            .iter()
-            .map(|branch| {
-                let results = Arc::clone(&results);
-                let context = Arc::clone(&context);
-                let branch_name = branch.name.clone();
-                let stage = branch.stage.clone();
-
-                thread::spawn(move || {
-                    let result = Self::execute_stage_static(&stage, &context);
-                    let mut results = results.lock().unwrap();
-                    results.push((branch_name, result));
-                })
-            })
+        let cells: Vec<_> = matrix
+            .cells()
+            .into_iter()
+            .map(|cell| (cell.name(), cell))
             .collect();
+        self.execute_branches(stage, &cells, context, |cell, context| {
+            self.execute_cell(stage, cell, context, previous)
+        })
+    }
 
-        for handle in handles {
-            handle.join().unwrap();
+    /// Executes one matrix cell with its axis values in the environment
+    fn execute_cell(
+        &self,
+        stage: &Stage,
+        cell: &MatrixCell,
+        context: &mut PipelineContext,
+        previous: Option<&BuildRecord>,
+    ) -> Result<StageResult, crate::pipeline::PipelineError> {
+        for (axis, value) in &cell.values {
+            context.set_env(axis, value);
         }
 
-        let results = results.lock().unwrap();
-
-        let has_error = results.iter().any(|(_, r)| r.is_err());
-        if has_error {
-            let first_error = results.iter().find(|(_, r)| r.is_err()).unwrap().1.clone();
-            return first_error;
+        if let Some(ref when) = stage.when
+            && !evaluate_when(when, context)
+        {
+            tracing::info!(stage = %stage.name, cell = %cell, "Cell skipped due to when condition");
+            return Ok(StageResult::Skipped);
         }
 
-        Ok(StageResult::Success)
+        tracing::info!(stage = %stage.name, cell = %cell, "Executing matrix cell");
+        let outcome = self.execute_steps(&stage.steps, context);
+        let previous_result =
+            previous.and_then(|build| build.branch_result(&stage.name, &cell.name()));
+        self.finish_stage(outcome, &stage.post, previous_result, context)
     }
 
-    /// Static method to execute a stage
-    fn execute_stage_static(
+    /// Executes the parallel branches and steps of a stage
+    ///
+    /// The steps run after the branches unless a branch failed or was
+    /// aborted.
+    fn execute_stage_body(
+        &self,
         stage: &Stage,
-        context: &Arc<PipelineContext>,
+        context: &mut PipelineContext,
+        previous: Option<&BuildRecord>,
     ) -> Result<StageResult, crate::pipeline::PipelineError> {
+        let mut result = StageResult::Success;
         if !stage.parallel.is_empty() {
-            Self::execute_parallel_branches_static(&stage.parallel, context)?;
+            result = self.execute_parallel_branches(stage, context, previous)?;
+            if result.is_failure() || result.is_aborted() {
+                return Ok(result);
+            }
         }
 
-        if !stage.parallel.is_empty() || !stage.steps.is_empty() {
-            let result = Self::execute_steps_static(&stage.steps, context)?;
+        self.execute_steps(&stage.steps, context)?;
+        if result.is_unstable() {
             return Ok(result);
         }
-
         Ok(StageResult::Success)
     }
 
-    /// Static method to execute parallel branches
-    fn execute_parallel_branches_static(
-        branches: &[crate::pipeline::ParallelBranch],
-        context: &Arc<PipelineContext>,
+    /// Executes the parallel branches of a stage
+    ///
+    /// Each branch runs on its own copy of the context, so environment
+    /// changes made inside a branch stay local to it. Shared state such as
+    /// the stash store is visible to every branch.
+    fn execute_parallel_branches(
+        &self,
+        stage: &Stage,
+        context: &mut PipelineContext,
+        previous: Option<&BuildRecord>,
     ) -> Result<StageResult, crate::pipeline::PipelineError> {
-        use std::sync::{Arc, Mutex};
-        use std::thread;
-
-        let results = Arc::new(Mutex::new(Vec::new()));
-
-        let handles: Vec<_> = branches
+        let branches: Vec<_> = stage
+            .parallel
             .iter()
-            .map(|branch| {
-                let results = Arc::clone(&results);
-                let context = Arc::clone(&context);
-                let branch_name = branch.name.clone();
-                let stage = branch.stage.clone();
-
-                thread::spawn(move || {
-                    let result = Self::execute_stage_static(&stage, &context);
-                    let mut results = results.lock().unwrap();
-                    results.push((branch_name, result));
-                })
-            })
+            .map(|branch| (branch.name.clone(), branch))
             .collect();
+        self.execute_branches(stage, &branches, context, |branch, context| {
+            if let Some(ref when) = branch.stage.when
+                && !evaluate_when(when, context)
+            {
+                tracing::info!(branch = %branch.name, "Branch skipped due to when condition");
+                return Ok(StageResult::Skipped);
+            }
+            self.execute_stage(&branch.stage, context, previous)
+        })
+    }
 
-        for handle in handles {
-            handle.join().unwrap();
-        }
+    /// Runs named branches of `stage` concurrently and reports their results
+    ///
+    /// At most `branch_limit` branches run at once, the others start in
+    /// order as running ones finish. With `fail_fast` set, the first failed
+    /// branch cancels its siblings: running ones are killed and aborted,
+    /// queued ones never start. The per-branch results are kept in the
+    /// context under the stage name. Returns the first branch error, or the
+    /// stage result aggregated from the branch results.
+    fn execute_branches<B: Sync>(
+        &self,
+        stage: &Stage,
+        branches: &[(String, B)],
+        context: &mut PipelineContext,
+        run: impl Fn(&B, &mut PipelineContext) -> Result<StageResult, crate::pipeline::PipelineError>
+        + Sync,
+    ) -> Result<StageResult, crate::pipeline::PipelineError> {
+        let limit = self.branch_limit(stage);
+        tracing::info!(
+            stage = %stage.name,
+            branches = branches.len(),
+            max_parallel = limit,
+            fail_fast = stage.fail_fast,
+            "Executing branches"
+        );
 
-        let results = results.lock().unwrap();
+        let siblings = context.cancellation.child();
+        let next = AtomicUsize::new(0);
+        let outcomes = parking_lot::Mutex::new(Vec::with_capacity(branches.len()));
+        let shared: &PipelineContext = context;
+        std::thread::scope(|scope| {
+            for _ in 0..limit.min(branches.len()) {
+                scope.spawn(|| {
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some((name, branch)) = branches.get(index) else {
+                            break;
+                        };
+
+                        let start = Instant::now();
+                        let outcome = if siblings.is_cancelled() {
+                            Ok(StageResult::Aborted)
+                        } else {
+                            let mut context = shared.clone();
+                            context.cancellation = siblings.clone();
+                            context.processes = siblings.processes();
+                            run(branch, &mut context)
+                        };
+
+                        let failed = match outcome {
+                            Ok(result) => result.is_failure(),
+                            Err(_) => true,
+                        };
+                        if failed && stage.fail_fast {
+                            siblings.cancel(format!("Branch '{name}' failed (failFast)"));
+                        }
+                        outcomes.lock().push((index, outcome, start.elapsed()));
+                    }
+                });
+            }
+        });
 
-        let has_error = results.iter().any(|(_, r)| r.is_err());
-        if has_error {
-            let first_error = results.iter().find(|(_, r)| r.is_err()).unwrap().1.clone();
-            return first_error;
+        let mut outcomes = outcomes.into_inner();
+        outcomes.sort_by_key(|(index, ..)| *index);
+
+        let mut records = Vec::with_capacity(outcomes.len());
+        let mut first_error = None;
+        for (index, outcome, duration) in outcomes {
+            let name = &branches[index].0;
+            let result = outcome.unwrap_or_else(|e| {
+                tracing::error!(stage = %stage.name, branch = %name, error = %e, "Branch error");
+                first_error.get_or_insert(e);
+                StageResult::Failure
+            });
+            records.push(BranchRecord::new(name, result, duration));
         }
 
-        Ok(StageResult::Success)
+        tracing::info!(stage = %stage.name, "Branch results\n{}", format_branches(&records));
+        let result = branches_result(&records);
+        context.branch_results.insert(stage.name.clone(), records);
+
+        first_error.map_or(Ok(result), Err)
     }
 
-    /// Static method to execute steps
-    fn execute_steps_static(
-        steps: &[Step],
-        context: &Arc<PipelineContext>,
-    ) -> Result<StageResult, crate::pipeline::PipelineError> {
-        for step in steps {
-            Self::execute_step_static(step, context)?;
+    /// Returns how many branches of `stage` may run at once
+    ///
+    /// A stage limit can lower the executor limit but not raise it.
+    fn branch_limit(&self, stage: &Stage) -> usize {
+        let limit = self.config.max_parallel.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+        });
+        stage
+            .max_parallel
+            .map_or(limit, |stage_limit| stage_limit.min(limit))
+            .max(1)
+    }
+
+    /// Records the result of a finished stage in the context and the build
+    /// record
+    fn record_stage(
+        context: &mut PipelineContext,
+        build: &mut Option<BuildRecord>,
+        name: &str,
+        result: StageResult,
+        duration: Duration,
+    ) {
+        tracing::info!(
+            stage = %name,
+            result = %result,
+            duration_ms = duration.as_millis(),
+            "Stage completed"
+        );
+
+        context.record_stage_result(name, result);
+        if let Some(build) = build.as_mut() {
+            let branches = context.branch_results.get(name).cloned();
+            build.add_stage(name, result, duration).branches = branches.unwrap_or_default();
         }
-        Ok(StageResult::Success)
     }
 
-    /// Static method to execute a single step
-    fn execute_step_static(
-        step: &Step,
-        context: &Arc<PipelineContext>,
+    /// Records a finished build, then applies the job's retention policy
+    ///
+    /// History and retention problems are logged rather than failing the
+    /// build.
+    fn record_build(
+        history: &BuildHistory,
+        build: &BuildRecord,
+        discarder: Option<&BuildDiscarder>,
+    ) {
+        if let Err(e) = history.record(build) {
+            tracing::warn!(job = %build.job, build = build.number, error = %e, "Failed to record build");
+            return;
+        }
+        tracing::info!(job = %build.job, build = build.number, result = %build.result, "Build recorded");
+
+        let Some(discarder) = discarder else {
+            return;
+        };
+        let result = history
+            .set_discarder(&build.job, discarder)
+            .and_then(|()| apply_retention(history, &build.job, discarder, false));
+        match result {
+            Ok(report) if !report.is_empty() => tracing::info!("{report}"),
+            Ok(_) => {}
+            Err(e) => tracing::warn!(job = %build.job, error = %e, "Build retention failed"),
+        }
+    }
+
+    /// Executes the post-conditions that apply to a result
+    ///
+    /// `cleanup` conditions run after all others. Post-conditions of an
+    /// aborted build run outside the cancelled process scope.
+    fn execute_post(
+        &self,
+        post: &[PostCondition],
+        result: StageResult,
+        previous: Option<StageResult>,
+        context: &mut PipelineContext,
     ) -> Result<(), crate::pipeline::PipelineError> {
-        match &step.step_type {
-            StepType::Shell { command } => {
-                let shell_config = ShellConfig {
-                    cwd: context.cwd.clone(),
-                    env: context.env.clone(),
-                    shell: "sh".to_string(),
-                    streaming: false,
-                    timeout: None,
-                };
-
-                let shell_command = ShellCommand::new(&shell_config);
-                let result = shell_command.execute(command)?;
-
-                if !result.is_success() {
-                    return Err(crate::pipeline::PipelineError::CommandFailed {
-                        code: result.exit_code,
-                        stderr: result.stderr,
-                    });
-                }
-            }
-            StepType::Echo { message } => {
-                println!("{message}");
+        let mut detached;
+        let context = if result.is_aborted() {
+            detached = context.detach_cancellation();
+            &mut detached
+        } else {
+            context
+        };
+
+        let (cleanup, others): (Vec<_>, Vec<_>) = post.iter().partition(|c| c.is_cleanup());
+        for condition in others.into_iter().chain(cleanup) {
+            if condition.should_execute(result, previous) {
+                tracing::debug!(post = %condition, "Executing post-condition");
+                self.execute_steps(condition.steps(), context)?;
             }
-            _ => {}
         }
         Ok(())
     }
@@ -401,9 +792,12 @@ impl LocalExecutor {
     fn execute_steps(
         &self,
         steps: &[Step],
-        context: &PipelineContext,
+        context: &mut PipelineContext,
     ) -> Result<StageResult, crate::pipeline::PipelineError> {
         for step in steps {
+            if let Some(reason) = context.cancellation.reason() {
+                return Err(crate::pipeline::PipelineError::Aborted { reason });
+            }
             self.execute_step(step, context)?;
         }
         Ok(StageResult::Success)
@@ -413,14 +807,15 @@ impl LocalExecutor {
     fn execute_step(
         &self,
         step: &Step,
-        context: &PipelineContext,
+        context: &mut PipelineContext,
     ) -> Result<(), crate::pipeline::PipelineError> {
         match &step.step_type {
             StepType::Shell { command } => {
                 self.execute_shell(command, context)?;
             }
             StepType::Echo { message } => {
-                println!("{message}");
+                let message = expand_variables(message, &context.env);
+                println!("{}", context.secrets.mask(&message));
             }
             StepType::Retry { count, step } => {
                 let mut last_error = None;
@@ -438,7 +833,12 @@ impl LocalExecutor {
                                 total = count,
                                 "Step failed, retrying"
                             );
-                            std::thread::sleep(std::time::Duration::from_millis(100));
+                            if context
+                                .cancellation
+                                .wait_timeout(Duration::from_millis(100))
+                            {
+                                break;
+                            }
                         }
                     }
                 }
@@ -449,13 +849,85 @@ impl LocalExecutor {
             StepType::Timeout { duration, step } => {
                 self.execute_timeout(*duration, step.as_ref(), context)?;
             }
-            _ => {
-                tracing::warn!(step_type = %step.step_type, "Step type not yet implemented");
+            StepType::Stash {
+                name,
+                includes,
+                excludes,
+            } => {
+                context
+                    .stashes
+                    .save(name, &context.cwd, includes, excludes)?;
+            }
+            StepType::Unstash { name } => {
+                context.stashes.restore(name, &context.cwd)?;
+            }
+            StepType::Input {
+                message,
+                default,
+                id,
+                submitter,
+            } => {
+                let mut request =
+                    InputRequest::new(message, default.as_ref(), id.as_ref(), submitter.as_ref());
+                request.pipeline_id.clone_from(&context.pipeline_id);
+
+                let answer = self
+                    .config
+                    .input
+                    .resolve(&request, &context.env, step.timeout)?;
+                tracing::info!(
+                    input = %request.id,
+                    submitter = answer.submitter.as_deref().unwrap_or("<unknown>"),
+                    "Input approved"
+                );
+
+                let key = request.env_key();
+                if let Some(submitter) = answer.submitter {
+                    context.set_env(format!("{key}_SUBMITTER"), submitter);
+                }
+                context.set_env(key, answer.value.unwrap_or_else(|| "true".to_string()));
+            }
+            StepType::Dir { path, steps } => {
+                self.execute_dir(path, steps, context)?;
+            }
+            StepType::Custom { name } => {
+                let custom = self.config.custom_steps.get(name).ok_or_else(|| {
+                    crate::pipeline::PipelineError::CustomStepNotFound { name: name.clone() }
+                })?;
+                custom.execute(context)?;
             }
         }
         Ok(())
     }
 
+    /// Executes steps inside a directory
+    ///
+    /// The path is resolved relative to the current working directory and
+    /// the previous directory is restored afterwards, even if a step fails.
+    fn execute_dir(
+        &self,
+        path: &str,
+        steps: &[Step],
+        context: &mut PipelineContext,
+    ) -> Result<(), crate::pipeline::PipelineError> {
+        let dir = context.cwd.join(expand_variables(path, &context.env));
+        if !dir.is_dir() {
+            if self.config.require_existing_dirs {
+                return Err(crate::pipeline::PipelineError::Io(format!(
+                    "Directory not found: {}",
+                    dir.display()
+                )));
+            }
+            std::fs::create_dir_all(&dir)?;
+        }
+
+        tracing::debug!(dir = %dir.display(), "Entering directory");
+        context.push_dir(dir);
+        let result = self.execute_steps(steps, context);
+        context.pop_dir();
+        result.map(|_| ())
+    }
+
     /// Executes a shell command
     fn execute_shell(
         &self,
@@ -472,9 +944,11 @@ impl LocalExecutor {
             },
             streaming: false,
             timeout: None,
+            kill_grace: self.kill_grace(),
+            masker: context.secrets.clone(),
         };
 
-        let shell_command = ShellCommand::new(&shell_config);
+        let shell_command = ShellCommand::new(&shell_config).scope(Arc::clone(&context.processes));
         let result = shell_command.execute(command)?;
 
         if !result.is_success() {
@@ -488,117 +962,136 @@ impl LocalExecutor {
     }
 
     /// Executes a step with timeout
+    ///
+    /// Context changes made by the step are kept only if it completes in time.
+    /// Otherwise every process the step started is killed.
     fn execute_timeout(
         &self,
         duration: std::time::Duration,
         step: &Step,
-        context: &PipelineContext,
+        context: &mut PipelineContext,
     ) -> Result<(), crate::pipeline::PipelineError> {
         let (tx, rx) = std::sync::mpsc::channel();
 
         let executor = self.clone();
-        let step = Arc::new(step.clone());
-        let context = Arc::new(context.clone());
+        let scope = context.processes.child();
+        let mut step_context = context.clone();
+        step_context.processes = Arc::clone(&scope);
+        let timed_step = step.clone();
 
         std::thread::spawn(move || {
-            let result = executor.execute_step_arc(&step, &context);
-            let _ = tx.send(result);
+            let result = executor.execute_step(&timed_step, &mut step_context);
+            let _ = tx.send((result, step_context));
         });
 
         match rx.recv_timeout(duration) {
-            Ok(Ok(())) => Ok(()),
-            Ok(Err(e)) => Err(e),
-            Err(_) => Err(crate::pipeline::PipelineError::Timeout { duration }),
-        }
-    }
-
-    /// Executes a step with Arc references
-    fn execute_step_arc(
-        &self,
-        step: &Arc<Step>,
-        context: &Arc<PipelineContext>,
-    ) -> Result<(), crate::pipeline::PipelineError> {
-        match &step.step_type {
-            StepType::Shell { command } => {
-                let shell_config = ShellConfig {
-                    cwd: context.cwd.clone(),
-                    env: context.env.clone(),
-                    shell: if self.config.shell.is_empty() {
-                        "sh".to_string()
-                    } else {
-                        self.config.shell.clone()
-                    },
-                    streaming: false,
-                    timeout: None,
-                };
-
-                let shell_command = ShellCommand::new(&shell_config);
-                let result = shell_command.execute(command)?;
-
-                if !result.is_success() {
-                    return Err(crate::pipeline::PipelineError::CommandFailed {
-                        code: result.exit_code,
-                        stderr: result.stderr,
-                    });
-                }
-            }
-            StepType::Echo { message } => {
-                println!("{message}");
-            }
-            StepType::Retry { count, step } => {
-                let mut last_error = None;
-                let mut succeeded = false;
-                let step_arc = Arc::new(step.as_ref().clone());
-                for _ in 0..*count {
-                    match self.execute_step_arc(&step_arc, context) {
-                        Ok(()) => {
-                            succeeded = true;
-                            break;
-                        }
-                        Err(e) => {
-                            last_error = Some(e);
-                            std::thread::sleep(std::time::Duration::from_millis(100));
-                        }
-                    }
-                }
-                if !succeeded {
-                    return Err(last_error.unwrap());
-                }
-            }
-            StepType::Timeout { duration, step } => {
-                self.execute_timeout_arc(*duration, step, context)?;
+            Ok((Ok(()), mut step_context)) => {
+                step_context.processes = Arc::clone(&context.processes);
+                *context = step_context;
+                Ok(())
             }
-            _ => {
-                tracing::warn!(step_type = %step.step_type, "Step type not yet implemented");
+            Ok((Err(e), _)) => Err(e),
+            Err(_) => {
+                let reason = KillReason::Timeout(duration);
+                let step_name = step
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| step.step_type.to_string());
+                scope.kill(reason.clone(), self.kill_grace());
+                tracing::error!(step = %step_name, reason = %reason, "Step killed");
+
+                Err(reason.into_error(step_name))
             }
         }
-        Ok(())
     }
+}
 
-    /// Executes a step with timeout using Arc references
-    fn execute_timeout_arc(
-        &self,
-        duration: std::time::Duration,
-        step: &Step,
-        context: &Arc<PipelineContext>,
-    ) -> Result<(), crate::pipeline::PipelineError> {
-        let (tx, rx) = std::sync::mpsc::channel();
-
-        let executor = self.clone();
-        let step = Arc::new(step.clone());
-        let context = Arc::clone(context);
+/// Derives a filesystem-safe job name from a pipeline name
+fn job_name(pipeline_id: &str) -> String {
+    pipeline_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
 
-        std::thread::spawn(move || {
-            let result = executor.execute_step_arc(&step, &context);
-            let _ = tx.send(result);
-        });
+/// Returns the result of a parallel or matrix stage from its branch results
+///
+/// Any failed branch fails the stage, then aborted and unstable branches
+/// take precedence over successful ones. A stage whose branches were all
+/// skipped is skipped.
+fn branches_result(branches: &[BranchRecord]) -> StageResult {
+    let any = |result: StageResult| branches.iter().any(|branch| branch.result == result);
+    if any(StageResult::Failure) {
+        StageResult::Failure
+    } else if any(StageResult::Aborted) {
+        StageResult::Aborted
+    } else if any(StageResult::Unstable) {
+        StageResult::Unstable
+    } else if !branches.is_empty() && branches.iter().all(|branch| branch.result.is_skipped()) {
+        StageResult::Skipped
+    } else {
+        StageResult::Success
+    }
+}
 
-        match rx.recv_timeout(duration) {
-            Ok(Ok(())) => Ok(()),
-            Ok(Err(e)) => Err(e),
-            Err(_) => Err(crate::pipeline::PipelineError::Timeout { duration }),
-        }
+/// Formats branch results as a table with one row per branch
+fn format_branches(branches: &[BranchRecord]) -> String {
+    use std::fmt::Write;
+
+    let width = branches
+        .iter()
+        .map(|branch| branch.name.len())
+        .max()
+        .unwrap_or_default()
+        .max("BRANCH".len());
+    let mut table = format!("{:<width$}  {:<8}  DURATION", "BRANCH", "RESULT");
+    for branch in branches {
+        let _ = write!(
+            table,
+            "\n{:<width$}  {:<8}  {}ms",
+            branch.name,
+            branch.result.to_string(),
+            branch.duration_ms
+        );
     }
+    table
+}
+
+/// Resolves pipeline parameters to their values for this build
+///
+/// A parameter set in the environment wins over its default; choice
+/// parameters default to their first option.
+fn parameter_values(pipeline: &Pipeline, context: &PipelineContext) -> HashMap<String, String> {
+    let params = &pipeline.parameters;
+    let defaults = params
+        .boolean
+        .iter()
+        .map(|(name, value)| (name, value.to_string()))
+        .chain(
+            params
+                .string
+                .iter()
+                .map(|(name, value)| (name, value.clone())),
+        )
+        .chain(
+            params
+                .choice
+                .iter()
+                .map(|(name, choices)| (name, choices.first().cloned().unwrap_or_default())),
+        );
+
+    defaults
+        .map(|(name, default)| {
+            let value = context.get_env(name).cloned().unwrap_or(default);
+            (name.clone(), value)
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -608,7 +1101,7 @@ mod tests {
 
     #[test]
     fn test_local_executor_creation() {
-        let executor = LocalExecutor::new();
+        let executor = LocalExecutor::new().without_history();
         let caps = executor.capabilities();
 
         assert!(caps.can_execute_shell);
@@ -618,7 +1111,7 @@ mod tests {
 
     #[test]
     fn test_local_executor_health() {
-        let executor = LocalExecutor::new();
+        let executor = LocalExecutor::new().without_history();
         let health = executor.health_check();
 
         assert!(health.is_operational());
@@ -655,7 +1148,7 @@ mod tests {
 
     #[test]
     fn test_local_executor_validate_valid_pipeline() {
-        let executor = LocalExecutor::new();
+        let executor = LocalExecutor::new().without_history();
         let pipeline = Pipeline::builder()
             .agent(AgentType::Any)
             .stages(vec![Stage::new("Build", vec![Step::shell("echo test")])])
@@ -668,7 +1161,7 @@ mod tests {
 
     #[test]
     fn test_local_executor_validate_invalid_pipeline() {
-        let executor = LocalExecutor::new();
+        let executor = LocalExecutor::new().without_history();
         let pipeline = Pipeline::builder()
             .agent(AgentType::Any)
             .stages(vec![])
@@ -721,7 +1214,7 @@ mod tests {
 
     #[test]
     fn test_local_executor_dry_run() {
-        let executor = LocalExecutor::new();
+        let executor = LocalExecutor::new().without_history();
         let pipeline = Pipeline::builder()
             .agent(AgentType::Any)
             .stages(vec![Stage::new("Build", vec![Step::shell("echo test")])])
@@ -735,7 +1228,7 @@ mod tests {
 
     #[test]
     fn test_retry_step_success_first_attempt() {
-        let executor = LocalExecutor::new().with_cwd("/tmp");
+        let executor = LocalExecutor::new().without_history().with_cwd("/tmp");
         let step = Step::retry(3, Step::shell("echo success"));
 
         let pipeline = Pipeline::builder()
@@ -749,7 +1242,7 @@ mod tests {
 
     #[test]
     fn test_retry_step_all_fail() {
-        let executor = LocalExecutor::new();
+        let executor = LocalExecutor::new().without_history();
         let step = Step::retry(2, Step::shell("exit 1"));
 
         let pipeline = Pipeline::builder()
@@ -763,7 +1256,7 @@ mod tests {
 
     #[test]
     fn test_timeout_step_completes_in_time() {
-        let executor = LocalExecutor::new();
+        let executor = LocalExecutor::new().without_history();
         let step = Step::timeout(std::time::Duration::from_secs(5), Step::shell("echo quick"));
 
         let pipeline = Pipeline::builder()
@@ -777,7 +1270,7 @@ mod tests {
 
     #[test]
     fn test_timeout_step_times_out() {
-        let executor = LocalExecutor::new();
+        let executor = LocalExecutor::new().without_history();
         let step = Step::timeout(
             std::time::Duration::from_millis(100),
             Step::shell("sleep 10"),
@@ -792,9 +1285,54 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_timeout_kills_process_tree() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path())
+            .with_kill_grace_period(std::time::Duration::from_millis(200));
+        let mut context = PipelineContext::new();
+        context.set_cwd(temp_dir.path());
+
+        // Both processes ignore SIGTERM, so only SIGKILL stops them
+        let step = Step::timeout(
+            std::time::Duration::from_millis(300),
+            Step::shell("trap '' TERM; sleep 30 & echo $! > child.pid; wait").with_name("slow"),
+        );
+        let start = Instant::now();
+        let result = executor.execute_step(&step, &mut context);
+
+        assert_eq!(
+            result.unwrap_err(),
+            crate::pipeline::PipelineError::Timeout {
+                duration: std::time::Duration::from_millis(300),
+            }
+        );
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+        // The orphaned child is gone, or at most a zombie awaiting its reaper
+        let pid = std::fs::read_to_string(temp_dir.path().join("child.pid")).unwrap();
+        let stat_path = format!("/proc/{}/stat", pid.trim());
+        let deadline = Instant::now() + std::time::Duration::from_secs(2);
+        loop {
+            match std::fs::read_to_string(&stat_path) {
+                Ok(proc_stat) if !proc_stat.contains(") Z ") => {
+                    assert!(
+                        Instant::now() < deadline,
+                        "child still running: {proc_stat}"
+                    );
+                    std::thread::sleep(std::time::Duration::from_millis(20));
+                }
+                _ => break,
+            }
+        }
+    }
+
     #[test]
     fn test_parallel_execution_success() {
-        let executor = LocalExecutor::new().with_cwd("/tmp");
+        let executor = LocalExecutor::new().without_history().with_cwd("/tmp");
         let branch1 = crate::pipeline::ParallelBranch {
             name: "Branch1".to_string(),
             stage: Stage::new("Build1", vec![Step::shell("echo branch1")]),
@@ -817,7 +1355,7 @@ mod tests {
 
     #[test]
     fn test_parallel_execution_one_branch_fails() {
-        let executor = LocalExecutor::new().with_cwd("/tmp");
+        let executor = LocalExecutor::new().without_history().with_cwd("/tmp");
         let branch1 = crate::pipeline::ParallelBranch {
             name: "Branch1".to_string(),
             stage: Stage::new("Build1", vec![Step::shell("echo branch1")]),
@@ -838,9 +1376,135 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn test_fail_fast_cancels_sibling_branches() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path())
+            .with_max_parallel(4);
+
+        let branch = |name: &str, command: &str| crate::pipeline::ParallelBranch {
+            name: name.to_string(),
+            stage: Stage::new(name, vec![Step::shell(command)]),
+        };
+        let stage = Stage::new("Test", vec![])
+            .with_parallel(vec![
+                branch("Slow", "sleep 30 && touch slow"),
+                branch("Broken", "sleep 0.2 && exit 1"),
+                branch("Queued", "touch queued"),
+            ])
+            .with_fail_fast(true)
+            .with_max_parallel(2);
+        let mut context = executor.new_context(&CancellationHandle::new());
+
+        let start = Instant::now();
+        assert!(executor.execute_stage(&stage, &mut context, None).is_err());
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(!temp_dir.path().join("slow").exists());
+        assert!(!temp_dir.path().join("queued").exists());
+
+        let results: Vec<_> = context
+            .get_branch_results("Test")
+            .unwrap()
+            .iter()
+            .map(|branch| (branch.name.as_str(), branch.result))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                ("Slow", StageResult::Aborted),
+                ("Broken", StageResult::Failure),
+                ("Queued", StageResult::Aborted),
+            ]
+        );
+        assert!(!context.cancellation.is_cancelled());
+    }
+
+    #[test]
+    fn test_max_parallel_bounds_running_branches() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path())
+            .with_max_parallel(2);
+
+        let branches = (0..5)
+            .map(|i| crate::pipeline::ParallelBranch {
+                name: format!("Branch{i}"),
+                stage: Stage::new(
+                    format!("Branch{i}"),
+                    vec![Step::shell(
+                        "echo + >> running.log; sleep 0.1; echo - >> running.log",
+                    )],
+                ),
+            })
+            .collect();
+        let stage = Stage::new("Test", vec![]).with_parallel(branches);
+        let mut context = executor.new_context(&CancellationHandle::new());
+
+        let result = executor.execute_stage(&stage, &mut context, None);
+        assert_eq!(result.unwrap(), StageResult::Success);
+
+        let log = std::fs::read_to_string(temp_dir.path().join("running.log")).unwrap();
+        let mut running = 0;
+        let mut max_running = 0;
+        for line in log.lines() {
+            running += if line == "+" { 1 } else { -1 };
+            max_running = max_running.max(running);
+        }
+        assert_eq!(log.lines().count(), 10);
+        assert!(max_running <= 2, "{max_running} branches ran at once");
+        assert_eq!(context.get_branch_results("Test").unwrap().len(), 5);
+
+        // A stage can lower the executor limit but not raise it
+        assert_eq!(
+            executor.branch_limit(&stage.clone().with_max_parallel(1)),
+            1
+        );
+        assert_eq!(executor.branch_limit(&stage.with_max_parallel(8)), 2);
+    }
+
+    #[test]
+    fn test_branches_result_aggregates_branches() {
+        let aggregate = |results: &[StageResult]| {
+            let branches: Vec<_> = results
+                .iter()
+                .map(|result| BranchRecord::new("branch", *result, Duration::ZERO))
+                .collect();
+            branches_result(&branches)
+        };
+
+        assert_eq!(
+            aggregate(&[StageResult::Success, StageResult::Unstable]),
+            StageResult::Unstable
+        );
+        assert_eq!(
+            aggregate(&[
+                StageResult::Aborted,
+                StageResult::Failure,
+                StageResult::Unstable
+            ]),
+            StageResult::Failure
+        );
+        assert_eq!(
+            aggregate(&[StageResult::Success, StageResult::Aborted]),
+            StageResult::Aborted
+        );
+        assert_eq!(
+            aggregate(&[StageResult::Skipped, StageResult::Skipped]),
+            StageResult::Skipped
+        );
+        assert_eq!(
+            aggregate(&[StageResult::Skipped, StageResult::Success]),
+            StageResult::Success
+        );
+    }
+
     #[test]
     fn test_parallel_execution_single_branch() {
-        let executor = LocalExecutor::new().with_cwd("/tmp");
+        let executor = LocalExecutor::new().without_history().with_cwd("/tmp");
         let branch = crate::pipeline::ParallelBranch {
             name: "SingleBranch".to_string(),
             stage: Stage::new("Build", vec![Step::shell("echo single")]),
@@ -859,7 +1523,7 @@ mod tests {
 
     #[test]
     fn test_stage_with_parallel_and_steps() {
-        let executor = LocalExecutor::new().with_cwd("/tmp");
+        let executor = LocalExecutor::new().without_history().with_cwd("/tmp");
         let branch = crate::pipeline::ParallelBranch {
             name: "Branch1".to_string(),
             stage: Stage::new("Build1", vec![Step::shell("echo parallel")]),
@@ -882,7 +1546,7 @@ mod tests {
 
     #[test]
     fn test_matrix_execution_success() {
-        let executor = LocalExecutor::new().with_cwd("/tmp");
+        let executor = LocalExecutor::new().without_history().with_cwd("/tmp");
         let matrix = crate::pipeline::MatrixConfig::new()
             .add_axis("os", vec!["linux".to_string(), "macos".to_string()])
             .add_axis("version", vec!["1.0".to_string(), "2.0".to_string()]);
@@ -900,7 +1564,7 @@ mod tests {
 
     #[test]
     fn test_matrix_execution_single_axis() {
-        let executor = LocalExecutor::new().with_cwd("/tmp");
+        let executor = LocalExecutor::new().without_history().with_cwd("/tmp");
         let matrix = crate::pipeline::MatrixConfig::new()
             .add_axis("os", vec!["linux".to_string(), "windows".to_string()]);
 
@@ -917,7 +1581,7 @@ mod tests {
 
     #[test]
     fn test_matrix_execution_with_excludes() {
-        let executor = LocalExecutor::new().with_cwd("/tmp");
+        let executor = LocalExecutor::new().without_history().with_cwd("/tmp");
         let matrix = crate::pipeline::MatrixConfig::new()
             .add_axis("os", vec!["linux".to_string(), "macos".to_string()])
             .add_axis("version", vec!["1.0".to_string(), "2.0".to_string()])
@@ -964,34 +1628,162 @@ mod tests {
         ]));
     }
 
+    #[test]
+    fn test_matrix_cell_names_are_stable() {
+        let matrix = crate::pipeline::MatrixConfig::new()
+            .add_axis("OS", vec!["linux".to_string(), "macos".to_string()])
+            .add_axis("RUST", vec!["stable".to_string()]);
+
+        let names: Vec<String> = matrix.cells().iter().map(MatrixCell::name).collect();
+        assert_eq!(
+            names,
+            vec!["OS=linux, RUST=stable", "OS=macos, RUST=stable"]
+        );
+        assert_eq!(matrix.cells()[1].get("OS"), Some("macos"));
+    }
+
+    #[test]
+    fn test_matrix_cells_get_axis_environment() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let history = BuildHistory::new(temp_dir.path().join("history"));
+        let executor = LocalExecutor::new()
+            .with_cwd(temp_dir.path())
+            .with_history(history.clone());
+
+        let matrix = crate::pipeline::MatrixConfig::new()
+            .add_axis("OS", vec!["linux".to_string(), "macos".to_string()])
+            .add_axis("RUST", vec!["stable".to_string(), "nightly".to_string()]);
+        let stage = Stage::new(
+            "Test",
+            vec![
+                Step::shell("test -n \"$OS\""),
+                Step::shell("touch \"built-$OS-$RUST\""),
+            ],
+        )
+        .with_matrix(matrix)
+        .with_when(crate::pipeline::WhenCondition::environment(
+            "RUST", "stable",
+        ))
+        .with_post(crate::pipeline::PostCondition::always(vec![Step::shell(
+            "touch \"post-${OS}\"",
+        )]));
+
+        let pipeline = Pipeline::builder()
+            .name("matrix")
+            .agent(AgentType::Any)
+            .stages(vec![stage])
+            .build_unchecked();
+
+        assert_eq!(executor.execute(&pipeline).unwrap(), StageResult::Success);
+        assert!(temp_dir.path().join("built-linux-stable").exists());
+        assert!(temp_dir.path().join("built-macos-stable").exists());
+        assert!(!temp_dir.path().join("built-linux-nightly").exists());
+        assert!(temp_dir.path().join("post-linux").exists());
+        assert!(temp_dir.path().join("post-macos").exists());
+
+        let build = history.last_build("matrix").unwrap().unwrap();
+        let cells: Vec<_> = build.stages[0]
+            .branches
+            .iter()
+            .map(|cell| (cell.name.as_str(), cell.result))
+            .collect();
+        assert_eq!(
+            cells,
+            vec![
+                ("OS=linux, RUST=stable", StageResult::Success),
+                ("OS=linux, RUST=nightly", StageResult::Skipped),
+                ("OS=macos, RUST=stable", StageResult::Success),
+                ("OS=macos, RUST=nightly", StageResult::Skipped),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_matrix_failed_cell_fails_stage() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path());
+
+        let matrix = crate::pipeline::MatrixConfig::new()
+            .add_axis("TARGET", vec!["good".to_string(), "bad".to_string()]);
+        let stage = Stage::new("Build", vec![Step::shell("test \"$TARGET\" = good")])
+            .with_matrix(matrix)
+            .with_post(crate::pipeline::PostCondition::failure(vec![Step::shell(
+                "touch \"failed-$TARGET\"",
+            )]));
+        let mut context = executor.new_context(&CancellationHandle::new());
+
+        let result = executor.execute_stage(&stage, &mut context, None);
+        assert!(result.is_err());
+        assert!(temp_dir.path().join("failed-bad").exists());
+        assert!(!temp_dir.path().join("failed-good").exists());
+
+        let cells = context.get_branch_results("Build").unwrap();
+        assert_eq!(cells[0].result, StageResult::Success);
+        assert_eq!(cells[1].result, StageResult::Failure);
+        assert_eq!(branches_result(cells), StageResult::Failure);
+        assert!(format_branches(cells).contains("TARGET=bad   FAILURE"));
+    }
+
     #[test]
     fn test_variable_expansion_in_shell() {
-        let executor = LocalExecutor::new().with_cwd("/tmp");
+        let executor = LocalExecutor::new().without_history().with_cwd("/tmp");
         let step = Step::shell("echo ${MY_VAR}");
 
         let mut context = PipelineContext::new();
         context.set_env("MY_VAR", "test_value");
 
-        let result = executor.execute_step(&step, &context);
+        let result = executor.execute_step(&step, &mut context);
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_jenkins_variables_available() {
-        let executor = LocalExecutor::new().with_cwd("/tmp");
+        let executor = LocalExecutor::new().without_history().with_cwd("/tmp");
         let step = Step::shell("echo ${BUILD_NUMBER}");
 
         let mut context = PipelineContext::new();
         context.set_env("BUILD_NUMBER", "42");
 
-        let result = executor.execute_step(&step, &context);
+        let result = executor.execute_step(&step, &mut context);
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_secrets_masked_in_command_errors() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path())
+            .with_secret_env("DEPLOY_PASSWORD", "hunter2-pass");
+
+        let pipeline = Pipeline::builder()
+            .agent(AgentType::Any)
+            .environment(|env| env.secret("API_TOKEN", "s3cr3t-token"))
+            .stages(vec![Stage::new(
+                "Deploy",
+                vec![Step::shell(
+                    r#"echo "token=$API_TOKEN pass=$DEPLOY_PASSWORD" >&2; exit 3"#,
+                )],
+            )])
+            .build_unchecked();
+
+        assert_eq!(
+            executor.execute(&pipeline).unwrap_err(),
+            crate::pipeline::PipelineError::CommandFailed {
+                code: 3,
+                stderr: "token=**** pass=****\n".to_string(),
+            }
+        );
+    }
+
     #[test]
     fn test_shell_with_temp_path() {
         let temp_dir = tempfile::tempdir().unwrap();
-        let executor = LocalExecutor::new().with_cwd(temp_dir.path());
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path());
         let step = Step::shell("echo test");
 
         let pipeline = Pipeline::builder()
@@ -1002,4 +1794,596 @@ mod tests {
         let result = executor.execute(&pipeline);
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_when_condition_skips_stage() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path())
+            .with_env("BRANCH_NAME", "feature/login");
+
+        let deploy = Stage::new("Deploy", vec![Step::shell("touch deployed")])
+            .with_when(crate::pipeline::WhenCondition::branch("release/*"));
+        let build = Stage::new("Build", vec![Step::shell("touch built")])
+            .with_when(crate::pipeline::WhenCondition::branch("feature/*"));
+
+        let pipeline = Pipeline::builder()
+            .agent(AgentType::Any)
+            .stages(vec![deploy, build])
+            .build_unchecked();
+
+        let result = executor.execute(&pipeline);
+        assert_eq!(result.unwrap(), StageResult::Success);
+        assert!(!temp_dir.path().join("deployed").exists());
+        assert!(temp_dir.path().join("built").exists());
+    }
+
+    #[test]
+    fn test_when_condition_skips_parallel_branch() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path())
+            .with_env("DEPLOY_TARGET", "staging");
+
+        let prod = crate::pipeline::ParallelBranch {
+            name: "Prod".to_string(),
+            stage: Stage::new("Prod", vec![Step::shell("touch prod")]).with_when(
+                crate::pipeline::WhenCondition::environment("DEPLOY_TARGET", "prod"),
+            ),
+        };
+        let staging = crate::pipeline::ParallelBranch {
+            name: "Staging".to_string(),
+            stage: Stage::new("Staging", vec![Step::shell("touch staging")]).with_when(
+                crate::pipeline::WhenCondition::environment("DEPLOY_TARGET", "staging"),
+            ),
+        };
+
+        let stage = Stage::new("Deploy", vec![]).with_parallel(vec![prod, staging]);
+
+        let pipeline = Pipeline::builder()
+            .agent(AgentType::Any)
+            .stages(vec![stage])
+            .build_unchecked();
+
+        assert!(executor.execute(&pipeline).is_ok());
+        assert!(!temp_dir.path().join("prod").exists());
+        assert!(temp_dir.path().join("staging").exists());
+    }
+
+    #[test]
+    fn test_stash_between_stages() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path());
+
+        let build = Stage::new(
+            "Build",
+            vec![
+                Step::shell("mkdir -p out && echo app > out/app && echo log > out/build.log"),
+                Step::stash_with_excludes("binaries", "out/**", "**/*.log"),
+                Step::shell("rm -rf out"),
+            ],
+        );
+        let branch = |name: &str| crate::pipeline::ParallelBranch {
+            name: name.to_string(),
+            stage: Stage::new(
+                name,
+                vec![Step::unstash("binaries"), Step::shell("test -f out/app")],
+            ),
+        };
+        let test = Stage::new("Test", vec![Step::shell("test ! -f out/build.log")])
+            .with_parallel(vec![branch("Unit"), branch("Integration")]);
+
+        let pipeline = Pipeline::builder()
+            .agent(AgentType::Any)
+            .stages(vec![build, test])
+            .build_unchecked();
+
+        assert_eq!(executor.execute(&pipeline).unwrap(), StageResult::Success);
+        // The per-build stash area is removed once the build finishes
+        let tmp_entries = std::fs::read_dir(temp_dir.path().join("@tmp")).unwrap();
+        assert_eq!(tmp_entries.count(), 0);
+    }
+
+    #[test]
+    fn test_unstash_missing_fails() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path());
+
+        let pipeline = Pipeline::builder()
+            .agent(AgentType::Any)
+            .stages(vec![Stage::new("Test", vec![Step::unstash("nothing")])])
+            .build_unchecked();
+
+        let result = executor.execute(&pipeline);
+        assert_eq!(
+            result.unwrap_err(),
+            crate::pipeline::PipelineError::StashNotFound {
+                name: "nothing".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_dir_blocks_nest_and_restore_cwd() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path())
+            .with_env("SERVICE", "api");
+
+        let steps = vec![
+            Step::dir(
+                "services/${SERVICE}",
+                vec![
+                    Step::shell("echo api > where"),
+                    Step::dir("build", vec![Step::shell("touch marker")]),
+                    Step::stash("sources", "where"),
+                ],
+            ),
+            Step::dir("copy", vec![Step::unstash("sources")]),
+            Step::shell(
+                "test -f services/api/where && test -f services/api/build/marker \
+                 && test -f copy/where && test ! -f where",
+            ),
+        ];
+        let pipeline = Pipeline::builder()
+            .agent(AgentType::Any)
+            .stages(vec![Stage::new("Build", steps)])
+            .build_unchecked();
+
+        assert_eq!(executor.execute(&pipeline).unwrap(), StageResult::Success);
+    }
+
+    #[test]
+    fn test_dir_restores_cwd_after_failure() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path());
+        let mut context = PipelineContext::new();
+        context.set_cwd(temp_dir.path());
+
+        let step = Step::dir("sub", vec![Step::shell("exit 1")]);
+        assert!(executor.execute_step(&step, &mut context).is_err());
+        assert_eq!(context.cwd, temp_dir.path());
+        assert!(context.dir_stack.is_empty());
+    }
+
+    #[test]
+    fn test_dir_missing_fails_when_required() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path())
+            .with_require_existing_dirs(true);
+
+        let pipeline = Pipeline::builder()
+            .agent(AgentType::Any)
+            .stages(vec![Stage::new(
+                "Build",
+                vec![Step::dir("missing", vec![Step::echo("unreachable")])],
+            )])
+            .build_unchecked();
+
+        assert!(executor.execute(&pipeline).is_err());
+        assert!(!temp_dir.path().join("missing").exists());
+    }
+
+    #[test]
+    fn test_parallel_branches_have_own_dirs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path());
+
+        let branch = |name: &str| crate::pipeline::ParallelBranch {
+            name: name.to_string(),
+            stage: Stage::new(
+                name,
+                vec![Step::dir(
+                    name,
+                    vec![Step::shell("sleep 0.1 && pwd > cwd.txt")],
+                )],
+            ),
+        };
+        let stage =
+            Stage::new("Build", vec![]).with_parallel(vec![branch("frontend"), branch("backend")]);
+        let pipeline = Pipeline::builder()
+            .agent(AgentType::Any)
+            .stages(vec![stage])
+            .build_unchecked();
+
+        assert_eq!(executor.execute(&pipeline).unwrap(), StageResult::Success);
+        for name in ["frontend", "backend"] {
+            let cwd = std::fs::read_to_string(temp_dir.path().join(name).join("cwd.txt")).unwrap();
+            assert!(cwd.trim().ends_with(name));
+        }
+    }
+
+    #[test]
+    fn test_custom_step_sees_dir() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let registry = crate::pipeline::SharedRegistry::new();
+        registry.register(crate::pipeline::plugins::ClosureCustomStep::new(
+            "touch",
+            "Creates a marker file",
+            |context: &PipelineContext| {
+                std::fs::write(context.cwd.join("marker"), "")?;
+                Ok(())
+            },
+        ));
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path())
+            .with_custom_steps(registry);
+        let mut context = PipelineContext::new();
+        context.set_cwd(temp_dir.path());
+
+        let step = Step::dir("module", vec![Step::custom("touch")]);
+        executor.execute_step(&step, &mut context).unwrap();
+        assert!(temp_dir.path().join("module/marker").exists());
+
+        let result = executor.execute_step(&Step::custom("unknown"), &mut context);
+        assert_eq!(
+            result.unwrap_err(),
+            crate::pipeline::PipelineError::CustomStepNotFound {
+                name: "unknown".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_recorded_build_feeds_the_next_one() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let history = BuildHistory::new(temp_dir.path().join("history"));
+        let executor = LocalExecutor::new()
+            .with_cwd(temp_dir.path())
+            .with_history(history.clone());
+
+        let pipeline = Pipeline::builder()
+            .name("recorded")
+            .agent(AgentType::Any)
+            .stages(vec![Stage::new(
+                "Build",
+                vec![
+                    Step::shell("echo ${BUILD_NUMBER} >> numbers.log"),
+                    Step::shell("test -f fixed"),
+                ],
+            )])
+            .posts(vec![crate::pipeline::PostCondition::changed(vec![
+                Step::shell("echo ${BUILD_NUMBER} >> changed.log"),
+            ])])
+            .build_unchecked();
+
+        assert!(executor.execute(&pipeline).is_err());
+        std::fs::write(temp_dir.path().join("fixed"), "").unwrap();
+        assert_eq!(executor.execute(&pipeline).unwrap(), StageResult::Success);
+
+        let read = |name: &str| std::fs::read_to_string(temp_dir.path().join(name)).unwrap();
+        assert_eq!(read("numbers.log"), "1\n2\n");
+        // The first build has no previous one, the second follows a failure
+        assert_eq!(read("changed.log"), "1\n2\n");
+
+        let numbers: Vec<u64> = history
+            .builds("recorded")
+            .unwrap()
+            .iter()
+            .map(|b| b.number)
+            .collect();
+        assert_eq!(numbers, vec![2, 1]);
+    }
+
+    #[test]
+    fn test_unavailable_history_does_not_fail_build() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let blocked = temp_dir.path().join("history");
+        std::fs::write(&blocked, "not a directory").unwrap();
+        let executor = LocalExecutor::new()
+            .with_cwd(temp_dir.path())
+            .with_history(BuildHistory::new(&blocked));
+
+        let pipeline = Pipeline::builder()
+            .name("unrecorded")
+            .agent(AgentType::Any)
+            .stages(vec![Stage::new("Build", vec![Step::echo("build")])])
+            .build_unchecked();
+
+        assert_eq!(executor.execute(&pipeline).unwrap(), StageResult::Success);
+    }
+
+    #[test]
+    fn test_history_numbers_builds_and_feeds_post_conditions() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let history = BuildHistory::new(temp_dir.path().join("history"));
+        let executor = LocalExecutor::new()
+            .with_cwd(temp_dir.path())
+            .with_history(history.clone());
+
+        let log = |name: &str| Step::shell(format!("echo {name} >> posts.log"));
+        let pipeline = Pipeline::builder()
+            .name("my app")
+            .agent(AgentType::Any)
+            .with_parameters(crate::pipeline::Parameters::new().string("TARGET", "staging"))
+            .stages(vec![Stage::new(
+                "Build",
+                vec![
+                    Step::shell("echo ${BUILD_NUMBER} >> numbers.log"),
+                    Step::shell("test ! -f broken"),
+                ],
+            )])
+            .posts(vec![
+                crate::pipeline::PostCondition::changed(vec![log("changed")]),
+                crate::pipeline::PostCondition::fixed(vec![log("fixed")]),
+                crate::pipeline::PostCondition::regression(vec![log("regression")]),
+                crate::pipeline::PostCondition::failure(vec![log("failure")]),
+            ])
+            .build_unchecked();
+
+        let take_log = || {
+            let path = temp_dir.path().join("posts.log");
+            let content = std::fs::read_to_string(&path).unwrap_or_default();
+            let _ = std::fs::remove_file(&path);
+            content.lines().map(str::to_string).collect::<Vec<_>>()
+        };
+
+        assert_eq!(executor.execute(&pipeline).unwrap(), StageResult::Success);
+        assert_eq!(take_log(), vec!["changed"]);
+
+        std::fs::write(temp_dir.path().join("broken"), "").unwrap();
+        assert!(executor.execute(&pipeline).is_err());
+        assert_eq!(take_log(), vec!["changed", "regression", "failure"]);
+
+        std::fs::remove_file(temp_dir.path().join("broken")).unwrap();
+        assert_eq!(executor.execute(&pipeline).unwrap(), StageResult::Success);
+        assert_eq!(take_log(), vec!["changed", "fixed"]);
+
+        assert_eq!(executor.execute(&pipeline).unwrap(), StageResult::Success);
+        assert!(take_log().is_empty());
+
+        let numbers = std::fs::read_to_string(temp_dir.path().join("numbers.log")).unwrap();
+        assert_eq!(
+            numbers.lines().collect::<Vec<_>>(),
+            vec!["1", "2", "3", "4"]
+        );
+
+        let builds = history.builds("my_app").unwrap();
+        let results: Vec<_> = builds.iter().map(|b| (b.number, b.result)).collect();
+        assert_eq!(
+            results,
+            vec![
+                (4, StageResult::Success),
+                (3, StageResult::Success),
+                (2, StageResult::Failure),
+                (1, StageResult::Success),
+            ]
+        );
+        assert_eq!(builds[1].stage_result("Build"), Some(StageResult::Success));
+        assert_eq!(builds[2].stage_result("Build"), Some(StageResult::Failure));
+        assert_eq!(builds[0].parameters.get("TARGET").unwrap(), "staging");
+    }
+
+    #[test]
+    fn test_stage_post_uses_previous_stage_result() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .with_cwd(temp_dir.path())
+            .with_history(BuildHistory::new(temp_dir.path().join("history")));
+
+        let pipeline = Pipeline::builder()
+            .name("stage-post")
+            .agent(AgentType::Any)
+            .stages(vec![
+                Stage::new("Build", vec![Step::echo("build")]).with_post(
+                    crate::pipeline::PostCondition::changed(vec![Step::shell(
+                        "echo changed >> stage.log",
+                    )]),
+                ),
+            ])
+            .build_unchecked();
+
+        executor.execute(&pipeline).unwrap();
+        executor.execute(&pipeline).unwrap();
+
+        let log = std::fs::read_to_string(temp_dir.path().join("stage.log")).unwrap();
+        assert_eq!(log.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_build_discarder_applied_after_build() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let history = BuildHistory::new(temp_dir.path().join("history"));
+        let executor = LocalExecutor::new()
+            .with_cwd(temp_dir.path())
+            .with_history(history.clone());
+
+        let pipeline = Pipeline::builder()
+            .name("retained")
+            .agent(AgentType::Any)
+            .options(
+                crate::pipeline::PipelineOptions::default()
+                    .with_build_discarder(crate::pipeline::BuildDiscarder::new(2)),
+            )
+            .stages(vec![Stage::new("Build", vec![Step::echo("build")])])
+            .build_unchecked();
+
+        for _ in 0..3 {
+            executor.execute(&pipeline).unwrap();
+        }
+
+        let numbers: Vec<u64> = history
+            .builds("retained")
+            .unwrap()
+            .iter()
+            .map(|b| b.number)
+            .collect();
+        assert_eq!(numbers, vec![3, 2]);
+        assert_eq!(
+            history.discarder("retained").unwrap(),
+            Some(crate::pipeline::BuildDiscarder::new(2))
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_cancel_aborts_build_and_runs_posts() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let history = BuildHistory::new(temp_dir.path().join("history"));
+        let executor = LocalExecutor::new()
+            .with_cwd(temp_dir.path())
+            .with_history(history.clone())
+            .with_kill_grace_period(std::time::Duration::from_millis(200));
+
+        let log = |name: &str| Step::shell(format!("echo {name} >> posts.log"));
+        let pipeline = Pipeline::builder()
+            .name("cancelled")
+            .agent(AgentType::Any)
+            .stages(vec![
+                Stage::new("Build", vec![Step::shell("touch started; sleep 30")])
+                    .with_post(crate::pipeline::PostCondition::aborted(vec![log("stage")])),
+                Stage::new("Deploy", vec![Step::shell("touch deployed")]),
+            ])
+            .posts(vec![
+                crate::pipeline::PostCondition::cleanup(vec![log("cleanup")]),
+                crate::pipeline::PostCondition::failure(vec![log("failure")]),
+                crate::pipeline::PostCondition::aborted(vec![log("aborted")]),
+                crate::pipeline::PostCondition::always(vec![log("always")]),
+            ])
+            .build_unchecked();
+
+        let run = executor.start(pipeline);
+        let deadline = Instant::now() + std::time::Duration::from_secs(5);
+        while !temp_dir.path().join("started").exists() {
+            assert!(Instant::now() < deadline, "step never started");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let start = Instant::now();
+        assert!(run.cancel("stopped by test"));
+        assert_eq!(run.wait().unwrap(), StageResult::Aborted);
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+        assert!(!temp_dir.path().join("deployed").exists());
+        let posts = std::fs::read_to_string(temp_dir.path().join("posts.log")).unwrap();
+        assert_eq!(
+            posts.lines().collect::<Vec<_>>(),
+            vec!["stage", "aborted", "always", "cleanup"]
+        );
+
+        let build = history.last_build("cancelled").unwrap().unwrap();
+        assert_eq!(build.result, StageResult::Aborted);
+        assert_eq!(build.stage_result("Build"), Some(StageResult::Aborted));
+        assert_eq!(build.stage_result("Deploy"), None);
+    }
+
+    #[test]
+    fn test_cancelled_before_start_runs_no_stage() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path());
+        let pipeline = Pipeline::builder()
+            .agent(AgentType::Any)
+            .stages(vec![Stage::new("Build", vec![Step::shell("touch built")])])
+            .build_unchecked();
+
+        let cancellation = CancellationHandle::new();
+        cancellation.cancel("not needed");
+
+        let result = executor.execute_cancellable(&pipeline, &cancellation);
+        assert_eq!(result.unwrap(), StageResult::Aborted);
+        assert!(!temp_dir.path().join("built").exists());
+    }
+
+    fn input_config(dir: &std::path::Path) -> InputConfig {
+        InputConfig {
+            interactive: Some(false),
+            registry: crate::executor::InputRegistry::new(dir.join("inputs")),
+            timeout: Some(std::time::Duration::from_millis(100)),
+            ..InputConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_input_answer_is_stored_in_env() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path())
+            .with_input_config(input_config(temp_dir.path()))
+            .with_env("RUSTLINE_ANSWER_DEPLOY", "v1.2")
+            .with_env("RUSTLINE_SUBMITTER", "alice");
+
+        let stage = Stage::new(
+            "Deploy",
+            vec![
+                Step::approval("deploy", "Deploy to production?", "alice,bob"),
+                Step::shell(
+                    r#"test "$INPUT_DEPLOY" = v1.2 && test "$INPUT_DEPLOY_SUBMITTER" = alice"#,
+                ),
+            ],
+        );
+        let pipeline = Pipeline::builder()
+            .agent(AgentType::Any)
+            .stages(vec![stage])
+            .build_unchecked();
+
+        assert_eq!(executor.execute(&pipeline).unwrap(), StageResult::Success);
+    }
+
+    #[test]
+    fn test_input_rejects_unlisted_submitter() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path())
+            .with_input_config(input_config(temp_dir.path()))
+            .with_env("RUSTLINE_ANSWER_DEPLOY", "yes")
+            .with_env("RUSTLINE_SUBMITTER", "mallory");
+
+        let stage = Stage::new(
+            "Deploy",
+            vec![
+                Step::approval("deploy", "Deploy to production?", "alice"),
+                Step::shell("touch deployed"),
+            ],
+        );
+        let pipeline = Pipeline::builder()
+            .agent(AgentType::Any)
+            .stages(vec![stage])
+            .build_unchecked();
+
+        let result = executor.execute(&pipeline);
+        assert!(matches!(
+            result,
+            Err(crate::pipeline::PipelineError::InputRejected { .. })
+        ));
+        assert!(!temp_dir.path().join("deployed").exists());
+    }
+
+    #[test]
+    fn test_input_without_answer_times_out_and_aborts() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let executor = LocalExecutor::new()
+            .without_history()
+            .with_cwd(temp_dir.path())
+            .with_input_config(input_config(temp_dir.path()));
+
+        let stage = Stage::new("Deploy", vec![Step::input("Deploy to production?")]);
+        let pipeline = Pipeline::builder()
+            .agent(AgentType::Any)
+            .stages(vec![stage])
+            .build_unchecked();
+
+        let result = executor.execute(&pipeline);
+        assert!(matches!(
+            result,
+            Err(crate::pipeline::PipelineError::InputAborted { .. })
+        ));
+    }
 }
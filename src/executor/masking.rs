@@ -0,0 +1,165 @@
+//! Masking of secret values in output
+//!
+//! A `SecretMasker` knows the secret values of a build and replaces them
+//! with `MASK` wherever they would be printed: shell output, echo steps,
+//! logs and error messages. Besides the plain value it also masks the forms
+//! a secret is commonly printed in, base64 and URL encoding, and every line
+//! of a multi-line secret such as a private key.
+
+use parking_lot::RwLock;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// Replacement for masked values
+pub const MASK: &str = "****";
+
+/// Shortest value that is masked
+///
+/// Shorter values would mask unrelated output far more often than they
+/// would hide a secret.
+pub const MIN_SECRET_LEN: usize = 3;
+
+/// Replaces known secret values in text
+///
+/// Clones share the same set of secrets, so a secret added by one step of
+/// a build is masked in every step of it.
+#[derive(Debug, Clone, Default)]
+pub struct SecretMasker {
+    /// Patterns to replace, the secrets and their encodings
+    patterns: Arc<RwLock<BTreeSet<String>>>,
+}
+
+impl SecretMasker {
+    /// Creates a masker without secrets
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a secret value
+    ///
+    /// Values shorter than `MIN_SECRET_LEN` are ignored.
+    pub fn add(&self, secret: &str) {
+        if secret.len() < MIN_SECRET_LEN {
+            return;
+        }
+        let candidates = std::iter::once(secret.to_string())
+            .chain(secret.lines().map(str::trim).map(str::to_string))
+            .chain([
+                base64_encode(secret.as_bytes(), true),
+                base64_encode(secret.as_bytes(), false),
+                url_encode(secret),
+            ]);
+
+        self.patterns
+            .write()
+            .extend(candidates.filter(|pattern| pattern.len() >= MIN_SECRET_LEN));
+    }
+
+    /// Returns true if no secret is known
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.patterns.read().is_empty()
+    }
+
+    /// Replaces every known secret in `text` with `MASK`
+    #[must_use]
+    pub fn mask(&self, text: &str) -> String {
+        let patterns = self.patterns.read();
+
+        // Longer patterns first, so a secret is not partly masked through
+        // one of its lines or a shorter secret it contains
+        let mut ordered: Vec<&String> = patterns
+            .iter()
+            .filter(|pattern| text.contains(pattern.as_str()))
+            .collect();
+        ordered.sort_by_key(|pattern| std::cmp::Reverse(pattern.len()));
+
+        let mut masked = text.to_string();
+        for pattern in ordered {
+            masked = masked.replace(pattern.as_str(), MASK);
+        }
+        masked
+    }
+}
+
+/// Encodes bytes as standard base64
+fn base64_encode(bytes: &[u8], padding: bool) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | u32::from(byte) << (16 - 8 * i)
+        });
+        for i in 0..=chunk.len() {
+            let index = (group >> (18 - 6 * i)) & 0x3f;
+            encoded.push(char::from(ALPHABET[index as usize]));
+        }
+        if padding {
+            for _ in chunk.len()..3 {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Percent-encodes every byte outside the URL unreserved set
+fn url_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(char::from(byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_masks_value_and_encodings() {
+        let masker = SecretMasker::new();
+        masker.add("p@ss word!");
+
+        assert_eq!(masker.mask("token=p@ss word! end"), "token=**** end");
+        assert_eq!(masker.mask("b64 cEBzcyB3b3JkIQ=="), "b64 ****");
+        assert_eq!(masker.mask("url p%40ss%20word%21"), "url ****");
+        assert_eq!(masker.mask("nothing here"), "nothing here");
+    }
+
+    #[test]
+    fn test_masks_lines_of_multiline_secret() {
+        let masker = SecretMasker::new();
+        masker.add("-----BEGIN KEY-----\nAAAAB3NzaC1yc2E\n-----END KEY-----");
+
+        assert_eq!(masker.mask("line: AAAAB3NzaC1yc2E"), "line: ****");
+    }
+
+    #[test]
+    fn test_short_values_are_not_masked() {
+        let masker = SecretMasker::new();
+        masker.add("ab");
+
+        assert!(masker.is_empty());
+        assert_eq!(masker.mask("abc"), "abc");
+    }
+
+    #[test]
+    fn test_secrets_shared_by_clones() {
+        let masker = SecretMasker::new();
+        masker.add("s3cr3t-token");
+        masker.clone().add("another-secret");
+
+        assert_eq!(
+            masker.mask("deploy s3cr3t-token another-secret"),
+            "deploy **** ****"
+        );
+    }
+}
@@ -2,12 +2,28 @@
 //!
 //! This module contains traits and implementations for executing pipelines.
 
+mod cancel;
+mod history;
+mod input;
 mod local;
+mod masking;
+mod process;
+mod retention;
 mod shell;
+mod stash;
 mod temp_files;
 mod traits;
+mod when;
 
+pub use cancel::{BuildRun, CancellationHandle};
+pub use history::{BranchRecord, BuildHistory, BuildRecord, HISTORY_DIR_VAR, StageRecord};
+pub use input::{InputAnswer, InputConfig, InputRegistry, InputRequest};
 pub use local::{ExecutorConfig, LocalExecutor};
+pub use masking::{MASK, SecretMasker};
+pub use process::{DEFAULT_GRACE_PERIOD, KillReason, ProcessScope};
+pub use retention::{RetentionReport, apply_retention};
 pub use shell::{ShellCommand, ShellConfig, ShellResult, expand_variables, jenkins_shell_config};
+pub use stash::StashStore;
 pub use temp_files::{JenkinsPathResolver, TempFileManager};
 pub use traits::{ExecutorCapabilities, HealthStatus, PipelineContext, PipelineExecutor};
+pub use when::evaluate_when;
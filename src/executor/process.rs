@@ -0,0 +1,330 @@
+//! Process groups of running steps
+//!
+//! Shell steps start in their own process group and register it with the
+//! `ProcessScope` they run in. Killing a scope terminates every group
+//! registered in it or in one of its child scopes: SIGTERM first, then
+//! SIGKILL for groups still alive after the grace period. A killed scope
+//! refuses to start new processes, so a step that outlives its timeout
+//! cannot leave new work behind.
+
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Grace period between SIGTERM and SIGKILL when none is configured
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// How often a kill checks whether terminated groups have exited
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Why the processes of a scope were killed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KillReason {
+    /// The step exceeded its timeout
+    Timeout(Duration),
+
+    /// The build was aborted
+    Aborted(String),
+}
+
+impl fmt::Display for KillReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout(duration) => write!(f, "timed out after {duration:?}"),
+            Self::Aborted(reason) => write!(f, "aborted: {reason}"),
+        }
+    }
+}
+
+impl KillReason {
+    /// Converts the kill of `step` into the error the step fails with
+    ///
+    /// An elapsed deadline is reported as a timeout; any other kill as
+    /// `StepKilled`.
+    pub fn into_error(self, step: impl Into<String>) -> crate::pipeline::PipelineError {
+        match self {
+            Self::Timeout(duration) => crate::pipeline::PipelineError::Timeout { duration },
+            Self::Aborted(_) => crate::pipeline::PipelineError::StepKilled {
+                step: step.into(),
+                reason: self.to_string(),
+            },
+        }
+    }
+}
+
+/// Mutable state of a scope
+#[derive(Debug, Default)]
+struct ScopeState {
+    /// Process group IDs running in this scope or its children
+    groups: HashSet<u32>,
+
+    /// Set once the scope has been killed
+    killed: Option<KillReason>,
+}
+
+/// Set of process groups that are killed together
+#[derive(Debug, Default)]
+pub struct ProcessScope {
+    /// Enclosing scope, which also tracks this scope's groups
+    parent: Option<Arc<ProcessScope>>,
+
+    /// Registered groups and kill state
+    state: Mutex<ScopeState>,
+}
+
+impl ProcessScope {
+    /// Creates a root scope
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a scope whose groups are also killed with this one
+    #[must_use]
+    pub fn child(self: &Arc<Self>) -> Arc<Self> {
+        Arc::new(Self {
+            parent: Some(Arc::clone(self)),
+            state: Mutex::new(ScopeState::default()),
+        })
+    }
+
+    /// Returns why this scope or an enclosing one was killed
+    #[must_use]
+    pub fn killed(&self) -> Option<KillReason> {
+        self.state
+            .lock()
+            .killed
+            .clone()
+            .or_else(|| self.parent.as_ref().and_then(|parent| parent.killed()))
+    }
+
+    /// Returns the number of process groups running in this scope
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.state.lock().groups.len()
+    }
+
+    /// Returns true if no process group is running in this scope
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Registers a process group with this scope and its ancestors
+    ///
+    /// # Errors
+    ///
+    /// Returns the kill reason if the scope was already killed; the caller
+    /// must then kill the group itself.
+    pub(crate) fn register(&self, pgid: u32) -> Result<(), KillReason> {
+        {
+            let mut state = self.state.lock();
+            if let Some(ref reason) = state.killed {
+                return Err(reason.clone());
+            }
+            state.groups.insert(pgid);
+        }
+
+        if let Some(ref parent) = self.parent
+            && let Err(reason) = parent.register(pgid)
+        {
+            self.state.lock().groups.remove(&pgid);
+            return Err(reason);
+        }
+        Ok(())
+    }
+
+    /// Removes a finished process group from this scope and its ancestors
+    pub(crate) fn unregister(&self, pgid: u32) {
+        self.state.lock().groups.remove(&pgid);
+        if let Some(ref parent) = self.parent {
+            parent.unregister(pgid);
+        }
+    }
+
+    /// Kills every process group in this scope
+    ///
+    /// Sends SIGTERM and waits up to `grace` for the groups to finish, that
+    /// is for their leaders to be reaped and unregistered. SIGKILL then goes
+    /// to every group, which also stops members that ignored SIGTERM after
+    /// their leader exited. Returns the number of groups signalled. Killing
+    /// an already killed scope keeps the first reason.
+    pub fn kill(&self, reason: KillReason, grace: Duration) -> usize {
+        let message = reason.to_string();
+        let groups: Vec<u32> = {
+            let mut state = self.state.lock();
+            state.killed.get_or_insert(reason);
+            state.groups.iter().copied().collect()
+        };
+        if groups.is_empty() {
+            return 0;
+        }
+
+        tracing::warn!(groups = groups.len(), reason = %message, "Terminating process groups");
+        for &pgid in &groups {
+            signal_group(pgid, Signal::Terminate);
+        }
+
+        let deadline = Instant::now() + grace;
+        loop {
+            let running = {
+                let state = self.state.lock();
+                groups
+                    .iter()
+                    .filter(|pgid| state.groups.contains(pgid))
+                    .count()
+            };
+            if running == 0 {
+                break;
+            }
+            if Instant::now() >= deadline {
+                tracing::warn!(
+                    groups = running,
+                    "Grace period expired, killing process groups"
+                );
+                break;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        for &pgid in &groups {
+            signal_group(pgid, Signal::Kill);
+        }
+        groups.len()
+    }
+}
+
+/// Signals sent to process groups
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Signal {
+    /// Asks the group to terminate (SIGTERM)
+    Terminate,
+    /// Kills the group (SIGKILL)
+    Kill,
+}
+
+/// Sends a signal to every process in a group
+///
+/// Returns true if at least one process received it.
+#[cfg(unix)]
+pub(crate) fn signal_group(pgid: u32, signal: Signal) -> bool {
+    let Ok(pgid) = libc::pid_t::try_from(pgid) else {
+        return false;
+    };
+    let signal = match signal {
+        Signal::Terminate => libc::SIGTERM,
+        Signal::Kill => libc::SIGKILL,
+    };
+    // SAFETY: kill(2) has no memory-safety preconditions
+    unsafe { libc::kill(-pgid, signal) == 0 }
+}
+
+/// Sends a signal to every process in a group
+///
+/// Process groups only exist on Unix; elsewhere nothing is signalled.
+#[cfg(not(unix))]
+pub(crate) fn signal_group(_pgid: u32, _signal: Signal) -> bool {
+    false
+}
+
+/// Starts a command in a new process group
+///
+/// The group ID equals the child's PID.
+pub(crate) fn in_new_group(command: &mut std::process::Command) -> &mut std::process::Command {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    command
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::process::{Command, ExitStatus};
+    use std::thread::JoinHandle;
+
+    /// Starts a script in `scope`, reaped by a thread like a running step
+    fn spawn_group(scope: &Arc<ProcessScope>, script: &str) -> JoinHandle<ExitStatus> {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        let mut child = in_new_group(&mut command).spawn().unwrap();
+        scope.register(child.id()).unwrap();
+
+        let scope = Arc::clone(scope);
+        std::thread::spawn(move || {
+            let status = child.wait().unwrap();
+            scope.unregister(child.id());
+            status
+        })
+    }
+
+    #[test]
+    fn test_kill_terminates_group() {
+        let scope = Arc::new(ProcessScope::new());
+        let waiter = spawn_group(&scope, "sleep 30");
+
+        let start = Instant::now();
+        let killed = scope.kill(
+            KillReason::Aborted("test".to_string()),
+            Duration::from_secs(5),
+        );
+
+        assert_eq!(killed, 1);
+        assert!(!waiter.join().unwrap().success());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_kill_escalates_after_grace_period() {
+        let scope = Arc::new(ProcessScope::new());
+        let waiter = spawn_group(&scope, "trap '' TERM; sleep 30");
+        // Give the shell time to install the trap
+        std::thread::sleep(Duration::from_millis(100));
+
+        let start = Instant::now();
+        scope.kill(
+            KillReason::Timeout(Duration::ZERO),
+            Duration::from_millis(200),
+        );
+
+        assert!(!waiter.join().unwrap().success());
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_child_scope_groups_are_killed_with_parent() {
+        let parent = Arc::new(ProcessScope::new());
+        let child_scope = parent.child();
+        let waiter = spawn_group(&child_scope, "sleep 30");
+        assert_eq!(parent.len(), 1);
+
+        parent.kill(
+            KillReason::Aborted("test".to_string()),
+            Duration::from_secs(5),
+        );
+        waiter.join().unwrap();
+
+        assert!(child_scope.killed().is_some());
+        assert_eq!(
+            child_scope.register(1),
+            Err(KillReason::Aborted("test".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_unregister_removes_from_ancestors() {
+        let parent = Arc::new(ProcessScope::new());
+        let child_scope = parent.child();
+
+        child_scope.register(42).unwrap();
+        child_scope.unregister(42);
+
+        assert!(parent.is_empty());
+        assert!(child_scope.is_empty());
+    }
+}
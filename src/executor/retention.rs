@@ -0,0 +1,301 @@
+//! Build retention
+//!
+//! Applies a `BuildDiscarder` to the builds stored in a `BuildHistory`.
+//! Build limits remove whole builds with their logs and artifacts, while
+//! artifact limits only remove the archived artifacts of older builds.
+//!
+//! Count limits are applied newest first across all finished builds. Builds
+//! marked keep-forever and the last successful build are never removed, but
+//! still count towards the limits.
+
+use super::history::{BuildHistory, BuildRecord};
+use crate::pipeline::{BuildDiscarder, PipelineError};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds in a day, the unit of the `days_to_keep` limits
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// What a retention pass removed from a job
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionReport {
+    /// Job the pass ran on
+    pub job: String,
+
+    /// Builds removed entirely, newest first
+    pub builds_removed: Vec<u64>,
+
+    /// Builds whose artifacts were removed, newest first
+    pub artifacts_removed: Vec<u64>,
+
+    /// Bytes freed on disk
+    pub bytes_freed: u64,
+
+    /// Whether nothing was actually removed
+    pub dry_run: bool,
+}
+
+impl RetentionReport {
+    /// Returns true if the pass removed nothing
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.builds_removed.is_empty() && self.artifacts_removed.is_empty()
+    }
+}
+
+impl fmt::Display for RetentionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = if self.dry_run {
+            "would remove"
+        } else {
+            "removed"
+        };
+        if self.is_empty() {
+            return write!(f, "{}: nothing to remove", self.job);
+        }
+
+        write!(
+            f,
+            "{}: {verb} {} build(s) {} and artifacts of {} build(s) {} ({} bytes)",
+            self.job,
+            self.builds_removed.len(),
+            format_numbers(&self.builds_removed),
+            self.artifacts_removed.len(),
+            format_numbers(&self.artifacts_removed),
+            self.bytes_freed
+        )
+    }
+}
+
+/// Applies a retention policy to a job's builds
+///
+/// With `dry_run` set, the report lists what would be removed without
+/// touching the history.
+///
+/// # Errors
+///
+/// Returns `PipelineError::Io` if the history cannot be read or a build
+/// cannot be removed.
+pub fn apply_retention(
+    history: &BuildHistory,
+    job: &str,
+    discarder: &BuildDiscarder,
+    dry_run: bool,
+) -> Result<RetentionReport, PipelineError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    apply_retention_at(history, job, discarder, dry_run, now)
+}
+
+/// Applies a retention policy as if the current time were `now`
+fn apply_retention_at(
+    history: &BuildHistory,
+    job: &str,
+    discarder: &BuildDiscarder,
+    dry_run: bool,
+    now: u64,
+) -> Result<RetentionReport, PipelineError> {
+    let builds = history.builds(job)?;
+    let last_success = builds
+        .iter()
+        .find(|build| build.result.is_success())
+        .map(|build| build.number);
+    let protected = |build: &BuildRecord| build.keep_forever || Some(build.number) == last_success;
+
+    let mut report = RetentionReport {
+        job: job.to_string(),
+        dry_run,
+        ..RetentionReport::default()
+    };
+
+    let build_limit = (discarder.num_to_keep > 0).then_some(discarder.num_to_keep);
+    for (index, build) in builds.iter().enumerate() {
+        if protected(build) {
+            continue;
+        }
+
+        if expired(build, index, build_limit, discarder.days_to_keep, now) {
+            report.bytes_freed += if dry_run {
+                0
+            } else {
+                history.delete(job, build.number)?
+            };
+            report.builds_removed.push(build.number);
+        } else if expired(
+            build,
+            index,
+            discarder.artifact_num_to_keep,
+            discarder.artifact_days_to_keep,
+            now,
+        ) && history.artifacts_dir(job, build.number).exists()
+        {
+            report.bytes_freed += if dry_run {
+                0
+            } else {
+                history.delete_artifacts(job, build.number)?
+            };
+            report.artifacts_removed.push(build.number);
+        }
+    }
+
+    if !report.is_empty() {
+        tracing::info!(
+            job = %job,
+            builds = report.builds_removed.len(),
+            artifacts = report.artifacts_removed.len(),
+            bytes = report.bytes_freed,
+            dry_run,
+            "Applied build retention"
+        );
+    }
+    Ok(report)
+}
+
+/// Returns true if a build at `index` (newest first) is past either limit
+fn expired(
+    build: &BuildRecord,
+    index: usize,
+    num_to_keep: Option<usize>,
+    days_to_keep: Option<usize>,
+    now: u64,
+) -> bool {
+    let past_count = num_to_keep.is_some_and(|keep| index >= keep);
+    let past_age = days_to_keep.is_some_and(|days| {
+        let max_age = u64::try_from(days)
+            .unwrap_or(u64::MAX)
+            .saturating_mul(SECS_PER_DAY);
+        now.saturating_sub(build.started_at) > max_age
+    });
+    past_count || past_age
+}
+
+/// Formats build numbers as `#3, #2`
+fn format_numbers(numbers: &[u64]) -> String {
+    if numbers.is_empty() {
+        return "-".to_string();
+    }
+    numbers
+        .iter()
+        .map(|n| format!("#{n}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::StageResult;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    const NOW: u64 = 100 * SECS_PER_DAY;
+
+    /// Records builds with the given results, one day apart, oldest first
+    fn history_with(results: &[StageResult]) -> (TempDir, BuildHistory) {
+        let temp_dir = TempDir::new().unwrap();
+        let history = BuildHistory::new(temp_dir.path());
+        let count = results.len() as u64;
+
+        for (i, result) in results.iter().enumerate() {
+            let number = history.next_build_number("app").unwrap();
+            let mut build = BuildRecord::new("app", number);
+            build.started_at = NOW - (count - i as u64) * SECS_PER_DAY;
+            build.finish(*result, Duration::from_secs(1));
+            history.record(&build).unwrap();
+
+            let artifacts = history.artifacts_dir("app", number);
+            std::fs::create_dir_all(&artifacts).unwrap();
+            std::fs::write(artifacts.join("app.bin"), "1234").unwrap();
+            std::fs::write(history.log_path("app", number), "log").unwrap();
+        }
+        (temp_dir, history)
+    }
+
+    fn remaining(history: &BuildHistory) -> Vec<u64> {
+        history
+            .builds("app")
+            .unwrap()
+            .iter()
+            .map(|b| b.number)
+            .collect()
+    }
+
+    #[test]
+    fn test_num_to_keep_removes_oldest_builds() {
+        let (_dir, history) = history_with(&[StageResult::Success; 5]);
+
+        let report =
+            apply_retention_at(&history, "app", &BuildDiscarder::new(2), false, NOW).unwrap();
+
+        assert_eq!(report.builds_removed, vec![3, 2, 1]);
+        assert_eq!(remaining(&history), vec![5, 4]);
+        assert!(!history.build_dir("app", 1).exists());
+        assert!(report.bytes_freed > 0);
+    }
+
+    #[test]
+    fn test_days_to_keep_removes_old_builds() {
+        let (_dir, history) = history_with(&[StageResult::Success; 4]);
+
+        let discarder = BuildDiscarder::new(0).with_days_to_keep(2);
+        let report = apply_retention_at(&history, "app", &discarder, false, NOW).unwrap();
+
+        assert_eq!(report.builds_removed, vec![2, 1]);
+        assert_eq!(remaining(&history), vec![4, 3]);
+    }
+
+    #[test]
+    fn test_keep_forever_and_last_success_are_kept() {
+        let (_dir, history) = history_with(&[
+            StageResult::Success,
+            StageResult::Success,
+            StageResult::Success,
+            StageResult::Failure,
+            StageResult::Failure,
+        ]);
+        history.set_keep_forever("app", 1, true).unwrap();
+
+        let report =
+            apply_retention_at(&history, "app", &BuildDiscarder::new(1), false, NOW).unwrap();
+
+        // #3 is the last successful build
+        assert_eq!(report.builds_removed, vec![4, 2]);
+        assert_eq!(remaining(&history), vec![5, 3, 1]);
+    }
+
+    #[test]
+    fn test_artifact_limits_keep_builds() {
+        let (_dir, history) = history_with(&[StageResult::Success; 3]);
+
+        let discarder = BuildDiscarder::new(0).with_artifact_num_to_keep(1);
+        let report = apply_retention_at(&history, "app", &discarder, false, NOW).unwrap();
+
+        // The newest build is the last success and keeps its artifacts anyway
+        assert_eq!(report.artifacts_removed, vec![2, 1]);
+        assert!(report.builds_removed.is_empty());
+        assert_eq!(remaining(&history), vec![3, 2, 1]);
+        assert!(!history.artifacts_dir("app", 2).exists());
+        assert!(history.log_path("app", 2).exists());
+
+        // Nothing left to remove on the next pass
+        let report = apply_retention_at(&history, "app", &discarder, false, NOW).unwrap();
+        assert!(report.is_empty());
+    }
+
+    #[test]
+    fn test_dry_run_removes_nothing() {
+        let (_dir, history) = history_with(&[StageResult::Success; 3]);
+
+        let report =
+            apply_retention_at(&history, "app", &BuildDiscarder::new(1), true, NOW).unwrap();
+
+        assert_eq!(report.builds_removed, vec![2, 1]);
+        assert_eq!(remaining(&history), vec![3, 2, 1]);
+        assert!(
+            report
+                .to_string()
+                .starts_with("app: would remove 2 build(s) #2, #1")
+        );
+    }
+}
@@ -36,6 +36,10 @@
 //! | `STAGE_NAME` | Name of the current stage |
 //! | `NODE_NAME` | Name of the agent node |
 
+use super::masking::SecretMasker;
+use super::process::{
+    DEFAULT_GRACE_PERIOD, KillReason, ProcessScope, Signal, in_new_group, signal_group,
+};
 use crate::pipeline::PipelineError;
 use regex::Regex;
 use std::collections::HashMap;
@@ -48,13 +52,6 @@ use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use uuid::Uuid;
 
-#[cfg(test)]
-use std::sync::atomic::{AtomicUsize, Ordering};
-
-/// Counter for build numbers in test mode
-#[cfg(test)]
-static BUILD_COUNTER: AtomicUsize = AtomicUsize::new(1);
-
 /// Shell execution configuration
 #[derive(Debug, Clone)]
 pub struct ShellConfig {
@@ -72,6 +69,12 @@ pub struct ShellConfig {
 
     /// Timeout for commands (None = no timeout)
     pub timeout: Option<Duration>,
+
+    /// Time between SIGTERM and SIGKILL when a command is killed
+    pub kill_grace: Duration,
+
+    /// Secrets masked in the command's output and errors
+    pub masker: SecretMasker,
 }
 
 impl Default for ShellConfig {
@@ -82,6 +85,8 @@ impl Default for ShellConfig {
             shell: "sh".to_string(),
             streaming: false,
             timeout: None,
+            kill_grace: DEFAULT_GRACE_PERIOD,
+            masker: SecretMasker::new(),
         }
     }
 }
@@ -121,6 +126,7 @@ impl ShellResult {
 pub struct ShellCommand<'a> {
     config: &'a ShellConfig,
     env_override: HashMap<String, String>,
+    scope: Option<Arc<ProcessScope>>,
 }
 
 impl<'a> ShellCommand<'a> {
@@ -130,9 +136,18 @@ impl<'a> ShellCommand<'a> {
         Self {
             config,
             env_override: HashMap::new(),
+            scope: None,
         }
     }
 
+    /// Runs the command's process group in `scope`, so killing the scope
+    /// kills the command
+    #[must_use]
+    pub fn scope(mut self, scope: Arc<ProcessScope>) -> Self {
+        self.scope = Some(scope);
+        self
+    }
+
     /// Adds environment variables for this command only
     #[must_use]
     pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
@@ -162,7 +177,7 @@ impl<'a> ShellCommand<'a> {
 
         let start = Instant::now();
 
-        tracing::debug!(command = %expanded, "Executing shell command");
+        tracing::debug!(command = %self.config.masker.mask(&expanded), "Executing shell command");
 
         if self.config.streaming {
             self.execute_streaming(&expanded, &env)
@@ -181,41 +196,7 @@ impl<'a> ShellCommand<'a> {
         command: &str,
         env: &HashMap<String, String>,
     ) -> Result<ShellResult, PipelineError> {
-        let mut cmd = Command::new(&self.config.shell);
-        cmd.arg("-c");
-        cmd.arg(command);
-        cmd.current_dir(&self.config.cwd);
-        cmd.envs(env);
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
-
-        let output = cmd.output().map_err(|e| PipelineError::Io(e.to_string()))?;
-
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-
-        let exit_code = output.status.code().unwrap_or(-1);
-
-        if !stdout.is_empty() {
-            print!("{}", stdout);
-        }
-        if !stderr.is_empty() {
-            eprint!("{}", stderr);
-        }
-
-        if exit_code != 0 {
-            return Err(PipelineError::CommandFailed {
-                code: exit_code,
-                stderr,
-            });
-        }
-
-        Ok(ShellResult {
-            stdout,
-            stderr,
-            exit_code,
-            duration: Duration::ZERO,
-        })
+        Self::execute_captured_internal(command, self.config, env, self.scope.as_deref())
     }
 
     /// Executes command with streaming output
@@ -232,7 +213,9 @@ impl<'a> ShellCommand<'a> {
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
 
-        let mut child = cmd.spawn().map_err(|e| PipelineError::Io(e.to_string()))?;
+        let scope = self.scope.as_deref();
+        let mut child = spawn_in_scope(&mut cmd, &self.config.masker.mask(command), scope)?;
+        let pgid = child.id();
 
         let stdout = child.stdout.take().unwrap();
         let stderr = child.stderr.take().unwrap();
@@ -242,10 +225,12 @@ impl<'a> ShellCommand<'a> {
 
         let stdout_thread = {
             let stdout_handle = Arc::clone(&stdout_handle);
+            let masker = self.config.masker.clone();
             std::thread::spawn(move || {
                 let reader = io::BufReader::new(stdout);
                 for line in reader.lines() {
                     if let Ok(line) = line {
+                        let line = masker.mask(&line);
                         println!("{}", line);
                         let mut guard = stdout_handle.lock().unwrap();
                         guard.push_str(&line);
@@ -257,10 +242,12 @@ impl<'a> ShellCommand<'a> {
 
         let stderr_thread = {
             let stderr_handle = Arc::clone(&stderr_handle);
+            let masker = self.config.masker.clone();
             std::thread::spawn(move || {
                 let reader = io::BufReader::new(stderr);
                 for line in reader.lines() {
                     if let Ok(line) = line {
+                        let line = masker.mask(&line);
                         eprintln!("WARN: {}", line);
                         let mut guard = stderr_handle.lock().unwrap();
                         guard.push_str(&line);
@@ -270,9 +257,13 @@ impl<'a> ShellCommand<'a> {
             })
         };
 
-        let status = child.wait().map_err(|e| PipelineError::Io(e.to_string()))?;
+        let status = child.wait();
         let _ = stdout_thread.join();
         let _ = stderr_thread.join();
+        if let Some(scope) = scope {
+            scope.unregister(pgid);
+        }
+        let status = status.map_err(|e| PipelineError::Io(e.to_string()))?;
 
         let stdout = {
             let guard = stdout_handle.lock().unwrap();
@@ -286,10 +277,8 @@ impl<'a> ShellCommand<'a> {
         let exit_code = status.code().unwrap_or(-1);
 
         if exit_code != 0 {
-            return Err(PipelineError::CommandFailed {
-                code: exit_code,
-                stderr,
-            });
+            let command = self.config.masker.mask(command);
+            return Err(failure(&command, exit_code, stderr, scope));
         }
 
         Ok(ShellResult {
@@ -325,28 +314,39 @@ impl<'a> ShellCommand<'a> {
             .map(|(k, v)| (k.clone(), v.clone()))
             .collect();
 
+        // The command gets its own scope so a timeout kills only its processes
+        let scope = self
+            .scope
+            .as_ref()
+            .map_or_else(|| Arc::new(ProcessScope::new()), ProcessScope::child);
+
         let (tx, rx) = std::sync::mpsc::channel();
-        let cmd = Arc::new((expanded.clone(), self.config.clone(), env.clone()));
+        let config = self.config.clone();
+        let command_scope = Arc::clone(&scope);
+        let command_text = expanded.clone();
 
         std::thread::spawn(move || {
-            let result = Self::execute_captured_internal(&cmd.0, &cmd.1, &cmd.2);
+            let result =
+                Self::execute_captured_internal(&command_text, &config, &env, Some(&command_scope));
             let _ = tx.send(result);
         });
 
-        let result = match rx.recv_timeout(timeout) {
+        match rx.recv_timeout(timeout) {
             Ok(result) => result,
             Err(_) => {
-                return Err(PipelineError::Timeout { duration: timeout });
+                let reason = KillReason::Timeout(timeout);
+                scope.kill(reason.clone(), self.config.kill_grace);
+                Err(reason.into_error(self.config.masker.mask(&expanded)))
             }
-        };
-
-        result
+        }
     }
 
+    /// Runs a command in its own process group and captures its output
     fn execute_captured_internal(
         command: &str,
         config: &ShellConfig,
         env: &HashMap<String, String>,
+        scope: Option<&ProcessScope>,
     ) -> Result<ShellResult, PipelineError> {
         let mut cmd = Command::new(&config.shell);
         cmd.arg("-c");
@@ -356,10 +356,17 @@ impl<'a> ShellCommand<'a> {
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
 
-        let output = cmd.output().map_err(|e| PipelineError::Io(e.to_string()))?;
+        let masker = &config.masker;
+        let child = spawn_in_scope(&mut cmd, &masker.mask(command), scope)?;
+        let pgid = child.id();
+        let output = child.wait_with_output();
+        if let Some(scope) = scope {
+            scope.unregister(pgid);
+        }
+        let output = output.map_err(|e| PipelineError::Io(e.to_string()))?;
 
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        let stdout = masker.mask(&String::from_utf8_lossy(&output.stdout));
+        let stderr = masker.mask(&String::from_utf8_lossy(&output.stderr));
         let exit_code = output.status.code().unwrap_or(-1);
 
         if !stdout.is_empty() {
@@ -370,10 +377,7 @@ impl<'a> ShellCommand<'a> {
         }
 
         if exit_code != 0 {
-            return Err(PipelineError::CommandFailed {
-                code: exit_code,
-                stderr,
-            });
+            return Err(failure(&masker.mask(command), exit_code, stderr, scope));
         }
 
         Ok(ShellResult {
@@ -385,6 +389,44 @@ impl<'a> ShellCommand<'a> {
     }
 }
 
+/// Spawns a command in a new process group registered with `scope`
+///
+/// If the scope was killed in the meantime the new group is killed at once.
+fn spawn_in_scope(
+    cmd: &mut Command,
+    command: &str,
+    scope: Option<&ProcessScope>,
+) -> Result<std::process::Child, PipelineError> {
+    let mut child = in_new_group(cmd)
+        .spawn()
+        .map_err(|e| PipelineError::Io(e.to_string()))?;
+
+    if let Some(scope) = scope
+        && let Err(reason) = scope.register(child.id())
+    {
+        signal_group(child.id(), Signal::Kill);
+        let _ = child.wait();
+        return Err(reason.into_error(command));
+    }
+    Ok(child)
+}
+
+/// Builds the error for a failed command, noting if its scope was killed
+fn failure(
+    command: &str,
+    exit_code: i32,
+    stderr: String,
+    scope: Option<&ProcessScope>,
+) -> PipelineError {
+    match scope.and_then(ProcessScope::killed) {
+        Some(reason) => reason.into_error(command),
+        None => PipelineError::CommandFailed {
+            code: exit_code,
+            stderr,
+        },
+    }
+}
+
 /// Expands environment variables in a command string
 ///
 /// Variables are expanded using the `${VAR_NAME}` syntax.
@@ -505,21 +547,11 @@ pub fn jenkins_shell_config(
         shell: "sh".to_string(),
         streaming: false,
         timeout: None,
+        kill_grace: DEFAULT_GRACE_PERIOD,
+        masker: SecretMasker::new(),
     }
 }
 
-/// Updates shell config with build number (for test mode)
-#[cfg(test)]
-pub fn update_build_number(config: &mut ShellConfig) {
-    let build_num = BUILD_COUNTER.fetch_add(1, Ordering::SeqCst);
-    config
-        .env
-        .insert("BUILD_NUMBER".to_string(), build_num.to_string());
-    config
-        .env
-        .insert("BUILD_ID".to_string(), Uuid::new_v4().to_string());
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -588,6 +620,36 @@ mod tests {
         assert_eq!(config.env.get("STAGE_NAME").unwrap(), "Build");
     }
 
+    #[test]
+    fn test_output_is_masked() {
+        let config = ShellConfig {
+            env: HashMap::from([("TOKEN".to_string(), "s3cr3t-token".to_string())]),
+            ..ShellConfig::default()
+        };
+        config.masker.add("s3cr3t-token");
+
+        let result = ShellCommand::new(&config)
+            .execute(r#"echo "$TOKEN"; printf %s "$TOKEN" | base64 >&2"#)
+            .unwrap();
+        assert_eq!(result.stdout, "****\n");
+        assert_eq!(result.stderr, "****\n");
+
+        let streaming = ShellConfig {
+            streaming: true,
+            ..config.clone()
+        };
+        let result = ShellCommand::new(&streaming)
+            .execute(r#"echo "$TOKEN" >&2; exit 1"#)
+            .unwrap_err();
+        assert_eq!(
+            result,
+            PipelineError::CommandFailed {
+                code: 1,
+                stderr: "****\n".to_string(),
+            }
+        );
+    }
+
     #[test]
     fn test_shell_result_is_success() {
         let result = ShellResult {
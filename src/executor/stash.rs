@@ -0,0 +1,297 @@
+//! Stash store for handing files between stages
+//!
+//! Stashes are gzip-compressed tar archives kept in a per-build directory
+//! under the `TempFileManager` `@tmp/` area. The store is created lazily on
+//! the first stash, so pipelines that never stash leave the workspace alone.
+//! A single store is shared by every clone of a `PipelineContext`, which lets
+//! parallel branches and matrix cells exchange stashes.
+
+use super::temp_files::TempFileManager;
+use crate::pipeline::PipelineError;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use parking_lot::Mutex;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// Workspace directories managed by `TempFileManager`, never stashed
+const RESERVED_DIRS: &[&str] = &["@tmp", "@libs", "@script@libs"];
+
+/// Per-build store of named stashes
+#[derive(Debug)]
+pub struct StashStore {
+    /// Workspace root the temp area lives in
+    workspace: PathBuf,
+
+    /// Job name used to namespace the build's temp files
+    job_name: String,
+
+    /// Build ID used to namespace the build's temp files
+    build_id: String,
+
+    /// Temp file manager, created on first use
+    manager: Mutex<Option<TempFileManager>>,
+}
+
+impl StashStore {
+    /// Creates a stash store for a build
+    ///
+    /// Nothing is written to disk until the first stash is saved.
+    #[must_use]
+    pub fn new(workspace: impl Into<PathBuf>, job_name: &str, build_id: &str) -> Self {
+        Self {
+            workspace: workspace.into(),
+            job_name: job_name.to_string(),
+            build_id: build_id.to_string(),
+            manager: Mutex::new(None),
+        }
+    }
+
+    /// Stashes files under `base` matching `includes` and not `excludes`
+    ///
+    /// Both pattern lists are comma-separated globs relative to `base`, where
+    /// `*` stays within a directory and `**` spans directories. An empty
+    /// include list stashes everything. Returns the number of files stashed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::Io` if a pattern is invalid or the archive
+    /// cannot be written.
+    pub fn save(
+        &self,
+        name: &str,
+        base: &Path,
+        includes: &str,
+        excludes: &str,
+    ) -> Result<usize, PipelineError> {
+        let includes = parse_patterns(includes)?;
+        let excludes = parse_patterns(excludes)?;
+
+        let mut files = Vec::new();
+        collect_files(base, Path::new(""), &mut files)?;
+        files.retain(|rel| {
+            let rel = rel.to_string_lossy();
+            (includes.is_empty() || includes.iter().any(|p| p.matches_with(&rel, MATCH_OPTIONS)))
+                && !excludes.iter().any(|p| p.matches_with(&rel, MATCH_OPTIONS))
+        });
+
+        if files.is_empty() {
+            tracing::warn!(stash = %name, "No files matched, creating empty stash");
+        }
+
+        let path = self.archive_path(name)?;
+        // Write to a unique temp file first so concurrent readers never see
+        // a partial archive
+        let partial = path.with_extension(format!("{}.partial", uuid::Uuid::new_v4()));
+        {
+            let encoder = GzEncoder::new(File::create(&partial)?, Compression::default());
+            let mut builder = tar::Builder::new(encoder);
+            builder.follow_symlinks(false);
+            for rel in &files {
+                builder.append_path_with_name(base.join(rel), rel)?;
+            }
+            builder.into_inner()?.finish()?;
+        }
+        fs::rename(&partial, &path)?;
+
+        tracing::info!(stash = %name, files = files.len(), "Stashed files");
+        Ok(files.len())
+    }
+
+    /// Restores a stash into `dest`
+    ///
+    /// Returns the number of entries restored.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::StashNotFound` if no stash with that name was
+    /// saved in this build, or `PipelineError::Io` if extraction fails.
+    pub fn restore(&self, name: &str, dest: &Path) -> Result<usize, PipelineError> {
+        let path = self.archive_path(name)?;
+        let file = File::open(&path).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                PipelineError::StashNotFound {
+                    name: name.to_string(),
+                }
+            } else {
+                e.into()
+            }
+        })?;
+
+        fs::create_dir_all(dest)?;
+        let mut archive = tar::Archive::new(GzDecoder::new(file));
+        archive.set_preserve_mtime(true);
+
+        let mut restored = 0;
+        for entry in archive.entries()? {
+            // `unpack_in` refuses entries that would escape `dest`
+            if entry?.unpack_in(dest)? {
+                restored += 1;
+            }
+        }
+
+        tracing::info!(stash = %name, files = restored, "Unstashed files");
+        Ok(restored)
+    }
+
+    /// Returns the archive path for a stash, creating the store if needed
+    fn archive_path(&self, name: &str) -> Result<PathBuf, PipelineError> {
+        if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+            return Err(PipelineError::Io(format!("Invalid stash name: '{name}'")));
+        }
+
+        let mut manager = self.manager.lock();
+        let dir = if let Some(manager) = manager.as_ref() {
+            manager.stash_dir()
+        } else {
+            let created = TempFileManager::new(&self.workspace, &self.job_name, &self.build_id)?;
+            let dir = created.stash_dir();
+            fs::create_dir_all(&dir)?;
+            *manager = Some(created);
+            dir
+        };
+
+        Ok(dir.join(format!("{name}.tar.gz")))
+    }
+}
+
+/// Glob options giving Ant-style semantics to `*` and `**`
+const MATCH_OPTIONS: glob::MatchOptions = glob::MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Parses a comma-separated list of glob patterns
+fn parse_patterns(patterns: &str) -> Result<Vec<glob::Pattern>, PipelineError> {
+    patterns
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            glob::Pattern::new(p)
+                .map_err(|e| PipelineError::Io(format!("Invalid stash pattern '{p}': {e}")))
+        })
+        .collect()
+}
+
+/// Recursively collects files below `root`, as paths relative to it
+///
+/// Symlinks are collected as entries but never followed.
+fn collect_files(root: &Path, rel: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(root.join(rel))? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let rel_path = rel.join(&name);
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if RESERVED_DIRS.iter().any(|d| name == *d) {
+                continue;
+            }
+            collect_files(root, &rel_path, files)?;
+        } else {
+            files.push(rel_path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn workspace() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("target/release")).unwrap();
+        fs::write(dir.path().join("target/release/app"), "binary").unwrap();
+        fs::write(dir.path().join("target/release/app.d"), "deps").unwrap();
+        fs::write(dir.path().join("README.md"), "readme").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_save_and_restore_roundtrip() {
+        let ws = workspace();
+        let store = StashStore::new(ws.path(), "job", "1");
+
+        let count = store.save("bin", ws.path(), "target/**", "").unwrap();
+        assert_eq!(count, 2);
+
+        let dest = TempDir::new().unwrap();
+        store.restore("bin", dest.path()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dest.path().join("target/release/app")).unwrap(),
+            "binary"
+        );
+        assert!(!dest.path().join("README.md").exists());
+    }
+
+    #[test]
+    fn test_excludes_are_applied() {
+        let ws = workspace();
+        let store = StashStore::new(ws.path(), "job", "1");
+
+        let count = store
+            .save("bin", ws.path(), "**/*", "**/*.d, README.md")
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_single_star_stays_in_directory() {
+        let ws = workspace();
+        let store = StashStore::new(ws.path(), "job", "1");
+
+        assert_eq!(store.save("top", ws.path(), "*", "").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_reserved_dirs_are_not_stashed() {
+        let ws = workspace();
+        let store = StashStore::new(ws.path(), "job", "1");
+
+        store.save("first", ws.path(), "", "").unwrap();
+        // The first stash now lives in @tmp and must not be picked up
+        assert_eq!(store.save("all", ws.path(), "", "").unwrap(), 3);
+    }
+
+    #[test]
+    fn test_restore_missing_stash_fails() {
+        let ws = workspace();
+        let store = StashStore::new(ws.path(), "job", "1");
+
+        let result = store.restore("missing", ws.path());
+        assert_eq!(
+            result.unwrap_err(),
+            PipelineError::StashNotFound {
+                name: "missing".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_stash_name() {
+        let ws = workspace();
+        let store = StashStore::new(ws.path(), "job", "1");
+
+        assert!(store.save("../escape", ws.path(), "", "").is_err());
+    }
+
+    #[test]
+    fn test_store_is_lazy_and_cleaned_on_drop() {
+        let ws = workspace();
+        let store = StashStore::new(ws.path(), "job", "7");
+        assert!(!ws.path().join("@tmp").exists());
+
+        store.save("all", ws.path(), "", "").unwrap();
+        let stash_dir = ws.path().join("@tmp/job-7-stash");
+        assert!(stash_dir.join("all.tar.gz").exists());
+
+        drop(store);
+        assert!(!stash_dir.exists());
+    }
+}
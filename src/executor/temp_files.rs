@@ -288,7 +288,11 @@ impl TempFileManager {
                 let entry = entry?;
                 let file_name = entry.file_name();
                 if file_name.to_string_lossy().starts_with(&pattern) {
-                    fs::remove_file(entry.path())?;
+                    if entry.file_type()?.is_dir() {
+                        fs::remove_dir_all(entry.path())?;
+                    } else {
+                        fs::remove_file(entry.path())?;
+                    }
                 }
             }
         }
@@ -334,6 +338,16 @@ impl TempFileManager {
         &self.tmp_dir
     }
 
+    /// Gets the path to the stash directory for the current build
+    ///
+    /// The directory lives in `@tmp/` and is removed together with the
+    /// build's other temp files.
+    #[must_use]
+    pub fn stash_dir(&self) -> PathBuf {
+        let name = format!("{}-{}-stash", self.job_name, self.build_id);
+        self.tmp_dir.join(name)
+    }
+
     /// Gets the path to the libs directory
     #[must_use]
     pub fn libs_dir(&self) -> &Path {
@@ -2,8 +2,14 @@
 //!
 //! This module defines traits and interfaces for pipeline execution.
 
+use super::cancel::CancellationHandle;
+use super::history::BranchRecord;
+use super::masking::SecretMasker;
+use super::process::ProcessScope;
+use super::stash::StashStore;
 use crate::pipeline::{Pipeline, StageResult};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 /// Trait for executing pipelines
 #[allow(clippy::missing_errors_doc)]
@@ -105,17 +111,59 @@ pub struct PipelineContext {
 
     /// Stage results from previous stages
     pub stage_results: HashMap<String, StageResult>,
+
+    /// Per-branch results of the parallel and matrix stages that ran, by
+    /// stage name
+    pub branch_results: HashMap<String, Vec<BranchRecord>>,
+
+    /// Stash store shared by every clone of this context
+    pub stashes: Arc<StashStore>,
+
+    /// Process groups started by the steps running with this context
+    pub processes: Arc<ProcessScope>,
+
+    /// Handle that cancels the build this context belongs to
+    pub cancellation: CancellationHandle,
+
+    /// Directories to return to when leaving `dir` blocks
+    pub dir_stack: Vec<std::path::PathBuf>,
+
+    /// Secret values masked in output, shared by every clone of this context
+    pub secrets: SecretMasker,
 }
 
 impl PipelineContext {
     /// Creates a new pipeline context
     #[must_use]
     pub fn new() -> Self {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let pipeline_id = uuid::Uuid::new_v4().to_string();
+        let cancellation = CancellationHandle::new();
         Self {
             env: std::env::vars().collect(),
-            cwd: std::env::current_dir().unwrap_or_default(),
-            pipeline_id: uuid::Uuid::new_v4().to_string(),
+            stashes: Arc::new(StashStore::new(&cwd, "pipeline", &pipeline_id)),
+            processes: cancellation.processes(),
+            cancellation,
+            cwd,
+            pipeline_id,
             stage_results: HashMap::new(),
+            branch_results: HashMap::new(),
+            dir_stack: Vec::new(),
+            secrets: SecretMasker::new(),
+        }
+    }
+
+    /// Returns a copy of this context that ignores cancellation
+    ///
+    /// Used to run post-conditions of an aborted build, whose own process
+    /// scope no longer accepts new processes.
+    #[must_use]
+    pub fn detach_cancellation(&self) -> Self {
+        let cancellation = CancellationHandle::new();
+        Self {
+            processes: cancellation.processes(),
+            cancellation,
+            ..self.clone()
         }
     }
 
@@ -124,6 +172,13 @@ impl PipelineContext {
         self.env.insert(key.into(), value.into());
     }
 
+    /// Sets an environment variable whose value is masked in output
+    pub fn set_secret_env(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        let value = value.into();
+        self.secrets.add(&value);
+        self.env.insert(key.into(), value);
+    }
+
     /// Gets an environment variable
     #[must_use]
     pub fn get_env(&self, key: &str) -> Option<&String> {
@@ -135,6 +190,18 @@ impl PipelineContext {
         self.cwd = path.into();
     }
 
+    /// Enters a directory, resolved relative to the current one
+    pub fn push_dir(&mut self, path: impl AsRef<std::path::Path>) {
+        let dir = self.cwd.join(path);
+        self.dir_stack.push(std::mem::replace(&mut self.cwd, dir));
+    }
+
+    /// Returns to the directory active before the last `push_dir`
+    pub fn pop_dir(&mut self) -> Option<std::path::PathBuf> {
+        let previous = self.dir_stack.pop()?;
+        Some(std::mem::replace(&mut self.cwd, previous))
+    }
+
     /// Records the result of a stage
     pub fn record_stage_result(&mut self, stage_name: &str, result: StageResult) {
         self.stage_results.insert(stage_name.to_string(), result);
@@ -145,6 +212,12 @@ impl PipelineContext {
     pub fn get_stage_result(&self, stage_name: &str) -> Option<&StageResult> {
         self.stage_results.get(stage_name)
     }
+
+    /// Gets the per-branch results of a parallel or matrix stage
+    #[must_use]
+    pub fn get_branch_results(&self, stage_name: &str) -> Option<&[BranchRecord]> {
+        self.branch_results.get(stage_name).map(Vec::as_slice)
+    }
 }
 
 impl Default for PipelineContext {
@@ -0,0 +1,239 @@
+//! Evaluation of stage `when` conditions
+//!
+//! Branch and tag conditions are glob patterns (`release/*`, `v1.?.*`) matched
+//! against the current branch or tag. Those are taken from the environment
+//! first (`BRANCH_NAME`, `GIT_BRANCH`, `TAG_NAME`, `GIT_TAG`) and fall back to
+//! the git checkout found in the context working directory.
+
+use super::shell::expand_variables;
+use super::traits::PipelineContext;
+use crate::pipeline::WhenCondition;
+use std::process::Command;
+
+/// Environment variables consulted for the current branch, in order
+const BRANCH_VARS: &[&str] = &["BRANCH_NAME", "GIT_BRANCH"];
+
+/// Environment variables consulted for the current tag, in order
+const TAG_VARS: &[&str] = &["TAG_NAME", "GIT_TAG"];
+
+/// Evaluates a `when` condition against the pipeline context
+///
+/// Returns `true` when the stage guarded by `condition` should run.
+#[must_use]
+pub fn evaluate_when(condition: &WhenCondition, context: &PipelineContext) -> bool {
+    match condition {
+        WhenCondition::Branch { branch } => {
+            current_branch(context).is_some_and(|current| glob_matches(branch, &current))
+        }
+        WhenCondition::Tag { tag } => {
+            current_tag(context).is_some_and(|current| glob_matches(tag, &current))
+        }
+        WhenCondition::Environment { name, value } => context.get_env(name) == Some(value),
+        WhenCondition::Expression { expression } => evaluate_expression(expression, context),
+        WhenCondition::AllOf { conditions } => {
+            conditions.iter().all(|cond| evaluate_when(cond, context))
+        }
+        WhenCondition::AnyOf { conditions } => {
+            conditions.iter().any(|cond| evaluate_when(cond, context))
+        }
+    }
+}
+
+/// Resolves the branch being built
+///
+/// Remote prefixes such as `origin/` reported by `GIT_BRANCH` are stripped so
+/// that patterns only need to describe the branch name itself.
+fn current_branch(context: &PipelineContext) -> Option<String> {
+    BRANCH_VARS
+        .iter()
+        .find_map(|var| context.get_env(var).filter(|v| !v.is_empty()))
+        .map(|branch| branch.strip_prefix("origin/").unwrap_or(branch).to_string())
+        .or_else(|| {
+            git_output(context, &["rev-parse", "--abbrev-ref", "HEAD"])
+                // A detached checkout has no branch name
+                .filter(|branch| branch != "HEAD")
+        })
+}
+
+/// Resolves the tag being built, if the checkout is exactly on a tag
+fn current_tag(context: &PipelineContext) -> Option<String> {
+    TAG_VARS
+        .iter()
+        .find_map(|var| context.get_env(var).filter(|v| !v.is_empty()))
+        .cloned()
+        .or_else(|| git_output(context, &["describe", "--tags", "--exact-match", "HEAD"]))
+}
+
+/// Runs a git command in the context working directory and returns its
+/// trimmed stdout, or `None` if git is unavailable or the command failed
+fn git_output(context: &PipelineContext, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(&context.cwd)
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
+
+/// Matches a name against a glob pattern
+///
+/// Invalid patterns fall back to an exact comparison.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    match glob::Pattern::new(pattern) {
+        Ok(pattern) => pattern.matches(name),
+        Err(e) => {
+            tracing::warn!(pattern = %pattern, error = %e, "Invalid glob pattern, using exact match");
+            pattern == name
+        }
+    }
+}
+
+/// Evaluates a simple boolean expression
+///
+/// Variables are expanded with `${VAR}` syntax. The expanded text may be a
+/// single `left == right` or `left != right` comparison; otherwise it is true
+/// unless empty, `false` or `0`.
+fn evaluate_expression(expression: &str, context: &PipelineContext) -> bool {
+    let expanded = expand_variables(expression, &context.env);
+
+    if let Some((left, right)) = expanded.split_once("!=") {
+        return unquote(left) != unquote(right);
+    }
+    if let Some((left, right)) = expanded.split_once("==") {
+        return unquote(left) == unquote(right);
+    }
+
+    let value = unquote(&expanded);
+    !(value.is_empty() || value.eq_ignore_ascii_case("false") || value == "0")
+}
+
+/// Trims whitespace and surrounding quotes from an expression operand
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context_with(vars: &[(&str, &str)]) -> PipelineContext {
+        let mut context = PipelineContext::new();
+        for var in BRANCH_VARS.iter().chain(TAG_VARS) {
+            context.env.remove(*var);
+        }
+        // Point at a directory that is never a git checkout
+        context.set_cwd(std::env::temp_dir());
+        for (key, value) in vars {
+            context.set_env(*key, *value);
+        }
+        context
+    }
+
+    #[test]
+    fn test_branch_exact_and_glob() {
+        let context = context_with(&[("BRANCH_NAME", "release/1.2")]);
+
+        assert!(evaluate_when(
+            &WhenCondition::branch("release/1.2"),
+            &context
+        ));
+        assert!(evaluate_when(&WhenCondition::branch("release/*"), &context));
+        assert!(!evaluate_when(&WhenCondition::branch("main"), &context));
+    }
+
+    #[test]
+    fn test_branch_strips_remote_prefix() {
+        let context = context_with(&[("GIT_BRANCH", "origin/main")]);
+
+        assert!(evaluate_when(&WhenCondition::branch("main"), &context));
+    }
+
+    #[test]
+    fn test_tag_glob() {
+        let context = context_with(&[("TAG_NAME", "v1.4.0")]);
+
+        assert!(evaluate_when(&WhenCondition::tag("v1.*"), &context));
+        assert!(!evaluate_when(&WhenCondition::tag("v2.*"), &context));
+    }
+
+    #[test]
+    fn test_tag_missing_is_false() {
+        let context = context_with(&[]);
+
+        assert!(!evaluate_when(&WhenCondition::tag("*"), &context));
+    }
+
+    #[test]
+    fn test_environment_equality() {
+        let context = context_with(&[("DEPLOY_TARGET", "prod")]);
+
+        assert!(evaluate_when(
+            &WhenCondition::environment("DEPLOY_TARGET", "prod"),
+            &context
+        ));
+        assert!(!evaluate_when(
+            &WhenCondition::environment("DEPLOY_TARGET", "staging"),
+            &context
+        ));
+        assert!(!evaluate_when(
+            &WhenCondition::environment("UNSET_VAR_FOR_TEST", ""),
+            &context
+        ));
+    }
+
+    #[test]
+    fn test_nested_all_of_any_of() {
+        let context = context_with(&[("BRANCH_NAME", "main"), ("DEPLOY", "true")]);
+
+        let condition = WhenCondition::all_of(vec![
+            WhenCondition::any_of(vec![
+                WhenCondition::branch("main"),
+                WhenCondition::branch("release/*"),
+            ]),
+            WhenCondition::environment("DEPLOY", "true"),
+        ]);
+        assert!(evaluate_when(&condition, &context));
+
+        let condition = WhenCondition::all_of(vec![
+            WhenCondition::branch("main"),
+            WhenCondition::environment("DEPLOY", "false"),
+        ]);
+        assert!(!evaluate_when(&condition, &context));
+    }
+
+    #[test]
+    fn test_expression() {
+        let context = context_with(&[("MODE", "release"), ("FLAG", "false")]);
+
+        assert!(evaluate_when(
+            &WhenCondition::expression("${MODE} == 'release'"),
+            &context
+        ));
+        assert!(evaluate_when(
+            &WhenCondition::expression("${MODE} != debug"),
+            &context
+        ));
+        assert!(!evaluate_when(
+            &WhenCondition::expression("${FLAG}"),
+            &context
+        ));
+        assert!(evaluate_when(&WhenCondition::expression("true"), &context));
+    }
+
+    #[test]
+    fn test_glob_matches_invalid_pattern_falls_back_to_exact() {
+        assert!(glob_matches("[", "["));
+        assert!(!glob_matches("[", "main"));
+    }
+}
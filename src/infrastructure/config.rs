@@ -1,13 +1,14 @@
 //! Configuration management
 
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 
 /// Application configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     /// Default executor
     pub default_executor: String,
-    /// Cache directory
+    /// Cache directory, relative to the home directory unless absolute
     pub cache_dir: String,
     /// Log level
     pub log_level: String,
@@ -23,6 +24,24 @@ impl Default for Config {
     }
 }
 
+impl Config {
+    /// Returns the absolute cache directory
+    ///
+    /// A relative `cache_dir` is resolved against `$HOME`, or the temp
+    /// directory without one, never against the current directory.
+    #[must_use]
+    pub fn cache_home(&self) -> PathBuf {
+        let cache_dir = Path::new(&self.cache_dir);
+        if cache_dir.is_absolute() {
+            return cache_dir.to_path_buf();
+        }
+        std::env::var_os("HOME")
+            .filter(|home| !home.is_empty())
+            .map_or_else(std::env::temp_dir, PathBuf::from)
+            .join(cache_dir)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -33,4 +52,15 @@ mod tests {
         assert_eq!(config.default_executor, "local");
         assert_eq!(config.log_level, "info");
     }
+
+    #[test]
+    fn test_cache_home_is_absolute() {
+        assert!(Config::default().cache_home().is_absolute());
+
+        let config = Config {
+            cache_dir: "/var/cache/rustline".to_string(),
+            ..Config::default()
+        };
+        assert_eq!(config.cache_home(), PathBuf::from("/var/cache/rustline"));
+    }
 }
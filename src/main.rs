@@ -10,6 +10,8 @@
 //! - `rustline doc` - Generate documentation from pipeline comments
 //! - `rustline export` - Convert pipelines to CI/CD formats
 //! - `rustline completions` - Generate shell completions
+//! - `rustline input` - List and answer pending pipeline inputs
+//! - `rustline gc` - Remove old builds and artifacts from the build history
 //! - `rustline run` - Execute pipelines via rust-script
 //!
 //! ## Installation
@@ -34,6 +34,54 @@ pub enum PipelineError {
         duration: std::time::Duration,
     },
 
+    /// A step's processes were killed before it finished
+    #[error("Step '{step}' killed: {reason}")]
+    StepKilled {
+        /// Step whose processes were killed.
+        step: String,
+        /// Why the step was killed.
+        reason: String,
+    },
+
+    /// The build was cancelled before the step could run
+    #[error("Build aborted: {reason}")]
+    Aborted {
+        /// Why the build was cancelled.
+        reason: String,
+    },
+
+    /// Unstash requested a stash that was never saved
+    #[error("No stash named '{name}' found")]
+    StashNotFound {
+        /// Name of the missing stash.
+        name: String,
+    },
+
+    /// An input step was aborted or timed out without a default
+    #[error("Input '{id}' aborted: {reason}")]
+    InputAborted {
+        /// Identifier of the input step.
+        id: String,
+        /// Why the input was aborted.
+        reason: String,
+    },
+
+    /// An input step was answered by someone outside the submitter list
+    #[error("Input '{id}' cannot be answered by '{submitter}'")]
+    InputRejected {
+        /// Identifier of the input step.
+        id: String,
+        /// User who attempted to answer.
+        submitter: String,
+    },
+
+    /// A custom step was invoked but never registered
+    #[error("Custom step '{name}' is not registered")]
+    CustomStepNotFound {
+        /// Name of the missing step.
+        name: String,
+    },
+
     /// IO error occurred
     #[error("IO error: {0}")]
     Io(String),
@@ -97,6 +145,13 @@ pub enum ValidationError {
         value: usize,
     },
 
+    /// Invalid parallel branch limit
+    #[error("Invalid max parallel branches for stage '{stage}': must be positive")]
+    InvalidMaxParallel {
+        /// Name of the stage.
+        stage: String,
+    },
+
     /// Invalid agent type
     #[error("Invalid agent type: {0}")]
     InvalidAgentType(String),
@@ -36,6 +36,10 @@ pub struct Environment {
     /// Environment variables as key-value pairs.
     #[serde(flatten)]
     pub vars: std::collections::HashMap<String, String>,
+
+    /// Names of the variables whose values are masked in output.
+    #[serde(skip_serializing_if = "std::collections::BTreeSet::is_empty", default)]
+    pub secrets: std::collections::BTreeSet<String>,
 }
 
 impl Environment {
@@ -52,6 +56,23 @@ impl Environment {
         self
     }
 
+    /// Sets an environment variable whose value is a secret.
+    ///
+    /// The value is masked wherever a build would print it.
+    #[must_use]
+    pub fn secret(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let key = key.into();
+        self.vars.insert(key.clone(), value.into());
+        self.secrets.insert(key);
+        self
+    }
+
+    /// Returns true if the variable's value is a secret.
+    #[must_use]
+    pub fn is_secret(&self, key: &str) -> bool {
+        self.secrets.contains(key)
+    }
+
     /// Gets an environment variable by name.
     #[must_use]
     pub fn get(&self, key: &str) -> Option<&String> {
@@ -217,6 +238,47 @@ pub struct MatrixExclude {
     pub conditions: Vec<(String, String)>,
 }
 
+/// A single cell of the matrix, one value per axis
+///
+/// Cells run with their axis values set as environment variables.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatrixCell {
+    /// Axis names and values, in axis order
+    pub values: Vec<(String, String)>,
+}
+
+impl MatrixCell {
+    /// Returns the value of an axis in this cell
+    #[must_use]
+    pub fn get(&self, axis: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(name, _)| name == axis)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the cell name, such as `OS=linux, RUST=stable`
+    ///
+    /// Names list the axes in declaration order, so a cell keeps its name
+    /// across builds as long as the matrix axes are unchanged.
+    #[must_use]
+    pub fn name(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl std::fmt::Display for MatrixCell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, (axis, value)) in self.values.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{axis}={value}")?;
+        }
+        Ok(())
+    }
+}
+
 impl MatrixConfig {
     /// Creates a new empty matrix configuration
     #[must_use]
@@ -282,6 +344,15 @@ impl MatrixConfig {
             })
             .collect()
     }
+
+    /// Returns the cells of the matrix, excluded combinations left out
+    #[must_use]
+    pub fn cells(&self) -> Vec<MatrixCell> {
+        self.generate_combinations()
+            .into_iter()
+            .map(|values| MatrixCell { values })
+            .collect()
+    }
 }
 
 impl Validate for MatrixConfig {
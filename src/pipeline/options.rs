@@ -145,12 +145,20 @@ pub struct PipelineOptions {
 /// Build discarder configuration
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct BuildDiscarder {
-    /// Number of builds to keep
+    /// Number of builds to keep (0 keeps every build)
     pub num_to_keep: usize,
 
     /// Number of days to keep builds
     #[serde(skip_serializing_if = "Option::is_none")]
     pub days_to_keep: Option<usize>,
+
+    /// Number of builds to keep artifacts for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact_num_to_keep: Option<usize>,
+
+    /// Number of days to keep artifacts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact_days_to_keep: Option<usize>,
 }
 
 impl BuildDiscarder {
@@ -160,6 +168,8 @@ impl BuildDiscarder {
         Self {
             num_to_keep,
             days_to_keep: None,
+            artifact_num_to_keep: None,
+            artifact_days_to_keep: None,
         }
     }
 
@@ -170,6 +180,20 @@ impl BuildDiscarder {
         self.days_to_keep = Some(days);
         self
     }
+
+    /// Sets number of builds to keep artifacts for
+    #[must_use]
+    pub fn with_artifact_num_to_keep(mut self, num: usize) -> Self {
+        self.artifact_num_to_keep = Some(num);
+        self
+    }
+
+    /// Sets days to keep artifacts
+    #[must_use]
+    pub fn with_artifact_days_to_keep(mut self, days: usize) -> Self {
+        self.artifact_days_to_keep = Some(days);
+        self
+    }
 }
 
 /// Helper for validated duration values
@@ -337,5 +361,12 @@ mod tests {
         let discarder = BuildDiscarder::new(10).with_days_to_keep(30);
         assert_eq!(discarder.num_to_keep, 10);
         assert_eq!(discarder.days_to_keep, Some(30));
+        assert_eq!(discarder.artifact_num_to_keep, None);
+
+        let discarder = discarder
+            .with_artifact_num_to_keep(3)
+            .with_artifact_days_to_keep(7);
+        assert_eq!(discarder.artifact_num_to_keep, Some(3));
+        assert_eq!(discarder.artifact_days_to_keep, Some(7));
     }
 }
@@ -162,6 +162,17 @@ pub struct SharedRegistry {
     inner: Arc<Mutex<CustomStepRegistry>>,
 }
 
+impl std::fmt::Debug for SharedRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let guard = self.inner.lock().unwrap();
+        let mut names = guard.names();
+        names.sort_unstable();
+        f.debug_struct("SharedRegistry")
+            .field("steps", &names)
+            .finish()
+    }
+}
+
 impl SharedRegistry {
     /// Creates a new shared registry
     #[must_use]
@@ -41,6 +41,30 @@ pub enum PostCondition {
         /// Steps to execute
         steps: Vec<Step>,
     },
+
+    /// Execute on success after a failed or unstable previous run
+    Fixed {
+        /// Steps to execute
+        steps: Vec<Step>,
+    },
+
+    /// Execute on failure or instability after a successful previous run
+    Regression {
+        /// Steps to execute
+        steps: Vec<Step>,
+    },
+
+    /// Execute when the build was aborted
+    Aborted {
+        /// Steps to execute
+        steps: Vec<Step>,
+    },
+
+    /// Always execute, after every other condition
+    Cleanup {
+        /// Steps to execute
+        steps: Vec<Step>,
+    },
 }
 
 impl PostCondition {
@@ -69,6 +93,31 @@ impl PostCondition {
         Self::Changed { steps }
     }
 
+    /// Creates a "fixed" condition
+    pub fn fixed(steps: Vec<Step>) -> Self {
+        Self::Fixed { steps }
+    }
+
+    /// Creates a "regression" condition
+    pub fn regression(steps: Vec<Step>) -> Self {
+        Self::Regression { steps }
+    }
+
+    /// Creates an "aborted" condition
+    pub fn aborted(steps: Vec<Step>) -> Self {
+        Self::Aborted { steps }
+    }
+
+    /// Creates a "cleanup" condition
+    pub fn cleanup(steps: Vec<Step>) -> Self {
+        Self::Cleanup { steps }
+    }
+
+    /// Returns true if this condition runs after all others
+    pub fn is_cleanup(&self) -> bool {
+        matches!(self, Self::Cleanup { .. })
+    }
+
     /// Returns the steps for this condition
     pub fn steps(&self) -> &[Step] {
         match self {
@@ -76,7 +125,11 @@ impl PostCondition {
             | Self::Success { steps }
             | Self::Failure { steps }
             | Self::Unstable { steps }
-            | Self::Changed { steps } => steps,
+            | Self::Changed { steps }
+            | Self::Fixed { steps }
+            | Self::Regression { steps }
+            | Self::Aborted { steps }
+            | Self::Cleanup { steps } => steps,
         }
     }
 
@@ -87,7 +140,7 @@ impl PostCondition {
         previous: Option<super::StageResult>,
     ) -> bool {
         match self {
-            Self::Always { .. } => true,
+            Self::Always { .. } | Self::Cleanup { .. } => true,
             Self::Success { .. } => result.is_success(),
             Self::Failure { .. } => result.is_failure(),
             Self::Unstable { .. } => result.is_unstable() || result.is_failure(),
@@ -98,6 +151,15 @@ impl PostCondition {
                     true // First run always counts as changed
                 }
             }
+            Self::Fixed { .. } => {
+                result.is_success()
+                    && previous.is_some_and(|prev| prev.is_failure() || prev.is_unstable())
+            }
+            Self::Regression { .. } => {
+                (result.is_failure() || result.is_unstable())
+                    && previous.is_some_and(|prev| prev.is_success())
+            }
+            Self::Aborted { .. } => result.is_aborted(),
         }
     }
 }
@@ -110,6 +172,10 @@ impl fmt::Display for PostCondition {
             Self::Failure { steps } => write!(f, "failure({} steps)", steps.len()),
             Self::Unstable { steps } => write!(f, "unstable({} steps)", steps.len()),
             Self::Changed { steps } => write!(f, "changed({} steps)", steps.len()),
+            Self::Fixed { steps } => write!(f, "fixed({} steps)", steps.len()),
+            Self::Regression { steps } => write!(f, "regression({} steps)", steps.len()),
+            Self::Aborted { steps } => write!(f, "aborted({} steps)", steps.len()),
+            Self::Cleanup { steps } => write!(f, "cleanup({} steps)", steps.len()),
         }
     }
 }
@@ -237,4 +303,44 @@ mod tests {
         // Same result again - should not execute
         assert!(!cond.should_execute(StageResult::Success, Some(StageResult::Success)));
     }
+
+    #[test]
+    fn test_post_fixed_after_failure() {
+        let cond = PostCondition::fixed(vec![Step::echo("fixed")]);
+
+        assert!(cond.should_execute(StageResult::Success, Some(StageResult::Failure)));
+        assert!(cond.should_execute(StageResult::Success, Some(StageResult::Unstable)));
+        assert!(!cond.should_execute(StageResult::Success, Some(StageResult::Success)));
+        assert!(!cond.should_execute(StageResult::Failure, Some(StageResult::Failure)));
+        // Nothing can be fixed on the first run
+        assert!(!cond.should_execute(StageResult::Success, None));
+    }
+
+    #[test]
+    fn test_post_regression_after_success() {
+        let cond = PostCondition::regression(vec![Step::echo("regression")]);
+
+        assert!(cond.should_execute(StageResult::Failure, Some(StageResult::Success)));
+        assert!(cond.should_execute(StageResult::Unstable, Some(StageResult::Success)));
+        assert!(!cond.should_execute(StageResult::Failure, Some(StageResult::Failure)));
+        assert!(!cond.should_execute(StageResult::Success, Some(StageResult::Success)));
+        assert!(!cond.should_execute(StageResult::Failure, None));
+    }
+
+    #[test]
+    fn test_post_aborted_only_on_abort() {
+        let cond = PostCondition::aborted(vec![Step::echo("aborted")]);
+        assert!(cond.should_execute(StageResult::Aborted, None));
+        assert!(!cond.should_execute(StageResult::Failure, None));
+        assert!(!cond.should_execute(StageResult::Success, None));
+    }
+
+    #[test]
+    fn test_post_cleanup_always_executes() {
+        let cond = PostCondition::cleanup(vec![Step::echo("cleanup")]);
+        assert!(cond.is_cleanup());
+        assert!(cond.should_execute(StageResult::Success, None));
+        assert!(cond.should_execute(StageResult::Aborted, None));
+        assert!(!PostCondition::always(vec![]).is_cleanup());
+    }
 }
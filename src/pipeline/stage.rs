@@ -156,6 +156,14 @@ pub struct Stage {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub matrix: Option<super::MatrixConfig>,
 
+    /// Cancel the remaining branches once one of them fails
+    #[serde(default)]
+    pub fail_fast: bool,
+
+    /// Maximum number of branches running at once
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_parallel: Option<usize>,
+
     /// Optional when condition
     #[serde(skip_serializing_if = "Option::is_none")]
     pub when: Option<WhenCondition>,
@@ -195,6 +203,12 @@ impl Validate for Stage {
             when.validate()?;
         }
 
+        if self.max_parallel == Some(0) {
+            return Err(ValidationError::InvalidMaxParallel {
+                stage: self.name.clone(),
+            });
+        }
+
         // Validate parallel branches
         for branch in &self.parallel {
             branch.stage.validate()?;
@@ -218,6 +232,8 @@ impl Stage {
             steps,
             parallel: Vec::new(),
             matrix: None,
+            fail_fast: false,
+            max_parallel: None,
             when: None,
             post: Vec::new(),
         }
@@ -241,6 +257,18 @@ impl Stage {
         self
     }
 
+    /// Sets whether a failed branch cancels the remaining branches
+    pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Limits how many branches of this stage run at once
+    pub fn with_max_parallel(mut self, max_parallel: usize) -> Self {
+        self.max_parallel = Some(max_parallel);
+        self
+    }
+
     /// Sets when condition for this stage
     pub fn with_when(mut self, when: WhenCondition) -> Self {
         self.when = Some(when);
@@ -380,6 +408,20 @@ mod tests {
         assert_eq!(stage.post.len(), 1);
     }
 
+    #[test]
+    fn test_stage_validation_zero_max_parallel() {
+        let stage = Stage::new("Build", vec![Step::shell("echo")]).with_max_parallel(0);
+        let result = stage.validate();
+        assert!(matches!(
+            result,
+            Err(ValidationError::InvalidMaxParallel { .. })
+        ));
+
+        let stage = stage.with_max_parallel(4).with_fail_fast(true);
+        assert!(stage.validate().is_ok());
+        assert!(stage.fail_fast);
+    }
+
     #[test]
     fn test_stage_display() {
         let steps = vec![Step::shell("cargo build")];
@@ -44,8 +44,11 @@ pub enum StepType {
     Stash {
         /// Name of the stash
         name: String,
-        /// File pattern to include
+        /// Comma-separated glob patterns to include
         includes: String,
+        /// Comma-separated glob patterns to exclude
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        excludes: String,
     },
 
     /// Unstash previously stashed files
@@ -61,6 +64,12 @@ pub enum StepType {
         /// Default value
         #[serde(skip_serializing_if = "Option::is_none")]
         default: Option<String>,
+        /// Input identifier, derived from the message when absent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        /// Comma-separated list of users allowed to answer
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        submitter: Option<String>,
     },
 
     /// Change directory
@@ -70,6 +79,12 @@ pub enum StepType {
         /// Steps to execute in directory
         steps: Vec<Step>,
     },
+
+    /// Custom step registered with the executor
+    Custom {
+        /// Name of the registered step
+        name: String,
+    },
 }
 
 impl StepType {
@@ -108,6 +123,20 @@ impl StepType {
         Self::Stash {
             name: name.into(),
             includes: includes.into(),
+            excludes: String::new(),
+        }
+    }
+
+    /// Creates a stash step with exclude patterns
+    pub fn stash_with_excludes(
+        name: impl Into<String>,
+        includes: impl Into<String>,
+        excludes: impl Into<String>,
+    ) -> Self {
+        Self::Stash {
+            name: name.into(),
+            includes: includes.into(),
+            excludes: excludes.into(),
         }
     }
 
@@ -121,6 +150,8 @@ impl StepType {
         Self::Input {
             message: message.into(),
             default: None,
+            id: None,
+            submitter: None,
         }
     }
 
@@ -129,6 +160,22 @@ impl StepType {
         Self::Input {
             message: message.into(),
             default: Some(default.into()),
+            id: None,
+            submitter: None,
+        }
+    }
+
+    /// Creates an approval input that only the given submitters may answer
+    pub fn approval(
+        id: impl Into<String>,
+        message: impl Into<String>,
+        submitter: impl Into<String>,
+    ) -> Self {
+        Self::Input {
+            message: message.into(),
+            default: None,
+            id: Some(id.into()),
+            submitter: Some(submitter.into()),
         }
     }
 
@@ -139,6 +186,11 @@ impl StepType {
             steps,
         }
     }
+
+    /// Creates a custom step invocation
+    pub fn custom(name: impl Into<String>) -> Self {
+        Self::Custom { name: name.into() }
+    }
 }
 
 impl fmt::Display for StepType {
@@ -148,12 +200,18 @@ impl fmt::Display for StepType {
             Self::Echo { message } => write!(f, "echo({message})"),
             Self::Retry { count, .. } => write!(f, "retry({count})"),
             Self::Timeout { duration, .. } => write!(f, "timeout({duration:?})"),
-            Self::Stash { name, includes } => write!(f, "stash({name}, {includes})"),
+            Self::Stash {
+                name,
+                includes,
+                excludes,
+            } if !excludes.is_empty() => write!(f, "stash({name}, {includes}, !{excludes})"),
+            Self::Stash { name, includes, .. } => write!(f, "stash({name}, {includes})"),
             Self::Unstash { name } => write!(f, "unstash({name})"),
             Self::Input { message, .. } => write!(f, "input({message})"),
             Self::Dir { path, steps } => {
                 write!(f, "dir({path}, {} steps)", steps.len())
             }
+            Self::Custom { name } => write!(f, "custom({name})"),
         }
     }
 }
@@ -221,6 +279,15 @@ impl Step {
         Self::new(StepType::stash(name, includes))
     }
 
+    /// Creates a stash step with exclude patterns
+    pub fn stash_with_excludes(
+        name: impl Into<String>,
+        includes: impl Into<String>,
+        excludes: impl Into<String>,
+    ) -> Self {
+        Self::new(StepType::stash_with_excludes(name, includes, excludes))
+    }
+
     /// Creates an unstash step
     pub fn unstash(name: impl Into<String>) -> Self {
         Self::new(StepType::unstash(name))
@@ -231,10 +298,24 @@ impl Step {
         Self::new(StepType::input(message))
     }
 
+    /// Creates an approval input that only the given submitters may answer
+    pub fn approval(
+        id: impl Into<String>,
+        message: impl Into<String>,
+        submitter: impl Into<String>,
+    ) -> Self {
+        Self::new(StepType::approval(id, message, submitter))
+    }
+
     /// Creates a directory change step
     pub fn dir(path: impl Into<String>, steps: Vec<Step>) -> Self {
         Self::new(StepType::dir(path, steps))
     }
+
+    /// Creates a custom step invocation
+    pub fn custom(name: impl Into<String>) -> Self {
+        Self::new(StepType::custom(name))
+    }
 }
 
 impl fmt::Display for Step {
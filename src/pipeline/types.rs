@@ -23,6 +23,8 @@ pub enum StageResult {
     Unstable,
     /// Execution was skipped
     Skipped,
+    /// Execution was cancelled before it finished
+    Aborted,
 }
 
 impl StageResult {
@@ -49,6 +51,12 @@ impl StageResult {
     pub fn is_skipped(&self) -> bool {
         matches!(self, Self::Skipped)
     }
+
+    /// Returns true if result is aborted
+    #[must_use]
+    pub fn is_aborted(&self) -> bool {
+        matches!(self, Self::Aborted)
+    }
 }
 
 impl fmt::Display for StageResult {
@@ -58,6 +66,7 @@ impl fmt::Display for StageResult {
             Self::Failure => write!(f, "FAILURE"),
             Self::Unstable => write!(f, "UNSTABLE"),
             Self::Skipped => write!(f, "SKIPPED"),
+            Self::Aborted => write!(f, "ABORTED"),
         }
     }
 }
@@ -39,12 +39,21 @@ mod types_tests {
         assert!(StageResult::Skipped.is_skipped());
     }
 
+    #[test]
+    fn test_stage_result_is_aborted() {
+        assert!(StageResult::Aborted.is_aborted());
+        assert!(!StageResult::Aborted.is_success());
+        assert!(!StageResult::Aborted.is_failure());
+        assert!(!StageResult::Failure.is_aborted());
+    }
+
     #[test]
     fn test_stage_result_display() {
         assert_eq!(StageResult::Success.to_string(), "SUCCESS");
         assert_eq!(StageResult::Failure.to_string(), "FAILURE");
         assert_eq!(StageResult::Unstable.to_string(), "UNSTABLE");
         assert_eq!(StageResult::Skipped.to_string(), "SKIPPED");
+        assert_eq!(StageResult::Aborted.to_string(), "ABORTED");
     }
 
     #[test]